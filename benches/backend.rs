@@ -0,0 +1,97 @@
+#[macro_use]
+extern crate criterion;
+
+use std::io::{Read, Write};
+
+use bytehash::State;
+use criterion::{BatchSize, Criterion, ParameterizedBenchmark, Throughput};
+use tempfile::TempDir;
+
+use kelvin::{Backend, Blake2b, ByteHash, DiskBackend, MemBackend};
+
+const BLOB_SIZES: [usize; 3] = [64, 4 * 1024, 64 * 1024];
+
+// values read back in the `get` benchmarks, cycled through
+const STORED: u64 = 1_000;
+
+// the temporary directory of a disk backend is kept around as long as the
+// backend
+type New = fn() -> (Box<dyn Backend<Blake2b>>, Option<TempDir>);
+
+fn mem() -> (Box<dyn Backend<Blake2b>>, Option<TempDir>) {
+    (Box::new(MemBackend::new()), None)
+}
+
+fn disk() -> (Box<dyn Backend<Blake2b>>, Option<TempDir>) {
+    let dir = tempfile::tempdir().unwrap();
+    (Box::new(DiskBackend::new(dir.path()).unwrap()), Some(dir))
+}
+
+const BACKENDS: [(&str, New); 2] = [("mem", mem), ("disk", disk)];
+
+fn digest(i: u64) -> <Blake2b as ByteHash>::Digest {
+    let mut state = Blake2b::state();
+    state.write_all(&i.to_le_bytes()).unwrap();
+    state.fin()
+}
+
+fn backend_benches(c: &mut Criterion) {
+    for &(name, new_backend) in BACKENDS.iter() {
+        // every put stores a new value, the digests are computed outside of
+        // the measurement
+        c.bench(
+            &format!("{}_put", name),
+            ParameterizedBenchmark::new(
+                "put",
+                move |b, &size| {
+                    let (backend, _dir) = new_backend();
+                    let bytes = vec![0xab; size];
+                    let mut i = 0;
+                    b.iter_batched(
+                        || {
+                            i += 1;
+                            digest(i)
+                        },
+                        |digest| backend.put(digest, &bytes).unwrap(),
+                        BatchSize::SmallInput,
+                    )
+                },
+                BLOB_SIZES.to_vec(),
+            )
+            .throughput(|&size| Throughput::Bytes(size as u32)),
+        );
+
+        c.bench(
+            &format!("{}_get", name),
+            ParameterizedBenchmark::new(
+                "get",
+                move |b, &size| {
+                    let (backend, _dir) = new_backend();
+                    let digests: Vec<_> = (0..STORED).map(digest).collect();
+                    for &digest in &digests {
+                        backend.put(digest, &vec![0xab; size]).unwrap();
+                    }
+                    backend.flush().unwrap();
+
+                    // readers may run past the end of the value, as values
+                    // are decoded without knowing their length up front
+                    let mut bytes = vec![0; size];
+                    let mut cycle = digests.iter().cycle();
+                    b.iter(|| {
+                        let digest = cycle.next().unwrap();
+                        backend
+                            .get(digest)
+                            .unwrap()
+                            .read_exact(&mut bytes)
+                            .unwrap();
+                    })
+                },
+                BLOB_SIZES.to_vec(),
+            )
+            .throughput(|&size| Throughput::Bytes(size as u32)),
+        );
+    }
+}
+
+criterion_group!(benches, backend_benches);
+criterion_main!(benches);
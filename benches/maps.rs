@@ -0,0 +1,127 @@
+#[macro_use]
+extern crate criterion;
+
+use criterion::{BatchSize, Criterion};
+use tempfile::TempDir;
+
+use kelvin::{Blake2b, Compound, LeafIterable, MapMut, Store};
+use kelvin_btree::BTree;
+use kelvin_hamt::HAMT;
+
+const SIZES: [u32; 2] = [1_000, 10_000];
+
+// the temporary directory of a disk store is kept around as long as the store
+type Backend = fn() -> (Store<Blake2b>, Option<TempDir>);
+
+fn mem() -> (Store<Blake2b>, Option<TempDir>) {
+    (Store::volatile().unwrap(), None)
+}
+
+fn disk() -> (Store<Blake2b>, Option<TempDir>) {
+    let dir = tempfile::tempdir().unwrap();
+    (Store::new(dir.path()).unwrap(), Some(dir))
+}
+
+const BACKENDS: [(&str, Backend); 2] = [("mem", mem), ("disk", disk)];
+
+fn fill<M>(n: u32) -> M
+where
+    M: Compound<Blake2b, Leaf = (u32, u32)> + MapMut<u32, u32, Blake2b>,
+{
+    let mut map = M::default();
+    for i in 0..n {
+        map.insert(i, i).unwrap();
+    }
+    map
+}
+
+fn map_benches<M>(c: &mut Criterion, name: &str)
+where
+    M: Compound<Blake2b, Leaf = (u32, u32)> + MapMut<u32, u32, Blake2b>,
+{
+    c.bench_function_over_inputs(
+        &format!("{}_insert", name),
+        |b, &n| b.iter(|| fill::<M>(n)),
+        SIZES.to_vec(),
+    );
+
+    c.bench_function_over_inputs(
+        &format!("{}_get", name),
+        |b, &n| {
+            let map = fill::<M>(n);
+            b.iter(|| {
+                for i in 0..n {
+                    assert_eq!(*map.get(&i).unwrap().unwrap(), i);
+                }
+            })
+        },
+        SIZES.to_vec(),
+    );
+
+    c.bench_function_over_inputs(
+        &format!("{}_remove", name),
+        |b, &n| {
+            let map = fill::<M>(n);
+            b.iter_batched(
+                || map.clone(),
+                |mut map| {
+                    for i in 0..n {
+                        map.remove(&i).unwrap();
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        },
+        SIZES.to_vec(),
+    );
+
+    c.bench_function_over_inputs(
+        &format!("{}_iterate", name),
+        |b, &n| {
+            let map = fill::<M>(n);
+            b.iter(|| assert_eq!(map.iter().count(), n as usize))
+        },
+        SIZES.to_vec(),
+    );
+
+    for &(backend, new_store) in BACKENDS.iter() {
+        c.bench_function_over_inputs(
+            &format!("{}_persist_{}", name, backend),
+            move |b, &n| {
+                let (store, _dir) = new_store();
+                let map = fill::<M>(n);
+                b.iter_batched(
+                    || map.clone(),
+                    |mut map| store.persist(&mut map).unwrap(),
+                    BatchSize::LargeInput,
+                )
+            },
+            SIZES.to_vec(),
+        );
+
+        // restores the root, and every subtree on the way through the leaves
+        c.bench_function_over_inputs(
+            &format!("{}_restore_{}", name, backend),
+            move |b, &n| {
+                let (store, _dir) = new_store();
+                let snapshot = store.persist(&mut fill::<M>(n)).unwrap();
+                b.iter(|| {
+                    let map = store.restore(&snapshot).unwrap();
+                    assert_eq!(map.iter().count(), n as usize)
+                })
+            },
+            SIZES.to_vec(),
+        );
+    }
+}
+
+fn btree(c: &mut Criterion) {
+    map_benches::<BTree<u32, u32, Blake2b>>(c, "btree");
+}
+
+fn hamt(c: &mut Criterion) {
+    map_benches::<HAMT<u32, u32, Blake2b>>(c, "hamt");
+}
+
+criterion_group!(benches, btree, hamt);
+criterion_main!(benches);
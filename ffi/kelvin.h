@@ -0,0 +1,48 @@
+/* C bindings for kelvin stores and maps, see src/lib.rs */
+#ifndef KELVIN_H
+#define KELVIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define KELVIN_OK 0
+#define KELVIN_NOT_FOUND 1
+#define KELVIN_ERROR -1
+
+/* Length of the digests of persisted maps */
+#define KELVIN_DIGEST_LEN 32
+
+typedef struct KelvinStore KelvinStore;
+typedef struct KelvinMap KelvinMap;
+
+/* Message of the last error on the calling thread, or NULL */
+const char *kelvin_last_error(void);
+
+KelvinStore *kelvin_store_open(const char *path);
+KelvinStore *kelvin_store_volatile(void);
+void kelvin_store_free(KelvinStore *store);
+
+KelvinMap *kelvin_map_new(void);
+KelvinMap *kelvin_map_restore(const KelvinStore *store,
+                              const uint8_t *digest);
+void kelvin_map_free(KelvinMap *map);
+
+int kelvin_map_insert(KelvinMap *map, const uint8_t *key, size_t key_len,
+                      const uint8_t *val, size_t val_len);
+/* Values found are to be freed with kelvin_bytes_free */
+int kelvin_map_get(const KelvinMap *map, const uint8_t *key, size_t key_len,
+                   uint8_t **val, size_t *val_len);
+void kelvin_bytes_free(uint8_t *val, size_t val_len);
+
+int kelvin_persist(const KelvinStore *store, KelvinMap *map,
+                   uint8_t *digest);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,361 @@
+//! C bindings for kelvin stores and maps
+//!
+//! Maps are HAMTs from byte strings to byte strings, persisted to stores
+//! with blake2b digests of 32 bytes. The C declarations are in `kelvin.h`.
+//!
+//! Functions returning an `int` return `KELVIN_OK`, `KELVIN_NOT_FOUND` or
+//! `KELVIN_ERROR`, functions returning a pointer return `NULL` on errors.
+//! The message of the last error on the calling thread is returned by
+//! `kelvin_last_error`. Panics are caught and returned as errors, they never
+//! unwind into the caller.
+#![warn(missing_docs)]
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use kelvin::{Blake2b, ByteHash, Map, Store};
+use kelvin_hamt::HAMT;
+
+type Digest = <Blake2b as ByteHash>::Digest;
+
+/// The operation succeeded
+pub const KELVIN_OK: c_int = 0;
+/// The key or value looked up was not found
+pub const KELVIN_NOT_FOUND: c_int = 1;
+/// The operation failed, see `kelvin_last_error`
+pub const KELVIN_ERROR: c_int = -1;
+
+/// A store, on disk or in memory
+pub struct KelvinStore(Store<Blake2b>);
+
+/// A map from byte strings to byte strings
+pub struct KelvinMap(HAMT<Vec<u8>, Vec<u8>, Blake2b>);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+// Runs `f`, recording its error or panic as the last error
+fn guard<T, F: FnOnce() -> io::Result<T>>(f: F) -> Option<T> {
+    let message = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(t)) => return Some(t),
+        Ok(Err(e)) => e.to_string(),
+        Err(_) => "panic in kelvin".to_string(),
+    };
+    let message =
+        CString::new(message.replace('\0', "")).expect("nul bytes removed");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    None
+}
+
+fn status(result: Option<bool>) -> c_int {
+    match result {
+        Some(true) => KELVIN_OK,
+        Some(false) => KELVIN_NOT_FOUND,
+        None => KELVIN_ERROR,
+    }
+}
+
+fn null_pointer() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "null pointer")
+}
+
+unsafe fn as_ref<'a, T>(ptr: *const T) -> io::Result<&'a T> {
+    ptr.as_ref().ok_or_else(null_pointer)
+}
+
+unsafe fn as_mut<'a, T>(ptr: *mut T) -> io::Result<&'a mut T> {
+    ptr.as_mut().ok_or_else(null_pointer)
+}
+
+// `len` bytes at `ptr`, which may be null if `len` is 0
+unsafe fn bytes<'a>(ptr: *const u8, len: usize) -> io::Result<&'a [u8]> {
+    match (ptr.is_null(), len) {
+        (_, 0) => Ok(&[]),
+        (true, _) => Err(null_pointer()),
+        (false, _) => Ok(slice::from_raw_parts(ptr, len)),
+    }
+}
+
+/// Returns the message of the last error on the calling thread, or `NULL`
+///
+/// The message is valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn kelvin_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Opens the store in the directory at `path`, creating it if missing
+///
+/// # Safety
+///
+/// `path` has to be a nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn kelvin_store_open(
+    path: *const c_char,
+) -> *mut KelvinStore {
+    guard(|| {
+        let path = CStr::from_ptr(as_ref(path)?).to_str().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "path is not UTF-8")
+        })?;
+        Ok(Box::into_raw(Box::new(KelvinStore(Store::new(path)?))))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Creates a store keeping its values in memory
+#[no_mangle]
+pub extern "C" fn kelvin_store_volatile() -> *mut KelvinStore {
+    guard(|| Ok(Box::into_raw(Box::new(KelvinStore(Store::volatile()?)))))
+        .unwrap_or(ptr::null_mut())
+}
+
+/// Frees a store, maps restored from it stay usable
+///
+/// # Safety
+///
+/// `store` has to be returned by `kelvin_store_open` or
+/// `kelvin_store_volatile` and not freed yet, or be `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn kelvin_store_free(store: *mut KelvinStore) {
+    if !store.is_null() {
+        drop(Box::from_raw(store))
+    }
+}
+
+/// Creates an empty map
+#[no_mangle]
+pub extern "C" fn kelvin_map_new() -> *mut KelvinMap {
+    Box::into_raw(Box::new(KelvinMap(HAMT::new())))
+}
+
+/// Restores the map persisted to `store` with the 32 byte `digest`
+///
+/// # Safety
+///
+/// `store` has to be a live store and `digest` point to 32 bytes.
+#[no_mangle]
+pub unsafe extern "C" fn kelvin_map_restore(
+    store: *const KelvinStore,
+    digest: *const u8,
+) -> *mut KelvinMap {
+    guard(|| {
+        let store = &as_ref(store)?.0;
+        let mut hash = Digest::default();
+        let len = hash.len();
+        hash.copy_from_slice(bytes(digest, len)?);
+        let map = store.restore(&store.snapshot(hash))?;
+        Ok(Box::into_raw(Box::new(KelvinMap(map))))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Frees a map
+///
+/// # Safety
+///
+/// `map` has to be returned by `kelvin_map_new` or `kelvin_map_restore` and
+/// not freed yet, or be `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn kelvin_map_free(map: *mut KelvinMap) {
+    if !map.is_null() {
+        drop(Box::from_raw(map))
+    }
+}
+
+/// Inserts `val` at `key`, replacing the value there if any
+///
+/// Returns `KELVIN_OK`, or `KELVIN_ERROR` if the map could not be read
+/// from its store.
+///
+/// # Safety
+///
+/// `map` has to be a live map, `key` and `val` point to `key_len` and
+/// `val_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn kelvin_map_insert(
+    map: *mut KelvinMap,
+    key: *const u8,
+    key_len: usize,
+    val: *const u8,
+    val_len: usize,
+) -> c_int {
+    status(guard(|| {
+        let map = &mut as_mut(map)?.0;
+        let (key, val) = (bytes(key, key_len)?, bytes(val, val_len)?);
+        map.insert(key.to_vec(), val.to_vec())?;
+        Ok(true)
+    }))
+}
+
+/// Looks up the value at `key`
+///
+/// Returns `KELVIN_OK` with a copy of the value in `val` and `val_len`, to
+/// be freed with `kelvin_bytes_free`, or `KELVIN_NOT_FOUND`.
+///
+/// # Safety
+///
+/// `map` has to be a live map, `key` point to `key_len` bytes, and `val`
+/// and `val_len` be writable.
+#[no_mangle]
+pub unsafe extern "C" fn kelvin_map_get(
+    map: *const KelvinMap,
+    key: *const u8,
+    key_len: usize,
+    val: *mut *mut u8,
+    val_len: *mut usize,
+) -> c_int {
+    status(guard(|| {
+        let map = &as_ref(map)?.0;
+        let (val, val_len) = (as_mut(val)?, as_mut(val_len)?);
+        let found = match map.get(bytes(key, key_len)?)? {
+            Some(found) => found.to_vec().into_boxed_slice(),
+            None => return Ok(false),
+        };
+        *val_len = found.len();
+        *val = Box::into_raw(found) as *mut u8;
+        Ok(true)
+    }))
+}
+
+/// Frees a value returned by `kelvin_map_get`
+///
+/// # Safety
+///
+/// `val` and `val_len` have to be returned by `kelvin_map_get` and not
+/// freed yet, or `val` be `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn kelvin_bytes_free(val: *mut u8, val_len: usize) {
+    if !val.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(val, val_len)))
+    }
+}
+
+/// Persists `map` to `store`, writing its 32 byte digest to `digest`
+///
+/// # Safety
+///
+/// `store` and `map` have to be live, and `digest` point to 32 writable
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn kelvin_persist(
+    store: *const KelvinStore,
+    map: *mut KelvinMap,
+    digest: *mut u8,
+) -> c_int {
+    status(guard(|| {
+        let (store, map) = (&as_ref(store)?.0, &mut as_mut(map)?.0);
+        if digest.is_null() {
+            return Err(null_pointer());
+        }
+        let snapshot = store.persist(map)?;
+        let hash = snapshot.hash();
+        slice::from_raw_parts_mut(digest, hash.len()).copy_from_slice(hash);
+        Ok(true)
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    unsafe fn get(map: *const KelvinMap, key: &[u8]) -> Option<Vec<u8>> {
+        let (mut val, mut val_len) = (ptr::null_mut(), 0);
+        let found = kelvin_map_get(
+            map,
+            key.as_ptr(),
+            key.len(),
+            &mut val,
+            &mut val_len,
+        );
+        match found {
+            KELVIN_OK => {
+                let copy = slice::from_raw_parts(val, val_len).to_vec();
+                kelvin_bytes_free(val, val_len);
+                Some(copy)
+            }
+            KELVIN_NOT_FOUND => None,
+            _ => panic!("lookup failed"),
+        }
+    }
+
+    unsafe fn insert(map: *mut KelvinMap, key: &[u8], val: &[u8]) {
+        let inserted = kelvin_map_insert(
+            map,
+            key.as_ptr(),
+            key.len(),
+            val.as_ptr(),
+            val.len(),
+        );
+        assert_eq!(inserted, KELVIN_OK);
+    }
+
+    unsafe fn last_error() -> String {
+        CStr::from_ptr(kelvin_last_error())
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn persist_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = CString::new(dir.path().to_str().unwrap()).unwrap();
+        let mut digest = [0u8; 32];
+
+        unsafe {
+            let store = kelvin_store_open(path.as_ptr());
+            assert!(!store.is_null());
+            let map = kelvin_map_new();
+            for i in 0..100u32 {
+                insert(map, &i.to_be_bytes(), &[i as u8; 3]);
+            }
+            insert(map, b"", b"");
+            assert_eq!(kelvin_persist(store, map, digest.as_mut_ptr()), 0);
+            kelvin_map_free(map);
+            kelvin_store_free(store);
+
+            let store = kelvin_store_open(path.as_ptr());
+            let map = kelvin_map_restore(store, digest.as_ptr());
+            assert!(!map.is_null());
+            assert_eq!(get(map, &7u32.to_be_bytes()), Some(vec![7; 3]));
+            assert_eq!(get(map, b""), Some(vec![]));
+            assert_eq!(get(map, b"missing"), None);
+            kelvin_map_free(map);
+            kelvin_store_free(store);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        unsafe {
+            let store = kelvin_store_volatile();
+            let map = kelvin_map_restore(store, [0u8; 32].as_ptr());
+            assert!(map.is_null());
+            assert_eq!(last_error(), "Data not found");
+
+            let map = kelvin_map_restore(store, ptr::null());
+            assert!(map.is_null());
+            assert_eq!(last_error(), "null pointer");
+
+            let map = kelvin_map_new();
+            let inserted =
+                kelvin_map_insert(map, ptr::null(), 1, ptr::null(), 0);
+            assert_eq!(inserted, KELVIN_ERROR);
+            let mut digest = [0u8; 32];
+            let persisted =
+                kelvin_persist(ptr::null(), map, digest.as_mut_ptr());
+            assert_eq!(persisted, KELVIN_ERROR);
+
+            kelvin_map_free(map);
+            kelvin_store_free(store);
+        }
+    }
+}
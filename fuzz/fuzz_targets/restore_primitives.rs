@@ -0,0 +1,21 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use kelvin::tests::restore_bytes;
+
+// the first byte selects the type to restore from the remaining bytes,
+// errors are expected, panics and unbounded allocations are bugs
+fuzz_target!(|data: &[u8]| {
+    if let Some((&select, bytes)) = data.split_first() {
+        let _ = match select % 8 {
+            0 => restore_bytes::<u8>(bytes).map(drop),
+            1 => restore_bytes::<u64>(bytes).map(drop),
+            2 => restore_bytes::<i128>(bytes).map(drop),
+            3 => restore_bytes::<String>(bytes).map(drop),
+            4 => restore_bytes::<Option<u32>>(bytes).map(drop),
+            5 => restore_bytes::<Vec<u16>>(bytes).map(drop),
+            6 => restore_bytes::<(String, Option<Vec<i64>>)>(bytes).map(drop),
+            _ => restore_bytes::<Box<Vec<(u8, String)>>>(bytes).map(drop),
+        };
+    }
+});
@@ -0,0 +1,50 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use kelvin::tests::restore_bytes;
+use kelvin::Blake2b;
+
+use kelvin_bitset::BitSet;
+use kelvin_blob::Blob;
+use kelvin_btree::{BTree, Wide};
+use kelvin_crdt::{GCounter, GSet};
+use kelvin_dag::DagNode;
+use kelvin_hamt::HAMT;
+use kelvin_interval_map::IntervalMap;
+use kelvin_list::List;
+use kelvin_lru::LRU;
+use kelvin_priority_queue::PriorityQueue;
+use kelvin_small_map::SmallMap;
+use kelvin_sorted_vec_map::SortedVecMap;
+use kelvin_versioned_map::VersionedMap;
+
+// the first byte selects the structure to restore from the remaining bytes,
+// only the top level node is decoded, its children are restored as
+// references into an empty store
+fuzz_target!(|data: &[u8]| {
+    if let Some((&select, bytes)) = data.split_first() {
+        let _ = match select % 15 {
+            0 => restore_bytes::<BTree<u64, String, Blake2b>>(bytes).map(drop),
+            1 => restore_bytes::<BTree<u64, u64, Blake2b, Wide>>(bytes)
+                .map(drop),
+            2 => restore_bytes::<HAMT<String, u64, Blake2b>>(bytes).map(drop),
+            3 => restore_bytes::<BitSet<Blake2b>>(bytes).map(drop),
+            4 => restore_bytes::<Blob<Blake2b>>(bytes).map(drop),
+            5 => restore_bytes::<List<u64, Blake2b>>(bytes).map(drop),
+            6 => restore_bytes::<IntervalMap<u64, u8, Blake2b>>(bytes)
+                .map(drop),
+            7 => restore_bytes::<PriorityQueue<u64, u32, Blake2b>>(bytes)
+                .map(drop),
+            8 => restore_bytes::<SmallMap<u64, String, Blake2b>>(bytes)
+                .map(drop),
+            9 => restore_bytes::<SortedVecMap<u64, u64, Blake2b>>(bytes)
+                .map(drop),
+            10 => restore_bytes::<VersionedMap<u64, u64, Blake2b>>(bytes)
+                .map(drop),
+            11 => restore_bytes::<LRU<u64, u64, Blake2b>>(bytes).map(drop),
+            12 => restore_bytes::<DagNode<u64, Blake2b>>(bytes).map(drop),
+            13 => restore_bytes::<GSet<u64, Blake2b>>(bytes).map(drop),
+            _ => restore_bytes::<GCounter<u8, Blake2b>>(bytes).map(drop),
+        };
+    }
+});
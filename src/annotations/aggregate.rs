@@ -0,0 +1,79 @@
+use std::borrow::Borrow;
+use std::io;
+use std::ops::{Bound, RangeBounds};
+
+use bytehash::ByteHash;
+
+use super::{Combine, MaxKey, MinKey};
+use crate::{Compound, HandleRef};
+
+/// Method for aggregating annotations over a range of keys
+pub trait AggregateRange<K, H> {
+    /// Returns the combination of the `A` annotations of all leaves with
+    /// keys in `range`, or `None` if there are none
+    ///
+    /// Subtrees entirely inside the range contribute their annotation as a
+    /// whole, so only the subtrees at the edges of the range are descended
+    /// into. Only valid for collections keeping their leaves ordered by key.
+    fn aggregate_range<A, R>(&self, range: &R) -> io::Result<Option<A>>
+    where
+        R: RangeBounds<K>,
+        A: Combine<A>,
+        H: ByteHash,
+        Self: Compound<H>,
+        Self::Annotation: Borrow<A>;
+}
+
+fn before_start<K: Ord, R: RangeBounds<K>>(range: &R, k: &K) -> bool {
+    match range.start_bound() {
+        Bound::Included(start) => k < start,
+        Bound::Excluded(start) => k <= start,
+        Bound::Unbounded => false,
+    }
+}
+
+fn after_end<K: Ord, R: RangeBounds<K>>(range: &R, k: &K) -> bool {
+    match range.end_bound() {
+        Bound::Included(end) => k > end,
+        Bound::Excluded(end) => k >= end,
+        Bound::Unbounded => false,
+    }
+}
+
+impl<K, C, H> AggregateRange<K, H> for C
+where
+    K: Ord,
+    H: ByteHash,
+    C: Compound<H>,
+    C::Annotation: Borrow<MinKey<K>> + Borrow<MaxKey<K>>,
+{
+    fn aggregate_range<A, R>(&self, range: &R) -> io::Result<Option<A>>
+    where
+        R: RangeBounds<K>,
+        A: Combine<A>,
+        C::Annotation: Borrow<A>,
+    {
+        let mut parts: Vec<Option<A>> = vec![];
+        for handle in self.children() {
+            let ann = match handle.annotation() {
+                Some(ann) => ann,
+                None => continue,
+            };
+            let min: &MinKey<K> = (*ann).borrow();
+            let max: &MaxKey<K> = (*ann).borrow();
+            if after_end(range, min) {
+                break;
+            }
+            if before_start(range, max) {
+                continue;
+            }
+            if !before_start(range, min) && !after_end(range, max) {
+                let a: &A = (*ann).borrow();
+                parts.push(Some(a.clone()));
+            } else if let HandleRef::Node(node) = handle.inner()? {
+                parts.push(node.aggregate_range(range)?);
+            }
+        }
+        Ok(A::combine(&parts))
+    }
+}
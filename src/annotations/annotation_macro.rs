@@ -1,4 +1,10 @@
 /// Conveniance macro for creating annotation types combining several annotations
+///
+/// Each field is combined on its own, and the resulting struct implements
+/// `Borrow` for every field type, so search methods and traits written
+/// against a single annotation (such as `Count` or `MaxKeySearch`) work on
+/// the combined one. Plain tuples can not be used for this, since neither
+/// `From<&Leaf>` nor per-component `Borrow` can be implemented for them.
 #[macro_export]
 macro_rules! annotation {
     {  $pub:vis struct $struct_name:ident $( < $( $param:ident ),* > )*
@@ -65,6 +71,30 @@ macro_rules! annotation {
             }
         }
 
+        impl<$( $( $param ),* )* > PartialEq for $struct_name $( < $( $param ),* > )*
+        where
+            $( $ann_type : PartialEq ),*
+            $( , $( $whereclause )* )?
+
+        {
+            fn eq(&self, other: &Self) -> bool {
+                true $( && self.$ann_key == other.$ann_key )*
+            }
+        }
+
+        impl<$( $( $param ),* )* > std::fmt::Debug for $struct_name $( < $( $param ),* > )*
+        where
+            $( $ann_type : std::fmt::Debug ),*
+            $( , $( $whereclause )* )?
+
+        {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.debug_struct(stringify!($struct_name))
+                    $( .field(stringify!($ann_key), &self.$ann_key) )*
+                    .finish()
+            }
+        }
+
         impl<__A, $( $( $param ),* )* > __Combine<__A> for $struct_name $( < $( $param ),* > )*
         where
             $( __A: __Borrow<$ann_type> ),* ,
@@ -0,0 +1,198 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+
+use crate::{Associative, ByteHash, Content, Sink, Source};
+
+const BLOOM_BYTES: usize = 32;
+const BLOOM_BITS: usize = BLOOM_BYTES * 8;
+const BLOOM_HASHES: usize = 3;
+
+/// Annotation keeping a bloom filter over the keys of a subtree.
+///
+/// Searches can use it to skip subtrees that definitely do not contain a key,
+/// without fetching them from the backend.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Bloom([u8; BLOOM_BYTES]);
+
+// FNV-1a, since the filter is persisted we need a hash function that is
+// stable between program runs. Integers are hashed as little endian, and
+// sizes as 64 bits, so that 32 and 64 bit targets agree as long as the
+// `Hash` impl of the key writes the same values on both.
+struct BloomHasher(u64);
+
+impl Hasher for BloomHasher {
+    fn finish(&self) -> u64 {
+        // finalizer from splitmix64 to spread the fnv bits
+        let mut h = self.0;
+        h ^= h >> 30;
+        h = h.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        h ^= h >> 27;
+        h = h.wrapping_mul(0x94d0_49bb_1331_11eb);
+        h ^ (h >> 31)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64)
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as i64 as u64)
+    }
+}
+
+impl Bloom {
+    /// Creates a new, empty bloom filter
+    pub fn new() -> Self {
+        Bloom([0; BLOOM_BYTES])
+    }
+
+    /// Creates a bloom filter containing only `k`
+    pub fn from_key<K: Hash + ?Sized>(k: &K) -> Self {
+        let mut bloom = Bloom::new();
+        bloom.insert(k);
+        bloom
+    }
+
+    fn positions<K: Hash + ?Sized>(k: &K) -> [usize; BLOOM_HASHES] {
+        let mut hasher = BloomHasher(0xcbf2_9ce4_8422_2325);
+        k.hash(&mut hasher);
+        let h = hasher.finish();
+        let h1 = h as u32 as usize;
+        let h2 = (h >> 32) as usize | 1;
+        let mut positions = [0; BLOOM_HASHES];
+        for (i, pos) in positions.iter_mut().enumerate() {
+            *pos = h1.wrapping_add(i.wrapping_mul(h2)) % BLOOM_BITS;
+        }
+        positions
+    }
+
+    /// Add a key to the filter
+    pub fn insert<K: Hash + ?Sized>(&mut self, k: &K) {
+        for pos in Self::positions(k).iter() {
+            self.0[pos / 8] |= 1 << (pos % 8);
+        }
+    }
+
+    /// Returns false if the key is definitely not in the filter
+    pub fn may_contain<K: Hash + ?Sized>(&self, k: &K) -> bool {
+        self.may_contain_all(&Self::from_key(k))
+    }
+
+    /// Returns false if any key of `other` is definitely not in the filter
+    pub fn may_contain_all(&self, other: &Bloom) -> bool {
+        self.0.iter().zip(other.0.iter()).all(|(a, b)| a & b == *b)
+    }
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Bloom::new()
+    }
+}
+
+// the bits themselves are not readable, only how full the filter is
+impl fmt::Debug for Bloom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ones: u32 = self.0.iter().map(|byte| byte.count_ones()).sum();
+        write!(f, "Bloom({}/{} bits set)", ones, BLOOM_BYTES * 8)
+    }
+}
+
+impl Associative for Bloom {
+    fn op(&mut self, b: &Self) {
+        for (a, b) in self.0.iter_mut().zip(b.0.iter()) {
+            *a |= b
+        }
+    }
+}
+
+impl<K, V> From<&(K, V)> for Bloom
+where
+    K: Hash,
+{
+    fn from((k, _): &(K, V)) -> Self {
+        Bloom::from_key(k)
+    }
+}
+
+impl<H: ByteHash> Content<H> for Bloom {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        sink.write_all(&self.0)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let mut bloom = Bloom::new();
+        source.read_exact(&mut bloom.0)?;
+        Ok(bloom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::quickcheck_annotation;
+
+    quickcheck_annotation!(Bloom, (u8, u8));
+
+    #[test]
+    fn no_false_negatives() {
+        let mut bloom = Bloom::new();
+        for i in 0..64u32 {
+            bloom.insert(&i);
+        }
+        for i in 0..64u32 {
+            assert!(bloom.may_contain(&i));
+        }
+    }
+
+    #[test]
+    fn empty_contains_nothing() {
+        let bloom = Bloom::new();
+        for i in 0..64u32 {
+            assert!(!bloom.may_contain(&i));
+        }
+    }
+
+    #[test]
+    fn portable_positions() {
+        // the lengths of slices are hashed as 64 bits on every target
+        let mut hasher = BloomHasher(0xcbf2_9ce4_8422_2325);
+        hasher.write_usize(3);
+        let mut expected = BloomHasher(0xcbf2_9ce4_8422_2325);
+        expected.write(&3u64.to_le_bytes());
+        assert_eq!(hasher.finish(), expected.finish());
+    }
+
+    #[test]
+    fn combined() {
+        let mut a = Bloom::from_key("hello");
+        a.op(&Bloom::from_key("world"));
+        assert!(a.may_contain("hello"));
+        assert!(a.may_contain("world"));
+    }
+}
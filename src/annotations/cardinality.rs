@@ -1,21 +1,31 @@
 use std::borrow::Borrow;
 use std::io;
-use std::ops::AddAssign;
+use std::iter::Take;
+use std::ops::{AddAssign, Deref, SubAssign};
 
 use bytehash::ByteHash;
 use num::{One, Zero};
 
 use super::Associative;
-use crate::{Compound, Content, Sink, Source};
+use crate::iter::LeafIter;
+use crate::{Compound, Content, Handle, LeafIterable, Method, Sink, Source};
 
 /// Trait group for Cardinality inner type
 pub trait Counter: AddAssign + Copy + Zero + One {}
 impl<T> Counter for T where T: AddAssign + Copy + Zero + One {}
 
 /// Annotation that keeps track of total number of leaves
-#[derive(PartialEq, Eq, Clone)]
+#[derive(PartialEq, Eq, Clone, Debug)]
 pub struct Cardinality<T>(T);
 
+impl<T> Deref for Cardinality<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
 impl<T> Associative for Cardinality<T>
 where
     T: Counter,
@@ -67,3 +77,71 @@ where
             .unwrap_or_else(U::zero)
     }
 }
+
+/// Search method skipping the first `n` leaves, by the leaf counts of the
+/// subtrees
+///
+/// Once past the skipped leaves it selects every following leaf, so used
+/// with `LeafIterable::iter_by` it iterates from the leaf of rank `n` on.
+pub struct Nth<U>(U);
+
+impl<U> Nth<U> {
+    /// Creates a search for the leaf of rank `n`
+    pub fn new(n: U) -> Self {
+        Nth(n)
+    }
+}
+
+impl<C, H, U> Method<C, H> for Nth<U>
+where
+    C: Compound<H>,
+    C::Annotation: Borrow<Cardinality<U>>,
+    U: Counter + PartialOrd + SubAssign,
+    H: ByteHash,
+{
+    fn select(&mut self, handles: &[Handle<C, H>]) -> Option<usize> {
+        for (i, h) in handles.iter().enumerate() {
+            if let Some(ann) = h.annotation() {
+                let count: &Cardinality<U> = (*ann).borrow();
+                if self.0 < count.0 {
+                    return Some(i);
+                }
+                self.0 -= count.0;
+            }
+        }
+        None
+    }
+}
+
+/// Method for paging through the leaves of a collection
+pub trait Paginate<U, H> {
+    /// Returns an iterator over at most `limit` leaves, starting from the
+    /// leaf of rank `offset`
+    ///
+    /// Subtrees before `offset` are skipped by their counts, without
+    /// visiting their leaves.
+    fn iter_page(
+        &self,
+        offset: U,
+        limit: usize,
+    ) -> Take<LeafIter<'_, Self, Nth<U>, H>>
+    where
+        H: ByteHash,
+        Self: Compound<H>;
+}
+
+impl<U, C, H> Paginate<U, H> for C
+where
+    U: 'static + Counter + PartialOrd + SubAssign,
+    H: ByteHash,
+    C: Compound<H>,
+    C::Annotation: Borrow<Cardinality<U>>,
+{
+    fn iter_page(
+        &self,
+        offset: U,
+        limit: usize,
+    ) -> Take<LeafIter<'_, Self, Nth<U>, H>> {
+        self.iter_by(Nth::new(offset)).take(limit)
+    }
+}
@@ -1,10 +1,11 @@
+use std::borrow::Borrow;
 use std::io;
 use std::ops::Deref;
 
-use crate::{Associative, ByteHash, Content, Sink, Source};
+use crate::{Associative, ByteHash, Compound, Content, Sink, Source};
 
-/// Annotation used to keep track of minimum key in subtrees
-#[derive(Clone, Debug)]
+/// Annotation used to keep track of maximum key in subtrees
+#[derive(Clone, Debug, PartialEq)]
 pub struct MaxKey<K>(K);
 
 /// Trait group for keys
@@ -23,7 +24,7 @@ impl<K> Associative for MaxKey<K>
 where
     K: MaxKeyType,
 {
-    // Take the minimal key
+    // Take the maximal key
     fn op(&mut self, b: &Self) {
         if b.0 > self.0 {
             self.0 = b.0.clone()
@@ -52,3 +53,24 @@ where
         Ok(MaxKey(K::restore(source)?))
     }
 }
+
+/// Method for reading the largest key of the collection off its annotation
+pub trait LastKey<K, H> {
+    /// Returns the largest key in the collection, if any
+    fn last_key(&self) -> Option<K>;
+}
+
+impl<K, C, H> LastKey<K, H> for C
+where
+    K: MaxKeyType,
+    H: ByteHash,
+    C: Compound<H>,
+    C::Annotation: Borrow<MaxKey<K>>,
+{
+    fn last_key(&self) -> Option<K> {
+        self.annotation().map(|ann| {
+            let key: &MaxKey<K> = ann.borrow();
+            key.0.clone()
+        })
+    }
+}
@@ -0,0 +1,82 @@
+use std::io;
+use std::ops::Deref;
+
+use super::Project;
+use crate::{Associative, ByteHash, Content, Sink, Source};
+
+/// Annotation used to keep track of the maximum of a projection of the
+/// values in subtrees
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaxValue<T>(T);
+
+impl<T> Deref for MaxValue<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> Associative for MaxValue<T>
+where
+    T: Ord + Clone,
+{
+    // Take the maximal value
+    fn op(&mut self, b: &Self) {
+        if b.0 > self.0 {
+            self.0 = b.0.clone()
+        }
+    }
+}
+
+impl<K, V, T> From<&(K, V)> for MaxValue<T>
+where
+    V: Project<T>,
+{
+    fn from((_, v): &(K, V)) -> Self {
+        MaxValue(v.project())
+    }
+}
+
+impl<H: ByteHash, T: Content<H>> Content<H> for MaxValue<T>
+where
+    T: Ord + Clone,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.0.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(MaxValue(T::restore(source)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::quickcheck_annotation;
+
+    quickcheck_annotation!(MaxValue<u32>, (u8, u32));
+
+    #[derive(Clone)]
+    struct Lease {
+        _holder: u8,
+        expiry: u64,
+    }
+
+    impl Project<u64> for Lease {
+        fn project(&self) -> u64 {
+            self.expiry
+        }
+    }
+
+    #[test]
+    fn projected() {
+        let lease = |expiry| (0u32, Lease { _holder: 0, expiry });
+        let mut max: MaxValue<u64> = MaxValue::from(&lease(10));
+        max.op(&MaxValue::from(&lease(30)));
+        max.op(&MaxValue::from(&lease(20)));
+        assert_eq!(*max, 30);
+    }
+}
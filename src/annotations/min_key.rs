@@ -0,0 +1,76 @@
+use std::borrow::Borrow;
+use std::io;
+use std::ops::Deref;
+
+use crate::{Associative, ByteHash, Compound, Content, Sink, Source};
+
+/// Annotation used to keep track of minimum key in subtrees
+#[derive(Clone, Debug, PartialEq)]
+pub struct MinKey<K>(K);
+
+/// Trait group for keys
+pub trait MinKeyType: Ord + Clone {}
+impl<T> MinKeyType for T where T: Ord + Clone {}
+
+impl<K> Deref for MinKey<K> {
+    type Target = K;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<K> Associative for MinKey<K>
+where
+    K: MinKeyType,
+{
+    // Take the minimal key
+    fn op(&mut self, b: &Self) {
+        if b.0 < self.0 {
+            self.0 = b.0.clone()
+        }
+    }
+}
+
+impl<K, V> From<&(K, V)> for MinKey<K>
+where
+    K: MinKeyType,
+{
+    fn from((k, _): &(K, V)) -> Self {
+        MinKey(k.clone())
+    }
+}
+
+impl<H: ByteHash, K: Content<H>> Content<H> for MinKey<K>
+where
+    K: MinKeyType,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.0.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(MinKey(K::restore(source)?))
+    }
+}
+
+/// Method for reading the smallest key of the collection off its annotation
+pub trait FirstKey<K, H> {
+    /// Returns the smallest key in the collection, if any
+    fn first_key(&self) -> Option<K>;
+}
+
+impl<K, C, H> FirstKey<K, H> for C
+where
+    K: MinKeyType,
+    H: ByteHash,
+    C: Compound<H>,
+    C::Annotation: Borrow<MinKey<K>>,
+{
+    fn first_key(&self) -> Option<K> {
+        self.annotation().map(|ann| {
+            let key: &MinKey<K> = ann.borrow();
+            key.0.clone()
+        })
+    }
+}
@@ -3,24 +3,70 @@ use std::io;
 
 use bytehash::ByteHash;
 
-pub use cardinality::{Cardinality, Count, Counter};
+pub use aggregate::AggregateRange;
+pub use bloom::Bloom;
+pub use cardinality::{Cardinality, Count, Counter, Nth, Paginate};
 
-pub use max_key::{MaxKey, MaxKeyType};
+pub use max_key::{LastKey, MaxKey, MaxKeyType};
+pub use max_value::MaxValue;
+pub use min_key::{FirstKey, MinKey, MinKeyType};
+pub use sum::{Sum, Total};
+pub use tags::{TagSearch, Tags};
+pub use weight::{SelectWeighted, Weight, WeightSearch};
 
 use crate::{Content, Sink, Source};
 
+mod aggregate;
 mod annotation_macro;
+mod bloom;
 mod cardinality;
 
 mod max_key;
+mod max_value;
+mod min_key;
+mod sum;
+mod tags;
+mod weight;
 
 /// Wrapper trait for hiding generics when working on select functions
+///
+/// Implemented for handles, where empty handles have no annotation, and for
+/// `Option<A>`, which is convenient for combining annotations directly.
 pub trait Annotation<A: Clone> {
     /// Returns the annotation of &self, if any.
     fn annotation(&self) -> Option<Cow<A>>;
 }
 
+impl<A: Clone> Annotation<A> for Option<A> {
+    fn annotation(&self) -> Option<Cow<'_, A>> {
+        self.as_ref().map(Cow::Borrowed)
+    }
+}
+
+/// Projection of a value into the quantity tracked by a value annotation
+///
+/// Annotations such as `Sum` and `MaxValue` are computed from the values
+/// of `(key, value)` leaves through this trait, so they can track a single
+/// field of a larger value. Every type projects to itself.
+pub trait Project<T> {
+    /// Returns the quantity the value contributes to the annotation
+    fn project(&self) -> T;
+}
+
+impl<T: Clone> Project<T> for T {
+    fn project(&self) -> T {
+        self.clone()
+    }
+}
+
 /// Defines the associative operation for the annotation type
+///
+/// Custom annotations need to be created from a leaf with `From<&Leaf>`,
+/// and combined with an operation that is associative, since the shape of
+/// the tree, and thereby the grouping of the combined annotations, is an
+/// implementation detail of the collection. Empty subtrees are skipped when
+/// combining, so no identity element is needed. Use
+/// `quickcheck_annotation!` to test these laws for a custom annotation.
 pub trait Associative {
     /// Perform the associative operation on self
     fn op(&mut self, b: &Self);
@@ -60,7 +106,7 @@ where
 }
 
 /// Empty annotation
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct VoidAnnotation;
 
 impl<T> From<&T> for VoidAnnotation {
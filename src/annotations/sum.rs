@@ -0,0 +1,192 @@
+use std::borrow::Borrow;
+use std::io;
+use std::ops::{AddAssign, Deref};
+
+use bytehash::ByteHash;
+use num::Zero;
+
+use super::{Associative, MaxKey, Project};
+use crate::{Compound, Content, HandleRef, Sink, Source};
+
+/// Annotation that keeps track of the sum of a projection of the values
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Sum<T>(T);
+
+impl<T> Deref for Sum<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> Associative for Sum<T>
+where
+    T: AddAssign + Copy,
+{
+    fn op(&mut self, b: &Self) {
+        self.0 += b.0;
+    }
+}
+
+impl<K, V, T> From<&(K, V)> for Sum<T>
+where
+    V: Project<T>,
+{
+    fn from((_, v): &(K, V)) -> Self {
+        Sum(v.project())
+    }
+}
+
+impl<H, T> Content<H> for Sum<T>
+where
+    H: ByteHash,
+    T: Content<H>,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.0.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(Sum(T::restore(source)?))
+    }
+}
+
+/// Method for summing the values of a collection
+pub trait Total<T, H: ByteHash> {
+    /// Returns the sum of all values in the collection
+    fn total(&self) -> T;
+
+    /// Returns the sum of the values with keys less than `k`
+    ///
+    /// Only valid for collections keeping their leaves ordered by key, where
+    /// it only descends a single path. Range sums are the difference of two
+    /// such prefix sums.
+    fn total_below<K>(&self, k: &K) -> io::Result<T>
+    where
+        K: Ord,
+        Self: Compound<H>,
+        Self::Annotation: Borrow<MaxKey<K>>;
+}
+
+impl<T, C, H> Total<T, H> for C
+where
+    T: AddAssign + Copy + Zero,
+    H: ByteHash,
+    C: Compound<H>,
+    C::Annotation: Borrow<Sum<T>>,
+{
+    fn total(&self) -> T {
+        self.annotation()
+            .map(|ann| ann.borrow().0)
+            .unwrap_or_else(T::zero)
+    }
+
+    fn total_below<K>(&self, k: &K) -> io::Result<T>
+    where
+        K: Ord,
+        C::Annotation: Borrow<MaxKey<K>>,
+    {
+        let mut total = T::zero();
+        for handle in self.children() {
+            let ann = match handle.annotation() {
+                Some(ann) => ann,
+                None => continue,
+            };
+            let max: &MaxKey<K> = (*ann).borrow();
+            if **max < *k {
+                let sum: &Sum<T> = (*ann).borrow();
+                total += sum.0;
+                continue;
+            }
+            // the first subtree reaching `k` is the only one to descend into
+            if let HandleRef::Node(node) = handle.inner()? {
+                total += node.total_below(k)?;
+            }
+            break;
+        }
+        Ok(total)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::annotations::MaxKeyType;
+    use crate::{annotation, quickcheck_annotation, Blake2b, Handle, Store};
+
+    #[derive(Clone, Default)]
+    struct Ledger(Vec<Handle<Self, Blake2b>>);
+
+    annotation! {
+        struct LedgerAnnotation<K> {
+            key: MaxKey<K>,
+            sum: Sum<u64>,
+        }
+        where
+            K: MaxKeyType
+    }
+
+    impl Content<Blake2b> for Ledger {
+        fn persist(&mut self, sink: &mut Sink<Blake2b>) -> io::Result<()> {
+            self.0.persist(sink)
+        }
+
+        fn restore(source: &mut Source<Blake2b>) -> io::Result<Self> {
+            Ok(Ledger(Vec::restore(source)?))
+        }
+    }
+
+    impl Compound<Blake2b> for Ledger {
+        type Leaf = (u32, u64);
+        type Annotation = LedgerAnnotation<u32>;
+
+        fn children(&self) -> &[Handle<Self, Blake2b>] {
+            &self.0
+        }
+
+        fn children_mut(&mut self) -> &mut [Handle<Self, Blake2b>] {
+            &mut self.0
+        }
+    }
+
+    quickcheck_annotation!(LedgerAnnotation<u32>, (u32, u64));
+
+    // accounts 0..100, with account `i` holding a balance of `i`, in nodes
+    // of ten accounts each
+    fn ledger() -> Ledger {
+        let mut ledger = Ledger::default();
+        for n in 0..10u32 {
+            let mut node = Ledger::default();
+            for i in n * 10..n * 10 + 10 {
+                node.0.push(Handle::new_leaf((i, i as u64)));
+            }
+            ledger.0.push(Handle::new_node(node));
+        }
+        ledger
+    }
+
+    #[test]
+    fn total() {
+        assert_eq!(Ledger::default().total(), 0u64);
+        assert_eq!(ledger().total(), 4950u64);
+    }
+
+    #[test]
+    fn range_sums() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let snapshot = store.persist(&mut ledger()).unwrap();
+        let ledger = store.restore(&snapshot).unwrap();
+
+        for k in 0..=100u32 {
+            let expected: u64 = (0..k as u64).sum();
+            assert_eq!(ledger.total_below(&k).unwrap(), expected);
+        }
+
+        // sum of the balances of accounts 25..75
+        let range: u64 =
+            ledger.total_below(&75).unwrap() - ledger.total_below(&25).unwrap();
+        assert_eq!(range, (25..75).sum::<u64>());
+    }
+}
@@ -0,0 +1,186 @@
+use std::borrow::Borrow;
+use std::io;
+
+use bytehash::ByteHash;
+
+use super::{Associative, Project};
+use crate::{Compound, Content, Handle, Method, Sink, Source};
+
+/// Annotation keeping the union of the tag bits of the values in subtrees
+///
+/// Each value carries up to 64 tags. Searches can skip subtrees whose union
+/// lacks a requested tag without fetching them from the backend.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Tags(u64);
+
+impl Tags {
+    /// Creates an empty tag set
+    pub fn new() -> Self {
+        Tags(0)
+    }
+
+    /// Creates a tag set from a bitmask, with bit `i` representing tag `i`
+    pub fn from_bits(bits: u64) -> Self {
+        Tags(bits)
+    }
+
+    /// Returns the bitmask of the tag set
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Adds tag `tag` to the set, panics if `tag` is not below 64
+    pub fn insert(&mut self, tag: u32) {
+        assert!(tag < 64, "tag out of range");
+        self.0 |= 1 << tag
+    }
+
+    /// Returns true if the set contains `tag`
+    pub fn contains(&self, tag: u32) -> bool {
+        tag < 64 && self.0 & (1 << tag) != 0
+    }
+
+    /// Returns true if the set contains all tags of `other`
+    pub fn contains_all(&self, other: &Tags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl Associative for Tags {
+    fn op(&mut self, b: &Self) {
+        self.0 |= b.0
+    }
+}
+
+impl<K, V> From<&(K, V)> for Tags
+where
+    V: Project<Tags>,
+{
+    fn from((_, v): &(K, V)) -> Self {
+        v.project()
+    }
+}
+
+impl<H: ByteHash> Content<H> for Tags {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.0.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(Tags(u64::restore(source)?))
+    }
+}
+
+/// Search method finding the leaves carrying all of the requested tags
+pub struct TagSearch(Tags);
+
+impl TagSearch {
+    /// Creates a search for leaves carrying all tags in `tags`
+    pub fn new(tags: Tags) -> Self {
+        TagSearch(tags)
+    }
+}
+
+impl<C, H> Method<C, H> for TagSearch
+where
+    C: Compound<H>,
+    C::Annotation: Borrow<Tags>,
+    H: ByteHash,
+{
+    fn select(&mut self, handles: &[Handle<C, H>]) -> Option<usize> {
+        for (i, h) in handles.iter().enumerate() {
+            if let Some(ann) = h.annotation() {
+                let tags: &Tags = (*ann).borrow();
+                if tags.contains_all(&self.0) {
+                    return Some(i);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::tests::quickcheck::{Arbitrary, Gen};
+    use crate::{quickcheck_annotation, Blake2b, LeafIterable, Store};
+
+    quickcheck_annotation!(Tags, (u8, Tags));
+
+    impl Arbitrary for Tags {
+        fn arbitrary<G: Gen>(g: &mut G) -> Self {
+            Tags(g.next_u64())
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Catalog(Vec<Handle<Self, Blake2b>>);
+
+    impl Content<Blake2b> for Catalog {
+        fn persist(&mut self, sink: &mut Sink<Blake2b>) -> io::Result<()> {
+            self.0.persist(sink)
+        }
+
+        fn restore(source: &mut Source<Blake2b>) -> io::Result<Self> {
+            Ok(Catalog(Vec::restore(source)?))
+        }
+    }
+
+    impl Compound<Blake2b> for Catalog {
+        type Leaf = (u32, Tags);
+        type Annotation = Tags;
+
+        fn children(&self) -> &[Handle<Self, Blake2b>] {
+            &self.0
+        }
+
+        fn children_mut(&mut self) -> &mut [Handle<Self, Blake2b>] {
+            &mut self.0
+        }
+    }
+
+    #[test]
+    fn faceted() {
+        // items 0..100 in nodes of ten, tagged with 0 if even and 1 if
+        // divisible by 7
+        let tags_of = |i: u32| {
+            let mut tags = Tags::new();
+            if i.is_multiple_of(2) {
+                tags.insert(0);
+            }
+            if i.is_multiple_of(7) {
+                tags.insert(1);
+            }
+            tags
+        };
+        let mut catalog = Catalog::default();
+        for n in 0..10u32 {
+            let node = Catalog(
+                (n * 10..n * 10 + 10)
+                    .map(|i| Handle::new_leaf((i, tags_of(i))))
+                    .collect(),
+            );
+            catalog.0.push(Handle::new_node(node));
+        }
+
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let snapshot = store.persist(&mut catalog).unwrap();
+        let catalog = store.restore(&snapshot).unwrap();
+
+        let found = |bits| -> Vec<u32> {
+            catalog
+                .iter_by(TagSearch::new(Tags::from_bits(bits)))
+                .map(|leaf| leaf.unwrap().0)
+                .collect()
+        };
+
+        assert_eq!(
+            found(0b10),
+            (0..100).filter(|i| i % 7 == 0).collect::<Vec<_>>()
+        );
+        assert_eq!(found(0b11), [0, 14, 28, 42, 56, 70, 84, 98]);
+        assert!(found(0b100).is_empty());
+    }
+}
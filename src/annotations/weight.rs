@@ -0,0 +1,204 @@
+use std::borrow::Borrow;
+use std::io;
+use std::ops::{AddAssign, Deref, SubAssign};
+
+use bytehash::ByteHash;
+use num::Zero;
+use rand::distributions::uniform::SampleUniform;
+use rand::Rng;
+
+use super::{Associative, Project};
+use crate::{Branch, Compound, Content, Handle, Method, Sink, Source};
+
+/// Annotation keeping track of the cumulative weight of the values in
+/// subtrees, used for weighted random selection
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Weight<T>(T);
+
+impl<T> Deref for Weight<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> Associative for Weight<T>
+where
+    T: AddAssign + Copy,
+{
+    fn op(&mut self, b: &Self) {
+        self.0 += b.0;
+    }
+}
+
+impl<K, V, T> From<&(K, V)> for Weight<T>
+where
+    V: Project<T>,
+{
+    fn from((_, v): &(K, V)) -> Self {
+        Weight(v.project())
+    }
+}
+
+impl<H, T> Content<H> for Weight<T>
+where
+    H: ByteHash,
+    T: Content<H>,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.0.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(Weight(T::restore(source)?))
+    }
+}
+
+/// Search method finding the leaf covering `offset` into the cumulative
+/// weights of the leaves
+pub struct WeightSearch<T>(T);
+
+impl<T> WeightSearch<T> {
+    /// Creates a search for the leaf covering `offset`
+    pub fn new(offset: T) -> Self {
+        WeightSearch(offset)
+    }
+}
+
+impl<C, H, T> Method<C, H> for WeightSearch<T>
+where
+    C: Compound<H>,
+    C::Annotation: Borrow<Weight<T>>,
+    T: PartialOrd + SubAssign + Copy,
+    H: ByteHash,
+{
+    fn select(&mut self, handles: &[Handle<C, H>]) -> Option<usize> {
+        for (i, h) in handles.iter().enumerate() {
+            if let Some(ann) = h.annotation() {
+                let weight: &Weight<T> = (*ann).borrow();
+                if self.0 < weight.0 {
+                    return Some(i);
+                }
+                self.0 -= weight.0;
+            }
+        }
+        None
+    }
+}
+
+/// Method for picking leaves with probability proportional to their weight
+pub trait SelectWeighted<T, H> {
+    /// Returns a branch to a random leaf, chosen with probability
+    /// proportional to its weight. Leaves of zero weight are never chosen.
+    fn select_weighted<R: Rng>(
+        &self,
+        rng: &mut R,
+    ) -> io::Result<Option<Branch<'_, Self, H>>>
+    where
+        H: ByteHash,
+        Self: Compound<H>;
+}
+
+impl<T, C, H> SelectWeighted<T, H> for C
+where
+    T: AddAssign + SubAssign + PartialOrd + Copy + Zero + SampleUniform,
+    H: ByteHash,
+    C: Compound<H>,
+    C::Annotation: Borrow<Weight<T>>,
+{
+    fn select_weighted<R: Rng>(
+        &self,
+        rng: &mut R,
+    ) -> io::Result<Option<Branch<'_, Self, H>>> {
+        let total = match self.annotation() {
+            Some(ann) => ann.borrow().0,
+            None => return Ok(None),
+        };
+        if total <= T::zero() {
+            return Ok(None);
+        }
+        let offset = rng.gen_range(T::zero(), total);
+        Branch::new(self, &mut WeightSearch::new(offset))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::{quickcheck_annotation, Blake2b, Store};
+
+    quickcheck_annotation!(Weight<u64>, (u8, u64));
+
+    #[derive(Clone, Default)]
+    struct Stakes(Vec<Handle<Self, Blake2b>>);
+
+    impl Content<Blake2b> for Stakes {
+        fn persist(&mut self, sink: &mut Sink<Blake2b>) -> io::Result<()> {
+            self.0.persist(sink)
+        }
+
+        fn restore(source: &mut Source<Blake2b>) -> io::Result<Self> {
+            Ok(Stakes(Vec::restore(source)?))
+        }
+    }
+
+    impl Compound<Blake2b> for Stakes {
+        type Leaf = (u8, u64);
+        type Annotation = Weight<u64>;
+
+        fn children(&self) -> &[Handle<Self, Blake2b>] {
+            &self.0
+        }
+
+        fn children_mut(&mut self) -> &mut [Handle<Self, Blake2b>] {
+            &mut self.0
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let stakes = Stakes::default();
+        assert!(stakes.select_weighted(&mut rng).unwrap().is_none());
+
+        let stakes = Stakes(vec![Handle::new_leaf((0, 0))]);
+        assert!(stakes.select_weighted(&mut rng).unwrap().is_none());
+    }
+
+    #[test]
+    fn proportional() {
+        // stakers 0..4 with stakes 0, 1, 2 and 3, in nested nodes
+        let mut stakes = Stakes::default();
+        for pair in [[0, 1], [2, 3]].iter() {
+            let node = Stakes(
+                pair.iter()
+                    .map(|&i| Handle::new_leaf((i, i as u64)))
+                    .collect(),
+            );
+            stakes.0.push(Handle::new_node(node));
+        }
+
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let snapshot = store.persist(&mut stakes).unwrap();
+        let stakes = store.restore(&snapshot).unwrap();
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut picked = [0u32; 4];
+        for _ in 0..6000 {
+            let branch = stakes.select_weighted(&mut rng).unwrap().unwrap();
+            picked[branch.0 as usize] += 1;
+        }
+
+        assert_eq!(picked[0], 0);
+        for (i, &n) in picked.iter().enumerate().skip(1) {
+            let expected = 1000 * i as u32;
+            assert!(n > expected * 9 / 10);
+            assert!(n < expected * 11 / 10);
+        }
+    }
+}
@@ -0,0 +1,407 @@
+use std::any;
+use std::io::{self, Read, Write};
+
+use bytehash::ByteHash;
+
+use crate::content::Content;
+use crate::map::{KVPair, Map, MapRemove};
+use crate::sink::Sink;
+use crate::source::Source;
+use crate::store::{Snapshot, Store};
+
+/// A value of any type in an `AnyMap`, persisted on its own
+///
+/// The entry holds the tag of the type of the value, along with the digest
+/// it is persisted under.
+pub struct Erased<H: ByteHash> {
+    tag: u64,
+    digest: H::Digest,
+}
+
+impl<H: ByteHash> Clone for Erased<H> {
+    fn clone(&self) -> Self {
+        Erased {
+            tag: self.tag,
+            digest: self.digest,
+        }
+    }
+}
+
+impl<H: ByteHash> Content<H> for Erased<H> {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.tag.persist(sink)?;
+        sink.write_all(self.digest.as_ref())
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let tag = u64::restore(source)?;
+        let mut digest = H::Digest::default();
+        source.read_exact(digest.as_mut())?;
+        Ok(Erased { tag, digest })
+    }
+}
+
+/// A type that can be stored in an `AnyMap`, under a tag of its own
+///
+/// The tag is persisted along with every value, and checked against the
+/// type asked for on `get`, so it has to stay the same across builds and
+/// differ from the tags of the other types in the map. Tags are built with
+/// `tag`, from a name and the tags of any type parameters.
+pub trait Tagged {
+    /// The tag of the type
+    const TAG: u64;
+}
+
+/// Returns the tag of the type named `name`, with type parameters tagged
+/// `params`
+///
+/// The tag is the FNV-1a hash of the name followed by the parameters.
+pub const fn tag(name: &str, params: &[u64]) -> u64 {
+    const PRIME: u64 = 0x100_0000_01b3;
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let name = name.as_bytes();
+    let mut i = 0;
+    while i < name.len() {
+        hash = (hash ^ name[i] as u64).wrapping_mul(PRIME);
+        i += 1;
+    }
+    let mut i = 0;
+    while i < params.len() {
+        let bytes = params[i].to_be_bytes();
+        let mut j = 0;
+        while j < bytes.len() {
+            hash = (hash ^ bytes[j] as u64).wrapping_mul(PRIME);
+            j += 1;
+        }
+        i += 1;
+    }
+    hash
+}
+
+macro_rules! tagged {
+    ($($t:ty),*) => {
+        $(
+            impl Tagged for $t {
+                const TAG: u64 = tag(stringify!($t), &[]);
+            }
+        )*
+    };
+}
+
+tagged!(u8, u16, u32, u64, u128);
+tagged!(i16, i32, i64, i128);
+tagged!(String);
+
+impl<T: Tagged> Tagged for Option<T> {
+    const TAG: u64 = tag("Option", &[T::TAG]);
+}
+
+impl<T: Tagged> Tagged for Box<T> {
+    const TAG: u64 = tag("Box", &[T::TAG]);
+}
+
+impl<T: Tagged> Tagged for Vec<T> {
+    const TAG: u64 = tag("Vec", &[T::TAG]);
+}
+
+impl<A: Tagged, B: Tagged> Tagged for (A, B) {
+    const TAG: u64 = tag("(A, B)", &[A::TAG, B::TAG]);
+}
+
+/// A map of values of different types under string keys
+///
+/// Every value is persisted to the store on insert, and the map keeps its
+/// digest along with the tag of its type, see `Tagged`. Values are restored
+/// by `get` as the type asked for, and asking for another type than the one
+/// inserted is an error rather than a misread value. `commit` persists the
+/// map itself, returning a snapshot that `open` can later reopen.
+///
+/// Any map over `String` keys and `Erased` values that can remove by `&str`
+/// can back it, such as a `BTree<String, Erased<H>, H>`.
+pub struct AnyMap<C, H: ByteHash> {
+    store: Store<H>,
+    map: C,
+}
+
+impl<C, H> AnyMap<C, H>
+where
+    C: MapRemove<str, String, Erased<H>, H>
+        + for<'a> Map<'a, str, String, Erased<H>, H>
+        + Default,
+    C::Leaf: KVPair<String, Erased<H>>,
+    H: ByteHash,
+{
+    /// Creates an empty map, persisting values to `store`
+    pub fn new(store: Store<H>) -> Self {
+        AnyMap {
+            store,
+            map: C::default(),
+        }
+    }
+
+    /// Opens the map committed as `snapshot`
+    pub fn open(snapshot: &Snapshot<C, H>) -> io::Result<Self> {
+        let store = snapshot.store().clone();
+        Ok(AnyMap {
+            map: store.restore(snapshot)?,
+            store,
+        })
+    }
+
+    /// Persists `value` and inserts it under `key`, replacing any value of
+    /// any type
+    pub fn insert<T: Content<H> + Tagged>(
+        &mut self,
+        key: &str,
+        mut value: T,
+    ) -> io::Result<()> {
+        let snapshot = self.store.persist(&mut value)?;
+        let erased = Erased {
+            tag: T::TAG,
+            digest: *snapshot.hash(),
+        };
+        self.map.insert(key.to_string(), erased)?;
+        Ok(())
+    }
+
+    /// Restores the value of `key` as a `T`, if any
+    ///
+    /// Fails with `InvalidData` if the value was inserted as another type.
+    pub fn get<T: Content<H> + Tagged>(
+        &self,
+        key: &str,
+    ) -> io::Result<Option<T>> {
+        let erased = match self.erased(key)? {
+            Some(erased) => erased,
+            None => return Ok(None),
+        };
+        if erased.tag != T::TAG {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} does not hold a value of type {}",
+                    key,
+                    any::type_name::<T>()
+                ),
+            ));
+        }
+        self.store
+            .restore(&self.store.snapshot(erased.digest))
+            .map(Some)
+    }
+
+    /// Returns true if `key` holds a value of type `T`
+    pub fn holds<T: Content<H> + Tagged>(&self, key: &str) -> io::Result<bool> {
+        Ok(self.erased(key)?.is_some_and(|erased| erased.tag == T::TAG))
+    }
+
+    /// Returns true if the map holds a value of any type for `key`
+    pub fn contains(&self, key: &str) -> io::Result<bool> {
+        <C as Map<'_, str, String, Erased<H>, H>>::contains_key(&self.map, key)
+    }
+
+    /// Removes the value of `key`, returning whether there was one
+    pub fn remove(&mut self, key: &str) -> io::Result<bool> {
+        Ok(self.map.remove_borrowed(key)?.is_some())
+    }
+
+    /// Persists the map to the store, returning a snapshot of it
+    pub fn commit(&mut self) -> io::Result<Snapshot<C, H>> {
+        let snapshot = self.store.persist(&mut self.map)?;
+        self.store.flush()?;
+        Ok(snapshot)
+    }
+
+    fn erased(&self, key: &str) -> io::Result<Option<Erased<H>>> {
+        let found =
+            <C as Map<'_, str, String, Erased<H>, H>>::get(&self.map, key)?;
+        Ok(found.map(|erased| (*erased).clone()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::borrow::Borrow;
+    use std::mem;
+
+    use crate::annotations::VoidAnnotation;
+    use crate::compound::Compound;
+    use crate::map::MapMut;
+    use crate::search::Method;
+    use crate::{Blake2b, Handle};
+
+    // A flat map, searched linearly
+    #[derive(Clone, Default)]
+    struct Pairs(Vec<Handle<Self, Blake2b>>);
+
+    impl Content<Blake2b> for Pairs {
+        fn persist(&mut self, sink: &mut Sink<Blake2b>) -> io::Result<()> {
+            self.0.persist(sink)
+        }
+
+        fn restore(source: &mut Source<Blake2b>) -> io::Result<Self> {
+            Ok(Pairs(Vec::restore(source)?))
+        }
+    }
+
+    impl Compound<Blake2b> for Pairs {
+        type Leaf = (String, Erased<Blake2b>);
+        type Annotation = VoidAnnotation;
+
+        fn children(&self) -> &[Handle<Self, Blake2b>] {
+            &self.0
+        }
+
+        fn children_mut(&mut self) -> &mut [Handle<Self, Blake2b>] {
+            &mut self.0
+        }
+    }
+
+    impl Pairs {
+        fn position<O>(&self, k: &O) -> Option<usize>
+        where
+            String: Borrow<O>,
+            O: Eq + ?Sized,
+        {
+            self.0.iter().position(|handle| {
+                handle.leaf().map_or(false, |leaf| leaf.0.borrow() == k)
+            })
+        }
+    }
+
+    struct KeyIs<'a, O: ?Sized>(&'a O);
+
+    impl<'a, O: ?Sized> From<&'a O> for KeyIs<'a, O> {
+        fn from(k: &'a O) -> Self {
+            KeyIs(k)
+        }
+    }
+
+    impl<'a, O> Method<Pairs, Blake2b> for KeyIs<'a, O>
+    where
+        String: Borrow<O>,
+        O: Eq + ?Sized,
+    {
+        fn select(
+            &mut self,
+            handles: &[Handle<Pairs, Blake2b>],
+        ) -> Option<usize> {
+            handles.iter().position(|handle| {
+                handle
+                    .leaf()
+                    .map_or(false, |leaf| leaf.0.borrow() == self.0)
+            })
+        }
+    }
+
+    impl<'a, O> Map<'a, O, String, Erased<Blake2b>, Blake2b> for Pairs
+    where
+        String: Borrow<O>,
+        O: Eq + ?Sized + 'a,
+    {
+        type KeySearch = KeyIs<'a, O>;
+    }
+
+    impl MapMut<String, Erased<Blake2b>, Blake2b> for Pairs {
+        fn insert(
+            &mut self,
+            k: String,
+            v: Erased<Blake2b>,
+        ) -> io::Result<Option<Erased<Blake2b>>> {
+            match self.position(&k) {
+                Some(i) => {
+                    let leaf = self.0[i].leaf_mut().expect("leaf");
+                    Ok(Some(mem::replace(&mut leaf.1, v)))
+                }
+                None => {
+                    self.0.push(Handle::new_leaf((k, v)));
+                    Ok(None)
+                }
+            }
+        }
+
+        fn remove(
+            &mut self,
+            k: &String,
+        ) -> io::Result<Option<Erased<Blake2b>>> {
+            self.remove_borrowed(k.as_str())
+        }
+    }
+
+    impl MapRemove<str, String, Erased<Blake2b>, Blake2b> for Pairs {
+        fn remove_borrowed(
+            &mut self,
+            k: &str,
+        ) -> io::Result<Option<Erased<Blake2b>>> {
+            Ok(self.position(k).map(|i| {
+                let handle = self.0.remove(i);
+                handle.leaf().expect("leaf").1.clone()
+            }))
+        }
+    }
+
+    fn any_map() -> AnyMap<Pairs, Blake2b> {
+        AnyMap::new(Store::volatile().unwrap())
+    }
+
+    #[test]
+    fn tags() {
+        assert_ne!(u64::TAG, u32::TAG);
+        assert_ne!(<Vec<u64>>::TAG, <Vec<u32>>::TAG);
+        assert_ne!(<(u64, u32)>::TAG, <(u32, u64)>::TAG);
+        // tags are persisted, so they must never change
+        assert_eq!(tag("", &[]), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(tag("a", &[]), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn type_mismatch() {
+        let mut map = any_map();
+        map.insert("answer", 42u64).unwrap();
+        map.insert("name", String::from("kelvin")).unwrap();
+
+        let err = map.get::<u32>("answer").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(map.get::<String>("answer").is_err());
+        assert!(map.holds::<u64>("answer").unwrap());
+        assert!(!map.holds::<u32>("answer").unwrap());
+        assert!(!map.holds::<u64>("missing").unwrap());
+
+        // replaced by a value of another type
+        map.insert("answer", String::from("42")).unwrap();
+        assert_eq!(map.get::<String>("answer").unwrap().unwrap(), "42");
+        assert!(map.get::<u64>("answer").is_err());
+    }
+
+    #[test]
+    fn remove() {
+        let mut map = any_map();
+        map.insert("a", 1u64).unwrap();
+        map.insert("b", 2u64).unwrap();
+
+        assert!(map.remove("a").unwrap());
+        assert!(!map.remove("a").unwrap());
+        assert!(!map.contains("a").unwrap());
+        assert!(map.get::<u64>("a").unwrap().is_none());
+        assert_eq!(map.get::<u64>("b").unwrap(), Some(2));
+    }
+
+    #[test]
+    fn commit_open() {
+        let mut map = any_map();
+        map.insert("answer", 42u64).unwrap();
+        map.insert("names", vec![String::from("kelvin")]).unwrap();
+        let snapshot = map.commit().unwrap();
+
+        let mut map = AnyMap::open(&snapshot).unwrap();
+        assert_eq!(map.get::<u64>("answer").unwrap(), Some(42));
+        let names: Vec<String> = map.get("names").unwrap().unwrap();
+        assert_eq!(names, vec!["kelvin"]);
+        assert!(map.get::<u64>("missing").unwrap().is_none());
+
+        // the reopened map commits again to the same snapshot
+        assert_eq!(map.commit().unwrap().hash(), snapshot.hash());
+    }
+}
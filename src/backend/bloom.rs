@@ -0,0 +1,114 @@
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+// 1 MiB of bits, around 2% false positives at a million values
+const BLOOM_BITS: u64 = 1 << 23;
+const BLOOM_HASHES: u64 = 6;
+
+/// A bloom filter over digests, answering whether a value may be stored
+///
+/// Bits are set atomically, so the filter is updated and queried without
+/// locking.
+pub struct Bloom {
+    words: Vec<AtomicU64>,
+    dirty: AtomicBool,
+}
+
+// Digests are uniformly distributed already, so the bit positions are
+// derived from the digest bytes directly
+fn positions(key: &[u8]) -> impl Iterator<Item = (usize, u64)> {
+    let mut h = [0u64; 2];
+    for (i, chunk) in key.chunks(8).enumerate() {
+        let mut word = [0u8; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        h[i % 2] ^= u64::from_le_bytes(word);
+    }
+    let (a, b) = (h[0], h[1] | 1);
+    (0..BLOOM_HASHES).map(move |i| {
+        let bit = a.wrapping_add(i.wrapping_mul(b)) % BLOOM_BITS;
+        ((bit / 64) as usize, 1 << (bit % 64))
+    })
+}
+
+impl Bloom {
+    pub fn new() -> Self {
+        Bloom {
+            words: (0..BLOOM_BITS / 64).map(|_| AtomicU64::new(0)).collect(),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    pub fn insert(&self, key: &[u8]) {
+        for (word, mask) in positions(key) {
+            self.words[word].fetch_or(mask, Ordering::Relaxed);
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns false if the key was certainly never inserted
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        positions(key).all(|(word, mask)| {
+            self.words[word].load(Ordering::Relaxed) & mask != 0
+        })
+    }
+
+    /// Returns true if keys were inserted since the filter was last written
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Relaxed)
+    }
+
+    pub fn read<R: Read>(mut read: R) -> io::Result<Self> {
+        let bloom = Bloom::new();
+        let mut bytes = [0u8; 8];
+        for word in &bloom.words {
+            read.read_exact(&mut bytes)?;
+            word.store(u64::from_le_bytes(bytes), Ordering::Relaxed);
+        }
+        Ok(bloom)
+    }
+
+    pub fn write<W: Write>(&self, mut write: W) -> io::Result<()> {
+        self.dirty.store(false, Ordering::Relaxed);
+        for word in &self.words {
+            write.write_all(&word.load(Ordering::Relaxed).to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytehash::{Blake2b, ByteHash, State};
+
+    #[test]
+    fn no_false_negatives() {
+        let keys: Vec<_> = (0..1000u32)
+            .map(|i| {
+                let mut state = Blake2b::state();
+                state.write_all(&i.to_le_bytes()).unwrap();
+                state.fin()
+            })
+            .collect();
+
+        let bloom = Bloom::new();
+        for key in &keys[..500] {
+            bloom.insert(key.as_ref());
+        }
+        assert!(keys[..500]
+            .iter()
+            .all(|key| bloom.may_contain(key.as_ref())));
+        let false_positives = keys[500..]
+            .iter()
+            .filter(|key| bloom.may_contain(key.as_ref()))
+            .count();
+        assert!(false_positives < 10);
+
+        let mut bytes = vec![];
+        bloom.write(&mut bytes).unwrap();
+        assert!(!bloom.is_dirty());
+        let read = Bloom::read(&bytes[..]).unwrap();
+        assert!(keys[..500].iter().all(|key| read.may_contain(key.as_ref())));
+    }
+}
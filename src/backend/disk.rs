@@ -1,18 +1,39 @@
-use std::fs::{create_dir, File, OpenOptions};
-use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::fs::{create_dir, rename, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::PathBuf;
 
 use appendix::Index;
 use bytehash::ByteHash;
+use parking_lot::{Mutex, RwLock};
 
-use crate::backend::{Backend, PutResult};
+use crate::backend::bloom::Bloom;
+use crate::backend::{Backend, PutResult, Reader};
 
 /// A backend that stores its data in an `appendix` index, and a flat file
+///
+/// Values are appended to the `data` file, and the `index` maps their
+/// digests to offsets in it. The index is an on-disk hash table that is
+/// memory-mapped when the backend is opened, so opening does not scan or
+/// rebuild anything regardless of the size of the store, and lookups read
+/// the mapped pages directly. Lookups and puts share a read lock on the
+/// index, which flushing takes exclusively, so lookups only wait while the
+/// index is flushed.
+///
+/// Misses are answered by a bloom filter of the stored digests, saved on
+/// flush. The digests are also logged to a `keys` file as values are
+/// written, so a filter left out of date by a missing flush is rebuilt
+/// from the log on the next open.
 pub struct DiskBackend<H: ByteHash> {
-    index: Index<H::Digest, u64>,
-    data: File,
+    // only locked for writing on flush
+    index: RwLock<Index<H::Digest, u64>>,
+    // the data file, the log of the digests written to it, and the offset
+    // at its end
+    data: Mutex<(File, File, u64)>,
     data_path: PathBuf,
-    data_offset: u64,
+    // the digests stored, `None` if the filter could not be brought up to
+    // date with the data
+    bloom: Option<Bloom>,
+    bloom_path: PathBuf,
 }
 
 impl<H: ByteHash> DiskBackend<H> {
@@ -39,22 +60,131 @@ impl<H: ByteHash> DiskBackend<H> {
         let data_offset = data.metadata()?.len();
         data.seek(SeekFrom::End(0))?;
 
+        let mut keys = Self::open_keys(&dir.join("keys"), data_offset)?;
+
+        let bloom_path = dir.join("bloom");
+        let bloom = if data_offset == 0 {
+            Some(Bloom::new())
+        } else {
+            match Self::read_bloom(&bloom_path, data_offset) {
+                Some(bloom) => Some(bloom),
+                None => Self::rebuild_bloom(&mut keys, data_offset)?,
+            }
+        };
+
         Ok(DiskBackend {
-            index,
+            index: RwLock::new(index),
+            data: Mutex::new((data, keys, data_offset)),
             data_path,
-            data,
-            data_offset,
+            bloom,
+            bloom_path,
+        })
+    }
+
+    // The length of an entry in the keys log, a digest and the offset at
+    // the end of its value
+    fn key_len() -> u64 {
+        H::Digest::default().as_ref().len() as u64 + 8
+    }
+
+    // Opens the keys log for appending, starting it with the offset of the
+    // data it covers if it is new, and dropping any partially written entry
+    fn open_keys(path: &PathBuf, data_offset: u64) -> io::Result<File> {
+        let keys = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        let len = keys.metadata()?.len();
+        if len < 8 {
+            keys.set_len(0)?;
+            (&keys).write_all(&data_offset.to_le_bytes())?;
+        } else if (len - 8) % Self::key_len() != 0 {
+            keys.set_len(len - (len - 8) % Self::key_len())?;
+        }
+        Ok(keys)
+    }
+
+    // Rebuilds the filter from the keys log, if it covers all of the data
+    //
+    // The log does not cover stores written before it was introduced, whose
+    // filter is then left out.
+    fn rebuild_bloom(
+        keys: &mut File,
+        data_offset: u64,
+    ) -> io::Result<Option<Bloom>> {
+        keys.seek(SeekFrom::Start(0))?;
+        let mut read = BufReader::new(keys);
+        let mut offset = [0u8; 8];
+        read.read_exact(&mut offset)?;
+        if u64::from_le_bytes(offset) != 0 {
+            return Ok(None);
+        }
+
+        let bloom = Bloom::new();
+        let mut entry = vec![0u8; Self::key_len() as usize];
+        let digest_len = entry.len() - 8;
+        let mut covered = 0;
+        loop {
+            match read.read_exact(&mut entry) {
+                Ok(()) => {
+                    bloom.insert(&entry[..digest_len]);
+                    offset.copy_from_slice(&entry[digest_len..]);
+                    covered = u64::from_le_bytes(offset);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(if covered >= data_offset {
+            Some(bloom)
+        } else {
+            None
         })
     }
+
+    // Reads the saved filter, if it was written along with all the data
+    fn read_bloom(path: &PathBuf, data_offset: u64) -> Option<Bloom> {
+        let mut read = BufReader::new(File::open(path).ok()?);
+        let mut saved_offset = [0u8; 8];
+        read.read_exact(&mut saved_offset).ok()?;
+        if u64::from_le_bytes(saved_offset) != data_offset {
+            return None;
+        }
+        Bloom::read(read).ok()
+    }
+
+    // Saves the filter along with the offset of the data it covers,
+    // replacing the previous one only once completely written
+    fn write_bloom(&self, bloom: &Bloom, data_offset: u64) -> io::Result<()> {
+        let tmp_path = self.bloom_path.with_extension("tmp");
+        let mut write = BufWriter::new(File::create(&tmp_path)?);
+        write.write_all(&data_offset.to_le_bytes())?;
+        bloom.write(&mut write)?;
+        write.into_inner()?.sync_all()?;
+        rename(tmp_path, &self.bloom_path)
+    }
 }
 
-impl<H: ByteHash> Backend<H> for DiskBackend<H> {
-    fn get<'a>(&'a self, hash: &H::Digest) -> io::Result<Box<dyn Read + 'a>> {
-        match self.index.get(hash)? {
+impl<H: ByteHash> Backend<H> for DiskBackend<H>
+where
+    H::Digest: Sync,
+{
+    fn get<'a>(&'a self, hash: &H::Digest) -> io::Result<Reader<'a>> {
+        if let Some(ref bloom) = self.bloom {
+            if !bloom.may_contain(hash.as_ref()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "Data not found",
+                ));
+            }
+        }
+        let offset = self.index.read().get(hash)?.cloned();
+        match offset {
             Some(offset) => {
                 let mut file = File::open(&self.data_path)?;
-                file.seek(SeekFrom::Start(*offset))?;
-                Ok(Box::new(file))
+                file.seek(SeekFrom::Start(offset))?;
+                Ok(Reader::File(file))
             }
             None => {
                 Err(io::Error::new(io::ErrorKind::NotFound, "Data not found"))
@@ -62,27 +192,123 @@ impl<H: ByteHash> Backend<H> for DiskBackend<H> {
         }
     }
 
-    fn put(
-        &mut self,
-        hash: H::Digest,
-        bytes: Vec<u8>,
-    ) -> io::Result<PutResult> {
-        if self.index.insert(hash, self.data_offset)? {
-            // value already present
-            Ok(PutResult::AlreadyThere)
-        } else {
-            self.data.write_all(&bytes)?;
-            self.data_offset += bytes.len() as u64;
-            Ok(PutResult::Ok)
+    fn put(&self, hash: H::Digest, bytes: &[u8]) -> io::Result<PutResult> {
+        // puts are serialized on the data file
+        let (ref mut data, ref mut keys, ref mut data_offset) =
+            *self.data.lock();
+        let index = self.index.read();
+        let maybe_present = match self.bloom {
+            Some(ref bloom) => bloom.may_contain(hash.as_ref()),
+            None => true,
+        };
+        if maybe_present && index.get(&hash)?.is_some() {
+            return Ok(PutResult::AlreadyThere);
+        }
+        // the digest is logged before the value is written, so the log
+        // covers all of the data when the filter is rebuilt from it
+        let end = *data_offset + bytes.len() as u64;
+        let mut entry = hash.as_ref().to_vec();
+        entry.extend_from_slice(&end.to_le_bytes());
+        keys.write_all(&entry)?;
+        // the value is written before being indexed, so readers never find
+        // an offset without the data behind it
+        data.write_all(bytes)?;
+        index.insert(hash, *data_offset)?;
+        if let Some(ref bloom) = self.bloom {
+            bloom.insert(hash.as_ref());
         }
+        *data_offset += bytes.len() as u64;
+        Ok(PutResult::Ok)
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        self.data.flush()?;
-        self.index.flush()
+    fn flush(&self) -> io::Result<()> {
+        let (ref mut data, ref mut keys, data_offset) = *self.data.lock();
+        data.flush()?;
+        keys.flush()?;
+        self.index.write().flush()?;
+        // written last, so a saved filter never misses indexed values
+        match self.bloom {
+            Some(ref bloom) if bloom.is_dirty() => {
+                self.write_bloom(bloom, data_offset)
+            }
+            _ => Ok(()),
+        }
     }
 
     fn size(&self) -> usize {
-        self.index.on_disk_size() + self.data_offset as usize
+        self.index.read().on_disk_size() + self.data.lock().2 as usize
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytehash::{Blake2b, State};
+
+    use crate::tests::tempfile::tempdir;
+
+    fn digest(bytes: &[u8]) -> <Blake2b as ByteHash>::Digest {
+        let mut state = Blake2b::state();
+        state.write_all(bytes).unwrap();
+        state.fin()
+    }
+
+    fn read(backend: &DiskBackend<Blake2b>, bytes: &[u8]) -> io::Result<()> {
+        let mut read = vec![0u8; bytes.len()];
+        backend.get(&digest(bytes))?.read_exact(&mut read)?;
+        assert_eq!(read, bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn bloom_survives_reopen() {
+        let dir = tempdir().unwrap();
+        {
+            let backend = DiskBackend::<Blake2b>::new(dir.path()).unwrap();
+            backend.put(digest(b"saved"), b"saved").unwrap();
+            backend.flush().unwrap();
+        }
+
+        let backend = DiskBackend::<Blake2b>::new(dir.path()).unwrap();
+        assert!(backend.bloom.is_some());
+        read(&backend, b"saved").unwrap();
+        assert!(read(&backend, b"absent").is_err());
+
+        // written without a flush, the saved filter is out of date
+        backend.put(digest(b"unsaved"), b"unsaved").unwrap();
+        drop(backend);
+
+        // and rebuilt from the keys log
+        let backend = DiskBackend::<Blake2b>::new(dir.path()).unwrap();
+        let bloom = backend.bloom.as_ref().expect("filter is rebuilt");
+        assert!(bloom.may_contain(digest(b"unsaved").as_ref()));
+        assert!(!bloom.may_contain(digest(b"absent").as_ref()));
+        read(&backend, b"saved").unwrap();
+        read(&backend, b"unsaved").unwrap();
+        assert!(read(&backend, b"absent").is_err());
+
+        // the rebuilt filter is saved on the next flush
+        backend.flush().unwrap();
+        drop(backend);
+        std::fs::remove_file(dir.path().join("keys")).unwrap();
+        let backend = DiskBackend::<Blake2b>::new(dir.path()).unwrap();
+        assert!(backend.bloom.is_some());
+        read(&backend, b"unsaved").unwrap();
+    }
+
+    #[test]
+    fn bloom_left_out_without_keys() {
+        let dir = tempdir().unwrap();
+        {
+            let backend = DiskBackend::<Blake2b>::new(dir.path()).unwrap();
+            backend.put(digest(b"unsaved"), b"unsaved").unwrap();
+        }
+
+        // a log missing values would make the filter drop them
+        std::fs::remove_file(dir.path().join("keys")).unwrap();
+        let backend = DiskBackend::<Blake2b>::new(dir.path()).unwrap();
+        assert!(backend.bloom.is_none());
+        read(&backend, b"unsaved").unwrap();
     }
 }
@@ -0,0 +1,261 @@
+use std::io::{self, Cursor};
+
+use bytehash::ByteHash;
+
+use crate::backend::verify::check_digest;
+use crate::backend::{Backend, PutResult, Reader};
+
+/// Source of values missing from a `FetchingBackend`, like a peer or an
+/// IPFS node
+///
+/// Implemented for closures taking a digest, so fetchers can be written
+/// inline.
+pub trait BlockFetcher<H: ByteHash>: Send + Sync {
+    /// Returns the bytes of the value with `digest`, or a `NotFound` error
+    /// if it can not be found
+    fn fetch(&self, digest: &H::Digest) -> io::Result<Vec<u8>>;
+
+    /// Returns the bytes of each of `digests`, in order
+    ///
+    /// Called when prefetching the subtrees an iteration visits next. The
+    /// default fetches them one at a time, fetchers talking to a peer can
+    /// request them all in a single round trip instead.
+    fn fetch_many(&self, digests: &[H::Digest]) -> Vec<io::Result<Vec<u8>>> {
+        digests.iter().map(|digest| self.fetch(digest)).collect()
+    }
+}
+
+impl<H, F> BlockFetcher<H> for F
+where
+    H: ByteHash,
+    F: Fn(&H::Digest) -> io::Result<Vec<u8>> + Send + Sync,
+{
+    fn fetch(&self, digest: &H::Digest) -> io::Result<Vec<u8>> {
+        self(digest)
+    }
+}
+
+/// A backend wrapper fetching the values missing from it
+///
+/// Values not found in the wrapped backend are requested from the fetcher,
+/// so restoring a snapshot only known by its root digest fetches whatever
+/// nodes are missing along the way. Fetched values are checked against
+/// their digest, since the network can not be trusted, and written to the
+/// wrapped backend, so each is fetched only once.
+///
+/// Prefetch hints fetch the hinted values missing locally ahead of time,
+/// through `BlockFetcher::fetch_many`.
+pub struct FetchingBackend<B, F> {
+    local: B,
+    fetcher: F,
+}
+
+impl<B, F> FetchingBackend<B, F> {
+    /// Wraps `local`, fetching the values missing from it with `fetcher`
+    pub fn new(local: B, fetcher: F) -> Self {
+        FetchingBackend { local, fetcher }
+    }
+}
+
+impl<H, B, F> Backend<H> for FetchingBackend<B, F>
+where
+    H: ByteHash,
+    B: Backend<H>,
+    F: BlockFetcher<H>,
+{
+    fn get<'a>(&'a self, digest: &H::Digest) -> io::Result<Reader<'a>> {
+        match self.local.get(digest) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                let bytes = self.fetcher.fetch(digest)?;
+                check_digest::<H>(&bytes, digest)?;
+                self.local.put(*digest, &bytes)?;
+                Ok(Reader::Owned(Cursor::new(bytes)))
+            }
+            found => found,
+        }
+    }
+
+    fn put(&self, digest: H::Digest, bytes: &[u8]) -> io::Result<PutResult> {
+        self.local.put(digest, bytes)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.local.flush()
+    }
+
+    fn prefetch(&self, digests: &[H::Digest]) {
+        let missing: Vec<_> = digests
+            .iter()
+            .filter(|digest| match self.local.get(digest) {
+                Err(e) => e.kind() == io::ErrorKind::NotFound,
+                Ok(_) => false,
+            })
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            let fetched = self.fetcher.fetch_many(&missing);
+            // only a hint, values failing here are fetched again on `get`
+            for (digest, bytes) in missing.iter().zip(fetched) {
+                if let Ok(bytes) = bytes {
+                    if check_digest::<H>(&bytes, digest).is_ok() {
+                        let _ = self.local.put(*digest, &bytes);
+                    }
+                }
+            }
+        }
+        self.local.prefetch(digests)
+    }
+
+    fn size(&self) -> usize {
+        self.local.size()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Read;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use crate::annotations::Cardinality;
+    use crate::{
+        Blake2b, Compound, Content, Handle, LeafIterable, MemBackend, Sink,
+        Source, Store,
+    };
+
+    type Digest = <Blake2b as ByteHash>::Digest;
+
+    fn value(remote: &MemBackend<Blake2b>, digest: &Digest) -> Vec<u8> {
+        let mut bytes = vec![];
+        remote.get(digest).unwrap().read_to_end(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn fetches_missing_values() {
+        let remote = Arc::new(MemBackend::<Blake2b>::new());
+        let snapshot = Store::with_backend(remote.clone())
+            .persist(&mut vec![String::from("kelvin"); 3])
+            .unwrap();
+
+        let fetched = Arc::new(AtomicUsize::new(0));
+        let fetcher = {
+            let (remote, fetched) = (remote.clone(), fetched.clone());
+            move |digest: &Digest| {
+                fetched.fetch_add(1, Ordering::SeqCst);
+                remote.get(digest)?;
+                Ok(value(&remote, digest))
+            }
+        };
+        let local = Arc::new(MemBackend::<Blake2b>::new());
+        let backend = FetchingBackend::new(local.clone(), fetcher);
+
+        let store = Store::with_backend(backend);
+        let restored: Vec<String> = store.get_hash(snapshot.hash()).unwrap();
+        assert_eq!(restored, vec!["kelvin"; 3]);
+        assert_eq!(fetched.load(Ordering::SeqCst), 1);
+        assert!(local.get(snapshot.hash()).is_ok());
+
+        // kept locally, so not fetched again
+        let restored: Vec<String> = store.get_hash(snapshot.hash()).unwrap();
+        assert_eq!(restored.len(), 3);
+        assert_eq!(fetched.load(Ordering::SeqCst), 1);
+
+        let err = store.get_hash::<String>(&[0; 32]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[derive(Clone, Default)]
+    struct Tree(Vec<Handle<Self, Blake2b>>);
+
+    impl Content<Blake2b> for Tree {
+        fn persist(&mut self, sink: &mut Sink<Blake2b>) -> io::Result<()> {
+            self.0.persist(sink)
+        }
+
+        fn restore(source: &mut Source<Blake2b>) -> io::Result<Self> {
+            Ok(Tree(Vec::restore(source)?))
+        }
+    }
+
+    impl Compound<Blake2b> for Tree {
+        type Leaf = u64;
+        type Annotation = Cardinality<u64>;
+
+        fn children(&self) -> &[Handle<Self, Blake2b>] {
+            &self.0
+        }
+
+        fn children_mut(&mut self) -> &mut [Handle<Self, Blake2b>] {
+            &mut self.0
+        }
+    }
+
+    // A peer counting the values fetched singly and in batches
+    struct Peer {
+        remote: Arc<MemBackend<Blake2b>>,
+        single: Arc<AtomicUsize>,
+        batched: Arc<AtomicUsize>,
+    }
+
+    impl BlockFetcher<Blake2b> for Peer {
+        fn fetch(&self, digest: &Digest) -> io::Result<Vec<u8>> {
+            self.single.fetch_add(1, Ordering::SeqCst);
+            self.remote.get(digest)?;
+            Ok(value(&self.remote, digest))
+        }
+
+        fn fetch_many(&self, digests: &[Digest]) -> Vec<io::Result<Vec<u8>>> {
+            self.batched.fetch_add(digests.len(), Ordering::SeqCst);
+            digests
+                .iter()
+                .map(|digest| Ok(value(&self.remote, digest)))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn prefetches_siblings() {
+        let remote = Arc::new(MemBackend::<Blake2b>::new());
+        let node = |range: std::ops::Range<u64>| {
+            Handle::new_node(Tree(range.map(Handle::new_leaf).collect()))
+        };
+        let mut tree = Tree(vec![node(0..2), node(2..4), node(4..6)]);
+        let snapshot = Store::with_backend(remote.clone())
+            .persist(&mut tree)
+            .unwrap();
+
+        let (single, batched) = (Arc::default(), Arc::default());
+        let peer = Peer {
+            remote,
+            single: Arc::clone(&single),
+            batched: Arc::clone(&batched),
+        };
+        let local = Arc::new(MemBackend::<Blake2b>::new());
+        let store = Store::with_backend(FetchingBackend::new(local, peer));
+
+        let restored: Tree = store.get_hash(snapshot.hash()).unwrap();
+        let leaves: Vec<u64> =
+            restored.iter().map(|leaf| *leaf.unwrap()).collect();
+        assert_eq!(leaves, (0..6).collect::<Vec<_>>());
+
+        // the root and the first node, then both siblings in one batch
+        assert_eq!(single.load(Ordering::SeqCst), 2);
+        assert_eq!(batched.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn rejects_wrong_values() {
+        let fetcher = |_: &Digest| Ok(b"kelvin".to_vec());
+        let local = Arc::new(MemBackend::<Blake2b>::new());
+        let backend = FetchingBackend::new(local.clone(), fetcher);
+
+        match backend.get(&[0; 32]) {
+            Ok(_) => panic!("wrong value returned"),
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+        }
+        assert!(local.get(&[0; 32]).is_err());
+    }
+}
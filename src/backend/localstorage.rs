@@ -1,14 +1,12 @@
-use std::io::{self, Read, Write};
+use std::io;
 use std::marker::PhantomData;
 use std::path::PathBuf;
 
 use base64::{decode, encode_config, encode_config_buf, STANDARD_NO_PAD};
 use bytehash::ByteHash;
-use wasm_bindgen::prelude::*;
-use wasm_bindgen::{closure::Closure, JsCast};
-use web_sys::Storage;
+use wasm_bindgen::JsValue;
 
-use crate::backend::{Backend, PutResult};
+use crate::backend::{Backend, PutResult, Reader};
 
 pub struct WebBackend<H: ByteHash> {
     storage: web_sys::Storage,
@@ -16,11 +14,6 @@ pub struct WebBackend<H: ByteHash> {
     _marker: PhantomData<H>,
 }
 
-#[wasm_bindgen]
-extern "C" {
-    fn alert(s: &str);
-}
-
 unsafe impl<H: ByteHash> Send for WebBackend<H> {}
 unsafe impl<H: ByteHash> Sync for WebBackend<H> {}
 
@@ -41,35 +34,43 @@ impl<H: ByteHash> WebBackend<H> {
 }
 
 impl<H: ByteHash> Backend<H> for WebBackend<H> {
-    fn get<'a>(&'a self, hash: &H::Digest) -> io::Result<Box<dyn Read + 'a>> {
+    fn get<'a>(&'a self, hash: &H::Digest) -> io::Result<Reader<'a>> {
         let mut key = self.name.clone();
         encode_config_buf(hash.as_ref(), STANDARD_NO_PAD, &mut key);
 
-        if let Some(value) = self.storage.get_item(&key).unwrap() {
-            Ok(Box::new(io::Cursor::new(decode(&value).unwrap())))
+        if let Some(value) = self.storage.get_item(&key).map_err(js_error)? {
+            let bytes = decode(&value).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "Invalid base64")
+            })?;
+            Ok(Reader::Owned(io::Cursor::new(bytes)))
         } else {
             Err(io::Error::new(io::ErrorKind::NotFound, "Data not found"))
         }
     }
 
-    fn put(
-        &mut self,
-        hash: H::Digest,
-        bytes: Vec<u8>,
-    ) -> io::Result<PutResult> {
+    fn put(&self, hash: H::Digest, bytes: &[u8]) -> io::Result<PutResult> {
         let mut key = self.name.clone();
         encode_config_buf(hash.as_ref(), STANDARD_NO_PAD, &mut key);
 
-        if let Some(_) = self.storage.get_item(&key).unwrap() {
+        if self.storage.get_item(&key).map_err(js_error)?.is_some() {
             Ok(PutResult::AlreadyThere)
         } else {
-            let value = encode_config(&bytes, STANDARD_NO_PAD);
-            self.storage.set_item(&key, &value).unwrap();
+            let value = encode_config(bytes, STANDARD_NO_PAD);
+            self.storage.set_item(&key, &value).map_err(js_error)?;
             Ok(PutResult::Ok)
         }
     }
 
-    fn size(&self) -> usize {
-        unimplemented!()
+    // values are written to local storage as they are put
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
     }
 }
+
+// Local storage throws when unavailable or full
+fn js_error(e: JsValue) -> io::Error {
+    let msg = e
+        .as_string()
+        .unwrap_or_else(|| "Local storage error".into());
+    io::Error::new(io::ErrorKind::Other, msg)
+}
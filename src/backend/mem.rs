@@ -1,54 +1,61 @@
 use std::collections::HashMap;
-use std::io::{self, Cursor, Read};
+use std::io::{self, Cursor};
+use std::sync::Arc;
 
 use bytehash::ByteHash;
+use parking_lot::RwLock;
 
-use crate::backend::{Backend, PutResult};
+use crate::backend::{Backend, PutResult, Reader};
 
-type ByteMap<D> = HashMap<D, Vec<u8>>;
+type ByteMap<D> = HashMap<D, Arc<[u8]>>;
 
 /// A backend that stores its data in memory
 pub struct MemBackend<H: ByteHash> {
-    size: usize,
-    data: ByteMap<H::Digest>,
+    data: RwLock<(ByteMap<H::Digest>, usize)>,
 }
 
 impl<H: ByteHash> MemBackend<H> {
     /// Creates a new `MemBackend`
     pub fn new() -> Self {
         MemBackend {
-            size: 0,
-            data: HashMap::new(),
+            data: RwLock::new((HashMap::new(), 0)),
         }
     }
 }
 
-impl<H: ByteHash> Backend<H> for MemBackend<H> {
-    fn get<'a>(&'a self, hash: &H::Digest) -> io::Result<Box<dyn Read + 'a>> {
-        if let Some(data) = self.data.get(hash) {
-            Ok(Box::new(Cursor::new(data)))
+impl<H: ByteHash> Default for MemBackend<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H: ByteHash> Backend<H> for MemBackend<H>
+where
+    H::Digest: Sync,
+{
+    fn get<'a>(&'a self, hash: &H::Digest) -> io::Result<Reader<'a>> {
+        // the value is shared, so no lock is held while it is read
+        if let Some(data) = self.data.read().0.get(hash) {
+            Ok(Reader::Shared(Cursor::new(data.clone())))
         } else {
             Err(io::Error::new(io::ErrorKind::NotFound, "Data not found"))
         }
     }
 
-    fn put(
-        &mut self,
-        hash: H::Digest,
-        bytes: Vec<u8>,
-    ) -> io::Result<PutResult> {
-        self.size += bytes.len();
-        match self.data.insert(hash, bytes) {
+    fn put(&self, hash: H::Digest, bytes: &[u8]) -> io::Result<PutResult> {
+        let (ref mut map, ref mut size) = *self.data.write();
+        *size += bytes.len();
+        match map.insert(hash, bytes.into()) {
             Some(_) => Ok(PutResult::AlreadyThere),
             None => Ok(PutResult::Ok),
         }
     }
 
-    fn flush(&mut self) -> io::Result<()> {
+    fn flush(&self) -> io::Result<()> {
         Ok(())
     }
 
     fn size(&self) -> usize {
-        self.size
+        self.data.read().1
     }
 }
@@ -1,9 +1,16 @@
-use std::io::{self, Read};
+#[cfg(feature = "filesystem")]
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+use std::sync::Arc;
 
 use bytehash::ByteHash;
 
+mod fetch;
 mod mem;
+mod verify;
 
+#[cfg(feature = "filesystem")]
+mod bloom;
 #[cfg(feature = "filesystem")]
 mod disk;
 #[cfg(feature = "web")]
@@ -14,31 +21,115 @@ pub use self::localstorage::WebBackend as Persistant;
 
 #[cfg(feature = "filesystem")]
 pub use disk::DiskBackend as Persistant;
+#[cfg(feature = "filesystem")]
+pub use disk::DiskBackend;
 
+pub use self::fetch::{BlockFetcher, FetchingBackend};
 pub use self::mem::MemBackend as Volatile;
+pub use self::mem::MemBackend;
+pub use self::verify::VerifyingBackend;
+
+pub(crate) use self::verify::check_digest;
+
+/// A reader over a value in a backend
+///
+/// The readers of the provided backends have variants of their own, so
+/// restoring a value does not allocate and dynamically dispatch through a
+/// boxed reader.
+pub enum Reader<'a> {
+    /// A value borrowed from memory
+    Slice(&'a [u8]),
+    /// A value shared with the backend
+    Shared(Cursor<Arc<[u8]>>),
+    /// A value owned by the reader
+    Owned(Cursor<Vec<u8>>),
+    /// A file positioned at the start of the value
+    #[cfg(feature = "filesystem")]
+    File(File),
+    /// Any other reader
+    Boxed(Box<dyn Read + 'a>),
+}
 
+impl<'a> Read for Reader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Reader::Slice(slice) => slice.read(buf),
+            Reader::Shared(cursor) => cursor.read(buf),
+            Reader::Owned(cursor) => cursor.read(buf),
+            #[cfg(feature = "filesystem")]
+            Reader::File(file) => file.read(buf),
+            Reader::Boxed(read) => read.read(buf),
+        }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        match self {
+            Reader::Slice(slice) => slice.read_exact(buf),
+            Reader::Shared(cursor) => cursor.read_exact(buf),
+            Reader::Owned(cursor) => cursor.read_exact(buf),
+            #[cfg(feature = "filesystem")]
+            Reader::File(file) => file.read_exact(buf),
+            Reader::Boxed(read) => read.read_exact(buf),
+        }
+    }
+}
+
+/// The outcome of putting a value into a backend
 pub enum PutResult {
+    /// The value was stored
     Ok,
+    /// The value was already present, and was not stored again
     AlreadyThere,
 }
 
 /// Trait to implement custom backends
-pub trait Backend<H: ByteHash> {
+///
+/// The store calls into backends without locking, so reads never wait on
+/// each other or on writers at the store level. Backends synchronize their
+/// writes internally.
+pub trait Backend<H: ByteHash>: Send + Sync {
     /// Get a reader from a hash
-    fn get<'a>(&'a self, digest: &H::Digest) -> io::Result<Box<dyn Read + 'a>>;
+    fn get<'a>(&'a self, digest: &H::Digest) -> io::Result<Reader<'a>>;
 
     /// Put the serialized value in the backend.
-    fn put(
-        &mut self,
-        digest: H::Digest,
-        bytes: Vec<u8>,
-    ) -> io::Result<PutResult>;
+    fn put(&self, digest: H::Digest, bytes: &[u8]) -> io::Result<PutResult>;
 
     /// Flush changes to underlying medium
-    fn flush(&mut self) -> io::Result<()>;
+    fn flush(&self) -> io::Result<()>;
+
+    /// Hint that the given values are about to be read (optional)
+    ///
+    /// Called during iteration with the subtrees that will be visited next.
+    /// Backends with high read latency can start fetching them in the
+    /// background, so they are at hand once `get` is called.
+    fn prefetch(&self, _digests: &[H::Digest]) {}
 
     /// Return approximate size in bytes (optional)
     fn size(&self) -> usize {
         0
     }
 }
+
+/// A shared backend, so a backend handed to a `Store` can still be accessed
+/// by its owner
+impl<H: ByteHash, B: Backend<H> + ?Sized> Backend<H> for Arc<B> {
+    fn get<'a>(&'a self, digest: &H::Digest) -> io::Result<Reader<'a>> {
+        (**self).get(digest)
+    }
+
+    fn put(&self, digest: H::Digest, bytes: &[u8]) -> io::Result<PutResult> {
+        (**self).put(digest, bytes)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn prefetch(&self, digests: &[H::Digest]) {
+        (**self).prefetch(digests)
+    }
+
+    fn size(&self) -> usize {
+        (**self).size()
+    }
+}
@@ -0,0 +1,100 @@
+use std::io::{self, Cursor, Read, Write};
+
+use bytehash::{ByteHash, State};
+
+use crate::backend::{Backend, PutResult, Reader};
+
+/// A backend wrapper checking the values read against their digests
+///
+/// Values whose bytes do not hash to the digest they were requested by are
+/// reported as `InvalidData` errors, instead of being decoded into wrong
+/// values. Every value is read into memory and hashed before it is
+/// restored, so this is meant for backends that can not be trusted to
+/// return what was put into them, like disks that might be corrupted.
+pub struct VerifyingBackend<B> {
+    inner: B,
+}
+
+impl<B> VerifyingBackend<B> {
+    /// Wraps `inner`, verifying the values read from it
+    pub fn new(inner: B) -> Self {
+        VerifyingBackend { inner }
+    }
+}
+
+// Returns an `InvalidData` error unless `bytes` hash to `digest`
+pub(crate) fn check_digest<H: ByteHash>(
+    bytes: &[u8],
+    digest: &H::Digest,
+) -> io::Result<()> {
+    let mut state = H::state();
+    state.write_all(bytes)?;
+    if state.fin() != *digest {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Value does not match its digest",
+        ));
+    }
+    Ok(())
+}
+
+impl<H, B> Backend<H> for VerifyingBackend<B>
+where
+    H: ByteHash,
+    B: Backend<H>,
+{
+    fn get<'a>(&'a self, digest: &H::Digest) -> io::Result<Reader<'a>> {
+        let mut bytes = vec![];
+        self.inner.get(digest)?.read_to_end(&mut bytes)?;
+        check_digest::<H>(&bytes, digest)?;
+        Ok(Reader::Owned(Cursor::new(bytes)))
+    }
+
+    fn put(&self, digest: H::Digest, bytes: &[u8]) -> io::Result<PutResult> {
+        self.inner.put(digest, bytes)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn prefetch(&self, digests: &[H::Digest]) {
+        self.inner.prefetch(digests)
+    }
+
+    fn size(&self) -> usize {
+        self.inner.size()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use crate::{Blake2b, MemBackend, Store};
+
+    #[test]
+    fn rejects_wrong_value() {
+        let mem = Arc::new(MemBackend::<Blake2b>::new());
+        let store = Store::with_backend(VerifyingBackend::new(mem.clone()));
+        let snapshot = store.persist(&mut String::from("kelvin")).unwrap();
+        let other = store.persist(&mut String::from("kelvim")).unwrap();
+
+        // a fresh store, not serving the values from its cache
+        let fresh = Store::with_backend(VerifyingBackend::new(mem.clone()));
+        assert_eq!(fresh.restore(&snapshot).unwrap(), "kelvin");
+
+        // replace the value with the encoding of another one
+        let mut bytes = vec![];
+        mem.get(other.hash())
+            .unwrap()
+            .read_to_end(&mut bytes)
+            .unwrap();
+        mem.put(*snapshot.hash(), &bytes).unwrap();
+
+        let err = fresh.restore(&snapshot).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,162 @@
+//! Inspects a kelvin store directory
+//!
+//! Usage: `kelvin-cli <store directory> <command>`, with the commands:
+//!
+//! - `roots`: the digest of the latest state saved by `Root`, if any
+//! - `stats`: the size of the values and the index on disk
+//! - `cat <digest> [len]`: the bytes of a value in hex, 256 unless `len` is
+//!   given. Values are stored back to back, without their lengths, so the
+//!   bytes following the value are shown if it is shorter.
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+
+use kelvin::{Backend, Blake2b, ByteHash, DiskBackend};
+
+type Digest = <Blake2b as ByteHash>::Digest;
+
+const USAGE: &str = "usage: kelvin-cli <store directory> roots | stats | \
+                     cat <digest> [len]";
+
+// bytes shown by `cat` unless a length is given
+const CAT_LEN: u64 = 256;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let stdout = io::stdout();
+    match run(&args, &mut stdout.lock()) {
+        Ok(()) => (),
+        Err(ref e) if e.kind() == io::ErrorKind::InvalidInput => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2)
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1)
+        }
+    }
+}
+
+fn run<W: Write>(args: &[String], out: &mut W) -> io::Result<()> {
+    let (dir, command) = match args {
+        [dir, command, ..] => (Path::new(dir), command.as_str()),
+        _ => return Err(invalid_input("missing arguments")),
+    };
+    // opening a backend creates its directory, which is not wanted here
+    if !dir.join("data").is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no store in {}", dir.display()),
+        ));
+    }
+
+    match (command, &args[2..]) {
+        ("roots", []) => roots(dir, out),
+        ("stats", []) => stats(dir, out),
+        ("cat", [digest]) => cat(dir, &parse_digest(digest)?, CAT_LEN, out),
+        ("cat", [digest, len]) => {
+            let len = len
+                .parse()
+                .map_err(|_| invalid_input("length is not a number"))?;
+            cat(dir, &parse_digest(digest)?, len, out)
+        }
+        _ => Err(invalid_input("unknown command")),
+    }
+}
+
+fn roots<W: Write>(dir: &Path, out: &mut W) -> io::Result<()> {
+    let path = dir.join("root");
+    if !path.exists() {
+        return writeln!(out, "no root saved");
+    }
+    let mut digest = Digest::default();
+    fs::File::open(path)?.read_exact(&mut digest)?;
+    writeln!(out, "{}", hex(&digest))
+}
+
+fn stats<W: Write>(dir: &Path, out: &mut W) -> io::Result<()> {
+    let backend = DiskBackend::<Blake2b>::new(dir)?;
+    let data = fs::metadata(dir.join("data"))?.len();
+    writeln!(out, "data:  {} bytes", data)?;
+    writeln!(out, "index: {} bytes", backend.size() as u64 - data)
+}
+
+fn cat<W: Write>(
+    dir: &Path,
+    digest: &Digest,
+    len: u64,
+    out: &mut W,
+) -> io::Result<()> {
+    let backend = DiskBackend::<Blake2b>::new(dir)?;
+    let mut bytes = vec![];
+    backend.get(digest)?.take(len).read_to_end(&mut bytes)?;
+    for (i, line) in bytes.chunks(32).enumerate() {
+        writeln!(out, "{:08x}  {}", i * 32, hex(line))?;
+    }
+    Ok(())
+}
+
+fn parse_digest(s: &str) -> io::Result<Digest> {
+    let mut digest = Digest::default();
+    if s.len() != digest.len() * 2 || !s.is_ascii() {
+        return Err(invalid_input("digest is not 64 hex digits"));
+    }
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)
+            .map_err(|_| invalid_input("digest is not 64 hex digits"))?;
+    }
+    Ok(digest)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use kelvin::tests::tempfile::tempdir;
+    use kelvin::Root;
+
+    fn output(args: &[&str]) -> io::Result<String> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        let mut out = vec![];
+        run(&args, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn inspect() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let mut root = Root::<u64, Blake2b>::new(path).unwrap();
+        assert_eq!(output(&[path, "roots"]).unwrap(), "no root saved\n");
+
+        let snapshot = root.set_root(&mut 0x0102u64).unwrap();
+        let digest = hex(snapshot.hash());
+        assert_eq!(output(&[path, "roots"]).unwrap(), format!("{}\n", digest));
+        assert_eq!(
+            output(&[path, "cat", &digest]).unwrap(),
+            "00000000  0000000000000102\n"
+        );
+        assert_eq!(
+            output(&[path, "cat", &digest, "1"]).unwrap(),
+            "00000000  00\n"
+        );
+        assert!(output(&[path, "stats"]).unwrap().starts_with("data:  8 "));
+
+        let err = output(&[path, "cat", &"00".repeat(32)]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let err = output(&[path, "cat", "0a"]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = output(&[path, "fsck"]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
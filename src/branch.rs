@@ -49,6 +49,51 @@ where
             None
         })
     }
+
+    /// Constructs a branch to the last leaf of `node`
+    pub(crate) fn last(node: &'a C) -> io::Result<Option<Self>> {
+        let mut inner = UnsafeBranch::new_cached_back(Cached::Borrowed(node));
+        inner.search_back()?;
+        Ok(if inner.leaf().is_some() {
+            Some(Branch(inner))
+        } else {
+            None
+        })
+    }
+
+    /// Moves the branch to the preceding leaf
+    ///
+    /// Takes self by value, and returns the updated branch or `None`
+    pub(crate) fn prev(mut self) -> io::Result<Option<Self>> {
+        self.0.search_back()?;
+        Ok(if self.0.leaf().is_some() {
+            Some(self)
+        } else {
+            None
+        })
+    }
+
+    /// Returns the child offsets along the branch, from the root down
+    pub(crate) fn offsets(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.offsets()
+    }
+
+    /// Returns the child offsets along the branch, above the leaf
+    pub(crate) fn parent_offsets(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.parent_offsets()
+    }
+
+    /// Hints the store to fetch the persisted siblings following the node
+    /// holding the leaf, which an iteration visits next
+    pub(crate) fn prefetch_siblings(&self) {
+        self.0.prefetch_siblings()
+    }
+
+    /// Returns true if the leaf of `self` comes before the leaf of `other`
+    /// in the tree
+    pub(crate) fn precedes(&self, other: &Self) -> bool {
+        self.0.offsets().lt(other.0.offsets())
+    }
 }
 
 impl<'a, C, H> Deref for Branch<'a, C, H>
@@ -0,0 +1,335 @@
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+
+use bytehash::State;
+
+use crate::dump::{write_cbor_head, ARRAY, BYTES, MAP, TAG, TEXT, UINT};
+use crate::{Blake2b, ByteHash, Compound, Snapshot, Store};
+
+type Digest = <Blake2b as ByteHash>::Digest;
+
+// CIDv1 of a value, in the raw codec, with a blake2b-256 multihash: the
+// version, codec and multihash code as varints, and the digest length
+const CID_PREFIX: [u8; 6] = [0x01, 0x55, 0xa0, 0xe4, 0x02, 0x20];
+const CID_LEN: usize = CID_PREFIX.len() + 32;
+
+// CBOR tag of CIDs in DAG-CBOR
+const CID_TAG: u64 = 42;
+
+// upper bound of header and block lengths, against malformed archives
+const MAX_SECTION: u64 = 1 << 30;
+
+impl Store<Blake2b> {
+    /// Writes the values of `snapshot` to `writer` as a CAR archive,
+    /// returning the number of blocks written
+    ///
+    /// The archive is in the CARv1 format of IPLD, with the snapshot as its
+    /// only root. Every node is a block of the raw codec, addressed by a
+    /// CIDv1 of its blake2b-256 digest, so the blocks can be verified and
+    /// served by IPFS as they are. Nodes are written parents first and
+    /// subtrees shared within the tree only once.
+    ///
+    /// Like `sync_to`, only the nodes of the structure are followed,
+    /// snapshots stored within its leaves are not exported along.
+    pub fn export_car<C, W>(
+        &self,
+        snapshot: &Snapshot<C, Blake2b>,
+        mut writer: W,
+    ) -> io::Result<usize>
+    where
+        C: Compound<Blake2b>,
+        W: Write,
+    {
+        let mut header = vec![];
+        write_cbor_head(&mut header, MAP, 2)?;
+        write_cbor_str(&mut header, "roots")?;
+        write_cbor_head(&mut header, ARRAY, 1)?;
+        write_cbor_head(&mut header, TAG, CID_TAG)?;
+        // CIDs in DAG-CBOR are prefixed with the identity multibase
+        write_cbor_head(&mut header, BYTES, CID_LEN as u64 + 1)?;
+        header.push(0x00);
+        header.extend_from_slice(&cid(snapshot.hash()));
+        write_cbor_str(&mut header, "version")?;
+        write_cbor_head(&mut header, UINT, 1)?;
+        write_varint(&mut writer, header.len() as u64)?;
+        writer.write_all(&header)?;
+
+        let mut written = HashSet::new();
+        let mut pending = vec![*snapshot.hash()];
+        while let Some(digest) = pending.pop() {
+            if !written.insert(digest) {
+                continue;
+            }
+            let (node, bytes) = self.get_encoded::<C>(&digest)?;
+            write_varint(&mut writer, (CID_LEN + bytes.len()) as u64)?;
+            writer.write_all(&cid(&digest))?;
+            writer.write_all(&bytes)?;
+            // reversed, so the first child is written first
+            pending.extend(
+                node.children()
+                    .iter()
+                    .rev()
+                    .filter_map(|handle| handle.snapshot())
+                    .map(|snapshot| *snapshot.hash()),
+            );
+        }
+        writer.flush()?;
+        Ok(written.len())
+    }
+
+    /// Reads a CAR archive written by `export_car` into the store,
+    /// returning a snapshot of its root
+    ///
+    /// Only archives with a single root, and blocks addressed like the ones
+    /// `export_car` writes, can be imported. Every block is checked against
+    /// its digest before being stored, and the root has to be in the store
+    /// once all blocks are read. Otherwise an `InvalidData` error is
+    /// returned, and the blocks read so far are left in the store.
+    pub fn import_car<C, R>(
+        &self,
+        mut reader: R,
+    ) -> io::Result<Snapshot<C, Blake2b>>
+    where
+        C: Compound<Blake2b>,
+        R: Read,
+    {
+        let header_len = match read_varint(&mut reader)? {
+            Some(len) => len,
+            None => return Err(invalid("missing CAR header")),
+        };
+        let header = read_section(&mut reader, header_len)?;
+        let root = parse_header(&mut &header[..])?;
+
+        while let Some(len) = read_varint(&mut reader)? {
+            let block = read_section(&mut reader, len)?;
+            if block.len() < CID_LEN || block[..CID_PREFIX.len()] != CID_PREFIX
+            {
+                return Err(invalid("unsupported CID in CAR block"));
+            }
+            let mut digest = Digest::default();
+            digest.copy_from_slice(&block[CID_PREFIX.len()..CID_LEN]);
+            let bytes = &block[CID_LEN..];
+
+            let mut state = Blake2b::state();
+            state.write_all(bytes)?;
+            if state.fin() != digest {
+                return Err(invalid("CAR block does not match its digest"));
+            }
+            self.put(digest, bytes)?;
+        }
+        self.flush()?;
+
+        if !self.contains(&root) {
+            return Err(invalid("CAR root missing from the archive"));
+        }
+        Ok(Snapshot::new(root, self))
+    }
+}
+
+fn cid(digest: &Digest) -> [u8; CID_LEN] {
+    let mut cid = [0u8; CID_LEN];
+    cid[..CID_PREFIX.len()].copy_from_slice(&CID_PREFIX);
+    cid[CID_PREFIX.len()..].copy_from_slice(digest);
+    cid
+}
+
+// Parses a CARv1 header with a single root, returning its digest
+fn parse_header(header: &mut &[u8]) -> io::Result<Digest> {
+    let mut root = None;
+    let mut version = None;
+    for _ in 0..read_cbor_head(header, MAP)? {
+        let key = read_cbor_head(header, TEXT)?;
+        match &*read_section(header, key)? {
+            b"version" => version = Some(read_cbor_head(header, UINT)?),
+            b"roots" => {
+                if read_cbor_head(header, ARRAY)? != 1 {
+                    return Err(invalid("CAR archive without a single root"));
+                }
+                if read_cbor_head(header, TAG)? != CID_TAG {
+                    return Err(invalid("CAR root is not a CID"));
+                }
+                let len = read_cbor_head(header, BYTES)?;
+                let cid = read_section(header, len)?;
+                if cid.len() != CID_LEN + 1
+                    || cid[0] != 0x00
+                    || cid[1..CID_PREFIX.len() + 1] != CID_PREFIX
+                {
+                    return Err(invalid("unsupported CID as CAR root"));
+                }
+                let mut digest = Digest::default();
+                digest.copy_from_slice(&cid[CID_PREFIX.len() + 1..]);
+                root = Some(digest);
+            }
+            _ => return Err(invalid("unknown key in CAR header")),
+        }
+    }
+    match (version, root) {
+        (Some(1), Some(root)) => Ok(root),
+        (Some(_), _) => Err(invalid("unsupported CAR version")),
+        _ => Err(invalid("incomplete CAR header")),
+    }
+}
+
+fn write_cbor_str<W: Write>(writer: &mut W, s: &str) -> io::Result<()> {
+    write_cbor_head(writer, TEXT, s.len() as u64)?;
+    writer.write_all(s.as_bytes())
+}
+
+// Reads the initial bytes of a CBOR item, which has to be of major type
+// `major`, returning its length or value
+fn read_cbor_head<R: Read>(reader: &mut R, major: u8) -> io::Result<u64> {
+    let mut byte = [0u8];
+    reader.read_exact(&mut byte)?;
+    if byte[0] & 0xe0 != major {
+        return Err(invalid("unexpected item in CAR header"));
+    }
+    let mut read_be = |len: usize| -> io::Result<u64> {
+        let mut buf = [0u8; 8];
+        reader.read_exact(&mut buf[8 - len..])?;
+        Ok(u64::from_be_bytes(buf))
+    };
+    match byte[0] & 0x1f {
+        n @ 0..=23 => Ok(n as u64),
+        24 => read_be(1),
+        25 => read_be(2),
+        26 => read_be(4),
+        27 => read_be(8),
+        _ => Err(invalid("unsupported item in CAR header")),
+    }
+}
+
+fn read_section<R: Read>(reader: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    if len > MAX_SECTION {
+        return Err(invalid("CAR section too long"));
+    }
+    let mut bytes = vec![0u8; len as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn write_varint<W: Write>(writer: &mut W, mut n: u64) -> io::Result<()> {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+// Reads an unsigned LEB128 varint, `None` at the end of `reader`
+fn read_varint<R: Read>(reader: &mut R) -> io::Result<Option<u64>> {
+    let mut n = 0u64;
+    for i in 0..10 {
+        let mut byte = [0u8];
+        if reader.read(&mut byte)? == 0 {
+            return match i {
+                0 => Ok(None),
+                _ => Err(invalid("truncated varint in CAR archive")),
+            };
+        }
+        n |= ((byte[0] & 0x7f) as u64) << (i * 7);
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(n));
+        }
+    }
+    Err(invalid("varint too long in CAR archive"))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::annotations::Cardinality;
+    use crate::{Content, Handle, LeafIterable, Sink, Source};
+
+    #[derive(Clone, Default)]
+    struct Tree(Vec<Handle<Self, Blake2b>>);
+
+    impl Content<Blake2b> for Tree {
+        fn persist(&mut self, sink: &mut Sink<Blake2b>) -> io::Result<()> {
+            self.0.persist(sink)
+        }
+
+        fn restore(source: &mut Source<Blake2b>) -> io::Result<Self> {
+            Ok(Tree(Vec::restore(source)?))
+        }
+    }
+
+    impl Compound<Blake2b> for Tree {
+        type Leaf = u64;
+        type Annotation = Cardinality<u64>;
+
+        fn children(&self) -> &[Handle<Self, Blake2b>] {
+            &self.0
+        }
+
+        fn children_mut(&mut self) -> &mut [Handle<Self, Blake2b>] {
+            &mut self.0
+        }
+    }
+
+    fn leaves(tree: &Tree) -> Vec<u64> {
+        tree.iter().map(|leaf| *leaf.unwrap()).collect()
+    }
+
+    fn exported() -> (Vec<u8>, Snapshot<Tree, Blake2b>) {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let shared = Tree((0..3).map(Handle::new_leaf).collect());
+        let mut tree = Tree(vec![
+            Handle::new_node(shared.clone()),
+            Handle::new_leaf(7),
+            Handle::new_node(Tree(vec![Handle::new_node(shared)])),
+        ]);
+        let snapshot = store.persist(&mut tree).unwrap();
+
+        let mut car = vec![];
+        // the root, the shared node once, and the node above it
+        assert_eq!(store.export_car(&snapshot, &mut car).unwrap(), 3);
+        (car, snapshot)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (car, snapshot) = exported();
+
+        // the header, starting with its length and the root CID
+        assert_eq!(car[0], 60);
+        assert_eq!(car[1..11], *b"\xa2\x65roots\x81\xd8\x2a");
+        assert_eq!(car[14..20], CID_PREFIX);
+        assert_eq!(car[20..52], *snapshot.hash());
+
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let imported = store.import_car::<Tree, _>(&car[..]).unwrap();
+        assert_eq!(imported.hash(), snapshot.hash());
+        let tree = store.restore(&imported).unwrap();
+        assert_eq!(leaves(&tree), vec![0, 1, 2, 7, 0, 1, 2]);
+    }
+
+    fn import_error(car: &[u8]) -> io::ErrorKind {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        match store.import_car::<Tree, _>(car) {
+            Ok(_) => panic!("corrupted archive imported"),
+            Err(e) => e.kind(),
+        }
+    }
+
+    #[test]
+    fn corrupted() {
+        let (car, _) = exported();
+
+        let mut flipped = car.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert_eq!(import_error(&flipped), io::ErrorKind::InvalidData);
+
+        // the header alone, and a cut off block
+        assert_eq!(import_error(&car[..61]), io::ErrorKind::InvalidData);
+        assert_eq!(import_error(&car[..80]), io::ErrorKind::UnexpectedEof);
+        assert_eq!(import_error(&car[..1]), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -26,4 +26,15 @@ where
     fn annotation(&self) -> Option<Self::Annotation> {
         Self::Annotation::combine(self.children())
     }
+
+    /// Returns an independent copy of the structure, sharing all its nodes
+    /// with the original
+    ///
+    /// Nodes, in memory or persisted, stay shared until either copy mutates
+    /// them, and then only the path to the mutated leaf is copied. A fork
+    /// can be changed and dropped without affecting the original, and
+    /// without restoring it from a snapshot.
+    fn fork(&self) -> Self {
+        self.clone()
+    }
 }
@@ -1,3 +1,4 @@
+use std::cmp;
 use std::io::{self, Read, Write};
 use std::marker::PhantomData;
 
@@ -6,6 +7,7 @@ use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 use crate::sink::Sink;
 use crate::source::Source;
+use crate::store::{Snapshot, Store};
 
 /// The main trait for content-adressable types, MUST assure a 1-1 mapping between
 /// values of the type and hash digests.
@@ -17,6 +19,53 @@ where
     fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()>;
     /// Restore the type from a `Source`
     fn restore(source: &mut Source<H>) -> io::Result<Self>;
+
+    /// Returns the digest the type would be persisted under, without
+    /// writing it, or any of its nodes, to a store
+    ///
+    /// Nodes in memory are hashed in place, persisted nodes are represented
+    /// by their digest. Comparing root hashes tells whether anything changed
+    /// before paying for a persist.
+    fn root_hash(&mut self) -> io::Result<H::Digest> {
+        let mut sink = Sink::hashing();
+        self.persist(&mut sink)?;
+        sink.fin()
+    }
+
+    /// Applies `f` to a working copy of the value, which replaces the value
+    /// only if `f` succeeds
+    ///
+    /// If `f` returns an error, or panics, the value is left as it was
+    /// before the transaction. Copies of structures share their nodes until
+    /// mutated, see `Compound::fork`, so only the paths changed by `f` are
+    /// copied.
+    fn transaction<F, R>(&mut self, f: F) -> io::Result<R>
+    where
+        F: FnOnce(&mut Self) -> io::Result<R>,
+    {
+        let mut working = self.clone();
+        let result = f(&mut working)?;
+        *self = working;
+        Ok(result)
+    }
+
+    /// Like `transaction`, persisting the working copy to `store` before
+    /// it replaces the value
+    ///
+    /// A failed persist also leaves the value as it was.
+    fn persisted_transaction<F, R>(
+        &mut self,
+        store: &Store<H>,
+        f: F,
+    ) -> io::Result<(Snapshot<Self, H>, R)>
+    where
+        F: FnOnce(&mut Self) -> io::Result<R>,
+    {
+        self.transaction(|working| {
+            let result = f(working)?;
+            Ok((store.persist(working)?, result))
+        })
+    }
 }
 
 impl<T: Content<H>, H: ByteHash> Content<H> for Option<T> {
@@ -36,7 +85,10 @@ impl<T: Content<H>, H: ByteHash> Content<H> for Option<T> {
         match byte[0] {
             0 => Ok(None),
             1 => Ok(Some(T::restore(source)?)),
-            _ => panic!("Invalid Option encoding"),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid Option encoding",
+            )),
         }
     }
 }
@@ -100,6 +152,11 @@ impl<H: ByteHash> Content<H> for String {
     }
 }
 
+// Upper bound on the elements allocated up front when restoring a `Vec`, the
+// encoded length is not trusted beyond it, and the vector grows as elements
+// are actually read.
+const MAX_PREALLOC: u64 = 1024;
+
 impl<H: ByteHash, T: Content<H>> Content<H> for Vec<T> {
     fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
         sink.write_u64::<BigEndian>(self.len() as u64)?;
@@ -111,7 +168,7 @@ impl<H: ByteHash, T: Content<H>> Content<H> for Vec<T> {
 
     fn restore(source: &mut Source<H>) -> io::Result<Self> {
         let len = source.read_u64::<BigEndian>()?;
-        let mut vec = Vec::with_capacity(len as usize);
+        let mut vec = Vec::with_capacity(cmp::min(len, MAX_PREALLOC) as usize);
         for _ in 0..len {
             vec.push(T::restore(source)?)
         }
@@ -159,3 +216,61 @@ where
         Ok((A::restore(source)?, B::restore(source)?))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::panic::{self, AssertUnwindSafe};
+
+    use crate::Blake2b;
+
+    // vectors are content over any hash, one is picked for the calls
+    fn transaction<F, R>(values: &mut Vec<u64>, f: F) -> io::Result<R>
+    where
+        F: FnOnce(&mut Vec<u64>) -> io::Result<R>,
+    {
+        Content::<Blake2b>::transaction(values, f)
+    }
+
+    #[test]
+    fn transaction_rolls_back() {
+        let mut values = vec![1u64, 2];
+        let pushed = transaction(&mut values, |working| {
+            working.push(3);
+            Ok(working.len())
+        });
+        assert_eq!(pushed.unwrap(), 3);
+        assert_eq!(values, [1, 2, 3]);
+
+        let failed = transaction(&mut values, |working| -> io::Result<()> {
+            working.clear();
+            Err(io::Error::other("rolled back"))
+        });
+        assert_eq!(failed.unwrap_err().to_string(), "rolled back");
+        assert_eq!(values, [1, 2, 3]);
+
+        let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+            transaction(&mut values, |working| -> io::Result<()> {
+                working.clear();
+                panic!("rolled back")
+            })
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(values, [1, 2, 3]);
+    }
+
+    #[test]
+    fn persisted_transaction() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let mut values = vec![1u64, 2];
+        let (snapshot, ()) = values
+            .persisted_transaction(&store, |working| {
+                working.push(3);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(store.restore(&snapshot).unwrap(), [1, 2, 3]);
+        assert_eq!(values, [1, 2, 3]);
+    }
+}
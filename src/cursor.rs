@@ -0,0 +1,202 @@
+use std::io;
+use std::{slice, vec};
+
+use bytehash::ByteHash;
+
+use crate::branch::Branch;
+use crate::compound::Compound;
+use crate::content::Content;
+use crate::handle::Handle;
+use crate::search::{First, Method};
+use crate::sink::Sink;
+use crate::source::Source;
+use crate::store::Snapshot;
+
+/// A resumable position into the leaves of a snapshot
+///
+/// The cursor only consists of the snapshot root and the offsets of the
+/// last leaf read, so it can be persisted, or handed to another process
+/// sharing the backend, and resumed later against the same snapshot.
+#[derive(Clone)]
+pub struct Cursor<C, H: ByteHash> {
+    snapshot: Snapshot<C, H>,
+    position: Position,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Position {
+    Start,
+    // child offsets of the last leaf read, from the root down
+    After(Vec<u32>),
+    End,
+}
+
+// Descends along a fixed list of child offsets
+struct Path<'a>(slice::Iter<'a, u32>);
+
+impl<C, H> Method<C, H> for Path<'_>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    fn select(&mut self, _: &[Handle<C, H>]) -> Option<usize> {
+        self.0.next().map(|ofs| *ofs as usize)
+    }
+}
+
+impl<C, H> Cursor<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    /// Creates a cursor positioned before the first leaf of the snapshot
+    pub fn new(snapshot: &Snapshot<C, H>) -> Self {
+        Cursor {
+            snapshot: snapshot.clone(),
+            position: Position::Start,
+        }
+    }
+
+    /// Returns the snapshot the cursor iterates over
+    pub fn snapshot(&self) -> &Snapshot<C, H> {
+        &self.snapshot
+    }
+
+    /// Returns true if all leaves of the snapshot have been read
+    pub fn is_exhausted(&self) -> bool {
+        self.position == Position::End
+    }
+
+    /// Reads up to `n` leaves following the cursor position, and moves the
+    /// cursor past them
+    pub fn read(&mut self, n: usize) -> io::Result<Vec<C::Leaf>> {
+        let root = self.snapshot.restore()?;
+        let mut branch = match self.position {
+            Position::Start => Branch::new(&root, &mut First)?,
+            Position::After(ref path) => {
+                let found = Branch::new(&root, &mut Path(path.iter()))?
+                    .filter(|branch| {
+                        branch.offsets().eq(path.iter().map(|o| *o as usize))
+                    })
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "Cursor does not match snapshot",
+                        )
+                    })?;
+                found.search(&mut First)?
+            }
+            Position::End => None,
+        };
+
+        let mut leaves = Vec::new();
+        while leaves.len() < n {
+            match branch {
+                Some(found) => {
+                    leaves.push((*found).clone());
+                    self.position = Position::After(
+                        found.offsets().map(|ofs| ofs as u32).collect(),
+                    );
+                    branch = if leaves.len() < n {
+                        found.search(&mut First)?
+                    } else {
+                        None
+                    };
+                }
+                None => {
+                    self.position = Position::End;
+                    break;
+                }
+            }
+        }
+        Ok(leaves)
+    }
+}
+
+/// An iterator over the leaves of a snapshot, owning its position
+///
+/// Since it reads from the backend, it holds no borrow on the structure the
+/// snapshot was taken of, which can keep being mutated meanwhile.
+pub struct SnapshotIter<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    cursor: Cursor<C, H>,
+    batch: vec::IntoIter<C::Leaf>,
+}
+
+// number of leaves read from the backend at a time
+const BATCH_SIZE: usize = 256;
+
+impl<C, H> SnapshotIter<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    /// Creates an iterator over the leaves of `snapshot`
+    pub fn new(snapshot: &Snapshot<C, H>) -> Self {
+        SnapshotIter {
+            cursor: Cursor::new(snapshot),
+            batch: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl<C, H> Iterator for SnapshotIter<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    type Item = io::Result<C::Leaf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(leaf) = self.batch.next() {
+            return Some(Ok(leaf));
+        }
+        if self.cursor.is_exhausted() {
+            return None;
+        }
+        match self.cursor.read(BATCH_SIZE) {
+            Ok(leaves) => {
+                self.batch = leaves.into_iter();
+                self.batch.next().map(Ok)
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+impl<C, H> Content<H> for Cursor<C, H>
+where
+    C: Content<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.snapshot.persist(sink)?;
+        match self.position {
+            Position::Start => 0u8.persist(sink),
+            Position::After(ref mut path) => {
+                1u8.persist(sink)?;
+                path.persist(sink)
+            }
+            Position::End => 2u8.persist(sink),
+        }
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let snapshot = Content::restore(source)?;
+        let position = match u8::restore(source)? {
+            0 => Position::Start,
+            1 => Position::After(Vec::restore(source)?),
+            2 => Position::End,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid Cursor encoding",
+                ))
+            }
+        };
+        Ok(Cursor { snapshot, position })
+    }
+}
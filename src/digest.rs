@@ -0,0 +1,168 @@
+use std::fmt::{self, Write};
+use std::marker::PhantomData;
+
+use serde::de::{self, DeserializeSeed, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+use crate::{ByteHash, Content, Snapshot, Store};
+
+/// Serializes `digest` as a hex string, or as bytes in binary formats
+pub fn serialize<D, S>(digest: &D, serializer: S) -> Result<S::Ok, S::Error>
+where
+    D: AsRef<[u8]>,
+    S: Serializer,
+{
+    let bytes = digest.as_ref();
+    if serializer.is_human_readable() {
+        let mut hex = String::with_capacity(bytes.len() * 2);
+        for byte in bytes {
+            write!(hex, "{:02x}", byte).expect("writing to a String");
+        }
+        serializer.serialize_str(&hex)
+    } else {
+        serializer.serialize_bytes(bytes)
+    }
+}
+
+/// Deserializes a digest written by `serialize`
+pub fn deserialize<'de, D, De>(deserializer: De) -> Result<D, De::Error>
+where
+    D: Default + AsMut<[u8]>,
+    De: Deserializer<'de>,
+{
+    let visitor = DigestVisitor(PhantomData);
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_str(visitor)
+    } else {
+        deserializer.deserialize_bytes(visitor)
+    }
+}
+
+struct DigestVisitor<D>(PhantomData<D>);
+
+impl<D: Default + AsMut<[u8]>> DigestVisitor<D> {
+    fn from_bytes<E: de::Error>(bytes: &[u8]) -> Result<D, E> {
+        let mut digest = D::default();
+        let len = digest.as_mut().len();
+        if bytes.len() != len {
+            return Err(E::invalid_length(bytes.len(), &"a digest"));
+        }
+        digest.as_mut().copy_from_slice(bytes);
+        Ok(digest)
+    }
+}
+
+impl<'de, D: Default + AsMut<[u8]>> Visitor<'de> for DigestVisitor<D> {
+    type Value = D;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a digest as a hex string or bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, hex: &str) -> Result<D, E> {
+        if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+            return Err(E::invalid_value(de::Unexpected::Str(hex), &self));
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| E::invalid_value(de::Unexpected::Str(hex), &self))?;
+        Self::from_bytes(&bytes)
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<D, E> {
+        Self::from_bytes(bytes)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<D, A::Error> {
+        let mut bytes = vec![];
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Self::from_bytes(&bytes)
+    }
+}
+
+/// Snapshots are serialized as their digest, see `digest::serialize`
+impl<T: Content<H>, H: ByteHash> Serialize for Snapshot<T, H> {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serialize(self.hash(), serializer)
+    }
+}
+
+/// Deserializes snapshots of a store, returned by `Store::snapshot_seed`
+///
+/// A snapshot refers to the store it is restored from, which can not be
+/// deserialized along with it.
+pub struct SnapshotSeed<'s, T, H: ByteHash> {
+    store: &'s Store<H>,
+    _marker: PhantomData<T>,
+}
+
+impl<'de, T, H> DeserializeSeed<'de> for SnapshotSeed<'_, T, H>
+where
+    T: Content<H>,
+    H: ByteHash,
+{
+    type Value = Snapshot<T, H>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Snapshot::new(deserialize(deserializer)?, self.store))
+    }
+}
+
+impl<H: ByteHash> Store<H> {
+    /// Returns a seed deserializing snapshots of this store
+    ///
+    /// The digest is not checked against the store, a snapshot of a value
+    /// missing from it fails to restore with a `NotFound` error.
+    pub fn snapshot_seed<T: Content<H>>(&self) -> SnapshotSeed<'_, T, H> {
+        SnapshotSeed {
+            store: self,
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::tests::root_hash;
+    use crate::Blake2b;
+
+    #[test]
+    fn snapshot_round_trip() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let snapshot = store.persist(&mut String::from("kelvin")).unwrap();
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let hex = root_hash(&mut String::from("kelvin"));
+        assert_eq!(json, format!("\"{}\"", hex));
+
+        let mut deserializer = serde_json::Deserializer::from_str(&json);
+        let restored = store
+            .snapshot_seed::<String>()
+            .deserialize(&mut deserializer)
+            .unwrap();
+        assert_eq!(store.restore(&restored).unwrap(), "kelvin");
+    }
+
+    #[test]
+    fn invalid_digests() {
+        let parse = |json: &str| -> Result<[u8; 4], _> {
+            deserialize(&mut serde_json::Deserializer::from_str(json))
+        };
+        assert_eq!(parse("\"00ff10aa\"").unwrap(), [0, 255, 16, 170]);
+        assert!(parse("\"00ff10\"").is_err());
+        assert!(parse("\"00ff10zz\"").is_err());
+        assert!(parse("\"00ff10a\"").is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::io::{self, Write};
+
+use bytehash::ByteHash;
+
+use crate::compound::Compound;
+use crate::dump::hex;
+use crate::handle::HandleRef;
+use crate::{Snapshot, Store};
+
+// hex digits of the digest shown in node labels
+const DIGEST_PREFIX: usize = 8;
+
+impl<H: ByteHash> Store<H> {
+    /// Writes the tree of `snapshot` to `writer` as a Graphviz DOT graph
+    ///
+    /// Nodes are labeled with the start of their digest and their
+    /// annotation, leaves with their `Debug` rendering. Nodes are identified
+    /// by their digest, so a subtree shared within the tree is drawn once,
+    /// with an edge from each of its parents.
+    pub fn to_dot<C, W>(
+        &self,
+        snapshot: &Snapshot<C, H>,
+        mut writer: W,
+    ) -> io::Result<()>
+    where
+        C: Compound<H>,
+        C::Leaf: Debug,
+        C::Annotation: Debug,
+        W: Write,
+    {
+        let root = self.restore(snapshot)?;
+        let digest = hex(snapshot.hash().as_ref());
+        let mut drawn = HashSet::new();
+        drawn.insert(digest.clone());
+
+        writeln!(writer, "digraph kelvin {{")?;
+        writeln!(writer, "    node [shape=box];")?;
+        let annotation = root.annotation();
+        node(&mut writer, &digest, &root, annotation.as_ref(), &mut drawn)?;
+        writeln!(writer, "}}")?;
+        writer.flush()
+    }
+}
+
+fn node<C, H, W>(
+    writer: &mut W,
+    id: &str,
+    compound: &C,
+    annotation: Option<&C::Annotation>,
+    drawn: &mut HashSet<String>,
+) -> io::Result<()>
+where
+    C: Compound<H>,
+    C::Leaf: Debug,
+    C::Annotation: Debug,
+    H: ByteHash,
+    W: Write,
+{
+    let mut label = format!("{}\nnode", &id[..DIGEST_PREFIX.min(id.len())]);
+    if let Some(annotation) = annotation {
+        label.push_str(&format!("\n{:?}", annotation));
+    }
+    writeln!(writer, "    \"{}\" [label=\"{}\"];", id, escape(&label))?;
+
+    for (i, handle) in compound.children().iter().enumerate() {
+        match handle.inner()? {
+            HandleRef::None => (),
+            HandleRef::Leaf(leaf) => {
+                let leaf_id = format!("{}/{}", id, i);
+                let label = format!("leaf\n{:?}", leaf);
+                writeln!(
+                    writer,
+                    "    \"{}\" [label=\"{}\", shape=ellipse];",
+                    leaf_id,
+                    escape(&label)
+                )?;
+                writeln!(writer, "    \"{}\" -> \"{}\";", id, leaf_id)?;
+            }
+            HandleRef::Node(child) => {
+                // nodes not persisted yet can not be shared
+                let child_id = match handle.snapshot() {
+                    Some(snapshot) => hex(snapshot.hash().as_ref()),
+                    None => format!("{}/{}", id, i),
+                };
+                writeln!(writer, "    \"{}\" -> \"{}\";", id, child_id)?;
+                if drawn.insert(child_id.clone()) {
+                    let annotation = handle.annotation();
+                    node(
+                        writer,
+                        &child_id,
+                        &*child,
+                        annotation.as_deref(),
+                        drawn,
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// escapes `s` for a quoted DOT string, keeping line breaks
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::annotations::Cardinality;
+    use crate::{Blake2b, Content, Handle, Sink, Source};
+
+    #[derive(Clone, Default)]
+    struct Tree(Vec<Handle<Self, Blake2b>>);
+
+    impl Content<Blake2b> for Tree {
+        fn persist(&mut self, sink: &mut Sink<Blake2b>) -> io::Result<()> {
+            self.0.persist(sink)
+        }
+
+        fn restore(source: &mut Source<Blake2b>) -> io::Result<Self> {
+            Ok(Tree(Vec::restore(source)?))
+        }
+    }
+
+    impl Compound<Blake2b> for Tree {
+        type Leaf = u64;
+        type Annotation = Cardinality<u64>;
+
+        fn children(&self) -> &[Handle<Self, Blake2b>] {
+            &self.0
+        }
+
+        fn children_mut(&mut self) -> &mut [Handle<Self, Blake2b>] {
+            &mut self.0
+        }
+    }
+
+    #[test]
+    fn shared_subtree() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let shared = Tree(vec![Handle::new_leaf(1)]);
+        let mut tree = Tree(vec![
+            Handle::new_node(shared.clone()),
+            Handle::new_empty(),
+            Handle::new_node(shared),
+        ]);
+        let snapshot = store.persist(&mut tree).unwrap();
+        let root = hex(snapshot.hash());
+        let child = hex(tree.0[0].snapshot().unwrap().hash());
+
+        let mut dot = vec![];
+        store.to_dot(&snapshot, &mut dot).unwrap();
+        let expected = format!(
+            "digraph kelvin {{\n    \
+             node [shape=box];\n    \
+             \"{root}\" [label=\"{}\\nnode\\nCardinality(2)\"];\n    \
+             \"{root}\" -> \"{child}\";\n    \
+             \"{child}\" [label=\"{}\\nnode\\nCardinality(1)\"];\n    \
+             \"{child}/0\" [label=\"leaf\\n1\", shape=ellipse];\n    \
+             \"{child}\" -> \"{child}/0\";\n    \
+             \"{root}\" -> \"{child}\";\n\
+             }}\n",
+            &root[..8],
+            &child[..8],
+            root = root,
+            child = child,
+        );
+        assert_eq!(String::from_utf8(dot).unwrap(), expected);
+    }
+}
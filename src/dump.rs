@@ -0,0 +1,289 @@
+use std::fmt::Debug;
+use std::io::{self, Write};
+
+use bytehash::ByteHash;
+
+use crate::compound::Compound;
+use crate::handle::{Handle, HandleRef};
+use crate::hex::Hex;
+use crate::{Snapshot, Store};
+
+/// Output format of `Store::dump`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// JSON, with digests as hex strings
+    Json,
+    /// CBOR, with digests as byte strings
+    Cbor,
+}
+
+impl<H: ByteHash> Store<H> {
+    /// Writes the tree of `snapshot` to `writer`, for debugging
+    ///
+    /// Every node is written as a map of its `digest` and its `children`, a
+    /// child being `null` if empty, a map with the `leaf` as rendered by its
+    /// `Debug` implementation, or a node. Every node is restored, so dumping
+    /// large trees is slow.
+    pub fn dump<C, W>(
+        &self,
+        snapshot: &Snapshot<C, H>,
+        mut writer: W,
+        format: Format,
+    ) -> io::Result<()>
+    where
+        C: Compound<H>,
+        C::Leaf: Debug,
+        W: Write,
+    {
+        let root = self.restore(snapshot)?;
+        let mut dump = Dump {
+            writer: &mut writer,
+            format,
+        };
+        dump.node(Some(snapshot.hash().as_ref()), &root)?;
+        writer.flush()
+    }
+}
+
+struct Dump<W> {
+    writer: W,
+    format: Format,
+}
+
+impl<W: Write> Dump<W> {
+    fn node<C, H>(&mut self, digest: Option<&[u8]>, node: &C) -> io::Result<()>
+    where
+        C: Compound<H>,
+        C::Leaf: Debug,
+        H: ByteHash,
+    {
+        let children = node.children();
+        match self.format {
+            Format::Json => {
+                self.writer.write_all(b"{\"digest\":")?;
+                match digest {
+                    Some(digest) => self.json_str(&hex(digest))?,
+                    None => self.writer.write_all(b"null")?,
+                }
+                self.writer.write_all(b",\"children\":[")?;
+                for (i, child) in children.iter().enumerate() {
+                    if i > 0 {
+                        self.writer.write_all(b",")?;
+                    }
+                    self.child(child)?;
+                }
+                self.writer.write_all(b"]}")
+            }
+            Format::Cbor => {
+                self.cbor_head(MAP, 2)?;
+                self.cbor_str("digest")?;
+                match digest {
+                    Some(digest) => {
+                        self.cbor_head(BYTES, digest.len() as u64)?;
+                        self.writer.write_all(digest)?;
+                    }
+                    None => self.writer.write_all(&[NULL])?,
+                }
+                self.cbor_str("children")?;
+                self.cbor_head(ARRAY, children.len() as u64)?;
+                for child in children {
+                    self.child(child)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn child<C, H>(&mut self, handle: &Handle<C, H>) -> io::Result<()>
+    where
+        C: Compound<H>,
+        C::Leaf: Debug,
+        H: ByteHash,
+    {
+        match handle.inner()? {
+            HandleRef::None => match self.format {
+                Format::Json => self.writer.write_all(b"null"),
+                Format::Cbor => self.writer.write_all(&[NULL]),
+            },
+            HandleRef::Leaf(leaf) => {
+                let leaf = format!("{:?}", leaf);
+                match self.format {
+                    Format::Json => {
+                        self.writer.write_all(b"{\"leaf\":")?;
+                        self.json_str(&leaf)?;
+                        self.writer.write_all(b"}")
+                    }
+                    Format::Cbor => {
+                        self.cbor_head(MAP, 1)?;
+                        self.cbor_str("leaf")?;
+                        self.cbor_str(&leaf)
+                    }
+                }
+            }
+            HandleRef::Node(node) => {
+                let digest = handle.snapshot().map(|s| s.hash().as_ref());
+                self.node(digest, &*node)
+            }
+        }
+    }
+
+    fn json_str(&mut self, s: &str) -> io::Result<()> {
+        write_json_str(&mut self.writer, s)
+    }
+
+    fn cbor_head(&mut self, major: u8, len: u64) -> io::Result<()> {
+        write_cbor_head(&mut self.writer, major, len)
+    }
+
+    fn cbor_str(&mut self, s: &str) -> io::Result<()> {
+        self.cbor_head(TEXT, s.len() as u64)?;
+        self.writer.write_all(s.as_bytes())
+    }
+}
+
+// Writes `s` as a JSON string
+pub(crate) fn write_json_str<W: Write>(
+    writer: &mut W,
+    s: &str,
+) -> io::Result<()> {
+    writer.write_all(b"\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => writer.write_all(b"\\\"")?,
+            '\\' => writer.write_all(b"\\\\")?,
+            c if (c as u32) < 0x20 => write!(writer, "\\u{:04x}", c as u32)?,
+            c => write!(writer, "{}", c)?,
+        }
+    }
+    writer.write_all(b"\"")
+}
+
+// CBOR major types, and the simple value null
+pub(crate) const UINT: u8 = 0;
+pub(crate) const BYTES: u8 = 2 << 5;
+pub(crate) const TEXT: u8 = 3 << 5;
+pub(crate) const ARRAY: u8 = 4 << 5;
+pub(crate) const MAP: u8 = 5 << 5;
+pub(crate) const TAG: u8 = 6 << 5;
+const NULL: u8 = 0xf6;
+
+// Writes the initial bytes of a CBOR item of major type `major`, with the
+// length or value `len`
+pub(crate) fn write_cbor_head<W: Write>(
+    writer: &mut W,
+    major: u8,
+    len: u64,
+) -> io::Result<()> {
+    match len {
+        0..=23 => writer.write_all(&[major | len as u8]),
+        24..=0xff => writer.write_all(&[major | 24, len as u8]),
+        0x100..=0xffff => {
+            writer.write_all(&[major | 25])?;
+            writer.write_all(&(len as u16).to_be_bytes())
+        }
+        0x1_0000..=0xffff_ffff => {
+            writer.write_all(&[major | 26])?;
+            writer.write_all(&(len as u32).to_be_bytes())
+        }
+        _ => {
+            writer.write_all(&[major | 27])?;
+            writer.write_all(&len.to_be_bytes())
+        }
+    }
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn parse_hex<H: ByteHash>(s: &str) -> Option<H::Digest> {
+    s.parse::<Hex<H::Digest>>().ok().map(|hex| hex.0)
+}
+
+// The first bytes of `digest` in hex, enough to tell digests apart in traces
+#[cfg(feature = "tracing")]
+pub(crate) fn prefix(digest: &[u8]) -> String {
+    hex(&digest[..digest.len().min(4)])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::annotations::Cardinality;
+    use crate::{Blake2b, Content, Sink, Source};
+
+    #[derive(Clone, Default)]
+    struct Tree(Vec<Handle<Self, Blake2b>>);
+
+    impl Content<Blake2b> for Tree {
+        fn persist(&mut self, sink: &mut Sink<Blake2b>) -> io::Result<()> {
+            self.0.persist(sink)
+        }
+
+        fn restore(source: &mut Source<Blake2b>) -> io::Result<Self> {
+            Ok(Tree(Vec::restore(source)?))
+        }
+    }
+
+    impl Compound<Blake2b> for Tree {
+        type Leaf = String;
+        type Annotation = Cardinality<u64>;
+
+        fn children(&self) -> &[Handle<Self, Blake2b>] {
+            &self.0
+        }
+
+        fn children_mut(&mut self) -> &mut [Handle<Self, Blake2b>] {
+            &mut self.0
+        }
+    }
+
+    fn leaf(s: &str) -> Handle<Tree, Blake2b> {
+        Handle::new_leaf(s.into())
+    }
+
+    #[test]
+    fn json() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let child = Tree(vec![leaf("a\"b"), Handle::new_empty()]);
+        let mut tree = Tree(vec![leaf("c"), Handle::new_node(child)]);
+        let snapshot = store.persist(&mut tree).unwrap();
+        let child = tree.0[1].snapshot().unwrap().hash();
+
+        let mut json = vec![];
+        store.dump(&snapshot, &mut json, Format::Json).unwrap();
+        let expected = format!(
+            "{{\"digest\":\"{}\",\"children\":[{{\"leaf\":\"\\\"c\\\"\"}},\
+             {{\"digest\":\"{}\",\"children\":[\
+             {{\"leaf\":\"\\\"a\\\\\\\"b\\\"\"}},null]}}]}}",
+            hex(snapshot.hash()),
+            hex(child)
+        );
+        assert_eq!(String::from_utf8(json.clone()).unwrap(), expected);
+
+        let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value["children"][1]["children"][0]["leaf"], "\"a\\\"b\"");
+    }
+
+    #[test]
+    fn cbor() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let mut tree = Tree(vec![Handle::new_empty(), leaf("c")]);
+        let snapshot = store.persist(&mut tree).unwrap();
+
+        let mut cbor = vec![];
+        store.dump(&snapshot, &mut cbor, Format::Cbor).unwrap();
+        let mut expected = vec![0xa2, 0x66];
+        expected.extend_from_slice(b"digest");
+        expected.extend_from_slice(&[0x58, 32]);
+        expected.extend_from_slice(snapshot.hash());
+        expected.push(0x68);
+        expected.extend_from_slice(b"children");
+        expected.extend_from_slice(&[0x82, 0xf6, 0xa1, 0x64]);
+        expected.extend_from_slice(b"leaf");
+        expected.push(0x63);
+        expected.extend_from_slice(b"\"c\"");
+        assert_eq!(cbor, expected);
+    }
+}
@@ -0,0 +1,139 @@
+use std::io::{self, Write};
+
+use crate::dump::write_json_str;
+use crate::map::KVPair;
+
+/// Output format of `KeyValIterable::export`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Comma-separated values, as in RFC 4180, starting with a `key,value`
+    /// header line
+    Csv,
+    /// One JSON object of a `key` and a `value` string per line
+    JsonLines,
+}
+
+// Writes the `leaves` of a map in `format`, returning their number
+pub(crate) fn export<'a, K, V, L, I, W, FK, FV>(
+    leaves: I,
+    mut writer: W,
+    format: ExportFormat,
+    mut key_fmt: FK,
+    mut value_fmt: FV,
+) -> io::Result<u64>
+where
+    L: KVPair<K, V> + 'a,
+    I: Iterator<Item = io::Result<&'a L>>,
+    W: Write,
+    FK: FnMut(&K) -> String,
+    FV: FnMut(&V) -> String,
+{
+    if format == ExportFormat::Csv {
+        writer.write_all(b"key,value\r\n")?;
+    }
+    let mut exported = 0;
+    for leaf in leaves {
+        let leaf = leaf?;
+        let (k, v) = (key_fmt(leaf.key()), value_fmt(leaf.val()));
+        match format {
+            ExportFormat::Csv => {
+                write_csv_field(&mut writer, &k)?;
+                writer.write_all(b",")?;
+                write_csv_field(&mut writer, &v)?;
+                writer.write_all(b"\r\n")?;
+            }
+            ExportFormat::JsonLines => {
+                writer.write_all(b"{\"key\":")?;
+                write_json_str(&mut writer, &k)?;
+                writer.write_all(b",\"value\":")?;
+                write_json_str(&mut writer, &v)?;
+                writer.write_all(b"}\n")?;
+            }
+        }
+        exported += 1;
+    }
+    writer.flush()?;
+    Ok(exported)
+}
+
+// Writes a field, quoted if it holds separators, quotes or line breaks
+fn write_csv_field<W: Write>(writer: &mut W, field: &str) -> io::Result<()> {
+    if field.contains(&[',', '"', '\r', '\n'][..]) {
+        write!(writer, "\"{}\"", field.replace('"', "\"\""))
+    } else {
+        writer.write_all(field.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::annotations::Cardinality;
+    use crate::{
+        Blake2b, Compound, Content, Handle, KeyValIterable, Sink, Source, Store,
+    };
+
+    #[derive(Clone, Default)]
+    struct Names(Vec<Handle<Self, Blake2b>>);
+
+    impl Content<Blake2b> for Names {
+        fn persist(&mut self, sink: &mut Sink<Blake2b>) -> io::Result<()> {
+            self.0.persist(sink)
+        }
+
+        fn restore(source: &mut Source<Blake2b>) -> io::Result<Self> {
+            Ok(Names(Vec::restore(source)?))
+        }
+    }
+
+    impl Compound<Blake2b> for Names {
+        type Leaf = (u32, String);
+        type Annotation = Cardinality<u64>;
+
+        fn children(&self) -> &[Handle<Self, Blake2b>] {
+            &self.0
+        }
+
+        fn children_mut(&mut self) -> &mut [Handle<Self, Blake2b>] {
+            &mut self.0
+        }
+    }
+
+    fn names() -> Names {
+        let leaf = |k, v: &str| Handle::new_leaf((k, v.to_string()));
+        let node = Names(vec![leaf(2, "two, \"2\""), leaf(3, "three")]);
+        Names(vec![leaf(1, "one\n"), Handle::new_node(node)])
+    }
+
+    fn exported(names: &Names, format: ExportFormat) -> String {
+        let mut out = vec![];
+        let n = names
+            .export(&mut out, format, |k| k.to_string(), |v| v.clone())
+            .unwrap();
+        assert_eq!(n, 3);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn csv() {
+        assert_eq!(
+            exported(&names(), ExportFormat::Csv),
+            "key,value\r\n1,\"one\n\"\r\n2,\"two, \"\"2\"\"\"\r\n3,three\r\n"
+        );
+    }
+
+    #[test]
+    fn json_lines() {
+        // from a snapshot, restoring the node on the way
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let snapshot = store.persist(&mut names()).unwrap();
+        let names = store.restore(&snapshot).unwrap();
+        assert_eq!(
+            exported(&names, ExportFormat::JsonLines),
+            "{\"key\":\"1\",\"value\":\"one\\u000a\"}\n\
+             {\"key\":\"2\",\"value\":\"two, \\\"2\\\"\"}\n\
+             {\"key\":\"3\",\"value\":\"three\"}\n"
+        );
+    }
+}
@@ -3,6 +3,7 @@ use std::io::{self, Read, Write};
 use std::mem;
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
+use std::sync::OnceLock;
 
 use bytehash::ByteHash;
 use cache::Cached;
@@ -21,7 +22,10 @@ where
     H: ByteHash,
 {
     Leaf(C::Leaf),
-    Node(Box<C>, C::Annotation),
+    // The annotation of a mutable node is recomputed lazily, after being
+    // invalidated on mutable access. Clones of the handle share the node
+    // until either of them mutates it, which copies it first.
+    Node(Arc<C>, OnceLock<Option<C::Annotation>>),
     SharedNode(Arc<C>, C::Annotation),
     Persisted(Snapshot<C, H>, C::Annotation),
     None,
@@ -38,7 +42,7 @@ where
             HandleOwned::Leaf(l) => HandleInner::Leaf(l),
             HandleOwned::Node(c) => {
                 let ann = c.annotation().expect("Invalid empty owned node");
-                HandleInner::Node(Box::new(c), ann)
+                HandleInner::Node(Arc::new(c), OnceLock::from(Some(ann)))
             }
         }
     }
@@ -78,14 +82,14 @@ where
 
 /// Struct that wraps a user-facing representation of the mutable handle
 /// while still keeping track of a raw pointer to the original handle.
-/// This is to ensure that the associated annotations are automatically
-/// updated on mutable access.
+/// This is to ensure that the associated annotations are invalidated on
+/// mutable access, and recomputed the next time they are read.
 pub struct HandleMutWrap<'a, C, H>
 where
     C: Compound<H>,
     H: ByteHash,
 {
-    annotation: Option<&'a mut C::Annotation>,
+    annotation: Option<&'a mut OnceLock<Option<C::Annotation>>>,
     inner: HandleMut<'a, C, H>,
 }
 
@@ -117,12 +121,8 @@ where
     H: ByteHash,
 {
     fn drop(&mut self) {
-        if let (Some(ref mut ann), HandleMut::Node(ref node)) =
-            (self.annotation.as_mut(), &self.inner)
-        {
-            if let Some(annotation) = node.annotation() {
-                ***ann = annotation
-            }
+        if let Some(ref mut ann) = self.annotation {
+            ann.take();
         }
     }
 }
@@ -165,6 +165,11 @@ where
     }
 }
 
+// Takes a node out of its `Arc`, copying it if it is still shared
+fn unshare<C: Clone>(node: Arc<C>) -> C {
+    Arc::try_unwrap(node).unwrap_or_else(|node| (*node).clone())
+}
+
 impl<C, H> Clone for HandleInner<C, H>
 where
     C: Compound<H>,
@@ -197,16 +202,30 @@ where
             HandleInner::None => sink.write_all(&[0]),
             HandleInner::Leaf(ref mut leaf) => {
                 sink.write_all(&[1])?;
-                leaf.persist(sink)
+                let start = sink.len();
+                leaf.persist(sink)?;
+                sink.leaf_written(start);
+                Ok(())
             }
             HandleInner::Persisted(ref digest, ref mut ann) => {
                 sink.write_all(&[2])?;
                 sink.write_all((**digest).as_ref())?;
                 ann.persist(sink)
             }
-            HandleInner::Node(ref mut node, ref ann) => {
-                let snap = sink.store().persist(&mut **node)?;
-                self.0 = HandleInner::Persisted(snap, ann.clone());
+            // hashing leaves the node in memory, as if it was persisted
+            HandleInner::Node(ref node, ref ann) if sink.is_hashing() => {
+                match ann.get_or_init(|| node.annotation()).clone() {
+                    Some(mut ann) => {
+                        let digest = Sink::hash_child(&**node)?;
+                        sink.write_all(&[2])?;
+                        sink.write_all(digest.as_ref())?;
+                        ann.persist(sink)
+                    }
+                    None => sink.write_all(&[0]),
+                }
+            }
+            HandleInner::Node(_, _) => {
+                self.persist_node(|node| sink.persist_child(node))?;
                 self.persist(sink)
             }
             HandleInner::SharedNode(_, _) => unimplemented!(),
@@ -247,9 +266,9 @@ where
 
     /// Constructs a new node Handle
     pub fn new_node<I: Into<Box<C>>>(n: I) -> Handle<C, H> {
-        let node = n.into();
+        let node: Arc<C> = Arc::from(n.into());
         let ann = node.annotation().expect("Empty node handles are invalid");
-        Handle(HandleInner::Node(node, ann))
+        Handle(HandleInner::Node(node, OnceLock::from(Some(ann))))
     }
 
     /// Constructs a new empty node Handle
@@ -269,12 +288,39 @@ where
     /// Converts handle into leaf, panics on mismatching type
     pub fn into_node(self) -> C {
         if let HandleInner::Node(n, _) = self.0 {
-            *n
+            unshare(n)
         } else {
             panic!("Not a node")
         }
     }
 
+    /// Returns a mutable reference to the contained in-memory node, if any
+    #[cfg(feature = "rayon")]
+    pub(crate) fn node_mut(&mut self) -> Option<&mut C> {
+        match self.0 {
+            HandleInner::Node(ref mut node, _) => Some(Arc::make_mut(node)),
+            _ => None,
+        }
+    }
+
+    // Writes an in-memory node to the store, replacing it with its snapshot
+    pub(crate) fn persist_node<F>(&mut self, persist: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut C) -> io::Result<Snapshot<C, H>>,
+    {
+        if let HandleInner::Node(ref mut node, ref mut ann) = self.0 {
+            match ann.take().unwrap_or_else(|| node.annotation()) {
+                Some(ann) => {
+                    let snap = persist(Arc::make_mut(node))?;
+                    self.0 = HandleInner::Persisted(snap, ann);
+                }
+                // nodes emptied by mutation are persisted as empty
+                None => self.0 = HandleInner::None,
+            }
+        }
+        Ok(())
+    }
+
     /// Returns a reference to contained leaf, if any
     pub(crate) fn leaf(&self) -> Option<&C::Leaf> {
         match self.0 {
@@ -309,18 +355,31 @@ where
     }
 
     /// Return the annotation for the handle, unless None
+    ///
+    /// The annotations of nodes mutated since last read are recomputed.
     pub fn annotation(&self) -> Option<Cow<C::Annotation>> {
         match self.0 {
             HandleInner::None => None,
             HandleInner::Leaf(ref l) => {
                 Some(Cow::Owned(C::Annotation::from(l)))
             }
-            HandleInner::Node(_, ref ann)
-            | HandleInner::SharedNode(_, ref ann)
+            HandleInner::Node(ref node, ref ann) => ann
+                .get_or_init(|| node.annotation())
+                .as_ref()
+                .map(Cow::Borrowed),
+            HandleInner::SharedNode(_, ref ann)
             | HandleInner::Persisted(_, ref ann) => Some(Cow::Borrowed(ann)),
         }
     }
 
+    /// Returns the snapshot of the handle, if it points at a persisted node
+    pub fn snapshot(&self) -> Option<&Snapshot<C, H>> {
+        match self.0 {
+            HandleInner::Persisted(ref snap, _) => Some(snap),
+            _ => None,
+        }
+    }
+
     /// Returns a HandleRef from the Handle
     pub fn inner(&self) -> io::Result<HandleRef<C, H>> {
         Ok(match self.0 {
@@ -344,7 +403,7 @@ where
         match mem::replace(&mut self.0, with.into()) {
             HandleInner::None => HandleOwned::None,
             HandleInner::Leaf(l) => HandleOwned::Leaf(l),
-            HandleInner::Node(c, _) => HandleOwned::Node(*c),
+            HandleInner::Node(c, _) => HandleOwned::Node(unshare(c)),
             _ => unreachable!("Mutable handles cannot be persisted or shared"),
         }
     }
@@ -362,14 +421,17 @@ where
             },
             HandleInner::Node(ref mut n, ref mut ann) => HandleMutWrap {
                 annotation: Some(ann),
-                inner: HandleMut::Node(&mut **n),
+                inner: HandleMut::Node(Arc::make_mut(n)),
             },
             HandleInner::Persisted(_, _) => {
                 if let HandleInner::Persisted(snap, ann) =
                     mem::replace(&mut self.0, HandleInner::None)
                 {
                     let restored = snap.restore()?;
-                    *self = Handle(HandleInner::Node(Box::new(restored), ann));
+                    *self = Handle(HandleInner::Node(
+                        Arc::new(restored),
+                        OnceLock::from(Some(ann)),
+                    ));
                     return self.inner_mut();
                 } else {
                     unreachable!()
@@ -382,10 +444,14 @@ where
     #[doc(hidden)]
     pub fn make_shared(&mut self) {
         if let HandleInner::Node(_, _) = self.0 {
-            if let HandleInner::Node(node, ann) =
+            if let HandleInner::Node(node, mut ann) =
                 mem::replace(&mut self.0, HandleInner::None)
             {
-                self.0 = HandleInner::SharedNode(Arc::new(*node), ann)
+                if let Some(ann) =
+                    ann.take().unwrap_or_else(|| node.annotation())
+                {
+                    self.0 = HandleInner::SharedNode(node, ann)
+                }
             } else {
                 unreachable!()
             }
@@ -421,3 +487,199 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cell::Cell;
+
+    use crate::annotations::Associative;
+    use crate::{Blake2b, Store};
+
+    thread_local! {
+        static COMBINED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    // Annotation counting how often it is combined
+    #[derive(Clone)]
+    struct Counted(u64);
+
+    impl From<&u64> for Counted {
+        fn from(l: &u64) -> Self {
+            Counted(*l)
+        }
+    }
+
+    impl Associative for Counted {
+        fn op(&mut self, b: &Self) {
+            COMBINED.with(|c| c.set(c.get() + 1));
+            self.0 += b.0
+        }
+    }
+
+    impl<H: ByteHash> Content<H> for Counted {
+        fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+            self.0.persist(sink)
+        }
+
+        fn restore(source: &mut Source<H>) -> io::Result<Self> {
+            Ok(Counted(u64::restore(source)?))
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Tree(Vec<Handle<Self, Blake2b>>);
+
+    impl Content<Blake2b> for Tree {
+        fn persist(&mut self, sink: &mut Sink<Blake2b>) -> io::Result<()> {
+            self.0.persist(sink)
+        }
+
+        fn restore(source: &mut Source<Blake2b>) -> io::Result<Self> {
+            Ok(Tree(Vec::restore(source)?))
+        }
+    }
+
+    impl Compound<Blake2b> for Tree {
+        type Leaf = u64;
+        type Annotation = Counted;
+
+        fn children(&self) -> &[Handle<Self, Blake2b>] {
+            &self.0
+        }
+
+        fn children_mut(&mut self) -> &mut [Handle<Self, Blake2b>] {
+            &mut self.0
+        }
+    }
+
+    #[test]
+    fn lazy_annotations() {
+        let leaves = Tree((0..4).map(Handle::new_leaf).collect());
+        let mut handle =
+            Handle::<Tree, Blake2b>::new_node(Tree(vec![Handle::new_node(
+                leaves,
+            )]));
+
+        COMBINED.with(|c| c.set(0));
+        for _ in 0..100 {
+            if let HandleMut::Node(outer) = &mut *handle.inner_mut().unwrap() {
+                if let HandleMut::Node(inner) =
+                    &mut *outer.0[0].inner_mut().unwrap()
+                {
+                    if let HandleMut::Leaf(leaf) =
+                        &mut *inner.0[0].inner_mut().unwrap()
+                    {
+                        **leaf += 1;
+                    }
+                }
+            }
+        }
+        // nothing is recomputed until read
+        assert_eq!(COMBINED.with(Cell::get), 0);
+
+        assert_eq!(handle.annotation().unwrap().0, 106);
+        let combined = COMBINED.with(Cell::get);
+        assert!(combined > 0);
+
+        // and only once
+        assert_eq!(handle.annotation().unwrap().0, 106);
+        assert_eq!(COMBINED.with(Cell::get), combined);
+    }
+
+    fn node(handle: &Handle<Tree, Blake2b>) -> &Arc<Tree> {
+        match handle.0 {
+            HandleInner::Node(ref node, _) => node,
+            _ => panic!("Not a node"),
+        }
+    }
+
+    #[test]
+    fn copy_on_write() {
+        let leaves = |n| Tree((0..n).map(Handle::new_leaf).collect());
+        let original = Handle::<Tree, Blake2b>::new_node(Tree(vec![
+            Handle::new_node(leaves(2)),
+            Handle::new_node(leaves(4)),
+        ]));
+
+        let mut copy = original.clone();
+        assert!(Arc::ptr_eq(node(&original), node(&copy)));
+
+        if let HandleMut::Node(outer) = &mut *copy.inner_mut().unwrap() {
+            if let HandleMut::Node(inner) =
+                &mut *outer.0[0].inner_mut().unwrap()
+            {
+                if let HandleMut::Leaf(leaf) =
+                    &mut *inner.0[0].inner_mut().unwrap()
+                {
+                    **leaf += 10;
+                }
+            }
+        }
+
+        // only the path to the changed leaf was copied
+        assert!(!Arc::ptr_eq(node(&original), node(&copy)));
+        assert!(!Arc::ptr_eq(
+            node(&node(&original).0[0]),
+            node(&node(&copy).0[0])
+        ));
+        assert!(Arc::ptr_eq(
+            node(&node(&original).0[1]),
+            node(&node(&copy).0[1])
+        ));
+
+        assert_eq!(original.annotation().unwrap().0, 7);
+        assert_eq!(copy.annotation().unwrap().0, 17);
+    }
+
+    #[test]
+    fn root_hash() {
+        let leaves = |n| Tree((0..n).map(Handle::new_leaf).collect());
+        let mut tree = Tree(vec![
+            Handle::new_node(leaves(2)),
+            Handle::new_leaf(7),
+            Handle::new_node(Tree(vec![Handle::new_node(leaves(4))])),
+        ]);
+        let copy = tree.clone();
+        let hash = tree.root_hash().unwrap();
+
+        // the nodes stay in memory, still shared with the copy
+        assert!(Arc::ptr_eq(node(&tree.0[0]), node(&copy.0[0])));
+        assert!(Arc::ptr_eq(node(&tree.0[2]), node(&copy.0[2])));
+        node(&node(&tree.0[2]).0[0]);
+
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let snapshot = store.persist(&mut tree.clone()).unwrap();
+        assert_eq!(*snapshot.hash(), hash);
+
+        // persisted nodes are not restored, their digest is known
+        let mut restored = store.restore(&snapshot).unwrap();
+        assert_eq!(restored.root_hash().unwrap(), hash);
+        assert_eq!(store.stats().gets, 1);
+    }
+
+    #[test]
+    fn fork() {
+        let leaves = |n| Tree((0..n).map(Handle::new_leaf).collect());
+        let original = Tree(vec![
+            Handle::new_node(leaves(2)),
+            Handle::new_node(leaves(4)),
+        ]);
+
+        let mut fork = original.fork();
+        assert!(Arc::ptr_eq(node(&original.0[0]), node(&fork.0[0])));
+
+        if let HandleMut::Node(inner) = &mut *fork.0[1].inner_mut().unwrap() {
+            if let HandleMut::Leaf(leaf) = &mut *inner.0[0].inner_mut().unwrap()
+            {
+                **leaf += 10;
+            }
+        }
+
+        assert!(Arc::ptr_eq(node(&original.0[0]), node(&fork.0[0])));
+        assert!(!Arc::ptr_eq(node(&original.0[1]), node(&fork.0[1])));
+        assert_eq!(original.annotation().unwrap().0, 7);
+        assert_eq!(fork.annotation().unwrap().0, 17);
+    }
+}
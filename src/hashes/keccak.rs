@@ -0,0 +1,141 @@
+use std::io::{self, Write};
+
+use bytehash::{ByteHash, State};
+use sha3::Digest;
+
+/// Keccak-256, the hash used by Ethereum
+///
+/// Differs from `Sha3_256` only in the padding, which predates the SHA-3
+/// standard.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keccak256;
+
+/// SHA3-256, as standardized in FIPS 202
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sha3_256;
+
+/// The state of a Keccak-256 or SHA3-256 hash being computed, by the `sha3`
+/// crate
+pub enum KeccakState {
+    /// Keccak-256, with the original padding
+    Keccak(sha3::Keccak256),
+    /// SHA3-256
+    Sha3(sha3::Sha3_256),
+}
+
+impl ByteHash for Keccak256 {
+    type Digest = [u8; 32];
+    type State = KeccakState;
+
+    fn state() -> KeccakState {
+        KeccakState::Keccak(sha3::Keccak256::new())
+    }
+}
+
+impl ByteHash for Sha3_256 {
+    type Digest = [u8; 32];
+    type State = KeccakState;
+
+    fn state() -> KeccakState {
+        KeccakState::Sha3(sha3::Sha3_256::new())
+    }
+}
+
+impl State<[u8; 32]> for KeccakState {
+    fn fin(self) -> [u8; 32] {
+        match self {
+            KeccakState::Keccak(state) => state.finalize().into(),
+            KeccakState::Sha3(state) => state.finalize().into(),
+        }
+    }
+}
+
+impl Write for KeccakState {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        match self {
+            KeccakState::Keccak(state) => state.update(bytes),
+            KeccakState::Sha3(state) => state.update(bytes),
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::dump::hex;
+
+    fn hash<H: ByteHash>(bytes: &[u8]) -> String {
+        let mut state = H::state();
+        state.write_all(bytes).unwrap();
+        hex(state.fin().as_ref())
+    }
+
+    #[test]
+    fn keccak_vectors() {
+        assert_eq!(
+            hash::<Keccak256>(b""),
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+        );
+        assert_eq!(
+            hash::<Keccak256>(b"abc"),
+            "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45"
+        );
+    }
+
+    #[test]
+    fn sha3_vectors() {
+        assert_eq!(
+            hash::<Sha3_256>(b""),
+            "a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a"
+        );
+        assert_eq!(
+            hash::<Sha3_256>(b"abc"),
+            "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532"
+        );
+        assert_eq!(
+            hash::<Sha3_256>(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            ),
+            "41c0dba2a9d6240849100376a8235e2c82e1b9998a999e21db32dd97496d3376"
+        );
+        assert_eq!(
+            hash::<Sha3_256>(&[b'a'; 1_000_000]),
+            "5c8875ae474a3634ba4fd55ec85bffd661f32aca75c6d699d0cdcb6c115891c1"
+        );
+    }
+
+    #[test]
+    fn block_boundaries() {
+        // padding fitting in one byte, and starting a block of its own, for
+        // the 136 bytes absorbed per permutation
+        let vectors = [
+            (
+                135,
+                "e9cd15a85e4b32b79132637b22775876cc597d8be1d19d8daf42a46d2233f0f7",
+            ),
+            (
+                136,
+                "520624732be0bc2eff4ca18c833d85f5783325cb37fc3d8ec1d385aa9a6fc7f8",
+            ),
+            (
+                137,
+                "171c6f03b55a0672ff99dbca84110b6bc3b8f75e1b31b5979cca100ba74f520a",
+            ),
+        ];
+        for (len, expected) in vectors.iter() {
+            let bytes = vec![0xa5; *len];
+            let mut state = Keccak256::state();
+            for chunk in bytes.chunks(7) {
+                state.write_all(chunk).unwrap();
+            }
+            assert_eq!(hex(&state.fin()), *expected);
+            assert_eq!(hash::<Keccak256>(&bytes), *expected);
+        }
+    }
+}
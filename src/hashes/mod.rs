@@ -0,0 +1,11 @@
+#[cfg(feature = "sha3")]
+mod keccak;
+#[cfg(feature = "sha2")]
+mod sha256;
+mod truncated;
+
+#[cfg(feature = "sha3")]
+pub use self::keccak::{Keccak256, KeccakState, Sha3_256};
+#[cfg(feature = "sha2")]
+pub use self::sha256::{Sha256, Sha256State};
+pub use self::truncated::{Truncated, TruncatedState};
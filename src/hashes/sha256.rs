@@ -0,0 +1,89 @@
+use std::io::{self, Write};
+
+use bytehash::{ByteHash, State};
+use sha2::Digest;
+
+/// SHA-256, as standardized in FIPS 180-4, computed by the `sha2` crate
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sha256;
+
+/// The state of a SHA-256 hash being computed
+pub struct Sha256State(sha2::Sha256);
+
+impl ByteHash for Sha256 {
+    type Digest = [u8; 32];
+    type State = Sha256State;
+
+    fn state() -> Sha256State {
+        Sha256State(sha2::Sha256::new())
+    }
+}
+
+impl State<[u8; 32]> for Sha256State {
+    fn fin(self) -> [u8; 32] {
+        self.0.finalize().into()
+    }
+}
+
+impl Write for Sha256State {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.update(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::dump::hex;
+
+    fn sha256(bytes: &[u8]) -> String {
+        let mut state = Sha256::state();
+        state.write_all(bytes).unwrap();
+        hex(&state.fin())
+    }
+
+    #[test]
+    fn vectors() {
+        assert_eq!(
+            sha256(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            sha256(&[b'a'; 1_000_000]),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    #[test]
+    fn store() {
+        let store = crate::Store::<Sha256>::volatile().unwrap();
+        let snapshot = store.persist(&mut 7u64).unwrap();
+        assert_eq!(hex(snapshot.hash()), sha256(&7u64.to_be_bytes()));
+        assert_eq!(store.restore(&snapshot).unwrap(), 7);
+    }
+
+    #[test]
+    fn split_writes() {
+        let bytes: Vec<u8> = (0..200).collect();
+        for split in 0..bytes.len() {
+            let mut state = Sha256::state();
+            state.write_all(&bytes[..split]).unwrap();
+            state.write_all(&bytes[split..]).unwrap();
+            assert_eq!(hex(&state.fin()), sha256(&bytes));
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use std::any::type_name;
+use std::fmt;
+use std::hash::Hash;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::mem;
+
+use bytehash::{ByteHash, State};
+
+/// A hasher keeping the first bytes of the digests of `H`, as many as fit in
+/// the digest type `D`
+///
+/// Used as the hasher of a store, as in `Store<Truncated<Blake2b, [u8;
+/// 20]>>`, it shrinks every handle and proof level by the bytes cut off.
+/// A digest of `n` bytes only resists collisions up to about `2^(4n)`
+/// hashes, so the width should be chosen with that in mind.
+///
+/// Truncated digests are prefixes of the full ones, so values hash the same
+/// with any width. `D` can not be longer than the digests of `H`, hashing
+/// panics otherwise.
+pub struct Truncated<H, D>(PhantomData<(H, D)>);
+
+/// The state of a truncated hash being computed
+pub struct TruncatedState<H: ByteHash, D> {
+    state: H::State,
+    _marker: PhantomData<D>,
+}
+
+impl<H, D> Clone for Truncated<H, D> {
+    fn clone(&self) -> Self {
+        Truncated(PhantomData)
+    }
+}
+
+impl<H, D> PartialEq for Truncated<H, D> {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl<H, D> Eq for Truncated<H, D> {}
+
+impl<H, D> fmt::Debug for Truncated<H, D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Truncated<{}, {} bytes>",
+            type_name::<H>(),
+            mem::size_of::<D>()
+        )
+    }
+}
+
+impl<H, D> ByteHash for Truncated<H, D>
+where
+    H: ByteHash,
+    D: 'static
+        + AsRef<[u8]>
+        + AsMut<[u8]>
+        + Copy
+        + Eq
+        + Hash
+        + Default
+        + fmt::Debug
+        + Send,
+{
+    type Digest = D;
+    type State = TruncatedState<H, D>;
+
+    fn state() -> Self::State {
+        TruncatedState {
+            state: H::state(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<H, D> State<D> for TruncatedState<H, D>
+where
+    H: ByteHash,
+    D: AsMut<[u8]> + Default,
+{
+    fn fin(self) -> D {
+        let full = self.state.fin();
+        let mut digest = D::default();
+        let len = digest.as_mut().len();
+        assert!(
+            len <= full.as_ref().len(),
+            "Truncated digest longer than the digest of its hasher"
+        );
+        digest.as_mut().copy_from_slice(&full.as_ref()[..len]);
+        digest
+    }
+}
+
+impl<H: ByteHash, D> Write for TruncatedState<H, D> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.state.write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.state.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::annotations::Cardinality;
+    use crate::{
+        Blake2b, Compound, Content, Handle, LeafIterable, Sink, Source, Store,
+    };
+
+    type Blake2b160 = Truncated<Blake2b, [u8; 20]>;
+
+    #[derive(Clone, Default)]
+    struct Tree(Vec<Handle<Self, Blake2b160>>);
+
+    impl Content<Blake2b160> for Tree {
+        fn persist(&mut self, sink: &mut Sink<Blake2b160>) -> io::Result<()> {
+            self.0.persist(sink)
+        }
+
+        fn restore(source: &mut Source<Blake2b160>) -> io::Result<Self> {
+            Ok(Tree(Vec::restore(source)?))
+        }
+    }
+
+    impl Compound<Blake2b160> for Tree {
+        type Leaf = u64;
+        type Annotation = Cardinality<u64>;
+
+        fn children(&self) -> &[Handle<Self, Blake2b160>] {
+            &self.0
+        }
+
+        fn children_mut(&mut self) -> &mut [Handle<Self, Blake2b160>] {
+            &mut self.0
+        }
+    }
+
+    fn hash<H: ByteHash>(bytes: &[u8]) -> H::Digest {
+        let mut state = H::state();
+        state.write_all(bytes).unwrap();
+        state.fin()
+    }
+
+    #[test]
+    fn prefix() {
+        let full = hash::<Blake2b>(b"kelvin");
+        assert_eq!(hash::<Blake2b160>(b"kelvin"), full[..20]);
+        assert_eq!(
+            format!("{:?}", Truncated::<Blake2b, [u8; 20]>(PhantomData)),
+            "Truncated<bytehash::blake2b::Blake2b, 20 bytes>"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "longer than the digest")]
+    fn too_long() {
+        hash::<Truncated<Truncated<Blake2b, [u8; 16]>, [u8; 20]>>(b"");
+    }
+
+    #[test]
+    fn store() {
+        let store = Store::<Blake2b160>::volatile().unwrap();
+        let leaves = |range: std::ops::Range<u64>| {
+            Handle::new_node(Tree(range.map(Handle::new_leaf).collect()))
+        };
+        let mut tree = Tree(vec![leaves(0..3), leaves(3..5)]);
+        let snapshot = store.persist(&mut tree).unwrap();
+
+        let restored = store.restore(&snapshot).unwrap();
+        let all: Vec<u64> = restored.iter().map(|l| *l.unwrap()).collect();
+        assert_eq!(all, vec![0, 1, 2, 3, 4]);
+        assert_eq!(snapshot.hash().len(), 20);
+    }
+}
@@ -0,0 +1,116 @@
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use bytehash::ByteHash;
+
+use crate::content::Content;
+use crate::store::{Snapshot, Store};
+
+/// A digest, displayed and parsed as a lowercase hex string
+///
+/// Root hashes can thereby be printed in logs or on the command line, and
+/// parsed back with `str::parse`. The alternate form of `{:#x}` prefixes
+/// the digits with `0x`, which parsing accepts as well.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Hex<D>(pub D);
+
+impl<D: AsRef<[u8]>> fmt::LowerHex for Hex<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            f.write_str("0x")?;
+        }
+        for byte in self.0.as_ref() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl<D: AsRef<[u8]>> fmt::Display for Hex<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(self, f)
+    }
+}
+
+impl<D: Default + AsMut<[u8]>> FromStr for Hex<D> {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid hex digest {:?}", s),
+            )
+        };
+        let digits = s.strip_prefix("0x").unwrap_or(s);
+        let mut digest = D::default();
+        let bytes = digest.as_mut();
+        if digits.len() != bytes.len() * 2 || !digits.is_ascii() {
+            return Err(invalid());
+        }
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16)
+                .map_err(|_| invalid())?;
+        }
+        Ok(Hex(digest))
+    }
+}
+
+/// Snapshots are displayed as the hex of their digest
+impl<T: Content<H>, H: ByteHash> fmt::LowerHex for Snapshot<T, H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&Hex(self.hash().as_ref()), f)
+    }
+}
+
+/// Snapshots are displayed as the hex of their digest
+impl<T: Content<H>, H: ByteHash> fmt::Display for Snapshot<T, H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(self, f)
+    }
+}
+
+impl<H: ByteHash> Store<H> {
+    /// Returns a snapshot of the digest given in hex, as displayed by
+    /// snapshots and `Hex`
+    ///
+    /// The digest is not checked against the store, a snapshot of a value
+    /// missing from it fails to restore with a `NotFound` error.
+    pub fn parse_snapshot<T: Content<H>>(
+        &self,
+        hex: &str,
+    ) -> io::Result<Snapshot<T, H>> {
+        let digest: Hex<H::Digest> = hex.parse()?;
+        Ok(self.snapshot(digest.0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::Blake2b;
+
+    #[test]
+    fn display_parse() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let snapshot = store.persist(&mut 7u64).unwrap();
+
+        let hex = snapshot.to_string();
+        assert_eq!(hex.len(), 64);
+        assert_eq!(format!("{:x}", snapshot), hex);
+        assert_eq!(format!("{:#x}", snapshot), format!("0x{}", hex));
+        assert_eq!(Hex(snapshot.hash()).to_string(), hex);
+
+        let parsed: Snapshot<u64, _> = store.parse_snapshot(&hex).unwrap();
+        assert_eq!(store.restore(&parsed).unwrap(), 7);
+        let prefixed: Hex<[u8; 32]> = format!("0x{}", hex).parse().unwrap();
+        assert_eq!(prefixed.0, *snapshot.hash());
+
+        let bad_digit = format!("g{}", &hex[1..]);
+        for invalid in &["", "0x", "7", &hex[2..], &bad_digit[..]] {
+            assert!(invalid.parse::<Hex<[u8; 32]>>().is_err());
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::borrow::Borrow;
+use std::iter::FromIterator;
+use std::ops::Deref;
+
+use bytehash::ByteHash;
+
+use crate::content::Content;
+use crate::map::{KVPair, Map, MapMut, ValPath, ValPathMut};
+
+// Only restoring persisted nodes fails, and in-memory maps have none
+const INFALLIBLE: &str = "in-memory map failed";
+
+/// A map kept purely in memory, with infallible operations
+///
+/// Reading and writing a map only fails when restoring persisted nodes
+/// from a store. A map that is created empty, or from an iterator, and is
+/// never persisted has all of its nodes in memory, so its operations are
+/// given without the `io::Result`. `into_inner` returns the map, to be
+/// persisted with explicit errors from then on.
+#[derive(Clone, Default)]
+pub struct InMemory<C>(C);
+
+impl<C: Default> InMemory<C> {
+    /// Creates a new, empty in-memory map
+    pub fn new() -> Self {
+        InMemory(C::default())
+    }
+}
+
+impl<C> InMemory<C> {
+    /// Returns the map, with its fallible operations
+    pub fn into_inner(self) -> C {
+        self.0
+    }
+
+    /// Returns a reference to the value of `k`, if any
+    pub fn get<'a, O, K, V, H>(
+        &'a self,
+        k: &'a O,
+    ) -> Option<ValPath<'a, K, V, C, H>>
+    where
+        C: Map<'a, O, K, V, H>,
+        C::Leaf: KVPair<K, V>,
+        K: Content<H> + Eq + Borrow<O> + 'a,
+        O: Eq + ?Sized + 'a,
+        H: ByteHash,
+    {
+        self.0.get(k).expect(INFALLIBLE)
+    }
+
+    /// Returns a mutable reference to the value of `k`, if any
+    pub fn get_mut<'a, O, K, V, H>(
+        &'a mut self,
+        k: &'a O,
+    ) -> Option<ValPathMut<'a, K, V, C, H>>
+    where
+        C: Map<'a, O, K, V, H>,
+        C::Leaf: KVPair<K, V>,
+        K: Content<H> + Eq + Borrow<O> + 'a,
+        O: Eq + ?Sized + 'a,
+        H: ByteHash,
+    {
+        self.0.get_mut(k).expect(INFALLIBLE)
+    }
+
+    /// Returns true if the map contains a value for `k`
+    pub fn contains_key<'a, O, K, V, H>(&'a self, k: &'a O) -> bool
+    where
+        C: Map<'a, O, K, V, H>,
+        C::Leaf: KVPair<K, V>,
+        K: Content<H> + Eq + Borrow<O> + 'a,
+        O: Eq + ?Sized + 'a,
+        H: ByteHash,
+    {
+        self.0.contains_key(k).expect(INFALLIBLE)
+    }
+
+    /// Insert a key-value pair, returning the replaced value, if any
+    pub fn insert<K, V, H>(&mut self, k: K, v: V) -> Option<V>
+    where
+        C: MapMut<K, V, H>,
+        C::Leaf: KVPair<K, V>,
+        K: Content<H> + Eq,
+        H: ByteHash,
+    {
+        self.0.insert(k, v).expect(INFALLIBLE)
+    }
+
+    /// Remove the value with key `k`, returning it
+    pub fn remove<K, V, H>(&mut self, k: &K) -> Option<V>
+    where
+        C: MapMut<K, V, H>,
+        C::Leaf: KVPair<K, V>,
+        K: Content<H> + Eq,
+        H: ByteHash,
+    {
+        self.0.remove(k).expect(INFALLIBLE)
+    }
+}
+
+/// The map, for its read-only operations not covered by `InMemory`
+impl<C> Deref for InMemory<C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        &self.0
+    }
+}
+
+impl<C: FromIterator<T>, T> FromIterator<T> for InMemory<C> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        InMemory(C::from_iter(iter))
+    }
+}
@@ -2,20 +2,51 @@ use std::{io, mem};
 
 use crate::branch::{Branch, BranchMut};
 use crate::compound::Compound;
-use crate::search::{First, Method};
+use crate::cursor::SnapshotIter;
+use crate::handle::{Handle, HandleOwned};
+use crate::search::{Decision, First, Method, Select, Where};
+use crate::store::Store;
 use crate::ByteHash;
 
 /// An iterator over the leaves of a Compound type
-pub enum LeafIter<'a, C, M, H>
+///
+/// Iterators over all leaves, as returned by `LeafIterable::iter`, are
+/// double-ended, with the two ends stopping once they meet.
+pub struct LeafIter<'a, C, M, H>
 where
     C: Compound<H>,
     H: ByteHash,
 {
-    Initial(&'a C, M),
-    Branch(Branch<'a, C, H>, M),
+    node: &'a C,
+    method: M,
+    front: Cursor<Branch<'a, C, H>>,
+    back: Cursor<Branch<'a, C, H>>,
+    // offsets to the node holding the front leaf, siblings were prefetched
+    parent: Vec<usize>,
+}
+
+enum Cursor<B> {
+    Initial,
+    At(B),
     Exhausted,
 }
 
+impl<'a, C, M, H> LeafIter<'a, C, M, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    pub(crate) fn new(node: &'a C, method: M) -> Self {
+        LeafIter {
+            node,
+            method,
+            front: Cursor::Initial,
+            back: Cursor::Initial,
+            parent: vec![],
+        }
+    }
+}
+
 impl<'a, C, M, H> Iterator for LeafIter<'a, C, M, H>
 where
     C: Compound<H>,
@@ -25,37 +56,71 @@ where
     type Item = io::Result<&'a C::Leaf>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let old = mem::replace(self, LeafIter::Exhausted);
-        match old {
-            LeafIter::Initial(node, mut method) => {
-                match Branch::new(node, &mut method) {
-                    Ok(Some(branch)) => {
-                        *self = LeafIter::Branch(branch, method);
-                    }
-                    Ok(None) => {
-                        *self = LeafIter::Exhausted;
-                    }
-                    Err(e) => return Some(Err(e)),
-                }
+        let found = match mem::replace(&mut self.front, Cursor::Exhausted) {
+            Cursor::Initial => Branch::new(self.node, &mut self.method),
+            Cursor::At(branch) => branch.search(&mut self.method),
+            Cursor::Exhausted => return None,
+        };
+
+        if let Ok(Some(ref branch)) = found {
+            // entered a new node, fetch the following ones ahead of time
+            if !branch.parent_offsets().eq(self.parent.iter().cloned()) {
+                self.parent.clear();
+                self.parent.extend(branch.parent_offsets());
+                branch.prefetch_siblings();
             }
-            LeafIter::Branch(branch, mut method) => {
-                match branch.search(&mut method) {
-                    Ok(Some(branch)) => {
-                        *self = LeafIter::Branch(branch, method)
-                    }
-                    Err(e) => return Some(Err(e)),
-                    _ => (),
+        }
+
+        match found {
+            Ok(Some(branch)) => match self.back {
+                // met the leaves already yielded from the back
+                Cursor::At(ref back) if !branch.precedes(back) => {
+                    self.back = Cursor::Exhausted
                 }
-            }
-            LeafIter::Exhausted => return None,
+                _ => self.front = Cursor::At(branch),
+            },
+            Ok(None) => self.back = Cursor::Exhausted,
+            Err(e) => return Some(Err(e)),
         }
 
         let self_unsafe: &'a mut Self = unsafe { mem::transmute(self) };
 
-        match self_unsafe {
-            LeafIter::Branch(ref branch, _) => Some(Ok(&*branch)),
-            LeafIter::Initial(_, _) => unreachable!(),
-            LeafIter::Exhausted => None,
+        match self_unsafe.front {
+            Cursor::At(ref branch) => Some(Ok(&**branch)),
+            _ => None,
+        }
+    }
+}
+
+impl<'a, C, H> DoubleEndedIterator for LeafIter<'a, C, First, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let found = match mem::replace(&mut self.back, Cursor::Exhausted) {
+            Cursor::Initial => Branch::last(self.node),
+            Cursor::At(branch) => branch.prev(),
+            Cursor::Exhausted => return None,
+        };
+
+        match found {
+            Ok(Some(branch)) => match self.front {
+                // met the leaves already yielded from the front
+                Cursor::At(ref front) if !front.precedes(&branch) => {
+                    self.front = Cursor::Exhausted
+                }
+                _ => self.back = Cursor::At(branch),
+            },
+            Ok(None) => self.front = Cursor::Exhausted,
+            Err(e) => return Some(Err(e)),
+        }
+
+        let self_unsafe: &'a mut Self = unsafe { mem::transmute(self) };
+
+        match self_unsafe.back {
+            Cursor::At(ref branch) => Some(Ok(&**branch)),
+            _ => None,
         }
     }
 }
@@ -114,6 +179,110 @@ where
     }
 }
 
+/// An iterator moving the leaves out of a Compound
+pub struct Drain<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    // handles left to visit, in reverse order
+    stack: Vec<Handle<C, H>>,
+}
+
+impl<C, H> Drain<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    fn push_children(&mut self, mut node: C) {
+        for child in node.children_mut().iter_mut().rev() {
+            self.stack.push(mem::take(child))
+        }
+    }
+}
+
+impl<C, H> Iterator for Drain<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    type Item = io::Result<C::Leaf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(mut handle) = self.stack.pop() {
+            // make sure persisted nodes are restored
+            if let Err(e) = handle.inner_mut() {
+                return Some(Err(e));
+            }
+            match handle.replace(HandleOwned::None) {
+                HandleOwned::None => (),
+                HandleOwned::Leaf(l) => return Some(Ok(l)),
+                HandleOwned::Node(n) => self.push_children(n),
+            }
+        }
+        None
+    }
+}
+
+/// An iterator over the values of an iterator of results, stopping at the
+/// first error
+///
+/// The error is kept, to be checked with `error` or `finish` once the
+/// iteration is over.
+pub struct FailFast<I> {
+    iter: I,
+    error: Option<io::Error>,
+}
+
+impl<I> FailFast<I> {
+    /// Returns the error the iteration was stopped by, if any
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    /// Consumes the adapter, returning the error the iteration was stopped
+    /// by, if any
+    pub fn finish(self) -> io::Result<()> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<I, T> Iterator for FailFast<I>
+where
+    I: Iterator<Item = io::Result<T>>,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.error.is_some() {
+            return None;
+        }
+        match self.iter.next()? {
+            Ok(t) => Some(t),
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+/// Trait for treating iterators of results as iterators of plain values
+pub trait FailFastIterable<T>: Iterator<Item = io::Result<T>> + Sized {
+    /// Returns an adapter yielding the values, stopping at the first error
+    fn fail_fast(self) -> FailFast<Self> {
+        FailFast {
+            iter: self,
+            error: None,
+        }
+    }
+}
+
+impl<I, T> FailFastIterable<T> for I where I: Iterator<Item = io::Result<T>> {}
+
 /// Trait for iterating over the leaves of a Compuond
 pub trait LeafIterable<H>
 where
@@ -124,6 +293,31 @@ where
     fn iter(&self) -> LeafIter<Self, First, H>;
     /// Returns an iterator over the mutable leaves of the Compound
     fn iter_mut(&mut self) -> LeafIterMut<Self, First, H>;
+    /// Returns an iterator over the leaves found by repeatedly searching with
+    /// `method`
+    fn iter_by<M: Method<Self, H>>(
+        &self,
+        method: M,
+    ) -> LeafIter<'_, Self, M, H>;
+    /// Returns an iterator over the leaves whose annotations satisfy `f`,
+    /// skipping the subtrees whose annotations don't
+    fn iter_where<F>(&self, f: F) -> LeafIter<'_, Self, Where<F>, H>
+    where
+        F: FnMut(&Self::Annotation) -> bool;
+    /// Returns a branch to the first leaf reached by descending into the
+    /// subtrees `f` decides on, by their annotations
+    fn select<F>(&self, f: F) -> io::Result<Option<Branch<'_, Self, H>>>
+    where
+        F: FnMut(&Self::Annotation) -> Decision;
+    /// Returns an iterator moving out the leaves of the Compound, leaving it
+    /// empty
+    fn drain(&mut self) -> Drain<Self, H>;
+    /// Persists the Compound to `store`, and returns an iterator over the
+    /// leaves of the snapshot, holding no borrow on the Compound itself
+    fn iter_snapshot(
+        &mut self,
+        store: &Store<H>,
+    ) -> io::Result<SnapshotIter<Self, H>>;
 }
 
 impl<C, H> LeafIterable<H> for C
@@ -132,10 +326,73 @@ where
     H: ByteHash,
 {
     fn iter(&self) -> LeafIter<Self, First, H> {
-        LeafIter::Initial(self, First)
+        LeafIter::new(self, First)
     }
 
     fn iter_mut(&mut self) -> LeafIterMut<Self, First, H> {
         LeafIterMut::Initial(self, First)
     }
+
+    fn iter_by<M: Method<Self, H>>(
+        &self,
+        method: M,
+    ) -> LeafIter<'_, Self, M, H> {
+        LeafIter::new(self, method)
+    }
+
+    fn iter_where<F>(&self, f: F) -> LeafIter<'_, Self, Where<F>, H>
+    where
+        F: FnMut(&Self::Annotation) -> bool,
+    {
+        LeafIter::new(self, Where::new(f))
+    }
+
+    fn select<F>(&self, f: F) -> io::Result<Option<Branch<'_, Self, H>>>
+    where
+        F: FnMut(&Self::Annotation) -> Decision,
+    {
+        Branch::new(self, &mut Select::new(f))
+    }
+
+    fn drain(&mut self) -> Drain<Self, H> {
+        let mut drain = Drain { stack: vec![] };
+        drain.push_children(mem::take(self));
+        drain
+    }
+
+    fn iter_snapshot(
+        &mut self,
+        store: &Store<H>,
+    ) -> io::Result<SnapshotIter<Self, H>> {
+        Ok(SnapshotIter::new(&store.persist(self)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn results() -> impl Iterator<Item = io::Result<u32>> {
+        (0..10).map(|i| {
+            if i == 5 {
+                Err(io::Error::new(io::ErrorKind::Other, "broken"))
+            } else {
+                Ok(i)
+            }
+        })
+    }
+
+    #[test]
+    fn fail_fast() {
+        let mut iter = results().fail_fast();
+        let values: Vec<u32> = iter.by_ref().collect();
+        assert_eq!(values, vec![0, 1, 2, 3, 4]);
+        assert!(iter.next().is_none());
+        assert_eq!(iter.error().unwrap().to_string(), "broken");
+        assert!(iter.finish().is_err());
+
+        let mut iter = results().take(5).fail_fast();
+        assert_eq!(iter.by_ref().sum::<u32>(), 10);
+        assert!(iter.finish().is_ok());
+    }
 }
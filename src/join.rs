@@ -0,0 +1,179 @@
+use std::cmp::Ordering;
+use std::io;
+
+use bytehash::ByteHash;
+
+use crate::compound::Compound;
+use crate::handle::HandleRef;
+use crate::store::Snapshot;
+
+/// An entry of a join of two ordered structures
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Joined<K, V> {
+    /// The key is only present on the left side
+    Left(K, V),
+    /// The key is only present on the right side
+    Right(K, V),
+    /// The key is present on both sides, with the left and right values
+    Both(K, V, V),
+}
+
+// A leaf or a subtree yet to be visited
+enum Pending<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    Leaf(C::Leaf),
+    Node(Snapshot<C, H>),
+}
+
+// The pending leaves and subtrees of one side, in reverse order
+struct Side<C, H>(Vec<Pending<C, H>>)
+where
+    C: Compound<H>,
+    H: ByteHash;
+
+impl<C, H> Side<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    fn push_children(&mut self, node: &C) -> io::Result<()> {
+        for handle in node.children().iter().rev() {
+            if let Some(snapshot) = handle.snapshot() {
+                self.0.push(Pending::Node(snapshot.clone()));
+                continue;
+            }
+            match handle.inner()? {
+                HandleRef::Leaf(leaf) => {
+                    self.0.push(Pending::Leaf(leaf.clone()))
+                }
+                HandleRef::Node(node) => self.push_children(&node)?,
+                HandleRef::None => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn pop_leaf(&mut self) -> C::Leaf {
+        match self.0.pop() {
+            Some(Pending::Leaf(leaf)) => leaf,
+            _ => unreachable!("Leaf expected on top of the stack"),
+        }
+    }
+
+    // replaces the subtree on top of the stack with its children
+    fn expand(&mut self) -> io::Result<()> {
+        let node = match self.0.last() {
+            Some(Pending::Node(snapshot)) => snapshot.restore()?,
+            _ => return Ok(()),
+        };
+        self.0.pop();
+        self.push_children(&node)
+    }
+}
+
+/// An iterator joining the entries of two snapshots, in key order
+pub struct JoinIter<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    left: Side<C, H>,
+    right: Side<C, H>,
+}
+
+/// Walks two snapshots of structures ordered by key in lockstep, yielding
+/// the entries present on either side
+///
+/// Subtrees with the same digest on both sides are skipped without being
+/// restored, so the cost of the join is proportional to the parts of the
+/// snapshots that differ. Entries within such shared subtrees are not
+/// yielded.
+pub fn iter_joined<C, H, K, V>(
+    left: &Snapshot<C, H>,
+    right: &Snapshot<C, H>,
+) -> JoinIter<C, H>
+where
+    C: Compound<H, Leaf = (K, V)>,
+    H: ByteHash,
+    K: Ord,
+{
+    let (left, right) = if left.hash() == right.hash() {
+        (vec![], vec![])
+    } else {
+        (
+            vec![Pending::Node(left.clone())],
+            vec![Pending::Node(right.clone())],
+        )
+    };
+    JoinIter {
+        left: Side(left),
+        right: Side(right),
+    }
+}
+
+impl<C, H, K, V> JoinIter<C, H>
+where
+    C: Compound<H, Leaf = (K, V)>,
+    H: ByteHash,
+    K: Ord,
+{
+    fn step(&mut self) -> io::Result<Option<Joined<K, V>>> {
+        loop {
+            match (self.left.0.last(), self.right.0.last()) {
+                (None, None) => return Ok(None),
+                (Some(Pending::Node(a)), Some(Pending::Node(b))) => {
+                    if a.hash() == b.hash() {
+                        self.left.0.pop();
+                        self.right.0.pop();
+                    } else {
+                        self.left.expand()?;
+                        self.right.expand()?;
+                    }
+                }
+                (Some(Pending::Node(_)), _) => self.left.expand()?,
+                (_, Some(Pending::Node(_))) => self.right.expand()?,
+                (Some(Pending::Leaf((a, _))), Some(Pending::Leaf((b, _)))) => {
+                    return Ok(Some(match a.cmp(b) {
+                        Ordering::Less => {
+                            let (k, v) = self.left.pop_leaf();
+                            Joined::Left(k, v)
+                        }
+                        Ordering::Greater => {
+                            let (k, v) = self.right.pop_leaf();
+                            Joined::Right(k, v)
+                        }
+                        Ordering::Equal => {
+                            let (k, a) = self.left.pop_leaf();
+                            let (_, b) = self.right.pop_leaf();
+                            Joined::Both(k, a, b)
+                        }
+                    }));
+                }
+                (Some(Pending::Leaf(_)), None) => {
+                    let (k, v) = self.left.pop_leaf();
+                    return Ok(Some(Joined::Left(k, v)));
+                }
+                (None, Some(Pending::Leaf(_))) => {
+                    let (k, v) = self.right.pop_leaf();
+                    return Ok(Some(Joined::Right(k, v)));
+                }
+            }
+        }
+    }
+}
+
+impl<C, H, K, V> Iterator for JoinIter<C, H>
+where
+    C: Compound<H, Leaf = (K, V)>,
+    H: ByteHash,
+    K: Ord,
+{
+    type Item = io::Result<Joined<K, V>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.step().transpose()
+    }
+}
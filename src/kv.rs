@@ -0,0 +1,111 @@
+use std::io;
+
+use bytehash::ByteHash;
+
+use crate::map::{KVPair, Map, MapMut};
+use crate::{Proof, Snapshot, Store};
+
+type Bytes = Vec<u8>;
+
+/// A key-value store of byte strings, kept in a kelvin map
+///
+/// For libraries expecting a plain key-value store, keys and values are
+/// byte slices in, and owned bytes out. Changes are kept in memory until
+/// `commit` persists the map to the store, returning a snapshot of it that
+/// `open` can later reopen, and that proofs of entries are verified
+/// against.
+///
+/// Any map over `Vec<u8>` keys and values can back the store, such as a
+/// `BTree<Vec<u8>, Vec<u8>, H>` or a `HAMT<Vec<u8>, Vec<u8>, H>`.
+pub struct KvStore<C, H: ByteHash> {
+    store: Store<H>,
+    map: C,
+    committed: Option<H::Digest>,
+}
+
+impl<C, H> KvStore<C, H>
+where
+    C: MapMut<Bytes, Bytes, H>
+        + for<'a> Map<'a, [u8], Bytes, Bytes, H>
+        + Default,
+    C::Leaf: KVPair<Bytes, Bytes>,
+    H: ByteHash,
+{
+    /// Creates an empty key-value store, committing to `store`
+    pub fn new(store: Store<H>) -> Self {
+        KvStore {
+            store,
+            map: C::default(),
+            committed: None,
+        }
+    }
+
+    /// Opens the key-value store committed as `snapshot`
+    pub fn open(snapshot: &Snapshot<C, H>) -> io::Result<Self> {
+        let store = snapshot.store().clone();
+        Ok(KvStore {
+            map: store.restore(snapshot)?,
+            store,
+            committed: Some(*snapshot.hash()),
+        })
+    }
+
+    /// Returns the value of `key`, if any
+    pub fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let found = <C as Map<'_, [u8], Bytes, Bytes, H>>::get(&self.map, key)?;
+        Ok(found.map(|value| value.to_vec()))
+    }
+
+    /// Returns true if the store holds a value for `key`
+    pub fn contains(&self, key: &[u8]) -> io::Result<bool> {
+        <C as Map<'_, [u8], Bytes, Bytes, H>>::contains_key(&self.map, key)
+    }
+
+    /// Sets the value of `key` to `value`
+    pub fn set(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        self.map.insert(key.to_vec(), value.to_vec())?;
+        Ok(())
+    }
+
+    /// Removes the value of `key`, returning whether there was one
+    pub fn delete(&mut self, key: &[u8]) -> io::Result<bool> {
+        Ok(self.map.remove(&key.to_vec())?.is_some())
+    }
+
+    /// Persists the changes to the store, returning a snapshot of the map
+    pub fn commit(&mut self) -> io::Result<Snapshot<C, H>> {
+        let snapshot = self.store.persist(&mut self.map)?;
+        self.store.flush()?;
+        self.committed = Some(*snapshot.hash());
+        Ok(snapshot)
+    }
+
+    /// Returns the snapshot of the last `commit`, or of the one opened
+    pub fn committed(&self) -> Option<Snapshot<C, H>> {
+        self.committed
+            .map(|digest| Snapshot::new(digest, &self.store))
+    }
+
+    /// Returns a proof of the entry of `key` in the last commit, if any
+    ///
+    /// The proof is verified against the hash of `committed`, uncommitted
+    /// changes are not proven.
+    pub fn prove(&self, key: &[u8]) -> io::Result<Option<Proof<C, H>>> {
+        let snapshot = match self.committed() {
+            Some(snapshot) => snapshot,
+            None => return Ok(None),
+        };
+        let map = self.store.restore(&snapshot)?;
+        if !<C as Map<'_, [u8], Bytes, Bytes, H>>::contains_key(&map, key)? {
+            return Ok(None);
+        }
+        let mut search =
+            <C as Map<'_, [u8], Bytes, Bytes, H>>::KeySearch::from(key);
+        Proof::new(&snapshot, &mut search)
+    }
+
+    /// Returns the map backing the store
+    pub fn map(&self) -> &C {
+        &self.map
+    }
+}
@@ -1,50 +1,147 @@
 //! Kelvin, a Merkle-tree tooklit and backend
 #![warn(missing_docs)]
 
+// Times the rest of the enclosing block under `label`, when profiling, and
+// enters a `tracing` span of the same name with the given fields, when
+// tracing. Fields are only evaluated when tracing.
+macro_rules! span {
+    ($label:expr $(, $field:ident = $value:expr)*) => {
+        #[cfg(feature = "profiling")]
+        let _span = crate::profiling::Guard::new($label);
+        #[cfg(feature = "tracing")]
+        let _entered = tracing::debug_span!(
+            $label
+            $(, $field = tracing::field::display($value))*
+        )
+        .entered();
+    };
+}
+
 /// Test helpers
 pub mod tests;
 
 /// A collection of tree annotations
 pub mod annotations;
 
+mod any_map;
 mod backend;
 mod branch;
+mod car;
 mod compound;
 mod content;
+mod cursor;
 mod debug_draw;
+/// Serde support for digests, serialized as hex strings in human-readable
+/// formats
+///
+/// Usable on digest fields with `#[serde(with = "kelvin::digest")]`.
+#[cfg(feature = "serde")]
+pub mod digest;
+mod dot;
+mod dump;
+mod export;
 mod handle;
+mod hashes;
+mod hex;
+mod in_memory;
 mod iter;
+mod join;
+mod kv;
 mod map;
+mod merge;
+/// Prometheus metrics of the stats of stores
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "rayon")]
+mod par;
+/// The commonly used traits and types, for glob importing
+///
+/// `use kelvin::prelude::*;` brings in what implementing and using
+/// structures takes: the traits of contents, compounds, maps and their
+/// iterators, along with stores and snapshots over the default hash.
+pub mod prelude;
+/// Timings of persist, restore, hashing and backend IO
+#[cfg(feature = "profiling")]
+pub mod profiling;
+mod proof;
+mod replicate;
+#[cfg(feature = "filesystem")]
 mod root;
 mod search;
+#[cfg(feature = "server")]
+mod server;
 mod sink;
 mod source;
+mod stats;
 mod store;
+mod tar;
 mod unsafe_branch;
+mod validate;
 
 pub use crate::annotations::{
     Annotation, Associative, Combine, VoidAnnotation,
 };
-pub use crate::backend::Backend;
+pub use crate::any_map::{tag, AnyMap, Erased, Tagged};
+#[cfg(feature = "filesystem")]
+pub use crate::backend::DiskBackend;
+pub use crate::backend::{
+    Backend, BlockFetcher, FetchingBackend, MemBackend, PutResult, Reader,
+    VerifyingBackend,
+};
 pub use crate::branch::{Branch, BranchMut};
 pub use crate::compound::Compound;
 pub use crate::content::Content;
+pub use crate::cursor::{Cursor, SnapshotIter};
 pub use crate::debug_draw::DebugDraw;
+#[cfg(feature = "serde")]
+pub use crate::digest::SnapshotSeed;
+pub use crate::dump::Format;
+pub use crate::export::ExportFormat;
 pub use crate::handle::{
     Handle, HandleMut, HandleOwned, HandleRef, HandleType,
 };
-pub use crate::iter::LeafIterable;
+pub use crate::hex::Hex;
+pub use crate::in_memory::InMemory;
+pub use crate::iter::{
+    Drain, FailFast, FailFastIterable, LeafIter, LeafIterable,
+};
+pub use crate::join::{iter_joined, JoinIter, Joined};
+pub use crate::kv::KvStore;
 pub use crate::map::{
-    KeyValIterable, Map, ValPath, ValPathMut, ValRef, ValRefMut,
+    Entry, KeyValIterable, Map, MapMut, MapRemove, OccupiedEntry, VacantEntry,
+    ValPath, ValPathMut, ValRef, ValRefMut,
 };
+pub use crate::merge::merge3;
+#[cfg(feature = "rayon")]
+pub use crate::par::{ParLeafIter, ParLeafIterable, ParValIter};
+pub use crate::proof::{Proof, Proven};
+#[cfg(feature = "filesystem")]
 pub use crate::root::Root;
-pub use crate::search::Method;
+pub use crate::search::{
+    Decision, First, MaxKeySearch, Method, MinKeySearch, Select, Where,
+};
+#[cfg(feature = "server")]
+pub use crate::server::Server;
 pub use crate::sink::Sink;
 pub use crate::source::Source;
-pub use crate::store::{Shared, Snapshot, Store};
+pub use crate::stats::Stats;
+pub use crate::store::{Description, Shared, Snapshot, Store};
+pub use crate::validate::validate;
 
 // Re-export
 pub use bytehash::{Blake2b, ByteHash};
 
+#[cfg(feature = "sha3")]
+pub use crate::hashes::{Keccak256, KeccakState, Sha3_256};
+#[cfg(feature = "sha2")]
+pub use crate::hashes::{Sha256, Sha256State};
+pub use crate::hashes::{Truncated, TruncatedState};
+
 /// Persistant store using Blake2b
 pub type DefaultStore = Store<Blake2b>;
+
+/// Snapshot in a `DefaultStore`
+pub type DefaultSnapshot<T> = Snapshot<T, Blake2b>;
+
+/// Handle of a structure hashed with Blake2b
+pub type DefaultHandle<C> = Handle<C, Blake2b>;
@@ -1,6 +1,7 @@
 use std::borrow::Borrow;
-use std::io;
+use std::io::{self, Write};
 use std::marker::PhantomData;
+use std::mem;
 use std::ops::{Deref, DerefMut};
 
 use bytehash::ByteHash;
@@ -9,6 +10,8 @@ use owning_ref::{OwningRef, OwningRefMut, StableAddress};
 use crate::branch::{Branch, BranchMut};
 use crate::compound::Compound;
 use crate::content::Content;
+use crate::export::{self, ExportFormat};
+use crate::handle::HandleRef;
 use crate::iter::{LeafIter, LeafIterMut};
 use crate::search::{First, Method};
 
@@ -191,6 +194,11 @@ where
     H: ByteHash;
 
 /// Compound can be iterated over like a map
+///
+/// Leaves are encoded inline in the nodes holding them, without length
+/// prefixes, so persisted nodes are always restored whole: iterating over
+/// only the keys or only the values still decodes both halves of each leaf
+/// on the way.
 pub trait KeyValIterable<K, V, H>
 where
     Self: Compound<H>,
@@ -205,6 +213,25 @@ where
 
     /// Iterator over the keys of the map
     fn keys(&mut self) -> KeyIter<Self, K, V, First, H>;
+
+    /// Writes the entries of the map to `writer` in `format`, returning
+    /// their number
+    ///
+    /// Keys and values are rendered as strings by `key_fmt` and
+    /// `value_fmt`. Entries are written in order as the map is iterated, so
+    /// a map restored from a snapshot is streamed out without its subtrees
+    /// being loaded all at once.
+    fn export<W, FK, FV>(
+        &self,
+        writer: W,
+        format: ExportFormat,
+        key_fmt: FK,
+        value_fmt: FV,
+    ) -> io::Result<u64>
+    where
+        W: Write,
+        FK: FnMut(&K) -> String,
+        FV: FnMut(&V) -> String;
 }
 
 impl<C, K, V, H> KeyValIterable<K, V, H> for C
@@ -214,7 +241,7 @@ where
     H: ByteHash,
 {
     fn values(&self) -> ValIter<Self, K, V, First, H> {
-        ValIter(LeafIter::Initial(self, First), PhantomData)
+        ValIter(LeafIter::new(self, First), PhantomData)
     }
 
     fn values_mut(&mut self) -> ValIterMut<Self, K, V, First, H> {
@@ -222,7 +249,23 @@ where
     }
 
     fn keys(&mut self) -> KeyIter<Self, K, V, First, H> {
-        KeyIter(LeafIter::Initial(self, First), PhantomData)
+        KeyIter(LeafIter::new(self, First), PhantomData)
+    }
+
+    fn export<W, FK, FV>(
+        &self,
+        writer: W,
+        format: ExportFormat,
+        key_fmt: FK,
+        value_fmt: FV,
+    ) -> io::Result<u64>
+    where
+        W: Write,
+        FK: FnMut(&K) -> String,
+        FV: FnMut(&V) -> String,
+    {
+        let leaves = LeafIter::new(self, First);
+        export::export(leaves, writer, format, key_fmt, value_fmt)
     }
 }
 
@@ -242,6 +285,19 @@ where
     }
 }
 
+impl<'a, C, K, V, H> DoubleEndedIterator for ValIter<'a, C, K, V, First, H>
+where
+    C: Compound<H>,
+    C::Leaf: KVPair<K, V>,
+    K: 'a,
+    V: 'a,
+    H: ByteHash,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|result| result.map(KVPair::val))
+    }
+}
+
 impl<'a, C, K, V, M, H> Iterator for ValIterMut<'a, C, K, V, M, H>
 where
     C: Compound<H>,
@@ -274,6 +330,19 @@ where
     }
 }
 
+impl<'a, C, K, V, H> DoubleEndedIterator for KeyIter<'a, C, K, V, First, H>
+where
+    C: Compound<H>,
+    C::Leaf: KVPair<K, V>,
+    K: 'a,
+    V: 'a,
+    H: ByteHash,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|result| result.map(KVPair::key))
+    }
+}
+
 /// Value reference trait to hide generic arguments to users of the library
 pub trait ValRef<'a, V: 'a>: Deref<Target = V> + 'a
 where
@@ -330,6 +399,16 @@ where
         ValPath::new(self, &mut Self::KeySearch::from(k), k)
     }
 
+    /// Returns true if the map contains a value for key `k`
+    ///
+    /// Stops at the handle of the leaf holding the key and compares keys
+    /// only, no branch to the value is built. Leaves are encoded inline in
+    /// their nodes though, so the persisted nodes on the path are still
+    /// restored whole.
+    fn contains_key(&self, k: &'a O) -> io::Result<bool> {
+        find_key(self, &mut Self::KeySearch::from(k), k)
+    }
+
     /// Returns a reference to a mutable value in the map, if any
     fn get_mut(
         &mut self,
@@ -338,3 +417,293 @@ where
         ValPathMut::new(self, &mut Self::KeySearch::from(k.borrow()), k)
     }
 }
+
+/// Returns true if the search ends at the leaf of key `k`
+///
+/// Nodes are only borrowed on the way down, so persisted nodes are restored
+/// without replacing their handles, and the map is left untouched.
+fn find_key<C, M, K, V, O, H>(
+    node: &C,
+    method: &mut M,
+    k: &O,
+) -> io::Result<bool>
+where
+    C: Compound<H>,
+    C::Leaf: KVPair<K, V>,
+    M: Method<C, H>,
+    K: Borrow<O>,
+    O: Eq + ?Sized,
+    H: ByteHash,
+{
+    match method.select(node.children()) {
+        Some(i) => match node.children()[i].inner()? {
+            HandleRef::Node(n) => find_key(&*n, method, k),
+            HandleRef::Leaf(leaf) => Ok(leaf.key().borrow() == k),
+            HandleRef::None => Ok(false),
+        },
+        None => Ok(false),
+    }
+}
+
+/// Collection that can be modified like a map
+pub trait MapMut<K, V, H>
+where
+    Self: Compound<H> + for<'a> Map<'a, K, K, V, H>,
+    Self::Leaf: KVPair<K, V>,
+    K: Content<H> + Eq,
+    H: ByteHash,
+{
+    /// Insert a key-value pair, returning the replaced value, if any
+    fn insert(&mut self, k: K, v: V) -> io::Result<Option<V>>;
+
+    /// Remove the value with key `k`, returning it
+    fn remove(&mut self, k: &K) -> io::Result<Option<V>>;
+
+    /// Inserts a batch of key-value pairs, for duplicate keys the last
+    /// value is kept
+    ///
+    /// By default the pairs are inserted one at a time, each insert
+    /// searching down from the root. Persisted nodes are only restored by
+    /// the first insert passing through them, and hashed again on the next
+    /// persist, but annotations on the path can be computed again for every
+    /// insert. Structures can order the batch so that consecutive inserts
+    /// follow mostly the same path.
+    fn insert_batch<I>(&mut self, iter: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        for (k, v) in iter {
+            self.insert(k, v)?;
+        }
+        Ok(())
+    }
+
+    /// Applies `f` to the value of key `k` in place, returning whether the
+    /// key was present
+    ///
+    /// Only the nodes on the path to `k` are modified.
+    fn update<F>(&mut self, k: &K, f: F) -> io::Result<bool>
+    where
+        F: FnOnce(&mut V),
+    {
+        match self.get_mut(k)? {
+            Some(mut val) => {
+                f(&mut val);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Replaces the value of key `k` with `v`, moving out and returning the
+    /// previous value
+    ///
+    /// Unlike `insert`, the map is left unchanged if `k` is not present.
+    fn replace(&mut self, k: &K, v: V) -> io::Result<Option<V>> {
+        Ok(self.get_mut(k)?.map(|mut val| mem::replace(&mut *val, v)))
+    }
+
+    /// Returns the value of key `k`, inserting the result of `f` first if
+    /// `k` is not present
+    ///
+    /// The key is searched for without modifying the map, only the path to
+    /// the value returned is then brought into memory, to be annotated and
+    /// hashed again on the next persist.
+    fn get_or_insert_with<F>(
+        &mut self,
+        k: K,
+        f: F,
+    ) -> io::Result<ValPathMut<'_, K, V, Self, H>>
+    where
+        F: FnOnce() -> V,
+    {
+        if !find_key(self, &mut Self::KeySearch::from(&k), &k)? {
+            self.insert(k.clone(), f())?;
+        }
+        Ok(self.get_mut(&k)?.expect("value is present"))
+    }
+
+    /// Inserts `v` under key `k` unless `k` is already present, returning
+    /// whether it was inserted
+    ///
+    /// An existing value is never overwritten, `v` is dropped instead.
+    fn try_insert(&mut self, k: K, v: V) -> io::Result<bool> {
+        if find_key(self, &mut Self::KeySearch::from(&k), &k)? {
+            Ok(false)
+        } else {
+            self.insert(k, v)?;
+            Ok(true)
+        }
+    }
+
+    /// Gets the entry for key `k` for in-place manipulation
+    ///
+    /// Looking up the entry leaves the map untouched, the path to an
+    /// occupied entry is only brought into memory, and annotated again,
+    /// once its value is accessed mutably.
+    fn entry(&mut self, k: K) -> io::Result<Entry<'_, K, V, Self, H>> {
+        if find_key(self, &mut Self::KeySearch::from(&k), &k)? {
+            Ok(Entry::Occupied(OccupiedEntry {
+                map: self,
+                key: k,
+                _marker: PhantomData,
+            }))
+        } else {
+            Ok(Entry::Vacant(VacantEntry {
+                map: self,
+                key: k,
+                _marker: PhantomData,
+            }))
+        }
+    }
+}
+
+/// Map whose values can be removed by a borrowed form of their keys
+///
+/// `MapMut::remove` takes the owned key type, with this a map over `String`
+/// keys can remove the value of an `&str` without allocating a key.
+pub trait MapRemove<O, K, V, H>: MapMut<K, V, H>
+where
+    Self::Leaf: KVPair<K, V>,
+    K: Content<H> + Eq + Borrow<O>,
+    O: Eq + ?Sized,
+    H: ByteHash,
+{
+    /// Remove the value with a key borrowing as `k`, returning it
+    fn remove_borrowed(&mut self, k: &O) -> io::Result<Option<V>>;
+}
+
+/// A view into a single entry of a map, which is either vacant or occupied
+pub enum Entry<'a, K, V, C, H>
+where
+    C: Compound<H>,
+    C::Leaf: KVPair<K, V>,
+    H: ByteHash,
+{
+    /// The entry holds a value
+    Occupied(OccupiedEntry<'a, K, V, C, H>),
+    /// The entry is empty
+    Vacant(VacantEntry<'a, K, V, C, H>),
+}
+
+/// An entry holding a value
+pub struct OccupiedEntry<'a, K, V, C, H> {
+    map: &'a mut C,
+    key: K,
+    _marker: PhantomData<(V, H)>,
+}
+
+/// An empty entry
+pub struct VacantEntry<'a, K, V, C, H> {
+    map: &'a mut C,
+    key: K,
+    _marker: PhantomData<(V, H)>,
+}
+
+impl<'a, K, V, C, H> Entry<'a, K, V, C, H>
+where
+    C: MapMut<K, V, H>,
+    C::Leaf: KVPair<K, V>,
+    K: Content<H> + Eq,
+    H: ByteHash,
+{
+    /// Returns the key of the entry
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(o) => o.key(),
+            Entry::Vacant(v) => v.key(),
+        }
+    }
+
+    /// Inserts `default` if the entry is vacant, returning the value
+    pub fn or_insert(
+        self,
+        default: V,
+    ) -> io::Result<ValPathMut<'a, K, V, C, H>> {
+        self.or_insert_with(|| default)
+    }
+
+    /// Inserts the result of `f` if the entry is vacant, returning the value
+    pub fn or_insert_with<F>(
+        self,
+        f: F,
+    ) -> io::Result<ValPathMut<'a, K, V, C, H>>
+    where
+        F: FnOnce() -> V,
+    {
+        match self {
+            Entry::Occupied(o) => o.into_mut(),
+            Entry::Vacant(v) => v.insert(f()),
+        }
+    }
+
+    /// Modifies the value in place if the entry is occupied
+    pub fn and_modify<F>(mut self, f: F) -> io::Result<Self>
+    where
+        F: FnOnce(&mut V),
+    {
+        if let Entry::Occupied(ref mut o) = self {
+            f(&mut *o.get_mut()?)
+        }
+        Ok(self)
+    }
+}
+
+impl<'a, K, V, C, H> OccupiedEntry<'a, K, V, C, H>
+where
+    C: MapMut<K, V, H>,
+    C::Leaf: KVPair<K, V>,
+    K: Content<H> + Eq,
+    H: ByteHash,
+{
+    /// Returns the key of the entry
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Returns a reference to the value
+    pub fn get(&self) -> io::Result<ValPath<'_, K, V, C, H>> {
+        Ok(self.map.get(&self.key)?.expect("entry is present"))
+    }
+
+    /// Returns a mutable reference to the value
+    pub fn get_mut(&mut self) -> io::Result<ValPathMut<'_, K, V, C, H>> {
+        Ok(self.map.get_mut(&self.key)?.expect("entry is present"))
+    }
+
+    /// Converts the entry into a mutable reference to the value
+    pub fn into_mut(self) -> io::Result<ValPathMut<'a, K, V, C, H>> {
+        let OccupiedEntry { map, key, .. } = self;
+        Ok(map.get_mut(&key)?.expect("entry is present"))
+    }
+
+    /// Replaces the value of the entry, returning the old one
+    pub fn insert(&mut self, v: V) -> io::Result<V> {
+        Ok(mem::replace(&mut *self.get_mut()?, v))
+    }
+}
+
+impl<'a, K, V, C, H> VacantEntry<'a, K, V, C, H>
+where
+    C: MapMut<K, V, H>,
+    C::Leaf: KVPair<K, V>,
+    K: Content<H> + Eq,
+    H: ByteHash,
+{
+    /// Returns the key of the entry
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Takes ownership of the key
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Inserts a value into the entry, returning a mutable reference to it
+    pub fn insert(self, v: V) -> io::Result<ValPathMut<'a, K, V, C, H>> {
+        let VacantEntry { map, key, .. } = self;
+        map.insert(key.clone(), v)?;
+        Ok(map.get_mut(&key)?.expect("value was just inserted"))
+    }
+}
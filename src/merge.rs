@@ -0,0 +1,113 @@
+use std::cmp::Ordering;
+use std::io;
+
+use bytehash::ByteHash;
+
+use crate::compound::Compound;
+use crate::content::Content;
+use crate::join::{iter_joined, Joined};
+use crate::map::MapMut;
+use crate::store::Snapshot;
+
+// A key changed on one side, with its value in the base and on the side,
+// `None` where absent
+type Change<K, V> = (K, Option<V>, Option<V>);
+
+// The changes from `base` to `side`, in key order
+fn changes<C, H, K, V>(
+    base: &Snapshot<C, H>,
+    side: &Snapshot<C, H>,
+) -> impl Iterator<Item = io::Result<Change<K, V>>>
+where
+    C: Compound<H, Leaf = (K, V)>,
+    H: ByteHash,
+    K: Ord,
+    V: PartialEq,
+{
+    iter_joined(base, side).filter_map(|joined| match joined {
+        Ok(Joined::Left(k, v)) => Some(Ok((k, Some(v), None))),
+        Ok(Joined::Right(k, v)) => Some(Ok((k, None, Some(v)))),
+        Ok(Joined::Both(k, a, b)) => {
+            if a == b {
+                None
+            } else {
+                Some(Ok((k, Some(a), Some(b))))
+            }
+        }
+        Err(e) => Some(Err(e)),
+    })
+}
+
+fn apply<C, H, K, V>(map: &mut C, k: K, v: Option<V>) -> io::Result<()>
+where
+    C: Compound<H, Leaf = (K, V)> + MapMut<K, V, H>,
+    H: ByteHash,
+    K: Content<H> + Eq,
+{
+    match v {
+        Some(v) => map.insert(k, v)?,
+        None => map.remove(&k)?,
+    };
+    Ok(())
+}
+
+/// Merges the changes made to `base` in `ours` and in `theirs`, returning
+/// the merged map
+///
+/// Keys changed on only one side, or changed the same way on both, take the
+/// changed value. Keys changed differently on both sides are conflicts,
+/// passed to `resolver` along with their values in the base, ours and
+/// theirs, where `None` means absent. The value it returns is kept, or the
+/// key is removed if it returns `None`.
+///
+/// The merged map starts out as `ours`, with the changes of `theirs`
+/// applied. Both sides are compared to the base as in `iter_joined`, so
+/// only their changed subtrees are restored, and the structure has to be
+/// ordered by key.
+pub fn merge3<C, H, K, V, F>(
+    base: &Snapshot<C, H>,
+    ours: &Snapshot<C, H>,
+    theirs: &Snapshot<C, H>,
+    mut resolver: F,
+) -> io::Result<C>
+where
+    C: Compound<H, Leaf = (K, V)> + MapMut<K, V, H>,
+    H: ByteHash,
+    K: Content<H> + Ord,
+    V: PartialEq,
+    F: FnMut(&K, Option<&V>, Option<&V>, Option<&V>) -> Option<V>,
+{
+    let mut merged = ours.restore()?;
+    let mut our_changes = changes(base, ours);
+    let mut their_changes = changes(base, theirs);
+    let mut our_next = our_changes.next().transpose()?;
+    let mut their_next = their_changes.next().transpose()?;
+
+    loop {
+        // changes only made in ours are already merged
+        let order = match (&our_next, &their_next) {
+            (_, None) => break,
+            (None, Some(_)) => Ordering::Greater,
+            (Some((a, _, _)), Some((b, _, _))) => a.cmp(b),
+        };
+        match order {
+            Ordering::Less => our_next = our_changes.next().transpose()?,
+            Ordering::Greater => {
+                let (k, _, v) = their_next.take().expect("change of theirs");
+                apply(&mut merged, k, v)?;
+                their_next = their_changes.next().transpose()?;
+            }
+            Ordering::Equal => {
+                let (k, base, a) = our_next.take().expect("change of ours");
+                let (_, _, b) = their_next.take().expect("change of theirs");
+                if a != b {
+                    let v = resolver(&k, base.as_ref(), a.as_ref(), b.as_ref());
+                    apply(&mut merged, k, v)?;
+                }
+                our_next = our_changes.next().transpose()?;
+                their_next = their_changes.next().transpose()?;
+            }
+        }
+    }
+    Ok(merged)
+}
@@ -0,0 +1,178 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use bytehash::ByteHash;
+use parking_lot::RwLock;
+
+use crate::{Stats, Store};
+
+// the counters of `stats`, with their metric name and help text
+fn counters(stats: &Stats) -> [(&'static str, &'static str, u64); 6] {
+    [
+        ("kelvin_puts_total", "Values encoded and put", stats.values),
+        (
+            "kelvin_encoded_bytes_total",
+            "Bytes of the values put",
+            stats.encoded,
+        ),
+        (
+            "kelvin_written_bytes_total",
+            "Bytes of the values new to the backend",
+            stats.written,
+        ),
+        (
+            "kelvin_leaf_bytes_total",
+            "Bytes of the leaves in the values put",
+            stats.leaves,
+        ),
+        ("kelvin_gets_total", "Values restored", stats.gets),
+        (
+            "kelvin_cache_hits_total",
+            "Values restored without reading the backend",
+            stats.cache_hits,
+        ),
+    ]
+}
+
+// a registered store, as what is read from it
+struct Source {
+    name: String,
+    stats: Box<dyn Fn() -> Stats + Send + Sync>,
+    size: Box<dyn Fn() -> usize + Send + Sync>,
+}
+
+/// A registry of stores, exposing their `Stats` as Prometheus metrics
+///
+/// Every store is registered under a name, used as the `store` label of
+/// its metrics. `encode` writes them in the Prometheus text format, to be
+/// served on a scrape endpoint. The registry is a cheap handle, clones
+/// share the stores registered.
+#[derive(Clone, Default)]
+pub struct Registry(Arc<RwLock<Vec<Source>>>);
+
+impl Registry {
+    /// Creates an empty registry
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /// Registers `store` under `name`, replacing any store registered under
+    /// the same name
+    ///
+    /// The registry keeps the store alive for as long as it is registered.
+    pub fn register<H: ByteHash>(&self, name: &str, store: &Store<H>)
+    where
+        Store<H>: Send + Sync,
+    {
+        let (stats, size) = (store.clone(), store.clone());
+        let source = Source {
+            name: name.into(),
+            stats: Box::new(move || stats.stats()),
+            size: Box::new(move || size.size()),
+        };
+        let mut sources = self.0.write();
+        sources.retain(|source| source.name != name);
+        sources.push(source);
+    }
+
+    /// Removes the store registered under `name`, returning whether there
+    /// was one
+    pub fn unregister(&self, name: &str) -> bool {
+        let mut sources = self.0.write();
+        let len = sources.len();
+        sources.retain(|source| source.name != name);
+        sources.len() != len
+    }
+
+    /// Writes the metrics of the registered stores to `writer`, in the
+    /// Prometheus text format
+    pub fn encode<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let sources = self.0.read();
+        let sources_counters: Vec<_> = sources
+            .iter()
+            .map(|source| counters(&(source.stats)()))
+            .collect();
+
+        let names = counters(&Stats::default());
+        for (i, (name, help, _)) in names.iter().enumerate() {
+            writeln!(writer, "# HELP {} {}.", name, help)?;
+            writeln!(writer, "# TYPE {} counter", name)?;
+            for (source, counters) in sources.iter().zip(&sources_counters) {
+                let label = escape(&source.name);
+                writeln!(
+                    writer,
+                    "{}{{store=\"{}\"}} {}",
+                    name, label, counters[i].2
+                )?;
+            }
+        }
+
+        let name = "kelvin_size_bytes";
+        writeln!(writer, "# HELP {} Approximate size of the store.", name)?;
+        writeln!(writer, "# TYPE {} gauge", name)?;
+        for source in sources.iter() {
+            let label = escape(&source.name);
+            writeln!(
+                writer,
+                "{}{{store=\"{}\"}} {}",
+                name,
+                label,
+                (source.size)()
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Returns the metrics of the registered stores in the Prometheus text
+    /// format
+    pub fn gather(&self) -> String {
+        let mut text = vec![];
+        self.encode(&mut text)
+            .expect("writing to a Vec cannot fail");
+        String::from_utf8(text).expect("metrics are valid UTF-8")
+    }
+}
+
+// escapes a label value, as required by the text format
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::Blake2b;
+
+    #[test]
+    fn exposition() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let registry = Registry::new();
+        registry.register("a \"store\"", &store);
+
+        let snapshot = store.persist(&mut 42u64).unwrap();
+        store.restore(&snapshot).unwrap();
+
+        let text = registry.gather();
+        assert!(text.contains("# TYPE kelvin_puts_total counter\n"));
+        assert!(
+            text.contains("kelvin_puts_total{store=\"a \\\"store\\\"\"} 1\n")
+        );
+        assert!(text.contains(
+            "kelvin_written_bytes_total{store=\"a \\\"store\\\"\"} 8\n"
+        ));
+        assert!(
+            text.contains("kelvin_gets_total{store=\"a \\\"store\\\"\"} 1\n")
+        );
+        assert!(text.contains(
+            "kelvin_cache_hits_total{store=\"a \\\"store\\\"\"} 0\n"
+        ));
+
+        assert!(registry.unregister("a \"store\""));
+        assert!(!registry.unregister("a \"store\""));
+        assert!(!registry.gather().contains("store="));
+    }
+}
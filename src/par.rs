@@ -0,0 +1,249 @@
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+
+use bytehash::ByteHash;
+use cache::Cached;
+use rayon::iter::plumbing::UnindexedConsumer;
+use rayon::iter::IntoParallelRefMutIterator;
+use rayon::iter::{self as par, ParallelIterator};
+
+use crate::compound::Compound;
+use crate::handle::{Handle, HandleRef, HandleType};
+use crate::iter::LeafIterable;
+use crate::map::KVPair;
+use crate::store::{Snapshot, Store};
+
+// A part of the tree still to be iterated
+enum Piece<'a, C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    Borrowed(&'a Handle<C, H>),
+    // handles of restored nodes, owned by the piece
+    Owned(Handle<C, H>),
+    Err(io::Error),
+}
+
+// Takes ownership of a node, restored nodes are already owned
+fn owned<C: Clone>(cached: Cached<'_, C>) -> C {
+    match cached {
+        Cached::Spilled(node) => *node,
+        cached => (*cached).clone(),
+    }
+}
+
+impl<'a, C, H> Piece<'a, C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    // Splits a node into pieces for its children, restoring it if
+    // persisted. Leaves, empty handles and errors are returned as is.
+    fn expand(self) -> Result<Vec<Self>, Self> {
+        let node = match self {
+            Piece::Borrowed(handle) => match handle.inner() {
+                Ok(HandleRef::Node(Cached::Borrowed(node))) => {
+                    return Ok(node
+                        .children()
+                        .iter()
+                        .map(Piece::Borrowed)
+                        .collect())
+                }
+                Ok(HandleRef::Node(cached)) => owned(cached),
+                Ok(_) => return Err(self),
+                Err(e) => return Ok(vec![Piece::Err(e)]),
+            },
+            Piece::Owned(ref handle) => {
+                if handle.handle_type() != HandleType::Node {
+                    return Err(self);
+                }
+                match handle.inner() {
+                    Ok(HandleRef::Node(cached)) => owned(cached),
+                    Ok(_) => unreachable!("checked to be a node"),
+                    Err(e) => return Ok(vec![Piece::Err(e)]),
+                }
+            }
+            Piece::Err(_) => return Err(self),
+        };
+        let mut node = node;
+        Ok(node
+            .children_mut()
+            .iter_mut()
+            .map(|handle| Piece::Owned(mem::take(handle)))
+            .collect())
+    }
+
+    fn leaves(self) -> Vec<io::Result<C::Leaf>> {
+        let handle = match self {
+            Piece::Err(e) => return vec![Err(e)],
+            Piece::Borrowed(handle) => handle,
+            Piece::Owned(ref handle) => handle,
+        };
+        match handle.inner() {
+            Err(e) => vec![Err(e)],
+            Ok(HandleRef::Leaf(leaf)) => vec![Ok(leaf.clone())],
+            Ok(HandleRef::Node(node)) => {
+                node.iter().map(|leaf| leaf.cloned()).collect()
+            }
+            Ok(HandleRef::None) => vec![],
+        }
+    }
+}
+
+type Split<'a, C, H> = (Vec<Piece<'a, C, H>>, Option<Vec<Piece<'a, C, H>>>);
+
+fn split<C, H>(mut pieces: Vec<Piece<'_, C, H>>) -> Split<'_, C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    // descend until there is more than one piece to split
+    while pieces.len() == 1 {
+        let piece = pieces.pop().expect("length checked");
+        match piece.expand() {
+            Ok(expanded) => pieces = expanded,
+            Err(piece) => return (vec![piece], None),
+        }
+    }
+    if pieces.len() < 2 {
+        return (pieces, None);
+    }
+    let rest = pieces.split_off(pieces.len() / 2);
+    (pieces, Some(rest))
+}
+
+/// A parallel iterator over clones of the leaves of a Compound
+pub struct ParLeafIter<'a, C, H>(Vec<Piece<'a, C, H>>)
+where
+    C: Compound<H>,
+    H: ByteHash;
+
+impl<'a, C, H> ParallelIterator for ParLeafIter<'a, C, H>
+where
+    C: Compound<H>,
+    C::Leaf: Send,
+    Handle<C, H>: Send + Sync,
+    H: ByteHash,
+{
+    type Item = io::Result<C::Leaf>;
+
+    fn drive_unindexed<Co>(self, consumer: Co) -> Co::Result
+    where
+        Co: UnindexedConsumer<Self::Item>,
+    {
+        par::split(self.0, split)
+            .flat_map_iter(|pieces| pieces.into_iter().flat_map(Piece::leaves))
+            .drive_unindexed(consumer)
+    }
+}
+
+/// A parallel iterator over clones of the values of a map
+pub struct ParValIter<'a, C, K, V, H>(
+    ParLeafIter<'a, C, H>,
+    PhantomData<(K, V)>,
+)
+where
+    C: Compound<H>,
+    H: ByteHash;
+
+impl<'a, C, K, V, H> ParallelIterator for ParValIter<'a, C, K, V, H>
+where
+    C: Compound<H>,
+    C::Leaf: KVPair<K, V> + Send,
+    Handle<C, H>: Send + Sync,
+    K: Send + Sync,
+    V: Send + Sync,
+    H: ByteHash,
+{
+    type Item = io::Result<V>;
+
+    fn drive_unindexed<Co>(self, consumer: Co) -> Co::Result
+    where
+        Co: UnindexedConsumer<Self::Item>,
+    {
+        self.0
+            .map(|leaf| leaf.map(KVPair::into_val))
+            .drive_unindexed(consumer)
+    }
+}
+
+/// Trait for iterating over the leaves of a Compound in parallel
+///
+/// The tree is split at subtree boundaries, and subtrees are restored and
+/// iterated on the rayon thread pool, so backend reads of a full scan
+/// happen concurrently. Leaves are yielded as clones, in no particular
+/// order.
+pub trait ParLeafIterable<H>
+where
+    Self: Compound<H>,
+    H: ByteHash,
+{
+    /// Returns a parallel iterator over the leaves of the Compound
+    fn par_iter(&self) -> ParLeafIter<'_, Self, H>;
+
+    /// Returns a parallel iterator over the values of a map
+    fn par_values<K, V>(&self) -> ParValIter<'_, Self, K, V, H>
+    where
+        Self::Leaf: KVPair<K, V>;
+}
+
+impl<C, H> ParLeafIterable<H> for C
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    fn par_iter(&self) -> ParLeafIter<'_, Self, H> {
+        ParLeafIter(self.children().iter().map(Piece::Borrowed).collect())
+    }
+
+    fn par_values<K, V>(&self) -> ParValIter<'_, Self, K, V, H>
+    where
+        Self::Leaf: KVPair<K, V>,
+    {
+        ParValIter(self.par_iter(), PhantomData)
+    }
+}
+
+// Persists the in-memory subtrees of a node, siblings concurrently
+fn persist_children<C, H>(node: &mut C, store: &Store<H>) -> io::Result<()>
+where
+    C: Compound<H>,
+    Handle<C, H>: Send,
+    Store<H>: Sync,
+    H: ByteHash,
+{
+    node.children_mut().par_iter_mut().try_for_each(|handle| {
+        match handle.node_mut() {
+            Some(node) => {
+                persist_children(node, store)?;
+                handle.persist_node(|node| store.persist(node))
+            }
+            None => Ok(()),
+        }
+    })
+}
+
+impl<H: ByteHash> Store<H> {
+    /// Persists content to the store, like `persist`, hashing and writing
+    /// the subtrees of different branches concurrently on the rayon thread
+    /// pool
+    ///
+    /// As the subtrees are content-addressed, the order they are written in
+    /// does not matter, and the resulting snapshot is the same as with
+    /// `persist`.
+    pub fn persist_parallel<C>(
+        &self,
+        content: &mut C,
+    ) -> io::Result<Snapshot<C, H>>
+    where
+        C: Compound<H>,
+        Handle<C, H>: Send,
+        Self: Sync,
+    {
+        span!("persist_parallel");
+        persist_children(content, self)?;
+        self.persist(content)
+    }
+}
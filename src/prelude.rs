@@ -0,0 +1,14 @@
+pub use crate::annotations::{Annotation, Associative, Combine};
+pub use crate::compound::Compound;
+pub use crate::content::Content;
+pub use crate::handle::Handle;
+pub use crate::iter::{FailFastIterable, LeafIterable};
+pub use crate::map::{KeyValIterable, Map, MapMut, ValRef, ValRefMut};
+#[cfg(feature = "rayon")]
+pub use crate::par::ParLeafIterable;
+pub use crate::sink::Sink;
+pub use crate::source::Source;
+pub use crate::store::{Snapshot, Store};
+pub use crate::{DefaultHandle, DefaultSnapshot, DefaultStore};
+
+pub use bytehash::{Blake2b, ByteHash};
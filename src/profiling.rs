@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The time spent in a labeled span of the crate
+///
+/// Spans of different labels nest, the time of `persist` includes the time
+/// of the `hash` and `backend_put` spans within it. Spans entered on several
+/// threads at once are summed, so totals can exceed the wall-clock time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    /// Number of times the span was entered
+    pub count: u64,
+    /// Total time spent in the span
+    pub total: Duration,
+}
+
+type Table = BTreeMap<&'static str, Span>;
+
+// The tables of the live threads, and the spans of those that exited
+//
+// Locked in this order, before the table of any thread.
+static TABLES: Mutex<Vec<Arc<Mutex<Table>>>> = Mutex::new(Vec::new());
+static RETIRED: Mutex<Table> = Mutex::new(BTreeMap::new());
+
+thread_local! {
+    // spans are recorded per thread, so threads never wait on each other
+    static LOCAL: Local = Local::new();
+}
+
+// The table of a thread, only contended while spans are reported
+struct Local(Arc<Mutex<Table>>);
+
+impl Local {
+    fn new() -> Self {
+        let table = Arc::new(Mutex::new(BTreeMap::new()));
+        TABLES
+            .lock()
+            .expect("poisoned span tables")
+            .push(table.clone());
+        Local(table)
+    }
+}
+
+impl Drop for Local {
+    fn drop(&mut self) {
+        if let Ok(mut tables) = TABLES.lock() {
+            if let (Ok(mut retired), Ok(table)) =
+                (RETIRED.lock(), self.0.lock())
+            {
+                merge(&mut retired, &table);
+            }
+            tables.retain(|table| !Arc::ptr_eq(table, &self.0));
+        }
+    }
+}
+
+fn merge(into: &mut Table, from: &Table) {
+    for (label, span) in from {
+        let merged = into.entry(label).or_default();
+        merged.count += span.count;
+        merged.total += span.total;
+    }
+}
+
+/// Returns the spans recorded since the last `reset`, ordered by label
+///
+/// The labels are `persist`, `persist_pipelined`, `persist_parallel`,
+/// `restore`, `hash`, `backend_get`, `backend_put` and `flush`.
+///
+/// Every thread records its spans on its own, they are merged here.
+pub fn spans() -> Vec<(&'static str, Span)> {
+    let tables = TABLES.lock().expect("poisoned span tables");
+    let mut spans = RETIRED.lock().expect("poisoned span table").clone();
+    for table in tables.iter() {
+        merge(&mut spans, &table.lock().expect("poisoned span table"));
+    }
+    spans.into_iter().collect()
+}
+
+/// Clears the recorded spans
+pub fn reset() {
+    let tables = TABLES.lock().expect("poisoned span tables");
+    RETIRED.lock().expect("poisoned span table").clear();
+    for table in tables.iter() {
+        table.lock().expect("poisoned span table").clear();
+    }
+}
+
+pub(crate) struct Guard {
+    label: &'static str,
+    start: Instant,
+}
+
+impl Guard {
+    pub fn new(label: &'static str) -> Self {
+        Guard {
+            label,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        // the table might already be gone when the thread is exiting
+        let _ = LOCAL.try_with(|local| {
+            if let Ok(mut spans) = local.0.lock() {
+                let span = spans.entry(self.label).or_default();
+                span.count += 1;
+                span.total += elapsed;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{Blake2b, Store};
+
+    #[test]
+    fn persist_and_restore_spans() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let snapshot = store.persist(&mut 42u64).unwrap();
+        assert_eq!(store.restore(&snapshot).unwrap(), 42);
+
+        // other tests record spans concurrently, so only check for presence
+        let spans = spans();
+        for label in &["persist", "hash", "backend_put", "restore"] {
+            assert!(spans
+                .iter()
+                .any(|(recorded, span)| recorded == label && span.count > 0));
+        }
+    }
+
+    #[test]
+    fn spans_of_exited_threads() {
+        std::thread::spawn(|| {
+            let _span = Guard::new("test_exited");
+        })
+        .join()
+        .unwrap();
+
+        let spans = spans();
+        let (_, span) = spans
+            .iter()
+            .find(|(label, _)| *label == "test_exited")
+            .expect("span of the exited thread");
+        assert_eq!(span.count, 1);
+    }
+}
@@ -0,0 +1,158 @@
+use std::io::{self, Write};
+use std::marker::PhantomData;
+
+use bytehash::{ByteHash, State};
+
+use crate::backend::Reader;
+use crate::compound::Compound;
+use crate::content::Content;
+use crate::handle::HandleType;
+use crate::search::Method;
+use crate::sink::Sink;
+use crate::source::Source;
+use crate::store::{Snapshot, Store};
+
+/// A merkle proof of the path from the root of a snapshot down to a leaf
+///
+/// Consists of the encodings of the nodes along the path. Since node
+/// encodings include the annotations of their subtrees, the proof also
+/// proves the annotations along the path, such as the count or sum of the
+/// whole structure, against the root hash.
+pub struct Proof<C, H> {
+    // node encodings from the root down, with the index of the handle
+    // followed in each
+    levels: Vec<(Vec<u8>, u32)>,
+    _marker: PhantomData<(C, H)>,
+}
+
+/// The leaf and annotations proven by a `Proof`
+pub struct Proven<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    /// The proven leaf
+    pub leaf: C::Leaf,
+    /// The annotations of the subtrees along the path, from the whole
+    /// structure down to the leaf itself
+    pub annotations: Vec<C::Annotation>,
+}
+
+fn hash<H: ByteHash>(bytes: &[u8]) -> H::Digest {
+    let mut state = H::state();
+    state
+        .write_all(bytes)
+        .expect("In memory write should always succeed");
+    state.fin()
+}
+
+impl<C, H> Proof<C, H>
+where
+    C: Compound<H>,
+    H: ByteHash,
+{
+    /// Creates a proof for the leaf found by searching the snapshot with
+    /// `method`, descending a single path without backtracking
+    pub fn new<M: Method<C, H>>(
+        snapshot: &Snapshot<C, H>,
+        method: &mut M,
+    ) -> io::Result<Option<Self>> {
+        let store = snapshot.store();
+        let mut digest = *snapshot.hash();
+        let mut levels = vec![];
+        loop {
+            let bytes = store.get_bytes(&digest)?;
+            let node: C = store.get_hash(&digest)?;
+            let i = match method.select(node.children()) {
+                Some(i) => i,
+                None => return Ok(None),
+            };
+            levels.push((bytes, i as u32));
+            let handle = &node.children()[i];
+            match (handle.handle_type(), handle.snapshot()) {
+                (HandleType::Leaf, _) => {
+                    return Ok(Some(Proof {
+                        levels,
+                        _marker: PhantomData,
+                    }))
+                }
+                (HandleType::Node, Some(snapshot)) => digest = *snapshot.hash(),
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    /// Verifies the proof against the root hash of a snapshot, returning the
+    /// proven leaf and annotations, or `None` if the proof is invalid
+    pub fn verify(&self, root: &H::Digest) -> io::Result<Option<Proven<C, H>>>
+    where
+        H::Digest: Sync,
+    {
+        // the decoded nodes are never restored further
+        let store = Store::volatile()?;
+        let mut expected = *root;
+        let mut annotations = vec![];
+
+        for (n, (bytes, i)) in self.levels.iter().enumerate() {
+            if hash::<H>(bytes) != expected {
+                return Ok(None);
+            }
+            let mut source = Source::new(Reader::Slice(&bytes[..]), &store);
+            let mut node = C::restore(&mut source)?;
+
+            if n == 0 {
+                match node.annotation() {
+                    Some(ann) => annotations.push(ann),
+                    None => return Ok(None),
+                }
+            }
+            let handle = match node.children_mut().get_mut(*i as usize) {
+                Some(handle) => handle,
+                None => return Ok(None),
+            };
+            match handle.annotation() {
+                Some(ann) => annotations.push(ann.into_owned()),
+                None => return Ok(None),
+            }
+
+            let last = n + 1 == self.levels.len();
+            match (last, handle.handle_type(), handle.snapshot()) {
+                (true, HandleType::Leaf, _) => {
+                    let leaf = std::mem::take(handle).into_leaf();
+                    return Ok(Some(Proven { leaf, annotations }));
+                }
+                (false, HandleType::Node, Some(snapshot)) => {
+                    expected = *snapshot.hash()
+                }
+                _ => return Ok(None),
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<C, H> Clone for Proof<C, H> {
+    fn clone(&self) -> Self {
+        Proof {
+            levels: self.levels.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<C, H> Content<H> for Proof<C, H>
+where
+    C: 'static,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.levels.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(Proof {
+            levels: Vec::restore(source)?,
+            _marker: PhantomData,
+        })
+    }
+}
@@ -0,0 +1,435 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use bytehash::{ByteHash, State};
+
+use crate::backend::Reader;
+use crate::source::Source;
+use crate::{Compound, Snapshot, Store};
+
+// sent by both sides first, followed by the length of their digests
+const MAGIC: &[u8; 8] = b"kelvin\x00\x01";
+
+// upper bounds of the digests per level and of value lengths, against
+// malformed or malicious peers
+const MAX_DIGESTS: u32 = 1 << 20;
+const MAX_VALUE: u32 = 1 << 30;
+
+// Values received by `replicate_from`, kept in a temporary file until the
+// whole tree has arrived
+struct Staged<H: ByteHash> {
+    file: File,
+    // offset and length of each value in the file
+    values: HashMap<H::Digest, (u64, u32)>,
+    end: u64,
+}
+
+impl<H: ByteHash> Staged<H> {
+    fn new() -> io::Result<Self> {
+        Ok(Staged {
+            file: tempfile::tempfile()?,
+            values: HashMap::new(),
+            end: 0,
+        })
+    }
+
+    fn contains(&self, digest: &H::Digest) -> bool {
+        self.values.contains_key(digest)
+    }
+
+    // Appends the `len` bytes of the value of `digest` read from `reader`,
+    // checking them against the digest on the way
+    fn receive<R: Read>(
+        &mut self,
+        reader: &mut R,
+        digest: H::Digest,
+        len: u32,
+    ) -> io::Result<()> {
+        let mut state = H::state();
+        let mut value = (&mut *reader).take(len as u64);
+        let mut buf = [0u8; 8192];
+        let mut received = 0;
+        loop {
+            let n = value.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            state.write_all(&buf[..n])?;
+            self.file.write_all(&buf[..n])?;
+            received += n;
+        }
+        if received != len as usize {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if state.fin() != digest {
+            return Err(invalid("value does not match its digest"));
+        }
+        self.values.insert(digest, (self.end, len));
+        self.end += len as u64;
+        Ok(())
+    }
+
+    // Reads back the value of `digest`, if staged and not taken before
+    fn take(&mut self, digest: &H::Digest) -> io::Result<Option<Vec<u8>>> {
+        let (offset, len) = match self.values.remove(digest) {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let mut bytes = vec![0; len as usize];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut bytes)?;
+        Ok(Some(bytes))
+    }
+}
+
+impl<H: ByteHash> Store<H> {
+    /// Sends `snapshot` over `stream` to a store calling `replicate_from` on
+    /// the other end, returning the number of values sent
+    ///
+    /// Both sides start with a handshake, checking that they speak the same
+    /// protocol with digests of the same length. The root is then
+    /// announced, and the tree offered one level at a time: the receiver
+    /// answers with the digests it `wants`, which are streamed to it, and
+    /// only their children are offered next. Returns once the receiver has
+    /// confirmed storing every value.
+    ///
+    /// Only the nodes of the structure are followed, snapshots stored within
+    /// its leaves are not sent along.
+    pub fn replicate_to<C, S>(
+        &self,
+        snapshot: &Snapshot<C, H>,
+        mut stream: S,
+    ) -> io::Result<usize>
+    where
+        C: Compound<H>,
+        S: Read + Write,
+    {
+        handshake::<H, _>(&mut stream)?;
+        stream.write_all(snapshot.hash().as_ref())?;
+
+        let mut offered = vec![*snapshot.hash()];
+        let mut sent = 0;
+        loop {
+            write_digests::<H, _>(&mut stream, &offered)?;
+            stream.flush()?;
+            if offered.is_empty() {
+                break;
+            }
+            let offered_set: HashSet<_> = offered.drain(..).collect();
+            for digest in read_digests::<H, _>(&mut stream)? {
+                if !offered_set.contains(&digest) {
+                    return Err(invalid("peer wants a value not offered"));
+                }
+                let (node, bytes) = self.get_encoded::<C>(&digest)?;
+                offered.extend(
+                    node.children()
+                        .iter()
+                        .filter_map(|handle| handle.snapshot())
+                        .map(|snapshot| *snapshot.hash()),
+                );
+                write_u32(&mut stream, bytes.len() as u32)?;
+                stream.write_all(&bytes)?;
+                sent += 1;
+            }
+        }
+
+        if read_u32(&mut stream)? as usize != sent {
+            return Err(invalid("peer stored a different number of values"));
+        }
+        Ok(sent)
+    }
+
+    /// Receives a snapshot sent with `replicate_to` over `stream`, returning
+    /// it
+    ///
+    /// Every value is checked against its digest as it arrives, and staged in
+    /// a temporary file, so only the digests received are held in memory.
+    /// Values are only written to the store once the whole tree has been
+    /// received, each node after its subtree, so an interrupted replication
+    /// never leaves a node in the store without its subtree. Values already
+    /// received for an earlier level are not asked for again.
+    pub fn replicate_from<C, S>(
+        &self,
+        mut stream: S,
+    ) -> io::Result<Snapshot<C, H>>
+    where
+        C: Compound<H>,
+        S: Read + Write,
+    {
+        handshake::<H, _>(&mut stream)?;
+        let root = read_digest::<H, _>(&mut stream)?;
+
+        let mut staged = Staged::<H>::new()?;
+        loop {
+            let offered = read_digests::<H, _>(&mut stream)?;
+            if offered.is_empty() {
+                break;
+            }
+            let mut wanted = self.wants(&offered);
+            wanted.retain(|digest| !staged.contains(digest));
+            write_digests::<H, _>(&mut stream, &wanted)?;
+            stream.flush()?;
+
+            for digest in wanted {
+                let len = read_u32(&mut stream)?;
+                if len > MAX_VALUE {
+                    return Err(invalid("value too long"));
+                }
+                staged.receive(&mut stream, digest, len)?;
+            }
+        }
+
+        let stored = self.store_staged::<C>(&mut staged, &root)?;
+        self.flush()?;
+        write_u32(&mut stream, stored as u32)?;
+        stream.flush()?;
+
+        if !self.contains(&root) {
+            return Err(invalid("root was not sent"));
+        }
+        Ok(Snapshot::new(root, self))
+    }
+
+    // Puts the staged subtree of `digest` into the store, children first,
+    // returning the number of values stored
+    fn store_staged<C: Compound<H>>(
+        &self,
+        staged: &mut Staged<H>,
+        digest: &H::Digest,
+    ) -> io::Result<usize> {
+        let bytes = match staged.take(digest)? {
+            Some(bytes) => bytes,
+            // already in the store, or stored through another parent
+            None => return Ok(0),
+        };
+        let node = C::restore(&mut Source::new(Reader::Slice(&bytes), self))?;
+        let children: Vec<_> = node
+            .children()
+            .iter()
+            .filter_map(|handle| handle.snapshot())
+            .map(|snapshot| *snapshot.hash())
+            .collect();
+        drop(node);
+
+        let mut stored = 0;
+        for child in &children {
+            stored += self.store_staged::<C>(staged, child)?;
+        }
+        self.put(*digest, &bytes)?;
+        Ok(stored + 1)
+    }
+}
+
+fn handshake<H: ByteHash, S: Read + Write>(stream: &mut S) -> io::Result<()> {
+    let digest_len = H::Digest::default().as_ref().len() as u8;
+    stream.write_all(MAGIC)?;
+    stream.write_all(&[digest_len])?;
+    stream.flush()?;
+
+    let mut hello = [0u8; 9];
+    stream.read_exact(&mut hello)?;
+    if hello[..8] != MAGIC[..] {
+        return Err(invalid("peer does not speak the kelvin protocol"));
+    }
+    if hello[8] != digest_len {
+        return Err(invalid("peer uses digests of another length"));
+    }
+    Ok(())
+}
+
+fn write_digests<H: ByteHash, W: Write>(
+    writer: &mut W,
+    digests: &[H::Digest],
+) -> io::Result<()> {
+    write_u32(writer, digests.len() as u32)?;
+    for digest in digests {
+        writer.write_all(digest.as_ref())?;
+    }
+    Ok(())
+}
+
+fn read_digests<H: ByteHash, R: Read>(
+    reader: &mut R,
+) -> io::Result<Vec<H::Digest>> {
+    let count = read_u32(reader)?;
+    if count > MAX_DIGESTS {
+        return Err(invalid("too many digests"));
+    }
+    (0..count).map(|_| read_digest::<H, _>(reader)).collect()
+}
+
+fn read_digest<H: ByteHash, R: Read>(reader: &mut R) -> io::Result<H::Digest> {
+    let mut digest = H::Digest::default();
+    reader.read_exact(digest.as_mut())?;
+    Ok(digest)
+}
+
+fn write_u32<W: Write>(writer: &mut W, n: u32) -> io::Result<()> {
+    writer.write_all(&n.to_be_bytes())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use crate::annotations::Cardinality;
+    use crate::{
+        Blake2b, Content, Handle, LeafIterable, Sink, Source, Truncated,
+    };
+
+    #[derive(Clone, Default)]
+    struct Tree(Vec<Handle<Self, Blake2b>>);
+
+    impl Content<Blake2b> for Tree {
+        fn persist(&mut self, sink: &mut Sink<Blake2b>) -> io::Result<()> {
+            self.0.persist(sink)
+        }
+
+        fn restore(source: &mut Source<Blake2b>) -> io::Result<Self> {
+            Ok(Tree(Vec::restore(source)?))
+        }
+    }
+
+    impl Compound<Blake2b> for Tree {
+        type Leaf = u64;
+        type Annotation = Cardinality<u64>;
+
+        fn children(&self) -> &[Handle<Self, Blake2b>] {
+            &self.0
+        }
+
+        fn children_mut(&mut self) -> &mut [Handle<Self, Blake2b>] {
+            &mut self.0
+        }
+    }
+
+    fn tree(first: u64) -> Tree {
+        let node = |range: std::ops::Range<u64>| {
+            Handle::new_node(Tree(range.map(Handle::new_leaf).collect()))
+        };
+        Tree(vec![node(first..first + 2), node(10..12), node(20..22)])
+    }
+
+    // replicates `snapshot` from `source` to `target` over TCP
+    fn replicate(
+        source: &Store<Blake2b>,
+        snapshot: &Snapshot<Tree, Blake2b>,
+        target: &Store<Blake2b>,
+    ) -> (usize, Snapshot<Tree, Blake2b>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let target = target.clone();
+        let receiving = thread::spawn(move || {
+            let stream = listener.accept().unwrap().0;
+            target.replicate_from::<Tree, _>(stream)
+        });
+        let stream = TcpStream::connect(addr).unwrap();
+        let sent = source.replicate_to(snapshot, stream).unwrap();
+        match receiving.join().unwrap() {
+            Ok(received) => (sent, received),
+            Err(e) => panic!("receiving failed: {}", e),
+        }
+    }
+
+    fn leaves(
+        store: &Store<Blake2b>,
+        snapshot: &Snapshot<Tree, Blake2b>,
+    ) -> Vec<u64> {
+        let tree = store.restore(snapshot).unwrap();
+        tree.iter().map(|leaf| *leaf.unwrap()).collect()
+    }
+
+    #[test]
+    fn converge() {
+        let source = Store::<Blake2b>::volatile().unwrap();
+        let target = Store::<Blake2b>::volatile().unwrap();
+
+        let snapshot = source.persist(&mut tree(0)).unwrap();
+        let (sent, received) = replicate(&source, &snapshot, &target);
+        assert_eq!(sent, 4);
+        assert_eq!(received.hash(), snapshot.hash());
+        assert_eq!(leaves(&target, &received), vec![0, 1, 10, 11, 20, 21]);
+
+        // only the root and the changed node are missing
+        let snapshot = source.persist(&mut tree(5)).unwrap();
+        assert_eq!(replicate(&source, &snapshot, &target).0, 2);
+        assert_eq!(replicate(&source, &snapshot, &target).0, 0);
+        assert_eq!(leaves(&target, &snapshot), vec![5, 6, 10, 11, 20, 21]);
+    }
+
+    #[test]
+    fn shared_subtree() {
+        let source = Store::<Blake2b>::volatile().unwrap();
+        let target = Store::<Blake2b>::volatile().unwrap();
+
+        // the shared node is offered on the first and second level
+        let shared = Tree((0..3).map(Handle::new_leaf).collect());
+        let mut tree = Tree(vec![
+            Handle::new_node(shared.clone()),
+            Handle::new_leaf(7),
+            Handle::new_node(Tree(vec![Handle::new_node(shared)])),
+        ]);
+        let snapshot = source.persist(&mut tree).unwrap();
+        let (sent, received) = replicate(&source, &snapshot, &target);
+        assert_eq!(sent, 3);
+        assert_eq!(leaves(&target, &received), vec![0, 1, 2, 7, 0, 1, 2]);
+    }
+
+    #[test]
+    fn handshake_mismatch() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let receiving = thread::spawn(move || {
+            let store = Store::<Blake2b>::volatile().unwrap();
+            let stream = listener.accept().unwrap().0;
+            store.replicate_from::<Tree, _>(stream).map(|_| ())
+        });
+
+        // a peer with shorter digests
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let err = handshake::<Truncated<Blake2b, [u8; 20]>, _>(&mut stream)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = receiving.join().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn corrupted_value() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let receiving = thread::spawn(move || {
+            let store = Store::<Blake2b>::volatile().unwrap();
+            let stream = listener.accept().unwrap().0;
+            let received = store.replicate_from::<Tree, _>(stream).map(|_| ());
+            (received, store.contains(&[0; 32]))
+        });
+
+        // announces and offers a root, then sends other bytes for it
+        let mut stream = TcpStream::connect(addr).unwrap();
+        handshake::<Blake2b, _>(&mut stream).unwrap();
+        stream.write_all(&[0; 32]).unwrap();
+        write_digests::<Blake2b, _>(&mut stream, &[[0; 32]]).unwrap();
+        assert_eq!(read_digests::<Blake2b, _>(&mut stream).unwrap(), [[0; 32]]);
+        write_u32(&mut stream, 6).unwrap();
+        stream.write_all(b"kelvin").unwrap();
+
+        let (received, stored) = receiving.join().unwrap();
+        assert_eq!(received.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(!stored);
+    }
+}
@@ -22,7 +22,10 @@ where
     H: ByteHash,
 {
     /// Given a path, create a new `Root`
-    pub fn new<P: Into<PathBuf>>(path: P) -> io::Result<Self> {
+    pub fn new<P: Into<PathBuf>>(path: P) -> io::Result<Self>
+    where
+        H::Digest: Sync,
+    {
         let path = path.into();
         let store = Store::new(&path)?;
 
@@ -53,7 +56,7 @@ where
         self.store.flush()?;
         let root_file_path = self.path.join("root");
         let af = AtomicFile::new(root_file_path, AllowOverwrite);
-        af.write(|f| f.write_all(snapshot.as_bytes()))?;
+        af.write(|f| f.write_all(snapshot.hash().as_ref()))?;
         Ok(snapshot)
     }
 }
@@ -1,3 +1,8 @@
+use std::borrow::{Borrow, Cow};
+use std::cmp::Ordering;
+use std::marker::PhantomData;
+
+use crate::annotations::{MaxKey, MinKey};
 use crate::compound::Compound;
 use crate::handle::{Handle, HandleType};
 use crate::ByteHash;
@@ -12,6 +17,9 @@ where
     fn select(&mut self, handles: &[Handle<C, H>]) -> Option<usize>;
 }
 
+/// Search method finding the leaves in order, from the first one
+///
+/// This is the method of the iterators returned by `LeafIterable::iter`.
 #[derive(Clone)]
 pub struct First;
 
@@ -34,3 +42,150 @@ where
         None
     }
 }
+
+// Selects the handle whose annotated key compares as `wanted` against all
+// others, keeping the first one on ties.
+fn select_by_key<C, H, A, K>(
+    handles: &[Handle<C, H>],
+    wanted: Ordering,
+) -> Option<usize>
+where
+    C: Compound<H>,
+    C::Annotation: Borrow<A>,
+    A: std::ops::Deref<Target = K>,
+    K: Ord,
+    H: ByteHash,
+{
+    let mut best: Option<(usize, Cow<C::Annotation>)> = None;
+    for (i, h) in handles.iter().enumerate() {
+        if let Some(ann) = h.annotation() {
+            let better = match best {
+                None => true,
+                Some((_, ref best_ann)) => {
+                    let key: &A = (*ann).borrow();
+                    let best_key: &A = (**best_ann).borrow();
+                    (**key).cmp(&**best_key) == wanted
+                }
+            };
+            if better {
+                best = Some((i, ann))
+            }
+        }
+    }
+    best.map(|(i, _)| i)
+}
+
+/// Search method that descends into the subtree with the smallest key
+pub struct MinKeySearch<K>(PhantomData<K>);
+
+impl<K> Default for MinKeySearch<K> {
+    fn default() -> Self {
+        MinKeySearch(PhantomData)
+    }
+}
+
+impl<C, H, K> Method<C, H> for MinKeySearch<K>
+where
+    C: Compound<H>,
+    C::Annotation: Borrow<MinKey<K>>,
+    K: Ord,
+    H: ByteHash,
+{
+    fn select(&mut self, handles: &[Handle<C, H>]) -> Option<usize> {
+        select_by_key::<_, _, MinKey<K>, _>(handles, Ordering::Less)
+    }
+}
+
+/// Search method that descends into the subtree with the largest key
+pub struct MaxKeySearch<K>(PhantomData<K>);
+
+impl<K> Default for MaxKeySearch<K> {
+    fn default() -> Self {
+        MaxKeySearch(PhantomData)
+    }
+}
+
+impl<C, H, K> Method<C, H> for MaxKeySearch<K>
+where
+    C: Compound<H>,
+    C::Annotation: Borrow<MaxKey<K>>,
+    K: Ord,
+    H: ByteHash,
+{
+    fn select(&mut self, handles: &[Handle<C, H>]) -> Option<usize> {
+        select_by_key::<_, _, MaxKey<K>, _>(handles, Ordering::Greater)
+    }
+}
+
+/// The decision of a `Select` search on a subtree
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// Search the subtree
+    Descend,
+    /// Move on to the next subtree
+    Skip,
+}
+
+/// Search method guided by a closure deciding on subtrees by annotation
+///
+/// Subtrees are offered to the closure in order, and the first one it
+/// descends into is searched further. If no leaf in a subtree is accepted,
+/// the search backtracks and continues with the following subtrees.
+pub struct Select<F>(F);
+
+impl<F> Select<F> {
+    /// Creates a new search from a closure deciding on annotations
+    pub fn new(f: F) -> Self {
+        Select(f)
+    }
+}
+
+impl<C, H, F> Method<C, H> for Select<F>
+where
+    C: Compound<H>,
+    H: ByteHash,
+    F: FnMut(&C::Annotation) -> Decision,
+{
+    fn select(&mut self, handles: &[Handle<C, H>]) -> Option<usize> {
+        for (i, h) in handles.iter().enumerate() {
+            if let Some(ann) = h.annotation() {
+                if (self.0)(&*ann) == Decision::Descend {
+                    return Some(i);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Search method finding the leaves whose annotations satisfy a predicate
+///
+/// Subtrees whose annotations fail the predicate are skipped without being
+/// visited, so the predicate has to hold for every subtree containing a
+/// matching leaf, as is the case for bloom filters, tag masks or key bounds.
+pub struct Where<F>(F);
+
+impl<F> Where<F> {
+    /// Creates a new search from a predicate on annotations
+    pub fn new(f: F) -> Self {
+        Where(f)
+    }
+}
+
+impl<C, H, F> Method<C, H> for Where<F>
+where
+    C: Compound<H>,
+    H: ByteHash,
+    F: FnMut(&C::Annotation) -> bool,
+{
+    fn select(&mut self, handles: &[Handle<C, H>]) -> Option<usize> {
+        for (i, h) in handles.iter().enumerate() {
+            if let Some(ann) = h.annotation() {
+                if (self.0)(&*ann) {
+                    return Some(i);
+                }
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,328 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+use bytehash::{ByteHash, State};
+use parking_lot::{Condvar, Mutex};
+
+use crate::dump::{hex, parse_hex};
+use crate::Store;
+
+// upper bound of the request line and headers
+const MAX_HEAD: u64 = 8 * 1024;
+
+// upper bound of posted values
+const MAX_BODY: u64 = 64 * 1024 * 1024;
+
+// connections handled at once, further ones wait to be accepted
+const MAX_CONNECTIONS: usize = 64;
+
+// time a connection may stall while sending its request or reading the
+// response
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Serves the values of a store over HTTP
+///
+/// Answers three requests, one per connection:
+///
+/// - `GET /blob/<digest>`: the value with the hex `digest`
+/// - `POST /blob`: stores the request body, answering with its hex digest
+/// - `GET /root/<name>`: the hex digest of the root set under `name` with
+///   `Store::set_root`
+/// - `POST /flush`: flushes the store
+///
+/// Posted values are stored as they are, so values referring to others
+/// should be posted after them, for the store to stay complete. They are
+/// only flushed to disk by `POST /flush`, so that a batch of posts is
+/// flushed once.
+pub struct Server<H: ByteHash> {
+    store: Store<H>,
+}
+
+struct Response {
+    status: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: &'static str, body: Vec<u8>) -> Self {
+        Response { status, body }
+    }
+
+    fn text(status: &'static str, text: &str) -> Self {
+        Response::new(status, text.as_bytes().to_vec())
+    }
+}
+
+impl<H: ByteHash> Server<H> {
+    /// Creates a server of the values in `store`
+    pub fn new(store: Store<H>) -> Self {
+        Server { store }
+    }
+
+    /// Accepts connections on `listener`, handling each on a thread of its
+    /// own, until accepting fails
+    ///
+    /// At most 64 connections are handled at once, and connections stalling
+    /// for 30 seconds are dropped.
+    pub fn serve(&self, listener: &TcpListener) -> io::Result<()>
+    where
+        Self: Sync,
+    {
+        let active = &(Mutex::new(0usize), Condvar::new());
+        thread::scope(|scope| loop {
+            {
+                let mut count = active.0.lock();
+                while *count >= MAX_CONNECTIONS {
+                    active.1.wait(&mut count);
+                }
+                *count += 1;
+            }
+            let stream = listener.accept()?.0;
+            stream.set_read_timeout(Some(TIMEOUT))?;
+            stream.set_write_timeout(Some(TIMEOUT))?;
+            scope.spawn(move || {
+                // the client hung up or sent garbage, nothing to answer
+                let _ = self.handle(stream);
+                *active.0.lock() -= 1;
+                active.1.notify_one();
+            });
+        })
+    }
+
+    /// Handles a single request read from `stream`, writing the response
+    /// back to it
+    pub fn handle<S: Read + Write>(&self, mut stream: S) -> io::Result<()> {
+        let response = {
+            let mut reader = BufReader::new(&mut stream);
+            match self.respond(&mut reader) {
+                Ok(response) => response,
+                Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {
+                    Response::text("400 Bad Request", &e.to_string())
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            response.status,
+            response.body.len()
+        )?;
+        stream.write_all(&response.body)?;
+        stream.flush()
+    }
+
+    fn respond<R: BufRead>(&self, reader: &mut R) -> io::Result<Response> {
+        let mut head = (&mut *reader).take(MAX_HEAD);
+        let request = read_line(&mut head)?;
+        let mut content_length = None;
+        loop {
+            let line = read_line(&mut head)?;
+            if line.is_empty() {
+                break;
+            }
+            let mut header = line.splitn(2, ':');
+            let name = header.next().unwrap_or("").trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                let value = header.next().unwrap_or("").trim();
+                content_length = Some(
+                    value
+                        .parse::<u64>()
+                        .map_err(|_| invalid("Content-Length"))?,
+                );
+            }
+        }
+
+        let mut parts = request.split(' ');
+        let (method, path) = match (parts.next(), parts.next()) {
+            (Some(method), Some(path)) => (method, path),
+            _ => return Err(invalid("malformed request line")),
+        };
+        let segments: Vec<&str> = path.split('/').collect();
+        match (method, &segments[..]) {
+            ("GET", ["", "blob", digest]) => {
+                let digest = match parse_hex::<H>(digest) {
+                    Some(digest) => digest,
+                    None => return Err(invalid("malformed digest")),
+                };
+                match self.store.get_bytes(&digest) {
+                    Ok(bytes) => Ok(Response::new("200 OK", bytes)),
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                        Ok(Response::text("404 Not Found", "no such value"))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            ("POST", ["", "blob"]) => {
+                let len = match content_length {
+                    Some(len) if len <= MAX_BODY => len,
+                    Some(_) => {
+                        return Ok(Response::text(
+                            "413 Payload Too Large",
+                            "value too long",
+                        ))
+                    }
+                    None => {
+                        return Ok(Response::text(
+                            "411 Length Required",
+                            "missing Content-Length",
+                        ))
+                    }
+                };
+                // grown as the body arrives, not trusting the length
+                let mut body = vec![];
+                (&mut *reader).take(len).read_to_end(&mut body)?;
+                if body.len() as u64 != len {
+                    return Err(invalid("truncated body"));
+                }
+                let mut state = H::state();
+                state.write_all(&body)?;
+                let digest = state.fin();
+                self.store.put(digest, &body)?;
+                Ok(Response::text("201 Created", &hex(digest.as_ref())))
+            }
+            ("POST", ["", "flush"]) => {
+                self.store.flush()?;
+                Ok(Response::text("200 OK", "flushed"))
+            }
+            ("GET", ["", "root", name]) => match self.store.root_digest(name) {
+                Some(digest) => {
+                    Ok(Response::text("200 OK", &hex(digest.as_ref())))
+                }
+                None => Ok(Response::text("404 Not Found", "no such root")),
+            },
+            ("GET", _) | ("POST", _) => {
+                Ok(Response::text("404 Not Found", "no such resource"))
+            }
+            _ => Ok(Response::text("405 Method Not Allowed", "GET or POST")),
+        }
+    }
+}
+
+// Reads a line ending in CRLF, without it
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .map_err(|_| invalid("malformed header"))?;
+    if !line.ends_with("\r\n") {
+        return Err(invalid("header too long or cut off"));
+    }
+    line.truncate(line.len() - 2);
+    Ok(line)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::net::TcpStream;
+
+    use crate::Blake2b;
+
+    // the status line and body of the response to `request`
+    fn request(server: &Server<Blake2b>, request: &[u8]) -> (String, Vec<u8>) {
+        let mut stream = io::Cursor::new(request.to_vec());
+        let mut written = vec![];
+        server.handle(ReadWrite(&mut stream, &mut written)).unwrap();
+        let end = written.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8(written[..end].to_vec()).unwrap();
+        let status = head.lines().next().unwrap().to_string();
+        (status, written[end + 4..].to_vec())
+    }
+
+    struct ReadWrite<'a>(&'a mut io::Cursor<Vec<u8>>, &'a mut Vec<u8>);
+
+    impl<'a> Read for ReadWrite<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl<'a> Write for ReadWrite<'a> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.1.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn blobs_and_roots() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let snapshot = store.persist(&mut 7u64).unwrap();
+        let server = Server::new(store.clone());
+        let digest = hex(snapshot.hash());
+
+        let get = format!("GET /blob/{} HTTP/1.1\r\nHost: a\r\n\r\n", digest);
+        let (status, body) = request(&server, get.as_bytes());
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body, 7u64.to_be_bytes());
+
+        let post = b"POST /blob HTTP/1.1\r\ncontent-length: 6\r\n\r\nkelvin";
+        let (status, body) = request(&server, post);
+        assert_eq!(status, "HTTP/1.1 201 Created");
+        let posted = String::from_utf8(body).unwrap();
+        let get = format!("GET /blob/{} HTTP/1.1\r\n\r\n", posted);
+        assert_eq!(request(&server, get.as_bytes()).1, b"kelvin");
+        let flush = b"POST /flush HTTP/1.1\r\n\r\n";
+        assert_eq!(request(&server, flush).0, "HTTP/1.1 200 OK");
+
+        let get = b"GET /root/state HTTP/1.1\r\n\r\n";
+        assert_eq!(request(&server, get).0, "HTTP/1.1 404 Not Found");
+        store.set_root("state", &snapshot);
+        assert_eq!(request(&server, get).1, digest.as_bytes());
+    }
+
+    #[test]
+    fn bad_requests() {
+        let server = Server::new(Store::<Blake2b>::volatile().unwrap());
+        let status = |req: &[u8]| request(&server, req).0;
+
+        let missing = format!("GET /blob/{} HTTP/1.1\r\n\r\n", "00".repeat(32));
+        assert_eq!(status(missing.as_bytes()), "HTTP/1.1 404 Not Found");
+        assert_eq!(
+            status(b"GET /blob/00 HTTP/1.1\r\n\r\n"),
+            "HTTP/1.1 400 Bad Request"
+        );
+        assert_eq!(
+            status(b"POST /blob HTTP/1.1\r\n\r\nkelvin"),
+            "HTTP/1.1 411 Length Required"
+        );
+        assert_eq!(
+            status(b"POST /blob HTTP/1.1\r\nContent-Length: 9\r\n\r\nkelvin"),
+            "HTTP/1.1 400 Bad Request"
+        );
+        assert_eq!(
+            status(b"DELETE /blob HTTP/1.1\r\n\r\n"),
+            "HTTP/1.1 405 Method Not Allowed"
+        );
+        assert_eq!(status(b"GET /blob"), "HTTP/1.1 400 Bad Request");
+    }
+
+    #[test]
+    fn serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let server = Server::new(Store::<Blake2b>::volatile().unwrap());
+            server.serve(&listener)
+        });
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"POST /blob HTTP/1.1\r\nContent-Length: 1\r\n\r\nk")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+    }
+}
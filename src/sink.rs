@@ -1,8 +1,15 @@
+use std::cell::RefCell;
 use std::io::{self, Write};
+use std::mem;
+use std::sync::mpsc::SyncSender;
 
 use bytehash::{ByteHash, State};
 
-use crate::store::Store;
+use crate::content::Content;
+use crate::store::{Snapshot, Store};
+
+// Encoded values on their way to the writer thread of a pipelined persist
+pub(crate) type Writer<H> = SyncSender<(<H as ByteHash>::Digest, Vec<u8>)>;
 
 pub trait SinkTrait<H: ByteHash>
 where
@@ -11,42 +18,139 @@ where
     fn recur(&self) -> Sink<H>;
 }
 
+// Buffers are kept for reuse up to this many, and up to this capacity
+const POOL_LEN: usize = 32;
+const POOL_CAPACITY: usize = 64 * 1024;
+
+thread_local! {
+    // nested sinks each take a buffer, so one per level of the tree is kept
+    static BUFFERS: RefCell<Vec<Vec<u8>>> = const { RefCell::new(vec![]) };
+}
+
 /// A sink for bytes, used in implementing `Content`
 pub struct Sink<'a, H: ByteHash> {
     bytes: Vec<u8>,
-    store: &'a Store<H>,
+    // sinks without a store only hash their values
+    store: Option<&'a Store<H>>,
+    writer: Option<&'a Writer<H>>,
 }
 
 impl<'a, H: ByteHash> Sink<'a, H> {
     pub(crate) fn new(store: &'a Store<H>) -> Self {
+        Self::with_writer(store, None)
+    }
+
+    // A sink handing its value, and those of its children, to a writer
+    // thread instead of putting them into the store
+    pub(crate) fn with_writer(
+        store: &'a Store<H>,
+        writer: Option<&'a Writer<H>>,
+    ) -> Self {
+        Self::build(Some(store), writer)
+    }
+
+    // A sink hashing its value, and those of its children, without writing
+    // them anywhere
+    pub(crate) fn hashing() -> Self {
+        Self::build(None, None)
+    }
+
+    fn build(
+        store: Option<&'a Store<H>>,
+        writer: Option<&'a Writer<H>>,
+    ) -> Self {
+        let bytes = BUFFERS
+            .with(|buffers| buffers.borrow_mut().pop())
+            .unwrap_or_default();
         Sink {
-            bytes: vec![],
+            bytes,
             store,
+            writer,
         }
     }
 
-    pub(crate) fn store(&self) -> &Store<H> {
-        self.store
+    // Persists a child value the same way this sink is persisted
+    pub(crate) fn persist_child<T: Content<H>>(
+        &self,
+        content: &mut T,
+    ) -> io::Result<Snapshot<T, H>> {
+        let store = self.store.expect("hashing sinks persist no children");
+        let mut sink = Self::with_writer(store, self.writer);
+        content.persist(&mut sink)?;
+        Ok(Snapshot::new(sink.fin()?, store))
+    }
+
+    // Hashes a child value without persisting it
+    //
+    // Hashing a copy leaves nodes shared with forks as they are, copying a
+    // node only clones the handles of its children.
+    pub(crate) fn hash_child<T: Content<H>>(
+        content: &T,
+    ) -> io::Result<H::Digest> {
+        let mut sink = Self::hashing();
+        content.clone().persist(&mut sink)?;
+        sink.fin()
     }
 
-    pub(crate) fn fin(self) -> io::Result<H::Digest> {
-        let Sink { bytes, .. } = self;
-        let mut hasher = H::state();
-        hasher
-            .write_all(&bytes)
-            .expect("In memory write should always succeed");
-        let hash = hasher.fin();
-        self.store.put(hash, bytes)?;
+    pub(crate) fn is_hashing(&self) -> bool {
+        self.store.is_none()
+    }
+
+    // Counts the bytes written since `start` as the encoding of a leaf
+    pub(crate) fn leaf_written(&self, start: usize) {
+        if let Some(store) = self.store {
+            store.count_leaf(self.bytes.len() - start)
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub(crate) fn fin(mut self) -> io::Result<H::Digest> {
+        let hash = {
+            span!("hash", len = self.bytes.len());
+            let mut hasher = H::state();
+            hasher
+                .write_all(&self.bytes)
+                .expect("In memory write should always succeed");
+            hasher.fin()
+        };
+        match (self.writer, self.store) {
+            (Some(writer), _) => writer
+                .send((hash, mem::take(&mut self.bytes)))
+                .map_err(|_| io::Error::other("Writer stopped"))?,
+            (None, Some(store)) => {
+                store.put(hash, &self.bytes)?;
+            }
+            (None, None) => (),
+        }
         Ok(hash)
     }
 }
 
+impl<'a, H: ByteHash> Drop for Sink<'a, H> {
+    fn drop(&mut self) {
+        let mut bytes = mem::take(&mut self.bytes);
+        if bytes.capacity() <= POOL_CAPACITY {
+            bytes.clear();
+            // the pool might already be gone when the thread is exiting
+            let _ = BUFFERS.try_with(|buffers| {
+                let mut buffers = buffers.borrow_mut();
+                if buffers.len() < POOL_LEN {
+                    buffers.push(bytes)
+                }
+            });
+        }
+    }
+}
+
 impl<'a, H> SinkTrait<H> for Sink<'a, H>
 where
     H: ByteHash,
 {
     fn recur(&self) -> Sink<H> {
-        Self::new(self.store)
+        Self::build(self.store, self.writer)
     }
 }
 
@@ -59,3 +163,28 @@ impl<'a, H: ByteHash> io::Write for Sink<'a, H> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::Blake2b;
+
+    #[test]
+    fn buffers_are_reused() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let snapshot = store.persist(&mut vec![1u64; 1000]).unwrap();
+
+        let pooled = BUFFERS.with(|buffers| buffers.borrow().len());
+        assert!(pooled > 0);
+
+        // the pooled buffer is taken again, and returned cleared
+        let again = store.persist(&mut vec![1u64; 1000]).unwrap();
+        assert_eq!(snapshot.hash(), again.hash());
+        BUFFERS.with(|buffers| {
+            let buffers = buffers.borrow();
+            assert_eq!(buffers.len(), pooled);
+            assert!(buffers.iter().all(|buffer| buffer.is_empty()));
+        });
+    }
+}
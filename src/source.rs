@@ -2,16 +2,17 @@ use std::io::{self, Read};
 
 use bytehash::ByteHash;
 
+use crate::backend::Reader;
 use crate::store::Store;
 
 /// A source of bytes, used in implementing `Content`
 pub struct Source<'a, H: ByteHash> {
-    read: Box<dyn Read + 'a>,
+    read: Reader<'a>,
     store: &'a Store<H>,
 }
 
 impl<'a, H: ByteHash> Source<'a, H> {
-    pub(crate) fn new(read: Box<dyn Read + 'a>, store: &'a Store<H>) -> Self {
+    pub(crate) fn new(read: Reader<'a>, store: &'a Store<H>) -> Self {
         Source { read, store }
     }
 
@@ -24,4 +25,8 @@ impl<'a, H: ByteHash> Read for Source<'a, H> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         self.read.read(buf)
     }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.read.read_exact(buf)
+    }
 }
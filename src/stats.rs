@@ -0,0 +1,100 @@
+use std::ops::Sub;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters of the values persisted through and restored from a `Store`
+///
+/// Counters accumulate over the lifetime of the store, the difference of
+/// two `Stats` taken before and after a persist gives the numbers of that
+/// persist alone.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of values encoded
+    pub values: u64,
+    /// Bytes of the encoded values, including those already in the backend
+    pub encoded: u64,
+    /// Bytes of the values new to the backend, and written to it
+    pub written: u64,
+    /// Bytes of the leaves in the encoded values
+    ///
+    /// Only nodes on the paths to modified leaves are encoded again, so this
+    /// bounds the bytes logically changed from above, by the size of their
+    /// unchanged siblings.
+    pub leaves: u64,
+    /// Number of values restored
+    pub gets: u64,
+    /// Number of values restored without reading the backend, out of those
+    /// kept alive by `restore_shared` or preloaded by `warm`
+    pub cache_hits: u64,
+}
+
+impl Stats {
+    /// Returns the bytes written per byte of leaves changed, or `None` if no
+    /// leaves were encoded
+    ///
+    /// Changing a single leaf writes every node on its path from the root
+    /// again, so this grows with the depth and fanout of the structure.
+    pub fn write_amplification(&self) -> Option<f64> {
+        if self.leaves == 0 {
+            None
+        } else {
+            Some(self.written as f64 / self.leaves as f64)
+        }
+    }
+}
+
+impl Sub for Stats {
+    type Output = Stats;
+
+    fn sub(self, other: Stats) -> Stats {
+        Stats {
+            values: self.values - other.values,
+            encoded: self.encoded - other.encoded,
+            written: self.written - other.written,
+            leaves: self.leaves - other.leaves,
+            gets: self.gets - other.gets,
+            cache_hits: self.cache_hits - other.cache_hits,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Counters {
+    values: AtomicU64,
+    encoded: AtomicU64,
+    written: AtomicU64,
+    leaves: AtomicU64,
+    gets: AtomicU64,
+    cache_hits: AtomicU64,
+}
+
+impl Counters {
+    pub fn value(&self, len: usize, new: bool) {
+        self.values.fetch_add(1, Ordering::Relaxed);
+        self.encoded.fetch_add(len as u64, Ordering::Relaxed);
+        if new {
+            self.written.fetch_add(len as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub fn leaf(&self, len: usize) {
+        self.leaves.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub fn restored(&self, cached: bool) {
+        self.gets.fetch_add(1, Ordering::Relaxed);
+        if cached {
+            self.cache_hits.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn get(&self) -> Stats {
+        Stats {
+            values: self.values.load(Ordering::Relaxed),
+            encoded: self.encoded.load(Ordering::Relaxed),
+            written: self.written.load(Ordering::Relaxed),
+            leaves: self.leaves.load(Ordering::Relaxed),
+            gets: self.gets.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -1,7 +1,14 @@
+use std::any::Any;
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
 use std::marker::PhantomData;
 use std::ops::Deref;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender};
+use std::sync::{Arc, Weak};
+use std::thread;
 use std::{fmt, io};
 
 use arrayvec::ArrayVec;
@@ -9,10 +16,14 @@ use bytehash::ByteHash;
 use cache::Cache;
 use parking_lot::RwLock;
 
-use crate::backend::{Backend, Persistant, PutResult, Volatile};
+#[cfg(any(feature = "filesystem", feature = "web"))]
+use crate::backend::Persistant;
+use crate::backend::{Backend, PutResult, Reader, Volatile};
+use crate::compound::Compound;
 use crate::content::Content;
 use crate::sink::Sink;
 use crate::source::Source;
+use crate::stats::{Counters, Stats};
 
 /// The main store type, wrapping backend and cache functionality
 #[derive(Clone)]
@@ -20,23 +31,222 @@ pub struct Store<H: ByteHash>(Arc<StoreInner<H>>);
 
 const GENERATIONS: usize = 8;
 
+// Encoded values buffered between a pipelined persist and its writer
+const PIPELINE_DEPTH: usize = 64;
+
+// Pages of the read cache, and their size
+const CACHE_PAGES: usize = 32;
+const CACHE_PAGE_SIZE: usize = 4096;
+
 pub struct StoreInner<H: ByteHash> {
-    generations: ArrayVec<[RwLock<Box<dyn Backend<H>>>; GENERATIONS]>,
-    #[allow(unused)]
+    generations: ArrayVec<[Box<dyn Backend<H>>; GENERATIONS]>,
+    // type names of the backends of the generations, for `describe`
+    backends: Vec<&'static str>,
+    // the directory of a store created with `new`
+    path: Option<PathBuf>,
+    // encoded values preloaded by `warm`
     cache: Cache<H::Digest>,
+    // digests inserted into the cache, some of which may have been evicted
+    warmed: RwLock<Warmed<H::Digest>>,
+    // values restored by `restore_shared`, for as long as they are alive
+    memo: Memo<H::Digest>,
+    stats: Counters,
+    // roots set by name, kept for the lifetime of the store
+    roots: RwLock<HashMap<String, Named<H::Digest>>>,
+}
+
+// The digest a name points at, and the channels of those watching it
+struct Named<D> {
+    digest: Option<D>,
+    watchers: Vec<Sender<D>>,
+}
+
+impl<D> Default for Named<D> {
+    fn default() -> Self {
+        Named {
+            digest: None,
+            watchers: vec![],
+        }
+    }
+}
+
+// Digests of the values preloaded into the read cache
+//
+// The cache evicts values without notice, so the digests are checked
+// against it when counting, and dropped once evicted whenever the set has
+// doubled since its last pruning.
+struct Warmed<K> {
+    digests: HashSet<K>,
+    prune_at: usize,
+}
+
+// Evicted digests are only dropped once the set has grown this much
+const WARMED_MIN_PRUNE: usize = 1024;
+
+impl<K: std::hash::Hash + Eq> Warmed<K> {
+    fn new() -> Self {
+        Warmed {
+            digests: HashSet::new(),
+            prune_at: WARMED_MIN_PRUNE,
+        }
+    }
+
+    fn insert(&mut self, key: K, cache: &Cache<K>) {
+        self.digests.insert(key);
+        if self.digests.len() >= self.prune_at {
+            self.digests
+                .retain(|key| cache.get::<Vec<u8>>(key).is_some());
+            self.prune_at = (self.digests.len() * 2).max(WARMED_MIN_PRUNE);
+        }
+    }
+
+    // Returns the number of values still in the cache
+    fn live(&self, cache: &Cache<K>) -> usize {
+        self.digests
+            .iter()
+            .filter(|key| cache.get::<Vec<u8>>(key).is_some())
+            .count()
+    }
+}
+
+// Dead entries are only removed once a shard of the memo has grown this
+// much past the live entries found at its last pruning
+const MEMO_MIN_PRUNE: usize = 1024;
+
+// Shards of the memo, picked by the first byte of the digest
+const MEMO_SHARDS: usize = 16;
+
+// Values shared by `restore_shared`, by digest
+//
+// Restoring checks the memo first, so it is sharded to keep concurrent
+// reads of different values off the same lock, and not locked at all
+// while it is empty.
+struct Memo<K> {
+    shards: Vec<RwLock<MemoShard<K>>>,
+    // entries over all shards, dead or alive
+    len: AtomicUsize,
+}
+
+struct MemoShard<K> {
+    values: HashMap<K, Weak<dyn Any + Send + Sync>>,
+    prune_at: usize,
+}
+
+impl<K: std::hash::Hash + Eq + AsRef<[u8]>> Memo<K> {
+    fn new() -> Self {
+        Memo {
+            shards: (0..MEMO_SHARDS)
+                .map(|_| {
+                    RwLock::new(MemoShard {
+                        values: HashMap::new(),
+                        prune_at: MEMO_MIN_PRUNE,
+                    })
+                })
+                .collect(),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    fn shard(&self, key: &K) -> &RwLock<MemoShard<K>> {
+        let first = key.as_ref().first().cloned().unwrap_or(0);
+        &self.shards[first as usize % MEMO_SHARDS]
+    }
+
+    fn get<T: 'static + Clone>(&self, key: &K) -> Option<T> {
+        if self.len.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        let value = self.shard(key).read().values.get(key)?.upgrade()?;
+        value.downcast_ref::<T>().cloned()
+    }
+
+    fn insert(&self, key: K, value: Weak<dyn Any + Send + Sync>) {
+        let mut shard = self.shard(&key).write();
+        if shard.values.insert(key, value).is_none() {
+            self.len.fetch_add(1, Ordering::Relaxed);
+        }
+        if shard.values.len() >= shard.prune_at {
+            let before = shard.values.len();
+            shard.values.retain(|_, value| value.strong_count() > 0);
+            self.len
+                .fetch_sub(before - shard.values.len(), Ordering::Relaxed);
+            shard.prune_at = (shard.values.len() * 2).max(MEMO_MIN_PRUNE);
+        }
+    }
+
+    // Returns the number of entries still alive
+    fn live(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| {
+                shard
+                    .read()
+                    .values
+                    .values()
+                    .filter(|value| value.strong_count() > 0)
+                    .count()
+            })
+            .sum()
+    }
+
+    // Returns the number of entries the memo holds before pruning the dead
+    // ones
+    fn capacity(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| {
+                let shard = shard.read();
+                shard.prune_at.max(shard.values.len())
+            })
+            .sum()
+    }
 }
 
 impl<H: ByteHash> fmt::Debug for Store<H> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Store")
+        let description = self.describe();
+        f.debug_struct("Store")
+            .field("backends", &description.backends)
+            .field("size", &description.size)
+            .field("cache_entries", &description.cache_entries)
+            .field("shared", &description.shared)
+            .field("roots", &description.roots)
+            .field("path", &description.path)
+            .finish()
     }
 }
 
+/// A description of a store, as returned by `Store::describe`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Description {
+    /// Type names of the backends, one per generation, newest first
+    pub backends: Vec<&'static str>,
+    /// Approximate size of the backends in bytes, as returned by
+    /// `Store::size`
+    pub size: usize,
+    /// Number of pages of the read cache filled by `Store::warm`
+    pub cache_pages: usize,
+    /// Size of each page of the read cache, its capacity in bytes being
+    /// `cache_pages * cache_page_size`
+    pub cache_page_size: usize,
+    /// Number of values preloaded by `Store::warm` still in the read cache
+    pub cache_entries: usize,
+    /// Number of values kept alive by `Store::restore_shared`
+    pub shared: usize,
+    /// Number of entries the memo of shared values holds before dropping
+    /// those no longer alive
+    pub shared_capacity: usize,
+    /// Number of named roots, set with `Store::set_root`
+    pub roots: usize,
+    /// Directory of a store created with `Store::new`
+    pub path: Option<PathBuf>,
+    /// Counters of the store, as returned by `Store::stats`
+    pub stats: Stats,
+}
+
 #[doc(hidden)]
 pub struct Shared<T, H: ByteHash>(T, PhantomData<H>);
 
-unsafe impl<T, H: ByteHash> Send for Shared<T, H> {}
-
 /// A snapshot of a structure state
 #[derive(Clone, Debug)]
 pub struct Snapshot<T, H: ByteHash> {
@@ -63,8 +273,25 @@ impl<T: Content<H>, H: ByteHash> Snapshot<T, H> {
         &self.hash
     }
 
-    pub(crate) fn as_bytes(&self) -> &[u8] {
-        self.hash.as_ref()
+    pub(crate) fn store(&self) -> &Store<H> {
+        &self.store
+    }
+}
+
+impl<C: Compound<H>, H: ByteHash> Snapshot<C, H> {
+    /// Returns the `A` component of the annotation of the snapshotted
+    /// structure, or `None` if it is empty
+    ///
+    /// Subtree annotations are persisted along with the handles pointing to
+    /// them, so only the root node is decoded, without restoring any of the
+    /// subtrees.
+    pub fn root_annotation<A>(&self) -> io::Result<Option<A>>
+    where
+        C::Annotation: Borrow<A>,
+        A: Clone,
+    {
+        let root = self.restore()?;
+        Ok(root.annotation().map(|ann| ann.borrow().clone()))
     }
 }
 
@@ -75,36 +302,85 @@ impl<N, H: ByteHash> Deref for Snapshot<N, H> {
     }
 }
 
+/// Snapshots are stored as their hash, and restored referring to the store
+/// being read from, so they can only link content within the same store.
+impl<T: Content<H>, H: ByteHash> Content<H> for Snapshot<T, H> {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        sink.write_all(self.hash.as_ref())
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let mut hash = H::Digest::default();
+        source.read_exact(hash.as_mut())?;
+        Ok(Snapshot::new(hash, source.store()))
+    }
+}
+
+// Keeps a copy of the bytes read through it
+struct Recorder<'a, R> {
+    read: R,
+    bytes: &'a mut Vec<u8>,
+}
+
+impl<'a, R: Read> Read for Recorder<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.read.read(buf)?;
+        self.bytes.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
 impl<H: ByteHash> Store<H> {
     /// Creates a new Store at `path`
-    pub fn new<P: Into<PathBuf>>(path: P) -> io::Result<Self> {
-        let pers = Persistant::new(path)?;
-        let mut generations = ArrayVec::new();
-        generations.push(RwLock::new(Box::new(pers) as Box<dyn Backend<H>>));
-
-        Ok(Store(Arc::new(StoreInner {
-            generations,
-            cache: Cache::new(32, 4096),
-        })))
+    #[cfg(any(feature = "filesystem", feature = "web"))]
+    pub fn new<P: Into<PathBuf>>(path: P) -> io::Result<Self>
+    where
+        H::Digest: Sync,
+    {
+        let path = path.into();
+        let mut store = Self::with_backend(Persistant::new(path.clone())?);
+        Arc::get_mut(&mut store.0)
+            .expect("store is not shared yet")
+            .path = Some(path);
+        Ok(store)
     }
 
     /// Creates a new volatile (in-memory only) Store
-    pub fn volatile() -> io::Result<Self> {
-        let pers = Volatile::new();
+    pub fn volatile() -> io::Result<Self>
+    where
+        H::Digest: Sync,
+    {
+        Ok(Self::with_backend(Volatile::new()))
+    }
+
+    /// Creates a new Store on top of a custom backend
+    pub fn with_backend<B: Backend<H> + 'static>(backend: B) -> Self {
         let mut generations = ArrayVec::new();
-        generations.push(RwLock::new(Box::new(pers) as Box<dyn Backend<H>>));
+        generations.push(Box::new(backend) as Box<dyn Backend<H>>);
 
-        Ok(Store(Arc::new(StoreInner {
+        Store(Arc::new(StoreInner {
             generations,
-            cache: Cache::new(32, 4096),
-        })))
+            backends: vec![std::any::type_name::<B>()],
+            path: None,
+            cache: Cache::new(CACHE_PAGES, CACHE_PAGE_SIZE),
+            warmed: RwLock::new(Warmed::new()),
+            memo: Memo::new(),
+            stats: Counters::default(),
+            roots: RwLock::new(HashMap::new()),
+        }))
     }
 
     /// Persists Content to the store, returning a Snapshot
+    ///
+    /// Persisted subtrees are replaced by handles to their snapshots, which
+    /// are written again as just their digest and annotation. Since only
+    /// mutable access restores them into nodes, persisting again after a
+    /// few changes only writes the paths down to the changed leaves.
     pub fn persist<T: Content<H>>(
         &self,
         content: &mut T,
     ) -> io::Result<Snapshot<T, H>> {
+        span!("persist");
         let mut sink = Sink::new(self);
         content.persist(&mut sink)?;
         Ok(Snapshot {
@@ -114,10 +390,49 @@ impl<H: ByteHash> Store<H> {
         })
     }
 
+    /// Persists Content to the store, like `persist`, writing the encoded
+    /// values to the backend on a separate thread
+    ///
+    /// A node can only be encoded once the digests of its children are
+    /// known, so encoding and hashing stay on the calling thread, while the
+    /// backend writes happen concurrently. Throughput then approaches
+    /// the slower of the two instead of their sum.
+    pub fn persist_pipelined<T: Content<H>>(
+        &self,
+        content: &mut T,
+    ) -> io::Result<Snapshot<T, H>>
+    where
+        Self: Sync,
+        H::Digest: Send,
+    {
+        span!("persist_pipelined");
+        let (writer, written) =
+            sync_channel::<(H::Digest, Vec<u8>)>(PIPELINE_DEPTH);
+        thread::scope(|scope| {
+            let writing = scope.spawn(move || {
+                for (hash, bytes) in written {
+                    self.put(hash, &bytes)?;
+                }
+                Ok::<_, io::Error>(())
+            });
+
+            let persisted = {
+                let mut sink = Sink::with_writer(self, Some(&writer));
+                content.persist(&mut sink).and_then(|_| sink.fin())
+            };
+            drop(writer);
+
+            // a failing writer stops the encoding, report its error first
+            writing.join().expect("writer thread panicked")?;
+            Ok(Snapshot::new(persisted?, self))
+        })
+    }
+
     pub(crate) fn flush(&self) -> io::Result<()> {
+        span!("flush");
         // TODO, sync to disk
         for gen in &self.0.generations {
-            gen.write().flush()?;
+            gen.flush()?;
         }
 
         Ok(())
@@ -126,9 +441,31 @@ impl<H: ByteHash> Store<H> {
     pub(crate) fn put(
         &self,
         hash: H::Digest,
-        bytes: Vec<u8>,
+        bytes: &[u8],
     ) -> io::Result<PutResult> {
-        self.0.generations[0].write().put(hash, bytes)
+        span!(
+            "backend_put",
+            digest = crate::dump::prefix(hash.as_ref()),
+            len = bytes.len()
+        );
+        let result = self.0.generations[0].put(hash, bytes)?;
+        let new = matches!(result, PutResult::Ok);
+        #[cfg(feature = "tracing")]
+        tracing::trace!(new, "value put");
+        self.0.stats.value(bytes.len(), new);
+        Ok(result)
+    }
+
+    pub(crate) fn count_leaf(&self, len: usize) {
+        self.0.stats.leaf(len)
+    }
+
+    /// Returns the counters of the values persisted through the store
+    ///
+    /// Comparing `written` with `leaves` across a persist shows how many
+    /// bytes the backend grew by for the leaves that were changed.
+    pub fn stats(&self) -> Stats {
+        self.0.stats.get()
     }
 
     /// Restores a snapshot from Backend
@@ -139,34 +476,310 @@ impl<H: ByteHash> Store<H> {
         self.get_hash(&snap.hash)
     }
 
-    pub(crate) fn get_hash<T: Content<H>>(
+    /// Returns a snapshot of the value with `digest`, as returned by
+    /// `Snapshot::hash`
+    ///
+    /// The digest is not checked against the store, a snapshot of a value
+    /// missing from it fails to restore with a `NotFound` error.
+    pub fn snapshot<T: Content<H>>(&self, digest: H::Digest) -> Snapshot<T, H> {
+        Snapshot::new(digest, self)
+    }
+
+    /// Points the root `name` at `snapshot`, notifying its watchers if the
+    /// root was replaced
+    ///
+    /// Named roots are kept in memory, shared by all clones of the store.
+    /// Use `Root` to keep the latest state between program runs.
+    pub fn set_root<T: Content<H>>(
+        &self,
+        name: &str,
+        snapshot: &Snapshot<T, H>,
+    ) {
+        let mut roots = self.0.roots.write();
+        let named = roots.entry(name.to_string()).or_default();
+        if named.digest == Some(snapshot.hash) {
+            return;
+        }
+        named.digest = Some(snapshot.hash);
+        // watchers that dropped their receiver are forgotten
+        named
+            .watchers
+            .retain(|watcher| watcher.send(snapshot.hash).is_ok());
+    }
+
+    /// Returns a snapshot of the root `name`, if it was set
+    pub fn root<T: Content<H>>(&self, name: &str) -> Option<Snapshot<T, H>> {
+        let digest = self.root_digest(name)?;
+        Some(Snapshot::new(digest, self))
+    }
+
+    // The digest of the root `name`, if it was set
+    pub(crate) fn root_digest(&self, name: &str) -> Option<H::Digest> {
+        self.0.roots.read().get(name)?.digest
+    }
+
+    /// Watches the root `name`, returning a receiver of the digest of every
+    /// snapshot the root is pointed at from now on
+    ///
+    /// Only replacements made through `set_root` on this store, or its
+    /// clones, are seen. The watch ends when the receiver is dropped.
+    pub fn watch(&self, name: &str) -> Receiver<H::Digest> {
+        let (watcher, receiver) = channel();
+        let mut roots = self.0.roots.write();
+        roots
+            .entry(name.to_string())
+            .or_default()
+            .watchers
+            .push(watcher);
+        receiver
+    }
+
+    /// Restores a snapshot from Backend, sharing the restored value
+    ///
+    /// While the returned value is alive, restoring the same digest again,
+    /// from this or any other snapshot, clones it instead of reading and
+    /// decoding it from the backend. Holding on to a shared value thereby
+    /// speeds up walking the snapshots that have it as a subtree.
+    ///
+    /// The store only keeps a weak reference, so values are not kept in
+    /// memory once the caller drops them.
+    pub fn restore_shared<T: Content<H> + Send + Sync>(
+        &self,
+        snap: &Snapshot<T, H>,
+    ) -> io::Result<Arc<T>> {
+        let restored = Arc::new(self.get_hash::<T>(&snap.hash)?);
+        let shared: Arc<dyn Any + Send + Sync> = restored.clone();
+        self.0.memo.insert(snap.hash, Arc::downgrade(&shared));
+        Ok(restored)
+    }
+
+    /// Preloads the top `depth` levels of a snapshot into the read cache
+    ///
+    /// Restoring the preloaded nodes afterwards is served from memory, so
+    /// the first queries on a freshly opened store do not have to wait on
+    /// the backend for every node on their path. A `depth` of 1 preloads
+    /// just the root node.
+    pub fn warm<C: Compound<H>>(
+        &self,
+        snapshot: &Snapshot<C, H>,
+        depth: usize,
+    ) -> io::Result<()> {
+        let mut level = vec![snapshot.hash];
+        for _ in 0..depth {
+            self.prefetch(&level);
+            let mut next = vec![];
+            for hash in level {
+                let (node, bytes) = self.get_encoded::<C>(&hash)?;
+                next.extend(
+                    node.children()
+                        .iter()
+                        .filter_map(|handle| handle.snapshot())
+                        .map(|snapshot| snapshot.hash),
+                );
+                self.0.cache.insert(hash, bytes);
+                self.0.warmed.write().insert(hash, &self.0.cache);
+            }
+            level = next;
+        }
+        Ok(())
+    }
+
+    /// Returns the digests out of `offered` that are missing from the store
+    ///
+    /// This is the receiving side of a replication: the sender offers the
+    /// digests of the nodes it is about to send, and only transfers the
+    /// ones asked for. Since values are content-addressed, a node that is
+    /// present implies its whole subtree is present as well.
+    pub fn wants(&self, offered: &[H::Digest]) -> Vec<H::Digest> {
+        let mut wanted: Vec<_> = offered
+            .iter()
+            .filter(|digest| !self.contains(digest))
+            .cloned()
+            .collect();
+        wanted.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+        wanted.dedup();
+        wanted
+    }
+
+    /// Copies a snapshot to another store, returning the number of values
+    /// transferred
+    ///
+    /// The children of each node are offered to `target`, and only the
+    /// subtrees it `wants` are descended into, so syncing a snapshot that
+    /// differs little from what the target already holds only transfers the
+    /// differing paths. The tree is copied depth first, each value written
+    /// right after its subtree, so only the nodes on the path being copied
+    /// are held in memory, and an interrupted sync never leaves a node in
+    /// the target without its subtree.
+    ///
+    /// Only the nodes of the structure are followed, snapshots stored within
+    /// its leaves are not copied along.
+    pub fn sync_to<C: Compound<H>>(
+        &self,
+        snapshot: &Snapshot<C, H>,
+        target: &Store<H>,
+    ) -> io::Result<usize> {
+        let mut transferred = 0;
+        for hash in target.wants(&[snapshot.hash]) {
+            transferred += self.sync_node::<C>(&hash, target)?;
+        }
+        target.flush()?;
+        Ok(transferred)
+    }
+
+    // Copies the node `hash` to `target` after the subtrees of its children
+    // that `target` wants, returning the number of values transferred
+    fn sync_node<C: Compound<H>>(
         &self,
         hash: &H::Digest,
-    ) -> io::Result<T> {
+        target: &Store<H>,
+    ) -> io::Result<usize> {
+        let (node, bytes) = self.get_encoded::<C>(hash)?;
+        let offered: Vec<_> = node
+            .children()
+            .iter()
+            .filter_map(|handle| handle.snapshot())
+            .map(|snapshot| snapshot.hash)
+            .collect();
+        drop(node);
+
+        let mut transferred = 0;
+        for child in target.wants(&offered) {
+            transferred += self.sync_node::<C>(&child, target)?;
+        }
+        target.put(*hash, &bytes)?;
+        Ok(transferred + 1)
+    }
+
+    pub(crate) fn contains(&self, hash: &H::Digest) -> bool {
+        self.0.generations.iter().any(|gen| gen.get(hash).is_ok())
+    }
+
+    pub(crate) fn prefetch(&self, digests: &[H::Digest]) {
         for gen in self.0.generations.as_ref() {
-            if let Ok(read) = gen.read().get(hash) {
-                let mut source = Source::new(read, self);
-                return T::restore(&mut source);
+            gen.prefetch(digests)
+        }
+    }
+
+    // Reads a value from the first generation holding it
+    //
+    // Errors other than the value not being found, such as a backend
+    // detecting a corrupted value, are returned instead of trying the next
+    // generation.
+    fn read(&self, hash: &H::Digest) -> io::Result<Reader<'_>> {
+        for gen in self.0.generations.as_ref() {
+            match gen.get(hash) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+                result => return result,
             }
         }
         Err(io::Error::new(io::ErrorKind::NotFound, "Data not found"))
     }
 
+    pub(crate) fn get_bytes(&self, hash: &H::Digest) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![];
+        self.read(hash)?.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    pub(crate) fn get_hash<T: Content<H>>(
+        &self,
+        hash: &H::Digest,
+    ) -> io::Result<T> {
+        span!("restore", digest = crate::dump::prefix(hash.as_ref()));
+        if let Some(restored) = self.0.memo.get::<T>(hash) {
+            self.0.stats.restored(true);
+            #[cfg(feature = "tracing")]
+            tracing::trace!("restored from memo");
+            return Ok(restored);
+        }
+        if let Some(bytes) = self.0.cache.get::<Vec<u8>>(hash) {
+            self.0.stats.restored(true);
+            #[cfg(feature = "tracing")]
+            tracing::trace!("restored from cache");
+            let mut source = Source::new(Reader::Slice(&bytes[..]), self);
+            return T::restore(&mut source);
+        }
+        let read = {
+            span!("backend_get", digest = crate::dump::prefix(hash.as_ref()));
+            self.read(hash)?
+        };
+        self.0.stats.restored(false);
+        T::restore(&mut Source::new(read, self))
+    }
+
+    // Restores a value, along with the bytes it was decoded from
+    pub(crate) fn get_encoded<T: Content<H>>(
+        &self,
+        hash: &H::Digest,
+    ) -> io::Result<(T, Vec<u8>)> {
+        let mut bytes = vec![];
+        let recorder = Recorder {
+            read: self.read(hash)?,
+            bytes: &mut bytes,
+        };
+        let mut source = Source::new(Reader::Boxed(Box::new(recorder)), self);
+        let t = T::restore(&mut source)?;
+        drop(source);
+        Ok((t, bytes))
+    }
+
     /// Returns the approximate size of the store
     pub fn size(&self) -> usize {
         let mut size = 0;
         for gen in self.0.generations.as_ref() {
-            size += gen.read().size();
+            size += gen.size();
         }
         size
     }
+
+    /// Describes the backends, caches and counters of the store, as shown
+    /// by its `Debug` output
+    pub fn describe(&self) -> Description {
+        let cache_entries = self.0.warmed.read().live(&self.0.cache);
+        Description {
+            backends: self.0.backends.clone(),
+            size: self.size(),
+            cache_pages: CACHE_PAGES,
+            cache_page_size: CACHE_PAGE_SIZE,
+            cache_entries,
+            shared: self.0.memo.live(),
+            shared_capacity: self.0.memo.capacity(),
+            roots: self
+                .0
+                .roots
+                .read()
+                .values()
+                .filter(|named| named.digest.is_some())
+                .count(),
+            path: self.0.path.clone(),
+            stats: self.stats(),
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+
+    use std::cell::Cell;
+
+    use crate::annotations::Cardinality;
     use crate::tests::tempfile::tempdir;
-    use crate::Blake2b;
+    use crate::{Blake2b, Handle};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn store_is_send_sync() {
+        assert_send_sync::<Store<Blake2b>>()
+    }
+
+    #[test]
+    fn snapshot_is_send_sync() {
+        assert_send_sync::<Snapshot<Vec<u64>, Blake2b>>();
+        assert_send_sync::<Shared<Vec<u64>, Blake2b>>()
+    }
 
     #[test]
     fn should_create_directory() {
@@ -180,6 +793,77 @@ mod test {
         assert!(sub_dir.exists());
     }
 
+    #[test]
+    fn describe() {
+        let dir = tempdir().unwrap();
+        let store = Store::<Blake2b>::new(dir.path()).unwrap();
+        let snapshot = store.persist(&mut 42u64).unwrap();
+        store.set_root("state", &snapshot);
+        // watched but never set
+        let _watch = store.watch("pending");
+        let _shared = store.restore_shared(&snapshot).unwrap();
+
+        let description = store.describe();
+        assert_eq!(description.backends.len(), 1);
+        assert!(description.backends[0].contains("DiskBackend"));
+        assert_eq!(description.cache_entries, 0);
+        assert_eq!(description.shared, 1);
+        assert!(description.shared_capacity >= 1);
+        assert_eq!(description.roots, 1);
+        assert_eq!(description.path.as_deref(), Some(dir.path()));
+        assert_eq!(description.stats.values, 1);
+
+        let debug = format!("{:?}", store);
+        assert!(debug.starts_with("Store { backends: ["));
+        assert!(debug.contains("roots: 1"));
+
+        let volatile = Store::<Blake2b>::volatile().unwrap();
+        assert_eq!(volatile.describe().path, None);
+    }
+
+    #[derive(Clone, Default)]
+    struct Tree(Vec<Handle<Self, Blake2b>>);
+
+    impl Content<Blake2b> for Tree {
+        fn persist(&mut self, sink: &mut Sink<Blake2b>) -> io::Result<()> {
+            self.0.persist(sink)
+        }
+
+        fn restore(source: &mut Source<Blake2b>) -> io::Result<Self> {
+            Ok(Tree(Vec::restore(source)?))
+        }
+    }
+
+    impl Compound<Blake2b> for Tree {
+        type Leaf = u64;
+        type Annotation = Cardinality<u64>;
+
+        fn children(&self) -> &[Handle<Self, Blake2b>] {
+            &self.0
+        }
+
+        fn children_mut(&mut self) -> &mut [Handle<Self, Blake2b>] {
+            &mut self.0
+        }
+    }
+
+    #[test]
+    fn describe_cache() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let leaves = |n| Tree((0..n).map(Handle::new_leaf).collect());
+        let mut tree = Tree(vec![
+            Handle::new_node(leaves(2)),
+            Handle::new_node(leaves(3)),
+        ]);
+        let snapshot = store.persist(&mut tree).unwrap();
+        assert_eq!(store.describe().cache_entries, 0);
+
+        store.warm(&snapshot, 1).unwrap();
+        assert_eq!(store.describe().cache_entries, 1);
+        store.warm(&snapshot, 2).unwrap();
+        assert_eq!(store.describe().cache_entries, 3);
+    }
+
     #[test]
     fn should_allow_two() {
         let dir = tempdir().unwrap();
@@ -189,4 +873,94 @@ mod test {
         }
         let _store = Store::<Blake2b>::new(dir.path()).unwrap();
     }
+
+    #[test]
+    fn read_while_writing() {
+        let dir = tempdir().unwrap();
+        let store = Store::<Blake2b>::new(dir.path()).unwrap();
+        let snapshot = store.persist(&mut 42u64).unwrap();
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let snapshot = snapshot.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        assert_eq!(snapshot.restore().unwrap(), 42);
+                    }
+                })
+            })
+            .collect();
+
+        for mut i in 0..1000u64 {
+            let snapshot = store.persist(&mut i).unwrap();
+            assert_eq!(store.restore(&snapshot).unwrap(), i);
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+
+    thread_local! {
+        static RESTORED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    // Value counting how often it is restored
+    #[derive(Clone)]
+    struct Counted(u64);
+
+    impl Content<Blake2b> for Counted {
+        fn persist(&mut self, sink: &mut Sink<Blake2b>) -> io::Result<()> {
+            self.0.persist(sink)
+        }
+
+        fn restore(source: &mut Source<Blake2b>) -> io::Result<Self> {
+            RESTORED.with(|r| r.set(r.get() + 1));
+            Ok(Counted(u64::restore(source)?))
+        }
+    }
+
+    #[test]
+    fn restore_shared() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let snapshot = store.persist(&mut Counted(42)).unwrap();
+
+        let shared = store.restore_shared(&snapshot).unwrap();
+        assert_eq!(RESTORED.with(|r| r.get()), 1);
+        for _ in 0..10 {
+            assert_eq!(store.restore(&snapshot).unwrap().0, 42);
+        }
+        assert_eq!(RESTORED.with(|r| r.get()), 1);
+
+        drop(shared);
+        assert_eq!(store.restore(&snapshot).unwrap().0, 42);
+        assert_eq!(RESTORED.with(|r| r.get()), 2);
+    }
+
+    #[test]
+    fn watch_root() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        assert!(store.root::<u64>("state").is_none());
+
+        let watching = store.watch("state");
+        let other = store.watch("other");
+
+        let first = store.persist(&mut 1u64).unwrap();
+        let second = store.persist(&mut 2u64).unwrap();
+        let clone = store.clone();
+        clone.set_root("state", &first);
+        // unchanged, not notified again
+        clone.set_root("state", &first);
+        clone.set_root("state", &second);
+
+        let seen: Vec<_> = watching.try_iter().collect();
+        assert_eq!(seen, vec![*first.hash(), *second.hash()]);
+        assert!(other.try_recv().is_err());
+
+        let root = store.root::<u64>("state").unwrap();
+        assert_eq!(store.restore(&root).unwrap(), 2);
+
+        drop(watching);
+        store.set_root("state", &first);
+        assert!(store.0.roots.read()["state"].watchers.is_empty());
+    }
 }
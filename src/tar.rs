@@ -0,0 +1,361 @@
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+
+use bytehash::ByteHash;
+
+use crate::backend::check_digest;
+use crate::dump::{hex, parse_hex};
+use crate::{Compound, Snapshot, Store};
+
+const BLOCK: usize = 512;
+
+// first line of the manifest, naming the format and its version
+const MANIFEST_MAGIC: &str = "kelvin-backup 1";
+const MANIFEST: &str = "manifest";
+// directory of the values, each named after the hex of its digest
+const BLOBS: &str = "blobs";
+
+// upper bound of the entries read, against malformed archives
+const MAX_ENTRY: u64 = 1 << 30;
+
+// Values still to write, parents being written after their children
+enum Pending<D> {
+    Node(D),
+    Value(D, Vec<u8>),
+}
+
+impl<H: ByteHash> Store<H> {
+    /// Writes the values of `snapshot` to `writer` as a tar archive,
+    /// returning the number of values written
+    ///
+    /// The archive starts with a `manifest` entry, naming the root and the
+    /// length of the digests, followed by every node as a `blobs/<digest>`
+    /// entry. Nodes are written children first, and subtrees shared within
+    /// the tree only once. The archive is written as the tree is walked, so
+    /// it can be piped to other tools without being buffered whole.
+    ///
+    /// Like `sync_to`, only the nodes of the structure are followed,
+    /// snapshots stored within its leaves are not backed up along.
+    pub fn backup_tar<C, W>(
+        &self,
+        snapshot: &Snapshot<C, H>,
+        mut writer: W,
+    ) -> io::Result<usize>
+    where
+        C: Compound<H>,
+        W: Write,
+    {
+        let digest_len = H::Digest::default().as_ref().len();
+        let manifest = format!(
+            "{}\ndigest-length {}\nroot {}\n",
+            MANIFEST_MAGIC,
+            digest_len,
+            hex(snapshot.hash().as_ref())
+        );
+        write_entry(&mut writer, "", MANIFEST, manifest.as_bytes())?;
+
+        let mut written = HashSet::new();
+        let mut pending = vec![Pending::Node(*snapshot.hash())];
+        while let Some(next) = pending.pop() {
+            match next {
+                Pending::Node(digest) => {
+                    if written.contains(&digest) {
+                        continue;
+                    }
+                    let (node, bytes) = self.get_encoded::<C>(&digest)?;
+                    pending.push(Pending::Value(digest, bytes));
+                    // reversed, so the first child is written first
+                    pending.extend(
+                        node.children()
+                            .iter()
+                            .rev()
+                            .filter_map(|handle| handle.snapshot())
+                            .map(|snapshot| Pending::Node(*snapshot.hash())),
+                    );
+                }
+                Pending::Value(digest, bytes) => {
+                    if written.insert(digest) {
+                        let name = hex(digest.as_ref());
+                        write_entry(&mut writer, BLOBS, &name, &bytes)?;
+                    }
+                }
+            }
+        }
+
+        // the end of the archive
+        writer.write_all(&[0; 2 * BLOCK])?;
+        writer.flush()?;
+        Ok(written.len())
+    }
+
+    /// Reads a tar archive written by `backup_tar` into the store,
+    /// returning a snapshot of its root
+    ///
+    /// Every value is checked against its digest before being stored, and
+    /// the root has to be in the store once the archive is read. Otherwise
+    /// an `InvalidData` error is returned, and the values read so far are
+    /// left in the store. Since they are written children first, none of
+    /// them is missing its subtree.
+    pub fn restore_tar<C, R>(&self, mut reader: R) -> io::Result<Snapshot<C, H>>
+    where
+        C: Compound<H>,
+        R: Read,
+    {
+        let root = match read_entry(&mut reader)? {
+            Some((path, manifest)) if path == MANIFEST => {
+                parse_manifest::<H>(&manifest)?
+            }
+            _ => return Err(invalid("archive does not start with a manifest")),
+        };
+
+        while let Some((path, bytes)) = read_entry(&mut reader)? {
+            let digest = path
+                .strip_prefix(BLOBS)
+                .and_then(|name| name.strip_prefix('/'))
+                .and_then(parse_hex::<H>)
+                .ok_or_else(|| invalid("unexpected entry in archive"))?;
+            check_digest::<H>(&bytes, &digest)?;
+            self.put(digest, &bytes)?;
+        }
+        self.flush()?;
+
+        if !self.contains(&root) {
+            return Err(invalid("root missing from the archive"));
+        }
+        Ok(Snapshot::new(root, self))
+    }
+}
+
+// Writes a regular file entry, padded to whole blocks
+fn write_entry<W: Write>(
+    writer: &mut W,
+    prefix: &str,
+    name: &str,
+    bytes: &[u8],
+) -> io::Result<()> {
+    if name.len() > 100 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "digests too long to name tar entries",
+        ));
+    }
+    let mut header = [0u8; BLOCK];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    write_octal(&mut header[100..108], 0o644);
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    write_octal(&mut header[124..136], bytes.len() as u64);
+    write_octal(&mut header[136..148], 0);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    let checksum = checksum(&header);
+    write_octal(&mut header[148..155], checksum);
+    header[155] = b' ';
+
+    writer.write_all(&header)?;
+    writer.write_all(bytes)?;
+    writer.write_all(&[0; BLOCK][..padding(bytes.len() as u64)])
+}
+
+// Reads the next entry, returning its path and contents, or `None` at the
+// end of the archive
+fn read_entry<R: Read>(
+    reader: &mut R,
+) -> io::Result<Option<(String, Vec<u8>)>> {
+    let mut header = [0u8; BLOCK];
+    reader.read_exact(&mut header)?;
+    if header.iter().all(|byte| *byte == 0) {
+        return Ok(None);
+    }
+    if read_octal(&header[148..156])? != checksum(&header) {
+        return Err(invalid("tar header checksum mismatch"));
+    }
+    if header[156] != b'0' && header[156] != 0 {
+        return Err(invalid("unexpected tar entry type"));
+    }
+
+    let name = read_str(&header[..100])?;
+    let prefix = read_str(&header[345..500])?;
+    let path = if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", prefix, name)
+    };
+
+    let len = read_octal(&header[124..136])?;
+    if len > MAX_ENTRY {
+        return Err(invalid("tar entry too long"));
+    }
+    let mut bytes = vec![];
+    (&mut *reader).take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let mut pad = [0u8; BLOCK];
+    reader.read_exact(&mut pad[..padding(len)])?;
+    Ok(Some((path, bytes)))
+}
+
+// Parses the manifest, returning the root
+fn parse_manifest<H: ByteHash>(manifest: &[u8]) -> io::Result<H::Digest> {
+    let manifest = std::str::from_utf8(manifest)
+        .map_err(|_| invalid("manifest is not UTF-8"))?;
+    let mut lines = manifest.lines();
+    if lines.next() != Some(MANIFEST_MAGIC) {
+        return Err(invalid("unsupported backup format"));
+    }
+    let digest_len = H::Digest::default().as_ref().len().to_string();
+    let mut root = None;
+    for line in lines {
+        match line.split_once(' ') {
+            Some(("digest-length", len)) if len == digest_len => (),
+            Some(("digest-length", _)) => {
+                return Err(invalid("backup uses digests of another length"))
+            }
+            Some(("root", digest)) => root = parse_hex::<H>(digest),
+            _ => return Err(invalid("unknown line in manifest")),
+        }
+    }
+    root.ok_or_else(|| invalid("manifest without a root"))
+}
+
+// Sum of the header bytes, with the checksum field taken as spaces
+fn checksum(header: &[u8; BLOCK]) -> u64 {
+    header
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| match i {
+            148..=155 => b' ' as u64,
+            _ => *byte as u64,
+        })
+        .sum()
+}
+
+// Writes `n` as zero-padded octal digits, followed by a NUL
+fn write_octal(field: &mut [u8], n: u64) {
+    let digits = format!("{:01$o}", n, field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+fn read_octal(field: &[u8]) -> io::Result<u64> {
+    let digits = read_str(field)?.trim_matches(' ');
+    u64::from_str_radix(digits, 8)
+        .map_err(|_| invalid("malformed number in tar header"))
+}
+
+// A NUL-terminated string field
+fn read_str(field: &[u8]) -> io::Result<&str> {
+    let len = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    std::str::from_utf8(&field[..len])
+        .map_err(|_| invalid("malformed tar header"))
+}
+
+// Bytes padding an entry of `len` bytes to whole blocks
+fn padding(len: u64) -> usize {
+    (BLOCK - (len % BLOCK as u64) as usize) % BLOCK
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::annotations::Cardinality;
+    use crate::{Blake2b, Content, Handle, LeafIterable, Sink, Source};
+
+    #[derive(Clone, Default)]
+    struct Tree(Vec<Handle<Self, Blake2b>>);
+
+    impl Content<Blake2b> for Tree {
+        fn persist(&mut self, sink: &mut Sink<Blake2b>) -> io::Result<()> {
+            self.0.persist(sink)
+        }
+
+        fn restore(source: &mut Source<Blake2b>) -> io::Result<Self> {
+            Ok(Tree(Vec::restore(source)?))
+        }
+    }
+
+    impl Compound<Blake2b> for Tree {
+        type Leaf = u64;
+        type Annotation = Cardinality<u64>;
+
+        fn children(&self) -> &[Handle<Self, Blake2b>] {
+            &self.0
+        }
+
+        fn children_mut(&mut self) -> &mut [Handle<Self, Blake2b>] {
+            &mut self.0
+        }
+    }
+
+    fn leaves(tree: &Tree) -> Vec<u64> {
+        tree.iter().map(|leaf| *leaf.unwrap()).collect()
+    }
+
+    fn backup() -> (Vec<u8>, Snapshot<Tree, Blake2b>) {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let shared = Tree((0..3).map(Handle::new_leaf).collect());
+        let mut tree = Tree(vec![
+            Handle::new_node(shared.clone()),
+            Handle::new_leaf(7),
+            Handle::new_node(Tree(vec![Handle::new_node(shared)])),
+        ]);
+        let snapshot = store.persist(&mut tree).unwrap();
+
+        let mut tar = vec![];
+        // the root, the shared node once, and the node above it
+        assert_eq!(store.backup_tar(&snapshot, &mut tar).unwrap(), 3);
+        (tar, snapshot)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (tar, snapshot) = backup();
+        assert_eq!(tar.len() % BLOCK, 0);
+        assert_eq!(&tar[..8], b"manifest");
+        assert_eq!(&tar[257..263], b"ustar\0");
+
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let restored = store.restore_tar::<Tree, _>(&tar[..]).unwrap();
+        assert_eq!(restored.hash(), snapshot.hash());
+        let tree = store.restore(&restored).unwrap();
+        assert_eq!(leaves(&tree), vec![0, 1, 2, 7, 0, 1, 2]);
+    }
+
+    fn restore_error(tar: &[u8]) -> io::ErrorKind {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        match store.restore_tar::<Tree, _>(tar) {
+            Ok(_) => panic!("corrupted archive restored"),
+            Err(e) => e.kind(),
+        }
+    }
+
+    #[test]
+    fn corrupted() {
+        let (tar, _) = backup();
+
+        // a value byte of the first blob, after the manifest entry
+        let mut flipped = tar.clone();
+        flipped[3 * BLOCK] ^= 1;
+        assert_eq!(restore_error(&flipped), io::ErrorKind::InvalidData);
+
+        let mut flipped = tar.clone();
+        flipped[0] ^= 1;
+        assert_eq!(restore_error(&flipped), io::ErrorKind::InvalidData);
+
+        // the manifest alone, and a cut off archive
+        let end = [0; 2 * BLOCK];
+        let manifest_only = [&tar[..2 * BLOCK], &end[..]].concat();
+        assert_eq!(restore_error(&manifest_only), io::ErrorKind::InvalidData);
+        assert_eq!(
+            restore_error(&tar[..3 * BLOCK]),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt::Debug;
+use std::io::{self, Cursor, Read};
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::backend::{Backend, MemBackend, PutResult, Reader};
+use crate::{
+    Blake2b, ByteHash, Compound, LeafIterable, Store, VerifyingBackend,
+};
+
+/// A corruption of the values read through a `CorruptingBackend`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Corruption {
+    /// Flips the bit with this index, wrapping around the length of the
+    /// value
+    FlipBit(usize),
+    /// Cuts the value off after this many bytes, wrapping around its length
+    /// so that it is always shortened
+    Truncate(usize),
+}
+
+impl Corruption {
+    fn apply(self, bytes: &mut Vec<u8>) {
+        if bytes.is_empty() {
+            return;
+        }
+        match self {
+            Corruption::FlipBit(bit) => {
+                let bit = bit % (bytes.len() * 8);
+                bytes[bit / 8] ^= 1 << (bit % 8)
+            }
+            Corruption::Truncate(len) => bytes.truncate(len % bytes.len()),
+        }
+    }
+}
+
+/// A backend wrapper returning corrupted values on demand
+///
+/// While a `Corruption` is set, it is applied to every value read. Writes
+/// are passed through unchanged, so the wrapped backend keeps the correct
+/// values.
+pub struct CorruptingBackend<B> {
+    inner: B,
+    corruption: RwLock<Option<Corruption>>,
+}
+
+impl<B> CorruptingBackend<B> {
+    /// Wraps `inner`, initially reading its values unchanged
+    pub fn new(inner: B) -> Self {
+        CorruptingBackend {
+            inner,
+            corruption: RwLock::new(None),
+        }
+    }
+
+    /// Sets the corruption to apply to the values read from now on, or
+    /// `None` to read them unchanged
+    pub fn corrupt(&self, corruption: Option<Corruption>) {
+        *self.corruption.write() = corruption
+    }
+}
+
+impl<H, B> Backend<H> for CorruptingBackend<B>
+where
+    H: ByteHash,
+    B: Backend<H>,
+{
+    fn get<'a>(&'a self, digest: &H::Digest) -> io::Result<Reader<'a>> {
+        let read = self.inner.get(digest)?;
+        match *self.corruption.read() {
+            None => Ok(read),
+            Some(corruption) => {
+                let mut bytes = vec![];
+                { read }.read_to_end(&mut bytes)?;
+                corruption.apply(&mut bytes);
+                Ok(Reader::Owned(Cursor::new(bytes)))
+            }
+        }
+    }
+
+    fn put(&self, digest: H::Digest, bytes: &[u8]) -> io::Result<PutResult> {
+        self.inner.put(digest, bytes)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn prefetch(&self, digests: &[H::Digest]) {
+        self.inner.prefetch(digests)
+    }
+
+    fn size(&self) -> usize {
+        self.inner.size()
+    }
+}
+
+/// Checks that corrupted values of `content` are never restored silently
+///
+/// `content` is persisted, and restored with all its leaves under each of
+/// `corruptions` in turn. Through a `VerifyingBackend`, every corruption
+/// has to be reported as `InvalidData`. Without one, restoring must not
+/// panic, and truncated values have to be reported as errors, while
+/// flipped bits may go unnoticed.
+pub fn check_corruption<C, I>(content: &C, corruptions: I) -> io::Result<()>
+where
+    C: Compound<Blake2b>,
+    C::Leaf: PartialEq + Debug,
+    I: IntoIterator<Item = Corruption>,
+{
+    let leaves = |store: &Store<Blake2b>, digest| -> io::Result<Vec<C::Leaf>> {
+        let restored: C = store.get_hash(digest)?;
+        restored.iter().map(|leaf| leaf.cloned()).collect()
+    };
+
+    let backend = Arc::new(CorruptingBackend::new(MemBackend::new()));
+    let digest = {
+        let store = Store::with_backend(backend.clone());
+        *store.persist(&mut content.clone())?.hash()
+    };
+    let verified = Store::with_backend(VerifyingBackend::new(backend.clone()));
+    let unverified = Store::with_backend(backend.clone());
+    let expected = leaves(&unverified, &digest)?;
+
+    for corruption in corruptions {
+        backend.corrupt(Some(corruption));
+        match leaves(&verified, &digest) {
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => (),
+            Err(e) => panic!("{:?} reported as {:?}", corruption, e),
+            Ok(_) => panic!("{:?} restored silently", corruption),
+        }
+        let restored = leaves(&unverified, &digest);
+        if let Corruption::Truncate(_) = corruption {
+            assert!(restored.is_err(), "{:?} restored silently", corruption);
+        }
+    }
+
+    backend.corrupt(None);
+    assert_eq!(leaves(&verified, &digest)?, expected);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::Snapshot;
+
+    fn restore(
+        backend: &Arc<CorruptingBackend<MemBackend<Blake2b>>>,
+        snapshot: &Snapshot<Vec<String>, Blake2b>,
+    ) -> io::Result<Vec<String>> {
+        Store::with_backend(backend.clone()).restore(snapshot)
+    }
+
+    #[test]
+    fn corrupts_reads() {
+        let backend = Arc::new(CorruptingBackend::new(MemBackend::new()));
+        let store = Store::with_backend(backend.clone());
+        let mut value = vec![String::from("kelvin"), String::from("merkle")];
+        let snapshot = store.persist(&mut value).unwrap();
+
+        backend.corrupt(Some(Corruption::Truncate(20)));
+        let err = restore(&backend, &snapshot).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        // the last bit of the last character, turning an 'e' into a 'd'
+        backend.corrupt(Some(Corruption::FlipBit(36 * 8 - 8)));
+        assert_eq!(restore(&backend, &snapshot).unwrap()[1], "merkld");
+
+        backend.corrupt(None);
+        assert_eq!(restore(&backend, &snapshot).unwrap(), value);
+    }
+}
@@ -0,0 +1,81 @@
+use std::io;
+
+use crate::backend::{MemBackend, Reader};
+use crate::{Blake2b, Content, Source, Store};
+
+/// Restores a `T` from `bytes`, as if they were a value read from a store
+///
+/// Handles to other values are restored as references into an empty store,
+/// so only the value itself is decoded. Used by the fuzz targets, which call
+/// it with arbitrary bytes: decoding must never panic or allocate
+/// unboundedly, and report malformed input as an error.
+pub fn restore_bytes<T: Content<Blake2b>>(bytes: &[u8]) -> io::Result<T> {
+    EMPTY.with(|store| {
+        let mut source = Source::new(Reader::Slice(bytes), store);
+        T::restore(&mut source)
+    })
+}
+
+/// Returns the encoding of `content`, as read back by `restore_bytes`
+///
+/// Values referenced by handles are persisted as well, into a store kept in
+/// memory for this purpose.
+pub fn persist_bytes<T: Content<Blake2b>>(
+    content: &mut T,
+) -> io::Result<Vec<u8>> {
+    SCRATCH.with(|store| {
+        let snapshot = store.persist(content)?;
+        store.get_bytes(snapshot.hash())
+    })
+}
+
+thread_local! {
+    // reusing the stores keeps the memory of their caches, which is not
+    // released on drop, from growing with every call
+    //
+    // nothing is ever written to `EMPTY`
+    static EMPTY: Store<Blake2b> = Store::with_backend(MemBackend::new());
+    static SCRATCH: Store<Blake2b> = Store::with_backend(MemBackend::new());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use quickcheck::quickcheck;
+
+    quickcheck! {
+        fn decode_arbitrary_bytes(bytes: Vec<u8>) -> bool {
+            let _ = restore_bytes::<u64>(&bytes);
+            let _ = restore_bytes::<i128>(&bytes);
+            let _ = restore_bytes::<Option<u16>>(&bytes);
+            let _ = restore_bytes::<String>(&bytes);
+            let _ = restore_bytes::<Vec<(u8, String)>>(&bytes);
+            let _ = restore_bytes::<Box<Option<Vec<u32>>>>(&bytes);
+            true
+        }
+    }
+
+    #[test]
+    fn persist_restore_bytes() {
+        let mut value = (7u64, vec![Some(String::from("kelvin")), None]);
+        let bytes = persist_bytes(&mut value).unwrap();
+        assert_eq!(
+            restore_bytes::<(u64, Vec<Option<String>>)>(&bytes).unwrap(),
+            value
+        );
+    }
+
+    #[test]
+    fn invalid_option_tag() {
+        let err = restore_bytes::<Option<u8>>(&[2, 0]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn oversized_vec_length() {
+        let bytes = u64::MAX.to_be_bytes();
+        let err = restore_bytes::<Vec<u64>>(&bytes).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,43 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use crate::map::KVPair;
+use crate::{ByteHash, Compound, LeafIterable};
+
+/// Asserts that the map `structure` holds exactly the entries of `model`
+///
+/// Every leaf is read, so entries lost or duplicated anywhere in the
+/// structure are caught right away, not only once they are looked up. The
+/// model can be any map iterating over references to its entries, like a
+/// `HashMap` or a `BTreeMap`.
+pub fn assert_equiv<'m, C, K, V, H, M>(structure: &C, model: &'m M)
+where
+    C: Compound<H>,
+    C::Leaf: KVPair<K, V>,
+    H: ByteHash,
+    K: 'm + Ord + Clone + Debug,
+    V: 'm + PartialEq + Clone + Debug,
+    &'m M: IntoIterator<Item = (&'m K, &'m V)>,
+{
+    let mut found = BTreeMap::new();
+    for leaf in structure.iter() {
+        let leaf = leaf.expect("reading the structure");
+        if found
+            .insert(leaf.key().clone(), leaf.val().clone())
+            .is_some()
+        {
+            panic!("key {:?} found twice in the structure", leaf.key())
+        }
+    }
+    let expected: BTreeMap<_, _> = model
+        .into_iter()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    assert!(
+        found == expected,
+        "structure differs from the model\n  \
+         expected: {:?}\n     found: {:?}",
+        expected,
+        found
+    );
+}
@@ -0,0 +1,188 @@
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::backend::{Backend, PutResult, Reader};
+use crate::ByteHash;
+
+#[cfg(feature = "filesystem")]
+use std::{fmt::Debug, path::Path, sync::Arc};
+
+#[cfg(feature = "filesystem")]
+use crate::{backend::DiskBackend, Blake2b, Compound, LeafIterable, Store};
+
+/// A fault injected by a `FaultyBackend`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// The operation returns an error, later operations succeed
+    Fail,
+    /// A put stores only the first half of the value, and reports success
+    ShortWrite,
+    /// The operation and all following ones report success, without
+    /// reaching the wrapped backend
+    Crash,
+}
+
+/// A backend wrapper injecting a `Fault` on the `n`th write operation
+///
+/// Puts and flushes count as write operations, starting from zero. Reads
+/// are passed through unchanged.
+pub struct FaultyBackend<B> {
+    inner: B,
+    fault: Fault,
+    at: usize,
+    ops: AtomicUsize,
+}
+
+impl<B> FaultyBackend<B> {
+    /// Wraps `inner`, injecting `fault` on write operation number `at`
+    pub fn new(inner: B, fault: Fault, at: usize) -> Self {
+        FaultyBackend {
+            inner,
+            fault,
+            at,
+            ops: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns true if the fault was injected
+    pub fn injected(&self) -> bool {
+        self.ops.load(Ordering::SeqCst) > self.at
+    }
+
+    // Returns the fault to inject into the next write operation, if any
+    fn next_op(&self) -> Option<Fault> {
+        let n = self.ops.fetch_add(1, Ordering::SeqCst);
+        match self.fault {
+            Fault::Crash if n >= self.at => Some(Fault::Crash),
+            fault if n == self.at => Some(fault),
+            _ => None,
+        }
+    }
+}
+
+fn fault_error() -> io::Error {
+    io::Error::other("Injected fault")
+}
+
+impl<H, B> Backend<H> for FaultyBackend<B>
+where
+    H: ByteHash,
+    B: Backend<H>,
+{
+    fn get<'a>(&'a self, digest: &H::Digest) -> io::Result<Reader<'a>> {
+        self.inner.get(digest)
+    }
+
+    fn put(&self, digest: H::Digest, bytes: &[u8]) -> io::Result<PutResult> {
+        match self.next_op() {
+            None => self.inner.put(digest, bytes),
+            Some(Fault::Fail) => Err(fault_error()),
+            Some(Fault::ShortWrite) => {
+                self.inner.put(digest, &bytes[..bytes.len() / 2])
+            }
+            Some(Fault::Crash) => Ok(PutResult::Ok),
+        }
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        match self.next_op() {
+            None | Some(Fault::ShortWrite) => self.inner.flush(),
+            Some(Fault::Fail) => Err(fault_error()),
+            Some(Fault::Crash) => Ok(()),
+        }
+    }
+
+    fn prefetch(&self, digests: &[H::Digest]) {
+        self.inner.prefetch(digests)
+    }
+
+    fn size(&self) -> usize {
+        self.inner.size()
+    }
+}
+
+/// Checks that a disk store stays consistent when crashing while persisting
+/// `content`
+///
+/// `content` is persisted and flushed into a fresh store under `dir`,
+/// crashing on every write operation in turn. The store reopened after
+/// each crash has to restore either all of `content`, or report its root
+/// as not found, and has to accept persisting `content` again.
+#[cfg(feature = "filesystem")]
+pub fn check_crash_consistency<C>(content: &C, dir: &Path) -> io::Result<()>
+where
+    C: Compound<Blake2b>,
+    C::Leaf: PartialEq + Debug,
+{
+    let leaves = |c: &C| -> io::Result<Vec<C::Leaf>> {
+        c.iter().map(|leaf| leaf.cloned()).collect()
+    };
+    let expected = leaves(content)?;
+
+    for at in 0.. {
+        let path = dir.join(format!("crash-{}", at));
+        let (hash, crashed) = {
+            let backend = Arc::new(FaultyBackend::new(
+                DiskBackend::<Blake2b>::new(&path)?,
+                Fault::Crash,
+                at,
+            ));
+            let store = Store::with_backend(backend.clone());
+            let snapshot = store.persist(&mut content.clone())?;
+            store.flush()?;
+            (*snapshot.hash(), backend.injected())
+        };
+
+        let store = Store::<Blake2b>::new(&path)?;
+        match store.get_hash::<C>(&hash) {
+            Ok(restored) => assert_eq!(leaves(&restored)?, expected),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                assert!(crashed, "root missing without a crash")
+            }
+            Err(e) => return Err(e),
+        }
+
+        let snapshot = store.persist(&mut content.clone())?;
+        store.flush()?;
+        assert_eq!(*snapshot.hash(), hash);
+        assert_eq!(leaves(&store.restore(&snapshot)?)?, expected);
+
+        if !crashed {
+            return Ok(());
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::backend::MemBackend;
+    use crate::{Blake2b, Store};
+
+    #[test]
+    fn fail() {
+        let store = Store::<Blake2b>::with_backend(FaultyBackend::new(
+            MemBackend::new(),
+            Fault::Fail,
+            1,
+        ));
+        store.persist(&mut 1u64).unwrap();
+        assert!(store.persist(&mut 2u64).is_err());
+        let snapshot = store.persist(&mut 2u64).unwrap();
+        assert_eq!(store.restore(&snapshot).unwrap(), 2);
+    }
+
+    #[test]
+    fn short_write() {
+        let store = Store::<Blake2b>::with_backend(FaultyBackend::new(
+            MemBackend::new(),
+            Fault::ShortWrite,
+            0,
+        ));
+        let snapshot = store.persist(&mut 42u64).unwrap();
+        let err = store.restore(&snapshot).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
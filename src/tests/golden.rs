@@ -0,0 +1,83 @@
+use std::fmt::Write;
+
+use crate::backend::MemBackend;
+use crate::{Blake2b, Content, Store};
+
+/// Returns the root digest of `content` as a hex string
+///
+/// The content is persisted into a store kept in memory for this purpose,
+/// which does not affect the digest, only the content and its encoding do.
+pub fn root_hash<C: Content<Blake2b>>(content: &mut C) -> String {
+    let snapshot = GOLDEN
+        .with(|store| store.persist(content))
+        .expect("in-memory persist");
+    let mut hex = String::with_capacity(64);
+    for byte in snapshot.hash() {
+        write!(hex, "{:02x}", byte).expect("writing to a String");
+    }
+    hex
+}
+
+/// Asserts that `content` persists to the root digest `expected`, given in
+/// hex
+///
+/// Used to check fixed structures against digests checked in with their
+/// tests. A different digest means the encoding or hashing order of some
+/// node has changed, and data persisted by earlier versions can no longer
+/// be found by its digest. If the change is intended, the expected digests
+/// have to be updated along with a note on the incompatibility.
+pub fn assert_root_hash<C: Content<Blake2b>>(content: &mut C, expected: &str) {
+    let found = root_hash(content);
+    assert!(
+        found == expected,
+        "root hash changed, persisted data would be lost\n  \
+         expected: {}\n     found: {}",
+        expected,
+        found
+    );
+}
+
+thread_local! {
+    // shared by all calls, since the memory of its cache is not released on
+    // drop
+    static GOLDEN: Store<Blake2b> = Store::with_backend(MemBackend::new());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn primitives() {
+        assert_root_hash(
+            &mut 0u64,
+            "81e47a19e6b29b0a65b9591762ce5143ed30d0261e5d24a3201752506b20f15c",
+        );
+        assert_root_hash(
+            &mut -1i128,
+            "681467b3337425fd38fa3983531ca1a6214de9264eebabdf9c9bc5d157d202b4",
+        );
+        assert_root_hash(
+            &mut String::from("kelvin"),
+            "57922454e27244ff3a78031eb41d1480f2a24c364b46152badd24228b83a8b96",
+        );
+        assert_root_hash(
+            &mut vec![1u8, 2, 3],
+            "69e2c536c5a64cd7c282a38033956f3d65ef936f5880353643e8e6349f9b182e",
+        );
+        assert_root_hash(
+            &mut (Some(7u32), None::<u32>),
+            "ea48144474d7898a3a731c0ecfa8a4589b8ba493df78a2102001ca9c1ecbbe24",
+        );
+        assert_root_hash(
+            &mut Box::new((0u8, ())),
+            "03170a2e7597b7b7e3d84c05391d139a62b157e78786d8c082f29dcf4c111314",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "root hash changed")]
+    fn changed() {
+        assert_root_hash(&mut 0u64, &"0".repeat(64));
+    }
+}
@@ -1,8 +1,29 @@
+mod corrupt;
+mod decode;
+mod equiv;
+mod fault;
+mod golden;
+mod quickcheck_annotation;
 mod quickcheck_map;
+mod quickcheck_proof;
+mod quickcheck_set;
+mod seed;
+mod stress;
+pub use corrupt::{check_corruption, CorruptingBackend, Corruption};
+pub use decode::{persist_bytes, restore_bytes};
+pub use equiv::assert_equiv;
+#[cfg(feature = "filesystem")]
+pub use fault::check_crash_consistency;
+pub use fault::{Fault, FaultyBackend};
+pub use golden::{assert_root_hash, root_hash};
 pub use quickcheck;
 pub use rand;
+pub use seed::{quickcheck_seeded, SEED_VAR};
+pub use stress::stress_store;
 pub use tempfile;
 
+use std::io;
+
 use crate::{ByteHash, Compound, HandleType};
 
 /// Trait to test for correct empty state of a structure
@@ -25,3 +46,32 @@ where
         }
     }
 }
+
+/// The operations of a set of `u8`, checked against a model by
+/// `quickcheck_set!`
+///
+/// Sets differ in their element types and method names, so the tests of each
+/// set structure implement this to adapt it.
+pub trait ModelSet: Sized {
+    /// Adds `e`, returning true if it was not present
+    fn insert(&mut self, e: u8) -> io::Result<bool>;
+
+    /// Returns true if `e` is present
+    fn contains(&self, e: u8) -> io::Result<bool>;
+
+    /// Adds the elements of `other`
+    fn union(&mut self, other: &Self) -> io::Result<()>;
+
+    /// Returns the elements, in any order
+    fn elements(&self) -> io::Result<Vec<u8>>;
+}
+
+/// Removal from a set, checked by `quickcheck_set!` along with the
+/// operations of `ModelSet`
+///
+/// Grow-only sets do not implement it, and are tested with
+/// `quickcheck_set!(new, grow_only)`, which never removes elements.
+pub trait ModelRemove: ModelSet {
+    /// Removes `e`, returning true if it was present
+    fn remove(&mut self, e: u8) -> io::Result<bool>;
+}
@@ -0,0 +1,65 @@
+/// Property test suite for annotations
+///
+/// Checks that combining annotations is associative, and that empty
+/// subtrees do not affect the result, for annotations created from
+/// arbitrary leaves. Annotations are compared by their persisted
+/// representation, so no `PartialEq` implementation is needed.
+///
+/// Defines test functions in the calling module, so it can only be used
+/// once per module.
+///
+/// Usage example: `quickcheck_annotation!(Cardinality<u64>, (u8, u8));`
+#[macro_export]
+macro_rules! quickcheck_annotation {
+    ($annotation:ty, $leaf:ty) => {
+        use $crate::tests::quickcheck::quickcheck as __quickcheck;
+
+        fn __annotate(leaf: &$leaf) -> $annotation {
+            <$annotation>::from(leaf)
+        }
+
+        fn __combine(elements: &[Option<$annotation>]) -> Option<$annotation> {
+            <$annotation as $crate::annotations::Combine<$annotation>>::combine(elements)
+        }
+
+        fn __same(a: Option<$annotation>, b: Option<$annotation>) -> bool {
+            let store = $crate::Store::<$crate::Blake2b>::volatile().unwrap();
+            match (a, b) {
+                (Some(mut a), Some(mut b)) => {
+                    store.persist(&mut a).unwrap().hash()
+                        == store.persist(&mut b).unwrap().hash()
+                }
+                (None, None) => true,
+                _ => false,
+            }
+        }
+
+        __quickcheck! {
+            fn annotation_associative(a: $leaf, b: $leaf, c: $leaf) -> bool {
+                let (a, b, c) =
+                    (Some(__annotate(&a)), Some(__annotate(&b)), Some(__annotate(&c)));
+                let left = __combine(&[__combine(&[a.clone(), b.clone()]), c.clone()]);
+                let right = __combine(&[a, __combine(&[b, c])]);
+                __same(left, right)
+            }
+
+            fn annotation_empty_identity(leaves: Vec<Option<$leaf>>) -> bool {
+                let annotations: Vec<_> =
+                    leaves.iter().map(|leaf| leaf.as_ref().map(__annotate)).collect();
+                let present: Vec<_> =
+                    annotations.iter().filter(|a| a.is_some()).cloned().collect();
+                __same(__combine(&annotations), __combine(&present))
+            }
+
+            fn annotation_fold(leaves: Vec<$leaf>) -> bool {
+                let annotations: Vec<_> =
+                    leaves.iter().map(|leaf| Some(__annotate(leaf))).collect();
+                let folded = annotations
+                    .iter()
+                    .cloned()
+                    .fold(None, |acc, a| __combine(&[acc, a]));
+                __same(__combine(&annotations), folded)
+            }
+        }
+    };
+}
@@ -1,31 +1,115 @@
 /// Model test suite for maps
 ///
 /// Usage example: `quickcheck_map!(|| HAMT::new());`
+///
+/// The operations are generated from a seed, printed by failing runs, which
+/// replays them when passed in the `KELVIN_QUICKCHECK_SEED` environment
+/// variable. See `tests::quickcheck_seeded`.
+///
+/// After each restore, the whole map is compared with the model, see
+/// `tests::assert_equiv`.
+///
+/// Maps ordered by key are tested with `quickcheck_ordered_map!` instead.
+///
+/// The operations are run against a store in a temporary directory, or in
+/// memory with `quickcheck_map!(|| HAMT::new(), volatile);`, which only
+/// tests the structure itself but runs without any IO.
 #[macro_export]
 macro_rules! quickcheck_map {
     ($new_map:expr) => {
+        $crate::quickcheck_map!(@suite $new_map, false, false, test_a, model,);
+    };
+    ($new_map:expr, volatile) => {
+        $crate::quickcheck_map!(@suite $new_map, false, true, test_a, model,);
+    };
+    ($new_map:expr, ordered) => {
+        $crate::quickcheck_map!(@ordered $new_map, false);
+    };
+    ($new_map:expr, ordered, volatile) => {
+        $crate::quickcheck_map!(@ordered $new_map, true);
+    };
+    (@ordered $new_map:expr, $volatile:expr) => {
+        $crate::quickcheck_map!(@suite $new_map, true, $volatile, test_a, model,
+                Op::First => {
+                    let a = test_a.first_key_value().unwrap().map(|l| *l);
+                    let b = model.iter().next().map(|(k, v)| (*k, *v));
+
+                    assert_eq!(a, b);
+                }
+                Op::Last => {
+                    let a = test_a.last_key_value().unwrap().map(|l| *l);
+                    let b = model.iter().next_back().map(|(k, v)| (*k, *v));
+
+                    assert_eq!(a, b);
+                }
+                Op::Nth(n) => {
+                    let a = test_a
+                        .iter_page(n as u64, 1)
+                        .next()
+                        .map(|l| *l.unwrap());
+                    let b = model.iter().nth(n as usize).map(|(k, v)| (*k, *v));
+
+                    assert_eq!(a, b);
+                }
+                Op::IterFrom(k) => {
+                    let a: Vec<_> =
+                        test_a.iter_from(&k).map(|l| *l.unwrap()).collect();
+                    let b: Vec<_> =
+                        model.range(k..).map(|(k, v)| (*k, *v)).collect();
+
+                    assert_eq!(a, b);
+                }
+                Op::IterRange(from, to) => {
+                    let (from, to) = (from.min(to), from.max(to));
+                    let a: Vec<_> = test_a
+                        .iter_from(&from)
+                        .map(|l| *l.unwrap())
+                        .take_while(|(k, _)| *k < to)
+                        .collect();
+                    let b: Vec<_> =
+                        model.range(from..to).map(|(k, v)| (*k, *v)).collect();
+
+                    assert_eq!(a, b);
+                }
+        );
+    };
+    // The map and model are named by the caller, so that the operations
+    // passed in can refer to them
+    (@suite $new_map:expr, $ordered:expr, $volatile:expr, $test_a:ident,
+     $model:ident, $($ordered_ops:tt)*) => {
         // mod inner_mod {
         use $crate::tests::tempfile::tempdir;
 
-        #[allow(unused)]
-        use std::collections::HashMap;
+        use std::collections::BTreeMap;
         use $crate::tests::CorrectEmptyState;
 
-        use $crate::tests::quickcheck::{quickcheck, Arbitrary, Gen};
+        use $crate::tests::quickcheck::{empty_shrinker, Arbitrary, Gen};
         #[allow(unused)]
-        use $crate::{annotations::Count, KeyValIterable, LeafIterable, Store};
+        use $crate::{
+            annotations::{Count, Paginate},
+            KeyValIterable, LeafIterable, Map, MapMut, Store,
+        };
 
         use $crate::tests::rand::Rng;
 
         const KEY_SPACE: u8 = 20;
+        // whether to generate the operations of ordered maps, and compare
+        // iteration results in order
+        const ORDERED: bool = $ordered;
+        // whether to run the operations against a store in memory, rather
+        // than in a temporary directory
+        const VOLATILE: bool = $volatile;
 
         #[derive(Clone, Debug)]
         pub enum Op {
             Insert(u8, u8),
             Get(u8),
             GetMut(u8),
+            Update(u8),
+            Replace(u8, u8),
             Remove(u8),
             RemoveAll,
+            Drain,
             Iter,
             IterMut,
             Values,
@@ -34,12 +118,19 @@ macro_rules! quickcheck_map {
             Persist,
             PersistRestore,
             Count,
+            Entry(u8, u8),
+            First,
+            Last,
+            Nth(u8),
+            IterFrom(u8),
+            IterRange(u8, u8),
         }
 
         impl Arbitrary for Op {
             fn arbitrary<G: Gen>(g: &mut G) -> Op {
                 let k: u8 = g.gen_range(0, KEY_SPACE);
-                let op = g.gen_range(0, 13);
+                let ops = if ORDERED { 22 } else { 17 };
+                let op = g.gen_range(0, ops);
                 match op {
                     0 => Op::Insert(k, g.gen()),
                     1 => Op::Iter,
@@ -54,57 +145,106 @@ macro_rules! quickcheck_map {
                     10 => Op::Persist,
                     11 => Op::PersistRestore,
                     12 => Op::Count,
+                    13 => Op::Drain,
+                    14 => Op::Update(k),
+                    15 => Op::Replace(k, g.gen()),
+                    16 => Op::Entry(k, g.gen()),
+                    17 => Op::First,
+                    18 => Op::Last,
+                    19 => Op::Nth(k),
+                    20 => Op::IterFrom(k),
+                    21 => Op::IterRange(k, g.gen_range(0, KEY_SPACE)),
                     _ => unreachable!(),
                 }
             }
+
+            // the sequence of operations is shrunk by quickcheck, dropping
+            // operations, and shrinking each of them with this
+            fn shrink(&self) -> Box<dyn Iterator<Item = Op>> {
+                match *self {
+                    Op::Insert(k, v) => {
+                        Box::new((k, v).shrink().map(|(k, v)| Op::Insert(k, v)))
+                    }
+                    Op::Get(k) => Box::new(k.shrink().map(Op::Get)),
+                    Op::GetMut(k) => Box::new(k.shrink().map(Op::GetMut)),
+                    Op::Update(k) => Box::new(k.shrink().map(Op::Update)),
+                    Op::Replace(k, v) => Box::new(
+                        (k, v).shrink().map(|(k, v)| Op::Replace(k, v)),
+                    ),
+                    Op::Remove(k) => Box::new(k.shrink().map(Op::Remove)),
+                    Op::Entry(k, v) => {
+                        Box::new((k, v).shrink().map(|(k, v)| Op::Entry(k, v)))
+                    }
+                    Op::Nth(n) => Box::new(n.shrink().map(Op::Nth)),
+                    Op::IterFrom(k) => Box::new(k.shrink().map(Op::IterFrom)),
+                    Op::IterRange(from, to) => Box::new(
+                        (from, to)
+                            .shrink()
+                            .map(|(from, to)| Op::IterRange(from, to)),
+                    ),
+                    _ => empty_shrinker(),
+                }
+            }
         }
 
         fn run_ops(ops: Vec<Op>) -> bool {
-            let dir = tempdir().unwrap();
-            let store = Store::<Blake2b>::new(&dir.path()).unwrap();
+            // the directory is removed when dropped, at the end of the run
+            let (store, _dir) = if VOLATILE {
+                (Store::<Blake2b>::volatile().unwrap(), None)
+            } else {
+                let dir = tempdir().unwrap();
+                (Store::<Blake2b>::new(&dir.path()).unwrap(), Some(dir))
+            };
 
-            let mut test_a = $new_map();
-            let mut model = HashMap::new();
+            let mut $test_a = $new_map();
+            let mut $model = BTreeMap::new();
 
             for op in ops {
                 match op {
                     Op::Insert(k, v) => {
-                        let a = test_a.insert(k, v).unwrap();
-                        let b = model.insert(k, v);
+                        let a = $test_a.insert(k, v).unwrap();
+                        let b = $model.insert(k, v);
                         assert_eq!(a, b);
                     }
 
                     Op::Iter => {
-                        let mut a: Vec<_> = test_a
+                        let mut a: Vec<_> = $test_a
                             .iter()
                             .map(|res| res.unwrap())
                             .cloned()
                             .collect();
-                        let mut b: Vec<_> = model
+                        let mut b: Vec<_> = $model
                             .iter()
                             .map(|(k, v)| (k.clone(), v.clone()))
                             .collect();
 
-                        a.sort();
-                        b.sort();
+                        if !ORDERED {
+                            a.sort();
+                            b.sort();
+                        }
 
                         assert_eq!(a, b);
                     }
 
                     Op::IterMut => {
-                        for (_, value) in test_a.iter_mut().map(|r| r.unwrap())
+                        for (_, value) in $test_a.iter_mut().map(|r| r.unwrap())
                         {
                             *value = value.wrapping_add(1)
                         }
 
-                        for (_, value) in model.iter_mut() {
+                        for (_, value) in $model.iter_mut() {
                             *value = value.wrapping_add(1)
                         }
                     }
 
                     Op::Get(k) => {
-                        let a = test_a.get(&k).unwrap();
-                        let b = model.get(&k);
+                        assert_eq!(
+                            $test_a.contains_key(&k).unwrap(),
+                            $model.contains_key(&k)
+                        );
+
+                        let a = $test_a.get(&k).unwrap();
+                        let b = $model.get(&k);
 
                         dbg!(a.is_some(), b.is_some());
 
@@ -119,109 +259,172 @@ macro_rules! quickcheck_map {
                     }
 
                     Op::GetMut(k) => {
-                        let a = test_a
+                        let a = $test_a
                             .get_mut(&k)
                             .unwrap()
                             .map(|mut val| *val = val.wrapping_add(1));
-                        let b = model
+                        let b = $model
                             .get_mut(&k)
                             .map(|val| *val = val.wrapping_add(1));
 
                         assert!(a == b)
                     }
 
+                    Op::Update(k) => {
+                        let a = $test_a
+                            .update(&k, |val| *val = val.wrapping_mul(3))
+                            .unwrap();
+                        let b = $model
+                            .get_mut(&k)
+                            .map(|val| *val = val.wrapping_mul(3))
+                            .is_some();
+
+                        assert!(a == b)
+                    }
+
+                    Op::Replace(k, v) => {
+                        let a = $test_a.replace(&k, v).unwrap();
+                        let b = $model
+                            .get_mut(&k)
+                            .map(|val| std::mem::replace(val, v));
+
+                        assert!(a == b)
+                    }
+
                     Op::Remove(k) => {
-                        let a = test_a.remove(&k).unwrap();
-                        let c = model.remove(&k);
+                        let a = $test_a.remove(&k).unwrap();
+                        let c = $model.remove(&k);
 
                         assert!(a == c);
                     }
 
                     Op::RemoveAll => {
-                        model.clear();
+                        $model.clear();
                         let mut keys = vec![];
-                        for (key, _) in test_a.iter().map(|res| res.unwrap()) {
+                        for (key, _) in $test_a.iter().map(|res| res.unwrap()) {
                             keys.push(key.clone());
                         }
                         for key in keys {
-                            test_a.remove(&key).unwrap();
+                            $test_a.remove(&key).unwrap();
                         }
-                        test_a.assert_correct_empty_state();
+                        $test_a.assert_correct_empty_state();
+                    }
+
+                    Op::Drain => {
+                        let mut a: Vec<_> =
+                            $test_a.drain().map(|res| res.unwrap()).collect();
+                        let mut b: Vec<_> =
+                            std::mem::take(&mut $model).into_iter().collect();
+
+                        if !ORDERED {
+                            a.sort();
+                            b.sort();
+                        }
+
+                        assert_eq!(a, b);
+                        $test_a.assert_correct_empty_state();
                     }
 
                     Op::Values => {
                         let mut a: Vec<_> =
-                            test_a.values().map(|v| *v.unwrap()).collect();
+                            $test_a.values().map(|v| *v.unwrap()).collect();
 
                         let mut c: Vec<_> =
-                            model.values().map(|v| *v).collect();
+                            $model.values().map(|v| *v).collect();
 
-                        a.sort();
-                        c.sort();
+                        if !ORDERED {
+                            a.sort();
+                            c.sort();
+                        }
 
                         assert!(a == c);
                     }
 
                     Op::ValuesMut => {
-                        let _res = test_a
+                        let _res = $test_a
                             .values_mut()
                             .map(|v| {
                                 let v = v.unwrap();
                                 *v = v.wrapping_add(1);
-                                ()
                             })
                             .collect::<Vec<_>>();
 
-                        let _res = model
+                        let _res = $model
                             .values_mut()
                             .map(|v| *v = v.wrapping_add(1))
                             .collect::<Vec<_>>();
 
                         let mut a: Vec<_> =
-                            test_a.values().map(|v| *v.unwrap()).collect();
+                            $test_a.values().map(|v| *v.unwrap()).collect();
 
                         let mut c: Vec<_> =
-                            model.values().map(|v| *v).collect();
+                            $model.values().map(|v| *v).collect();
 
-                        a.sort();
-                        c.sort();
+                        if !ORDERED {
+                            a.sort();
+                            c.sort();
+                        }
 
                         assert!(a == c);
                     }
                     Op::Keys => {
                         let mut a: Vec<_> =
-                            test_a.keys().map(|v| *v.unwrap()).collect();
+                            $test_a.keys().map(|v| *v.unwrap()).collect();
 
-                        let mut c: Vec<_> = model.keys().map(|k| *k).collect();
+                        let mut c: Vec<_> = $model.keys().map(|k| *k).collect();
 
-                        a.sort();
-                        c.sort();
+                        if !ORDERED {
+                            a.sort();
+                            c.sort();
+                        }
 
                         assert!(a == c);
                     }
                     Op::Persist => {
-                        store.persist(&mut test_a).unwrap();
+                        store.persist(&mut $test_a).unwrap();
                     }
                     Op::PersistRestore => {
-                        let snapshot = store.persist(&mut test_a).unwrap();
-                        test_a = store.restore(&snapshot).unwrap();
+                        let snapshot = store.persist(&mut $test_a).unwrap();
+                        $test_a = store.restore(&snapshot).unwrap();
+                        $crate::tests::assert_equiv(&$test_a, &$model);
                     }
                     Op::Count => {
-                        assert_eq!(test_a.count() as usize, model.len())
+                        assert_eq!($test_a.count() as usize, $model.len())
+                    }
+                    Op::Entry(k, v) => {
+                        let a = *$test_a.entry(k).unwrap().or_insert(v).unwrap();
+                        let b = *$model.entry(k).or_insert(v);
+
+                        assert_eq!(a, b);
                     }
+                    $($ordered_ops)*
+                    #[allow(unreachable_patterns)]
+                    _ => unreachable!("ordered operation on an unordered map"),
                 };
             }
             true
         }
 
-        quickcheck! {
-            fn map(ops: Vec<Op>) -> bool {
-                run_ops(ops)
-            }
+        #[test]
+        fn map() {
+            $crate::tests::quickcheck_seeded(run_ops as fn(Vec<Op>) -> bool)
         }
 
         use Op::*;
 
+        #[test]
+        fn shrink_op() {
+            // keys and values are shrunk one at a time
+            let shrunk: Vec<_> = Insert(3, 200).shrink().collect();
+            assert!(shrunk.iter().any(|op| matches!(op, Insert(0, 200))));
+            assert!(shrunk.iter().any(|op| matches!(op, Insert(3, 0))));
+            assert!(IterRange(4, 9).shrink().all(|op| match op {
+                IterRange(from, to) => from <= 4 && to <= 9,
+                _ => false,
+            }));
+            assert!(Persist.shrink().next().is_none());
+        }
+
         // regressions
         #[test]
         fn regression_pre_persist_fail() {
@@ -254,6 +457,20 @@ macro_rules! quickcheck_map {
             assert!(run_ops(vec![Insert(19, 45), Insert(7, 126), RemoveAll,]))
         }
 
+        #[test]
+        fn regression_drain_persisted() {
+            assert!(run_ops(vec![
+                Insert(3, 10),
+                Insert(12, 51),
+                Insert(8, 200),
+                Insert(17, 4),
+                PersistRestore,
+                Drain,
+                Insert(3, 11),
+                Count
+            ]))
+        }
+
         #[test]
         fn regression_get() {
             assert!(run_ops(vec![
@@ -300,3 +517,23 @@ macro_rules! quickcheck_map {
         }
     };
 }
+
+/// Model test suite for maps ordered by key
+///
+/// Runs the `quickcheck_map!` suite, comparing the results of iterating
+/// element by element with the model, in order rather than sorted. It also
+/// checks the ordered queries `first_key_value`, `last_key_value`,
+/// `iter_page` and `iter_from`.
+///
+/// Usage example: `quickcheck_ordered_map!(|| BTree::new());`, or
+/// `quickcheck_ordered_map!(|| BTree::new(), volatile);` to run against a
+/// store in memory
+#[macro_export]
+macro_rules! quickcheck_ordered_map {
+    ($new_map:expr) => {
+        $crate::quickcheck_map!($new_map, ordered);
+    };
+    ($new_map:expr, volatile) => {
+        $crate::quickcheck_map!($new_map, ordered, volatile);
+    };
+}
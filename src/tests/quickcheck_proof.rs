@@ -0,0 +1,122 @@
+/// Property test suite for merkle proofs of maps
+///
+/// Builds random maps of `u8` to `u8`, and checks the proofs produced for
+/// random keys against a model. Present keys have to be proven with their
+/// values, absent keys must not be provable at all. Proofs with a bit
+/// flipped, or checked against a flipped root hash, must never prove
+/// anything not in the map.
+///
+/// Usage example: `quickcheck_proofs!(BTree::new);`
+#[macro_export]
+macro_rules! quickcheck_proofs {
+    ($new_map:expr) => {
+        // in a module of its own, to not clash with the items of the tests
+        // it is used in
+        mod quickcheck_proofs {
+            use super::*;
+
+            use std::collections::BTreeMap;
+            use std::io;
+
+            use $crate::tests::{persist_bytes, restore_bytes};
+            #[allow(unused)]
+            use $crate::{
+                Blake2b, ByteHash, Compound, Map, MapMut, Proof, Snapshot,
+                Store,
+            };
+
+            type Digest = <Blake2b as ByteHash>::Digest;
+
+            fn prove<'a, C>(
+                snapshot: &Snapshot<C, Blake2b>,
+                k: &'a u8,
+            ) -> io::Result<Option<Proof<C, Blake2b>>>
+            where
+                C: Compound<Blake2b, Leaf = (u8, u8)>
+                    + Map<'a, u8, u8, u8, Blake2b>,
+            {
+                Proof::new(snapshot, &mut C::KeySearch::from(k))
+            }
+
+            // `proof` with the bit at `flip` of its encoding flipped, unless
+            // that makes it undecodable
+            fn mutate<C: 'static>(
+                proof: &Proof<C, Blake2b>,
+                flip: usize,
+            ) -> Option<Proof<C, Blake2b>> {
+                let mut bytes = persist_bytes(&mut proof.clone()).unwrap();
+                let bit = flip % (bytes.len() * 8);
+                bytes[bit / 8] ^= 1 << (bit % 8);
+                restore_bytes(&bytes).ok()
+            }
+
+            // the leaf proven by `proof` against `root`, if any
+            fn verify<C>(
+                proof: &Proof<C, Blake2b>,
+                root: &Digest,
+            ) -> Option<(u8, u8)>
+            where
+                C: Compound<Blake2b, Leaf = (u8, u8)>,
+            {
+                proof.verify(root).ok().flatten().map(|proven| proven.leaf)
+            }
+
+            fn run(entries: Vec<(u8, u8)>, k: u8, flip: usize) -> bool {
+                let store = Store::<Blake2b>::volatile().unwrap();
+
+                let mut map = $new_map();
+                let mut model = BTreeMap::new();
+                for (k, v) in entries {
+                    map.insert(k, v).unwrap();
+                    model.insert(k, v);
+                }
+                let snapshot = store.persist(&mut map).unwrap();
+                let root = *snapshot.hash();
+
+                let proven = prove(&snapshot, &k).unwrap();
+                let proof = match (proven, model.get(&k)) {
+                    (Some(proof), Some(v)) => {
+                        assert_eq!(verify(&proof, &root), Some((k, *v)));
+                        proof
+                    }
+                    (None, Some(_)) => panic!("no proof for a present key"),
+                    (Some(proof), None) => {
+                        // proving some other leaf on the way to the key
+                        match verify(&proof, &root) {
+                            Some((other, v)) => {
+                                assert!(other != k, "absent key proven");
+                                assert_eq!(model.get(&other), Some(&v));
+                            }
+                            None => panic!("proof does not verify"),
+                        }
+                        proof
+                    }
+                    (None, None) => return true,
+                };
+
+                // a proof must not verify against any other root
+                let mut other_root = root;
+                other_root[flip / 8 % other_root.len()] ^= 1 << (flip % 8);
+                assert_eq!(verify(&proof, &other_root), None);
+
+                // a flipped bit in a node encoding changes its hash, a
+                // flipped index leads off the path or to another leaf of
+                // the same node, so nothing false can be proven
+                if let Some(mutated) = mutate(&proof, flip) {
+                    if let Some((other, v)) = verify(&mutated, &root) {
+                        assert!(other != k, "mutated proof verifies");
+                        assert_eq!(model.get(&other), Some(&v));
+                    }
+                }
+                true
+            }
+
+            #[test]
+            fn proofs() {
+                $crate::tests::quickcheck_seeded(
+                    run as fn(Vec<(u8, u8)>, u8, usize) -> bool,
+                )
+            }
+        }
+    };
+}
@@ -0,0 +1,139 @@
+/// Model test suite for sets, implementing `tests::ModelSet` and
+/// `tests::ModelRemove`
+///
+/// The operations are called through the traits, so methods of the set of
+/// the same name do not get in the way. Sets that can not remove elements
+/// only implement `ModelSet`, and pass `grow_only`.
+///
+/// Usage example: `quickcheck_set!(BitSet::<Blake2b>::new);`, or
+/// `quickcheck_set!(GSet::<u8, Blake2b>::new, grow_only);`
+#[macro_export]
+macro_rules! quickcheck_set {
+    (@suite $new_set:expr, $($remove:ident)?) => {
+        // in a module of its own, to not clash with the items of the tests
+        // it is used in
+        mod quickcheck_set {
+            use super::*;
+
+            use $crate::tests::tempfile::tempdir;
+
+            use std::collections::HashSet;
+
+            use $crate::tests::quickcheck::{Arbitrary, Gen};
+            use $crate::tests::rand::Rng;
+            use $crate::tests::ModelSet;
+            use $crate::{Blake2b, Store};
+
+            const ELEMENT_SPACE: u8 = 20;
+
+            #[derive(Clone, Debug)]
+            pub enum Op {
+                Insert(u8),
+                Contains(u8),
+                $($remove(u8),)?
+                Union(Vec<u8>),
+                Iter,
+                Persist,
+                PersistRestore,
+            }
+
+            impl Arbitrary for Op {
+                // grow-only sets insert instead of removing
+                #[allow(unreachable_patterns)]
+                fn arbitrary<G: Gen>(g: &mut G) -> Op {
+                    let e: u8 = g.gen_range(0, ELEMENT_SPACE);
+                    let op = g.gen_range(0, 7);
+                    match op {
+                        0 => Op::Insert(e),
+                        1 => Op::Contains(e),
+                        $(2 => Op::$remove(e),)?
+                        2 => Op::Insert(e),
+                        3 => {
+                            let n = g.gen_range(0, ELEMENT_SPACE as usize);
+                            Op::Union(
+                                (0..n)
+                                    .map(|_| g.gen_range(0, ELEMENT_SPACE))
+                                    .collect(),
+                            )
+                        }
+                        4 => Op::Iter,
+                        5 => Op::Persist,
+                        6 => Op::PersistRestore,
+                        _ => unreachable!(),
+                    }
+                }
+            }
+
+            fn run_ops(ops: Vec<Op>) -> bool {
+                let dir = tempdir().unwrap();
+                let store = Store::<Blake2b>::new(&dir.path()).unwrap();
+
+                let mut test_a = $new_set();
+                let mut model = HashSet::new();
+
+                for op in ops {
+                    match op {
+                        Op::Insert(e) => {
+                            assert_eq!(
+                                ModelSet::insert(&mut test_a, e).unwrap(),
+                                model.insert(e)
+                            );
+                        }
+                        Op::Contains(e) => {
+                            assert_eq!(
+                                ModelSet::contains(&test_a, e).unwrap(),
+                                model.contains(&e)
+                            );
+                        }
+                        $(Op::$remove(e) => {
+                            assert_eq!(
+                                $crate::tests::ModelRemove::remove(
+                                    &mut test_a,
+                                    e
+                                )
+                                .unwrap(),
+                                model.remove(&e)
+                            );
+                        })?
+                        Op::Union(elements) => {
+                            let mut other = $new_set();
+                            for e in &elements {
+                                ModelSet::insert(&mut other, *e).unwrap();
+                            }
+                            ModelSet::union(&mut test_a, &other).unwrap();
+                            model.extend(elements);
+                        }
+                        Op::Iter => {
+                            let mut a = ModelSet::elements(&test_a).unwrap();
+                            let mut b: Vec<_> = model.iter().cloned().collect();
+
+                            a.sort();
+                            b.sort();
+
+                            assert_eq!(a, b);
+                        }
+                        Op::Persist => {
+                            store.persist(&mut test_a).unwrap();
+                        }
+                        Op::PersistRestore => {
+                            let snapshot = store.persist(&mut test_a).unwrap();
+                            test_a = store.restore(&snapshot).unwrap();
+                        }
+                    };
+                }
+                true
+            }
+
+            #[test]
+            fn set() {
+                $crate::tests::quickcheck_seeded(run_ops as fn(Vec<Op>) -> bool)
+            }
+        }
+    };
+    ($new_set:expr) => {
+        $crate::quickcheck_set!(@suite $new_set, Remove);
+    };
+    ($new_set:expr, grow_only) => {
+        $crate::quickcheck_set!(@suite $new_set,);
+    };
+}
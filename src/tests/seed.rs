@@ -0,0 +1,72 @@
+use std::env;
+
+use quickcheck::{QuickCheck, StdGen, Testable};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Environment variable to set the seed of `quickcheck_seeded` runs from
+pub const SEED_VAR: &str = "KELVIN_QUICKCHECK_SEED";
+
+// the default generator size of quickcheck
+const GEN_SIZE: usize = 100;
+
+fn gen_size() -> usize {
+    env::var("QUICKCHECK_GENERATOR_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(GEN_SIZE)
+}
+
+/// Checks the property `prop` like `quickcheck::quickcheck`, but from a
+/// seeded generator, so that a failing run can be replayed exactly
+///
+/// The seed is read from the `KELVIN_QUICKCHECK_SEED` environment variable,
+/// or picked at random if it is not set. It is printed before running, so
+/// the output of a failing test tells how to replay it:
+///
+/// `KELVIN_QUICKCHECK_SEED=<seed> cargo test <test name>`
+///
+/// The number of tests and the generator size are configured through
+/// `QUICKCHECK_TESTS` and `QUICKCHECK_GENERATOR_SIZE` as usual.
+pub fn quickcheck_seeded<A: Testable>(prop: A) {
+    let seed = match env::var(SEED_VAR) {
+        Ok(seed) => seed
+            .parse()
+            .unwrap_or_else(|_| panic!("{} is not a u64: {}", SEED_VAR, seed)),
+        Err(_) => rand::thread_rng().gen(),
+    };
+    eprintln!("quickcheck seed: {}={}", SEED_VAR, seed);
+
+    let gen = StdGen::new(StdRng::seed_from_u64(seed), gen_size());
+    QuickCheck::new().gen(gen).quickcheck(prop)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use quickcheck::Arbitrary;
+
+    #[test]
+    fn same_seed_same_inputs() {
+        let draw = |seed| {
+            let mut gen = StdGen::new(StdRng::seed_from_u64(seed), GEN_SIZE);
+            Vec::<(u8, String)>::arbitrary(&mut gen)
+        };
+        assert_eq!(draw(7), draw(7));
+        assert_ne!(draw(7), draw(8));
+    }
+
+    #[test]
+    fn runs_property() {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        fn prop(_: Vec<u8>) -> bool {
+            RUNS.fetch_add(1, Ordering::SeqCst);
+            true
+        }
+        quickcheck_seeded(prop as fn(Vec<u8>) -> bool);
+        assert!(RUNS.load(Ordering::SeqCst) > 0);
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io;
+use std::thread;
+
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::{Blake2b, ByteHash, Compound, LeafIterable, Snapshot, Store};
+
+type Digest = <Blake2b as ByteHash>::Digest;
+
+// number of distinct contents, shared by all threads so that the same values
+// are persisted and restored concurrently
+const SEEDS: u64 = 8;
+
+/// Hammers `store` from `threads` threads at once, checking its invariants
+///
+/// Each thread runs `rounds` operations picked at random: persisting,
+/// pipelined persisting, restoring, shared restoring and warming, on the
+/// contents `build` returns for a handful of seeds. Every restore has to
+/// return the leaves of the content persisted under that digest, and the
+/// same content has to persist to the same digest on every thread.
+///
+/// Afterwards, persisting all contents again from a single thread has to
+/// reproduce the same digests without writing anything new, so no value
+/// was lost to concurrent writes.
+pub fn stress_store<C, F>(
+    store: &Store<Blake2b>,
+    threads: usize,
+    rounds: usize,
+    build: F,
+) -> io::Result<()>
+where
+    C: Compound<Blake2b> + Send + Sync,
+    C::Leaf: PartialEq + Debug + Sync,
+    F: Fn(u64) -> C + Sync,
+{
+    let leaves = |c: &C| -> io::Result<Vec<C::Leaf>> {
+        c.iter().map(|leaf| leaf.cloned()).collect()
+    };
+    let expected = (0..SEEDS)
+        .map(|seed| leaves(&build(seed)))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // the digest each seed persisted to
+    let digests: Mutex<HashMap<u64, Digest>> = Mutex::new(HashMap::new());
+
+    let persisted = |seed: u64, digest: Digest| {
+        let first = *digests.lock().entry(seed).or_insert(digest);
+        assert_eq!(first, digest, "seed {} persisted differently", seed);
+    };
+
+    let check = |seed: u64, restored: &C| -> io::Result<()> {
+        assert_eq!(leaves(restored)?, expected[seed as usize]);
+        Ok(())
+    };
+
+    let run = |thread: usize| -> io::Result<()> {
+        let mut rng = StdRng::seed_from_u64(thread as u64);
+        for _ in 0..rounds {
+            let seed = rng.gen_range(0, SEEDS);
+            let known = digests.lock().get(&seed).copied();
+            match (rng.gen_range(0, 5), known) {
+                (0, _) | (_, None) => {
+                    let snapshot = store.persist(&mut build(seed))?;
+                    persisted(seed, *snapshot.hash());
+                }
+                (1, _) => {
+                    let snapshot = store.persist_pipelined(&mut build(seed))?;
+                    persisted(seed, *snapshot.hash());
+                }
+                (2, Some(digest)) => {
+                    check(seed, &store.get_hash::<C>(&digest)?)?;
+                }
+                (3, Some(digest)) => {
+                    let snapshot = Snapshot::new(digest, store);
+                    let shared = store.restore_shared::<C>(&snapshot)?;
+                    check(seed, &shared)?;
+                }
+                (_, Some(digest)) => {
+                    let snapshot = Snapshot::new(digest, store);
+                    store.warm(&snapshot, 2)?;
+                    check(seed, &store.restore(&snapshot)?)?;
+                }
+            }
+        }
+        Ok(())
+    };
+
+    thread::scope(|scope| {
+        let running: Vec<_> = (0..threads)
+            .map(|thread| scope.spawn(move || run(thread)))
+            .collect();
+        running
+            .into_iter()
+            .try_for_each(|t| t.join().expect("stress thread panicked"))
+    })?;
+
+    let before = store.stats();
+    for (seed, digest) in digests.into_inner() {
+        let snapshot = store.persist(&mut build(seed))?;
+        assert_eq!(*snapshot.hash(), digest);
+        check(seed, &store.restore(&snapshot)?)?;
+    }
+    let after = store.stats() - before;
+    assert_eq!(after.written, 0, "values lost to concurrent writes");
+    Ok(())
+}
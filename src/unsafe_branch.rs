@@ -8,7 +8,7 @@ use cache::Cached;
 use smallvec::SmallVec;
 
 use crate::compound::Compound;
-use crate::handle::{Handle, HandleRef};
+use crate::handle::{Handle, HandleRef, HandleType};
 use crate::search::Method;
 
 // how deep the branch can be without allocating
@@ -170,6 +170,13 @@ where
         }
     }
 
+    pub fn new_cached_back(cached: Cached<'a, C>) -> Self {
+        Level {
+            ofs: cached.children().len(),
+            node: NodeRef::new_cached(cached),
+        }
+    }
+
     pub fn insert_child(&mut self, node: C) {
         match &mut self.node {
             NodeRef::Cached(c) => {
@@ -230,10 +237,39 @@ where
                         HandleRef::None => Found::Nothing,
                     }
                 }
-                None => Found::Nothing,
+                // leave no leaf under the offset, or a search of a root
+                // holding leaves would stop at the one it started from
+                None => {
+                    self.ofs = children.len();
+                    Found::Nothing
+                }
             })
         }
     }
+
+    // finds the last non-empty child before `ofs`
+    fn search_back(&mut self) -> io::Result<Found> {
+        let node = self.inner_immutable();
+        let children = node.children();
+        let len = children.len();
+        match children[..self.ofs.min(len)]
+            .iter()
+            .rposition(|h| h.handle_type() != HandleType::None)
+        {
+            Some(i) => {
+                self.ofs = i;
+                Ok(match self.referencing()? {
+                    HandleRef::Leaf(_) => Found::Leaf,
+                    HandleRef::Node(_) => Found::Node,
+                    HandleRef::None => Found::Nothing,
+                })
+            }
+            None => {
+                self.ofs = len;
+                Ok(Found::Nothing)
+            }
+        }
+    }
 }
 
 impl<C, H> AsMut<C> for Level<'_, C, H>
@@ -256,6 +292,12 @@ where
         UnsafeBranch(vec)
     }
 
+    pub fn new_cached_back(node: Cached<'a, C>) -> Self {
+        let mut vec = SmallVec::new();
+        vec.push(Level::new_cached_back(node));
+        UnsafeBranch(vec)
+    }
+
     pub fn new_mutable(node: &'a mut C) -> Self {
         let mut vec = SmallVec::new();
         vec.push(Level::new_mutable(node));
@@ -297,6 +339,75 @@ where
         Ok(())
     }
 
+    /// Moves the branch to the closest leaf preceding its current position,
+    /// or to the last leaf of a freshly constructed back branch.
+    pub fn search_back(&mut self) -> io::Result<()> {
+        while let Some(last) = self.0.last_mut() {
+            let mut push = None;
+            match last.search_back()? {
+                Found::Leaf => {
+                    break;
+                }
+                Found::Node => match last.referencing()? {
+                    HandleRef::Node(cached) => {
+                        let level: Level<'a, _, _> = unsafe {
+                            mem::transmute(Level::new_cached_back(cached))
+                        };
+                        push = Some(level);
+                    }
+                    _ => unreachable!(),
+                },
+                Found::Nothing => {
+                    if self.0.len() > 1 {
+                        self.pop_level();
+                    } else {
+                        break;
+                    }
+                }
+            }
+            if let Some(level) = push.take() {
+                self.0.push(level);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the child offsets along the branch, from the root down
+    pub fn offsets(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.iter().map(|level| level.ofs)
+    }
+
+    /// Returns the child offsets of the levels above the leaf
+    pub fn parent_offsets(&self) -> impl Iterator<Item = usize> + '_ {
+        let len = self.0.len();
+        self.0[..len.saturating_sub(1)]
+            .iter()
+            .map(|level| level.ofs)
+    }
+
+    /// Hints the store about the persisted siblings following the node
+    /// holding the leaf
+    pub fn prefetch_siblings(&self) {
+        let len = self.0.len();
+        if len < 2 {
+            return;
+        }
+        let parent = &self.0[len - 2];
+        let node = parent.inner_immutable();
+        let mut store = None;
+        let digests: Vec<_> = node.children()[parent.ofs + 1..]
+            .iter()
+            .filter_map(|handle| handle.snapshot())
+            .map(|snapshot| {
+                store = Some(snapshot.store());
+                *snapshot.hash()
+            })
+            .collect();
+        if let Some(store) = store {
+            store.prefetch(&digests)
+        }
+    }
+
     pub fn advance(&mut self) {
         if let Some(level) = self.0.last_mut() {
             level.ofs += 1;
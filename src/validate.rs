@@ -0,0 +1,179 @@
+use std::io;
+
+use bytehash::ByteHash;
+
+use crate::compound::Compound;
+use crate::handle::HandleRef;
+
+/// Checks the structural invariants of a tree, returning an error describing
+/// the first violation found
+///
+/// Every node is visited, restoring persisted ones from their store, and
+/// checked to be non-empty unless it is the root, since emptied subtrees have
+/// to be replaced by empty handles to keep the tree canonical. The
+/// annotation stored in every node handle has to equal the one combined
+/// from the children of the node.
+///
+/// Structure specific invariants are checked by `check`, called on every
+/// node along with its path of child indices from the root. Its error
+/// message is reported with the path of the offending node.
+///
+/// Walking the whole tree is slow, this is meant for debugging and tests.
+pub fn validate<C, H, F>(root: &C, mut check: F) -> io::Result<()>
+where
+    C: Compound<H>,
+    C::Annotation: PartialEq,
+    H: ByteHash,
+    F: FnMut(&C, &[usize]) -> Result<(), String>,
+{
+    validate_node(root, &mut vec![], &mut check)
+}
+
+fn validate_node<C, H, F>(
+    node: &C,
+    path: &mut Vec<usize>,
+    check: &mut F,
+) -> io::Result<()>
+where
+    C: Compound<H>,
+    C::Annotation: PartialEq,
+    H: ByteHash,
+    F: FnMut(&C, &[usize]) -> Result<(), String>,
+{
+    check(node, path).map_err(|msg| invalid(path, &msg))?;
+    for (i, handle) in node.children().iter().enumerate() {
+        if let HandleRef::Node(child) = handle.inner()? {
+            path.push(i);
+            match (handle.annotation(), child.annotation()) {
+                (_, None) => return Err(invalid(path, "empty node")),
+                (Some(stored), Some(combined)) if *stored == combined => (),
+                _ => {
+                    return Err(invalid(
+                        path,
+                        "annotation differs from its children",
+                    ))
+                }
+            }
+            validate_node(&*child, path, check)?;
+            path.pop();
+        }
+    }
+    Ok(())
+}
+
+fn invalid(path: &[usize], msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} at node {:?}", msg, path),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Write;
+
+    use crate::annotations::Cardinality;
+    use crate::backend::Reader;
+    use crate::{Blake2b, Content, Handle, HandleMut, Sink, Source, Store};
+
+    #[derive(Clone, Default)]
+    struct Tree(Vec<Handle<Self, Blake2b>>);
+
+    impl Content<Blake2b> for Tree {
+        fn persist(&mut self, sink: &mut Sink<Blake2b>) -> io::Result<()> {
+            self.0.persist(sink)
+        }
+
+        fn restore(source: &mut Source<Blake2b>) -> io::Result<Self> {
+            Ok(Tree(Vec::restore(source)?))
+        }
+    }
+
+    impl Compound<Blake2b> for Tree {
+        type Leaf = u64;
+        type Annotation = Cardinality<u64>;
+
+        fn children(&self) -> &[Handle<Self, Blake2b>] {
+            &self.0
+        }
+
+        fn children_mut(&mut self) -> &mut [Handle<Self, Blake2b>] {
+            &mut self.0
+        }
+    }
+
+    fn leaves(n: u64) -> Tree {
+        Tree((0..n).map(Handle::new_leaf).collect())
+    }
+
+    #[test]
+    fn valid() {
+        let mut tree = Tree(vec![
+            Handle::new_node(leaves(2)),
+            Handle::new_leaf(7),
+            Handle::new_node(leaves(3)),
+        ]);
+        validate(&tree, |_, _| Ok(())).unwrap();
+
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let snapshot = store.persist(&mut tree).unwrap();
+        let restored = store.restore(&snapshot).unwrap();
+
+        let mut paths = vec![];
+        validate(&restored, |_, path| {
+            paths.push(path.to_vec());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(paths, vec![vec![], vec![0], vec![2]]);
+    }
+
+    #[test]
+    fn empty_node() {
+        let mut tree =
+            Tree(vec![Handle::new_leaf(1), Handle::new_node(leaves(1))]);
+        validate(&tree, |_, _| Ok(())).unwrap();
+
+        // removing the only leaf of a node, without removing the node
+        if let HandleMut::Node(node) = &mut *tree.0[1].inner_mut().unwrap() {
+            node.0[0] = Handle::new_empty();
+        }
+        let err = validate(&tree, |_, _| Ok(())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "empty node at node [1]");
+    }
+
+    #[test]
+    fn wrong_annotation() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let child = store.persist(&mut leaves(3)).unwrap();
+
+        // a tree referencing the child, annotated with 4 leaves instead of 3
+        let mut bytes = vec![];
+        bytes.write_all(&1u64.to_be_bytes()).unwrap();
+        bytes.write_all(&[2]).unwrap();
+        bytes.write_all(child.hash().as_ref()).unwrap();
+        bytes.write_all(&4u64.to_be_bytes()).unwrap();
+        let mut source = Source::new(Reader::Slice(&bytes), &store);
+        let tree = Tree::restore(&mut source).unwrap();
+
+        let err = validate(&tree, |_, _| Ok(())).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "annotation differs from its children at node [0]"
+        );
+    }
+
+    #[test]
+    fn check_errors() {
+        let tree = Tree(vec![Handle::new_node(leaves(2))]);
+        let err = validate(&tree, |node, path| match path.len() {
+            0 => Ok(()),
+            _ => Err(format!("{} leaves", node.0.len())),
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "2 leaves at node [0]");
+    }
+}
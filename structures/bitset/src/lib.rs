@@ -0,0 +1,568 @@
+use std::io;
+
+use kelvin::{
+    annotations::Associative, ByteHash, Compound, Content, Handle, HandleMut,
+    HandleRef, HandleType, Sink, Source,
+};
+
+const FANOUT_BITS: u32 = 4;
+const FANOUT: usize = 1 << FANOUT_BITS;
+
+const WORDS: usize = 4;
+const CHUNK_BITS: u64 = 64 * WORDS as u64;
+
+// a chunk index needs at most this many levels to be addressed
+const MAX_HEIGHT: u32 = (64 - 8) / FANOUT_BITS;
+
+/// A chunk of bits, stored as a leaf
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct Bits([u64; WORDS]);
+
+impl Bits {
+    fn get(&self, i: u64) -> bool {
+        self.0[i as usize / 64] & (1 << (i % 64)) != 0
+    }
+
+    fn set(&mut self, i: u64, value: bool) {
+        if value {
+            self.0[i as usize / 64] |= 1 << (i % 64)
+        } else {
+            self.0[i as usize / 64] &= !(1 << (i % 64))
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.iter().all(|w| *w == 0)
+    }
+
+    fn count_ones(&self) -> u64 {
+        self.0.iter().map(|w| w.count_ones() as u64).sum()
+    }
+
+    // number of set bits below `i`
+    fn rank(&self, i: u64) -> u64 {
+        let word = i as usize / 64;
+        let below: u64 =
+            self.0[..word].iter().map(|w| w.count_ones() as u64).sum();
+        let mask = (1u64 << (i % 64)) - 1;
+        below + (self.0[word] & mask).count_ones() as u64
+    }
+
+    // position of the `n`th set bit, if any
+    fn select(&self, mut n: u64) -> Option<u64> {
+        for (i, w) in self.0.iter().enumerate() {
+            let ones = w.count_ones() as u64;
+            if n < ones {
+                let mut w = *w;
+                for _ in 0..n {
+                    // clear the lowest set bit
+                    w &= w - 1;
+                }
+                return Some(i as u64 * 64 + w.trailing_zeros() as u64);
+            }
+            n -= ones;
+        }
+        None
+    }
+}
+
+impl<H: ByteHash> Content<H> for Bits {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        for w in self.0.iter_mut() {
+            w.persist(sink)?
+        }
+        Ok(())
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let mut bits = Bits::default();
+        for w in bits.0.iter_mut() {
+            *w = u64::restore(source)?
+        }
+        Ok(bits)
+    }
+}
+
+/// Annotation keeping track of the number of set bits in subtrees
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ones(u64);
+
+impl Associative for Ones {
+    fn op(&mut self, b: &Self) {
+        self.0 += b.0
+    }
+}
+
+impl From<&Bits> for Ones {
+    fn from(bits: &Bits) -> Self {
+        Ones(bits.count_ones())
+    }
+}
+
+impl<H: ByteHash> Content<H> for Ones {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.0.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(Ones(u64::restore(source)?))
+    }
+}
+
+/// A node in the BitSet trie
+///
+/// The children of a node are indexed by a digit of the chunk index, empty
+/// chunks and subtrees are not stored.
+#[derive(Clone)]
+pub struct BitNode<H: ByteHash>([Handle<Self, H>; FANOUT]);
+
+impl<H: ByteHash> Default for BitNode<H> {
+    fn default() -> Self {
+        BitNode(Default::default())
+    }
+}
+
+fn ones<H: ByteHash>(handle: &Handle<BitNode<H>, H>) -> u64 {
+    handle.annotation().map(|ann| ann.0).unwrap_or(0)
+}
+
+// the child to follow for chunk `c` in a node at `level`
+fn digit(c: u64, level: u32) -> usize {
+    ((c >> ((level - 1) * FANOUT_BITS)) as usize) & (FANOUT - 1)
+}
+
+impl<H: ByteHash> BitNode<H> {
+    fn is_empty(&self) -> bool {
+        self.0.iter().all(|h| h.is_none())
+    }
+
+    fn get(&self, level: u32, c: u64, bit: u64) -> io::Result<bool> {
+        match self.0[digit(c, level)].inner()? {
+            HandleRef::None => Ok(false),
+            HandleRef::Leaf(bits) => Ok(bits.get(bit)),
+            HandleRef::Node(n) => n.get(level - 1, c, bit),
+        }
+    }
+
+    // sets the bit, returning its previous value
+    fn set(
+        &mut self,
+        level: u32,
+        c: u64,
+        bit: u64,
+        value: bool,
+    ) -> io::Result<bool> {
+        let d = digit(c, level);
+
+        if self.0[d].is_none() {
+            if value {
+                self.0[d] = if level == 1 {
+                    let mut bits = Bits::default();
+                    bits.set(bit, true);
+                    Handle::new_leaf(bits)
+                } else {
+                    let mut node = Self::default();
+                    node.set(level - 1, c, bit, true)?;
+                    Handle::new_node(node)
+                };
+            }
+            return Ok(false);
+        }
+
+        let (previous, empty) = match &mut *self.0[d].inner_mut()? {
+            HandleMut::None => unreachable!(),
+            HandleMut::Leaf(bits) => {
+                let previous = bits.get(bit);
+                bits.set(bit, value);
+                (previous, bits.is_zero())
+            }
+            HandleMut::Node(n) => {
+                let previous = n.set(level - 1, c, bit, value)?;
+                (previous, n.is_empty())
+            }
+        };
+
+        // do not keep empty chunks or subtrees around
+        if empty {
+            self.0[d] = Handle::new_empty();
+        }
+        Ok(previous)
+    }
+
+    fn rank(&self, level: u32, c: u64, bit: u64) -> io::Result<u64> {
+        let d = digit(c, level);
+        let below: u64 = self.0[..d].iter().map(ones).sum();
+        Ok(below
+            + match self.0[d].inner()? {
+                HandleRef::None => 0,
+                HandleRef::Leaf(bits) => bits.rank(bit),
+                HandleRef::Node(n) => n.rank(level - 1, c, bit)?,
+            })
+    }
+
+    // position of the `n`th set bit, relative to the start of the node
+    fn select(&self, level: u32, mut n: u64) -> io::Result<Option<u64>> {
+        let span = CHUNK_BITS << ((level - 1) * FANOUT_BITS);
+        for (i, handle) in self.0.iter().enumerate() {
+            let ones = ones(handle);
+            if n < ones {
+                let pos = match handle.inner()? {
+                    HandleRef::None => unreachable!(),
+                    HandleRef::Leaf(bits) => bits.select(n),
+                    HandleRef::Node(node) => node.select(level - 1, n)?,
+                };
+                return Ok(pos.map(|pos| i as u64 * span + pos));
+            }
+            n -= ones;
+        }
+        Ok(None)
+    }
+}
+
+impl<H: ByteHash> Content<H> for BitNode<H> {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        let mut mask = 0u16;
+        for (i, handle) in self.0.iter().enumerate() {
+            if handle.handle_type() != HandleType::None {
+                mask |= 1 << i;
+            }
+        }
+
+        mask.persist(sink)?;
+
+        for (i, handle) in self.0.iter_mut().enumerate() {
+            if mask & (1 << i) != 0 {
+                handle.persist(sink)?
+            }
+        }
+        Ok(())
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let mut node = BitNode::default();
+        let mask = u16::restore(source)?;
+        for (i, handle) in node.0.iter_mut().enumerate() {
+            if mask & (1 << i) != 0 {
+                *handle = Handle::restore(source)?
+            }
+        }
+        Ok(node)
+    }
+}
+
+impl<H: ByteHash> Compound<H> for BitNode<H> {
+    type Leaf = Bits;
+    type Annotation = Ones;
+
+    fn children_mut(&mut self) -> &mut [Handle<Self, H>] {
+        &mut self.0
+    }
+
+    fn children(&self) -> &[Handle<Self, H>] {
+        &self.0
+    }
+}
+
+/// A sparse set of bits, addressed by `u64` positions
+///
+/// Bits are stored in chunks of 256, in a trie where only the chunks with
+/// set bits are present. Every subtree is annotated with its number of set
+/// bits, so `rank` and `select` only follow a single path.
+#[derive(Clone)]
+pub struct BitSet<H: ByteHash> {
+    // number of node levels above the chunks
+    height: u32,
+    root: BitNode<H>,
+}
+
+impl<H: ByteHash> Default for BitSet<H> {
+    fn default() -> Self {
+        BitSet {
+            height: 1,
+            root: BitNode::default(),
+        }
+    }
+}
+
+impl<H: ByteHash> BitSet<H> {
+    /// Creates a new, empty BitSet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the number of chunks addressable at the current height
+    fn capacity(&self) -> u64 {
+        1 << (self.height * FANOUT_BITS)
+    }
+
+    /// Returns the number of set bits
+    pub fn count_ones(&self) -> u64 {
+        self.root.annotation().map(|ann| ann.0).unwrap_or(0)
+    }
+
+    /// Returns true if no bits are set
+    pub fn is_empty(&self) -> bool {
+        self.root.is_empty()
+    }
+
+    /// Returns the value of the bit at `pos`
+    pub fn get(&self, pos: u64) -> io::Result<bool> {
+        let c = pos / CHUNK_BITS;
+        if c >= self.capacity() {
+            return Ok(false);
+        }
+        self.root.get(self.height, c, pos % CHUNK_BITS)
+    }
+
+    /// Sets the bit at `pos` to `value`, returning its previous value
+    pub fn set(&mut self, pos: u64, value: bool) -> io::Result<bool> {
+        let c = pos / CHUNK_BITS;
+        if value {
+            while c >= self.capacity() && self.height < MAX_HEIGHT {
+                // push the current trie down a level
+                if !self.root.is_empty() {
+                    let old = std::mem::take(&mut self.root);
+                    self.root.0[0] = Handle::new_node(old);
+                }
+                self.height += 1;
+            }
+        } else if c >= self.capacity() {
+            return Ok(false);
+        }
+        self.root.set(self.height, c, pos % CHUNK_BITS, value)
+    }
+
+    /// Returns the number of set bits below `pos`
+    pub fn rank(&self, pos: u64) -> io::Result<u64> {
+        let c = pos / CHUNK_BITS;
+        if c >= self.capacity() {
+            return Ok(self.count_ones());
+        }
+        self.root.rank(self.height, c, pos % CHUNK_BITS)
+    }
+
+    /// Returns the position of the `n`th set bit, counting from zero
+    pub fn select(&self, n: u64) -> io::Result<Option<u64>> {
+        self.root.select(self.height, n)
+    }
+
+    /// Checks the invariants of the set, returning an error describing the
+    /// first violation found
+    ///
+    /// On top of the checks of `kelvin::validate`, chunks have to be stored
+    /// exactly `height` levels below the root, and no chunk may be all
+    /// zeroes, since those are removed.
+    pub fn validate(&self) -> io::Result<()> {
+        if self.height == 0 || self.height > MAX_HEIGHT {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid height {}", self.height),
+            ));
+        }
+        let height = self.height as usize;
+        kelvin::validate(&self.root, |node: &BitNode<H>, path| {
+            // nodes are only descended into above the bottom level
+            let level = height - path.len();
+            for handle in node.0.iter() {
+                match (handle.handle_type(), level) {
+                    (HandleType::None, _) | (HandleType::Node, 2..) => (),
+                    (HandleType::Leaf, 1) => {
+                        if let HandleRef::Leaf(bits) =
+                            handle.inner().map_err(|e| e.to_string())?
+                        {
+                            if bits.is_zero() {
+                                return Err("empty chunk".into());
+                            }
+                        }
+                    }
+                    (HandleType::Leaf, _) => {
+                        return Err("chunk above the bottom level".into())
+                    }
+                    (HandleType::Node, _) => {
+                        return Err("node at the bottom level".into())
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+impl<H: ByteHash> Content<H> for BitSet<H> {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        (self.height as u8).persist(sink)?;
+        self.root.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let height = u8::restore(source)? as u32;
+        if height == 0 || height > MAX_HEIGHT {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid BitSet height",
+            ));
+        }
+        Ok(BitSet {
+            height,
+            root: BitNode::restore(source)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use kelvin::tests::assert_root_hash;
+    use kelvin::tests::{ModelRemove, ModelSet};
+    use kelvin::{quickcheck_set, Blake2b, Store};
+
+    #[test]
+    fn set_get() {
+        let mut set = BitSet::<Blake2b>::new();
+        assert!(set.is_empty());
+        assert!(!set.get(1000).unwrap());
+
+        assert!(!set.set(1000, true).unwrap());
+        assert!(set.set(1000, true).unwrap());
+        assert!(set.get(1000).unwrap());
+        assert!(!set.get(999).unwrap());
+        assert_eq!(set.count_ones(), 1);
+
+        assert!(set.set(1000, false).unwrap());
+        assert!(!set.set(1000, false).unwrap());
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn validate() {
+        let mut set = BitSet::<Blake2b>::new();
+        for pos in (0..100_000).step_by(7) {
+            set.set(pos, true).unwrap();
+        }
+        set.set(u64::MAX, true).unwrap();
+        set.validate().unwrap();
+
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let snapshot = store.persist(&mut set).unwrap();
+        let mut restored = store.restore(&snapshot).unwrap();
+        restored.validate().unwrap();
+
+        for pos in (0..100_000).step_by(7) {
+            restored.set(pos, false).unwrap();
+        }
+        restored.validate().unwrap();
+
+        let mut empty_chunk = BitSet::<Blake2b>::new();
+        empty_chunk.root.0[0] = Handle::new_leaf(Bits::default());
+        let err = empty_chunk.validate().unwrap_err();
+        assert_eq!(err.to_string(), "empty chunk at node []");
+
+        let mut too_high = BitSet::<Blake2b>::new();
+        too_high.set(0, true).unwrap();
+        too_high.height = 2;
+        let err = too_high.validate().unwrap_err();
+        assert_eq!(err.to_string(), "chunk above the bottom level at node []");
+    }
+
+    #[test]
+    fn rank_select() {
+        let mut set = BitSet::<Blake2b>::new();
+        let n = 20_000u64;
+        // every third bit
+        for i in (0..n).step_by(3) {
+            set.set(i, true).unwrap();
+        }
+        let ones = n.div_ceil(3);
+        assert_eq!(set.count_ones(), ones);
+
+        for pos in 0..n {
+            assert_eq!(set.get(pos).unwrap(), pos % 3 == 0);
+            assert_eq!(set.rank(pos).unwrap(), pos.div_ceil(3));
+        }
+        for i in 0..ones {
+            assert_eq!(set.select(i).unwrap(), Some(i * 3));
+        }
+        assert_eq!(set.select(ones).unwrap(), None);
+    }
+
+    #[test]
+    fn sparse() {
+        let mut set = BitSet::<Blake2b>::new();
+        let positions = [0, 5, 1 << 20, 1 << 40, u64::MAX];
+        for pos in positions.iter() {
+            set.set(*pos, true).unwrap();
+        }
+        for (i, pos) in positions.iter().enumerate() {
+            assert!(set.get(*pos).unwrap());
+            assert_eq!(set.rank(*pos).unwrap(), i as u64);
+            assert_eq!(set.select(i as u64).unwrap(), Some(*pos));
+        }
+        assert!(!set.get((1 << 40) + 1).unwrap());
+    }
+
+    #[test]
+    fn persist_restore() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+
+        let mut set = BitSet::<Blake2b>::new();
+        for i in 0..5000u64 {
+            set.set(i * 7, true).unwrap();
+        }
+
+        let snapshot = store.persist(&mut set).unwrap();
+        let mut restored = store.restore(&snapshot).unwrap();
+
+        assert_eq!(restored.count_ones(), 5000);
+        assert_eq!(restored.select(100).unwrap(), Some(700));
+        assert_eq!(restored.rank(701).unwrap(), 101);
+
+        for i in 0..5000u64 {
+            assert!(restored.set(i * 7, false).unwrap());
+        }
+        assert!(restored.is_empty());
+    }
+
+    impl ModelSet for BitSet<Blake2b> {
+        fn insert(&mut self, e: u8) -> io::Result<bool> {
+            Ok(!self.set(e as u64, true)?)
+        }
+
+        fn contains(&self, e: u8) -> io::Result<bool> {
+            self.get(e as u64)
+        }
+
+        fn union(&mut self, other: &Self) -> io::Result<()> {
+            for e in other.elements()? {
+                self.set(e as u64, true)?;
+            }
+            Ok(())
+        }
+
+        fn elements(&self) -> io::Result<Vec<u8>> {
+            (0..self.count_ones())
+                .map(|n| Ok(self.select(n)?.expect("n < count_ones") as u8))
+                .collect()
+        }
+    }
+
+    impl ModelRemove for BitSet<Blake2b> {
+        fn remove(&mut self, e: u8) -> io::Result<bool> {
+            self.set(e as u64, false)
+        }
+    }
+
+    quickcheck_set!(BitSet::<Blake2b>::new);
+
+    #[test]
+    fn golden_root_hash() {
+        let mut set = BitSet::<Blake2b>::new();
+        for i in 0..100 {
+            set.set(i * i, true).unwrap();
+        }
+        assert_root_hash(
+            &mut set,
+            "cf8518acf8d48477a98c0fca536f9c83081cc1e7fba6b34470431a6f032ac8fd",
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use std::io::{self, BufRead, BufReader, Read};
+
+/// Chunks are never cut before reaching this size
+pub const MIN_CHUNK: usize = 2 * 1024;
+/// Chunks are always cut when reaching this size
+pub const MAX_CHUNK: usize = 64 * 1024;
+// Cutting when the low 13 bits of the rolling hash are zero gives chunks of
+// about 8KiB on average
+const CUT_MASK: u64 = (1 << 13) - 1;
+
+// Pseudo-random value per byte for the rolling gear hash
+fn gear(byte: u8) -> u64 {
+    let mut z = (byte as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Splits a byte stream into content-defined chunks
+///
+/// Chunk boundaries only depend on the bytes preceding them, so an edit
+/// only changes the chunks around it, and the rest can be shared.
+pub struct Chunker<R> {
+    reader: BufReader<R>,
+    done: bool,
+}
+
+impl<R: Read> Chunker<R> {
+    /// Creates a new chunker reading from `reader`
+    pub fn new(reader: R) -> Self {
+        Chunker {
+            reader: BufReader::new(reader),
+            done: false,
+        }
+    }
+
+    fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut chunk = Vec::with_capacity(MIN_CHUNK);
+        let mut hash = 0u64;
+
+        loop {
+            let (consumed, cut) = {
+                let buf = self.reader.fill_buf()?;
+                if buf.is_empty() {
+                    self.done = true;
+                    break;
+                }
+                let mut consumed = 0;
+                let mut cut = false;
+                for byte in buf {
+                    consumed += 1;
+                    chunk.push(*byte);
+                    hash = (hash << 1).wrapping_add(gear(*byte));
+                    if chunk.len() >= MAX_CHUNK
+                        || (chunk.len() >= MIN_CHUNK && hash & CUT_MASK == 0)
+                    {
+                        cut = true;
+                        break;
+                    }
+                }
+                (consumed, cut)
+            };
+            self.reader.consume(consumed);
+            if cut {
+                break;
+            }
+        }
+
+        Ok(if chunk.is_empty() { None } else { Some(chunk) })
+    }
+}
+
+impl<R: Read> Iterator for Chunker<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        self.next_chunk().transpose()
+    }
+}
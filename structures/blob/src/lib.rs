@@ -0,0 +1,455 @@
+use std::io::{self, Read};
+
+use arrayvec::ArrayVec;
+
+use kelvin::{
+    annotations::Associative, ByteHash, Compound, Content, Handle, HandleRef,
+    HandleType, LeafIterable, Sink, Snapshot, Source, Store,
+};
+
+mod chunker;
+
+pub use chunker::{Chunker, MAX_CHUNK, MIN_CHUNK};
+
+const FANOUT: usize = 16;
+
+/// A byte stream, split into content-defined chunks stored as leaves
+#[derive(Clone)]
+pub struct Blob<H: ByteHash>(ArrayVec<[Handle<Self, H>; FANOUT]>);
+
+/// Annotation keeping track of the number of bytes in subtrees
+#[derive(Clone, Debug, PartialEq)]
+pub struct ByteSize(u64);
+
+impl Associative for ByteSize {
+    fn op(&mut self, b: &Self) {
+        self.0 += b.0
+    }
+}
+
+impl From<&Vec<u8>> for ByteSize {
+    fn from(chunk: &Vec<u8>) -> Self {
+        ByteSize(chunk.len() as u64)
+    }
+}
+
+impl<H: ByteHash> Content<H> for ByteSize {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.0.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(ByteSize(u64::restore(source)?))
+    }
+}
+
+impl<H: ByteHash> Default for Blob<H> {
+    fn default() -> Self {
+        Blob(Default::default())
+    }
+}
+
+impl<H: ByteHash> Blob<H> {
+    /// Creates a new, empty Blob
+    pub fn new() -> Self {
+        Blob(Default::default())
+    }
+
+    /// Reads `reader` to the end, and builds a Blob of its contents
+    pub fn from_reader<R: Read>(reader: R) -> io::Result<Self> {
+        let mut level = vec![];
+        for chunk in Chunker::new(reader) {
+            level.push(Handle::new_leaf(chunk?));
+        }
+
+        while level.len() > FANOUT {
+            let mut handles = level.into_iter().peekable();
+            let mut next = vec![];
+            while handles.peek().is_some() {
+                let mut node = Self::new();
+                for handle in handles.by_ref().take(FANOUT) {
+                    node.0.push(handle);
+                }
+                next.push(Handle::new_node(node));
+            }
+            level = next;
+        }
+
+        let mut blob = Self::new();
+        for handle in level {
+            blob.0.push(handle);
+        }
+        Ok(blob)
+    }
+
+    /// Returns the length of the Blob in bytes
+    pub fn len(&self) -> u64 {
+        self.annotation().map(|size| size.0).unwrap_or(0)
+    }
+
+    /// Returns true if the Blob contains no bytes
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a reader streaming the contents of the Blob, one chunk at a
+    /// time
+    pub fn reader(&self) -> BlobReader<'_> {
+        BlobReader {
+            chunks: Box::new(self.iter()),
+            current: &[],
+        }
+    }
+
+    /// Checks the invariants of the Blob, returning an error describing the
+    /// first violation found
+    ///
+    /// On top of the checks of `kelvin::validate`, all chunks have to be at
+    /// the same depth and between `MIN_CHUNK` and `MAX_CHUNK` bytes long,
+    /// except for a shorter last chunk. Every node but the last one of its
+    /// level has to be full, as built by `from_reader`.
+    pub fn validate(&self) -> io::Result<()> {
+        let mut leaf_depth = None;
+        // the levels that reached their last node, and a short last chunk
+        let mut ended = vec![];
+        let mut short = false;
+        kelvin::validate(self, |node: &Self, path| {
+            let depth = path.len();
+            if ended.len() <= depth {
+                ended.resize(depth + 1, false);
+            }
+            if ended[depth] {
+                return Err("node after the last of its level".into());
+            }
+            ended[depth] = depth > 0 && node.0.len() < FANOUT;
+            if depth == 0
+                && node.0.len() == 1
+                && node.0[0].handle_type() == HandleType::Node
+            {
+                return Err("root with a single node".into());
+            }
+
+            let mut leaves = 0;
+            for handle in node.0.iter() {
+                match handle.handle_type() {
+                    HandleType::Leaf => leaves += 1,
+                    HandleType::Node => continue,
+                    HandleType::None => return Err("empty handle".into()),
+                }
+                if let HandleRef::Leaf(chunk) =
+                    handle.inner().map_err(|e| e.to_string())?
+                {
+                    if short || chunk.is_empty() || chunk.len() > MAX_CHUNK {
+                        return Err(format!("chunk of {}", chunk.len()));
+                    }
+                    short = chunk.len() < MIN_CHUNK;
+                }
+            }
+            match (leaves, leaf_depth) {
+                (0, _) => Ok(()),
+                (n, _) if n < node.0.len() => {
+                    Err("chunks next to nodes".into())
+                }
+                (_, None) => {
+                    leaf_depth = Some(depth);
+                    Ok(())
+                }
+                (_, Some(d)) if d == depth => Ok(()),
+                (_, Some(d)) => {
+                    Err(format!("chunks at depth {}, others at {}", depth, d))
+                }
+            }
+        })
+    }
+}
+
+/// Streaming reader over the contents of a Blob
+pub struct BlobReader<'a> {
+    chunks: Box<dyn Iterator<Item = io::Result<&'a Vec<u8>>> + 'a>,
+    current: &'a [u8],
+}
+
+impl<'a> Read for BlobReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.current.is_empty() {
+            match self.chunks.next() {
+                Some(chunk) => self.current = &chunk?[..],
+                None => return Ok(0),
+            }
+        }
+        self.current.read(buf)
+    }
+}
+
+/// Streaming reader owning its position in a Blob
+///
+/// Unlike `BlobReader`, it does not borrow the Blob, and only keeps the
+/// nodes along the path to the chunk being read in memory, so a map with
+/// blob snapshots as values can be exported in constant memory.
+pub struct BlobStream<H: ByteHash> {
+    // nodes along the path to the current chunk, with the index of the next
+    // child to visit in each
+    path: Vec<(Blob<H>, usize)>,
+    current: io::Cursor<Vec<u8>>,
+}
+
+// The next step of a stream through the tree
+enum Step<H: ByteHash> {
+    Chunk(Vec<u8>),
+    Descend(Blob<H>),
+    Skip,
+}
+
+impl<H: ByteHash> BlobStream<H> {
+    fn new(blob: Blob<H>) -> Self {
+        BlobStream {
+            path: vec![(blob, 0)],
+            current: io::Cursor::new(vec![]),
+        }
+    }
+
+    fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        while let Some((node, i)) = self.path.last_mut() {
+            let step = match node.0.get(*i) {
+                Some(handle) => match handle.inner()? {
+                    HandleRef::Leaf(chunk) => Step::Chunk(chunk.clone()),
+                    HandleRef::Node(node) => Step::Descend((*node).clone()),
+                    HandleRef::None => Step::Skip,
+                },
+                None => {
+                    self.path.pop();
+                    continue;
+                }
+            };
+            *i += 1;
+            match step {
+                Step::Chunk(chunk) => return Ok(Some(chunk)),
+                Step::Descend(node) => self.path.push((node, 0)),
+                Step::Skip => (),
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<H: ByteHash> Read for BlobStream<H> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.current.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            match self.next_chunk()? {
+                Some(chunk) => self.current = io::Cursor::new(chunk),
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+impl<H: ByteHash> Content<H> for Blob<H> {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        (self.0.len() as u8).persist(sink)?;
+        for h in &mut self.0 {
+            h.persist(sink)?
+        }
+        Ok(())
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let mut blob = Blob::default();
+        let len = u8::restore(source)? as usize;
+        if len > FANOUT {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Too many Blob children",
+            ));
+        }
+        for _ in 0..len {
+            blob.0.push(Handle::restore(source)?);
+        }
+        Ok(blob)
+    }
+}
+
+impl<H: ByteHash> Compound<H> for Blob<H> {
+    type Leaf = Vec<u8>;
+    type Annotation = ByteSize;
+
+    fn children_mut(&mut self) -> &mut [Handle<Self, H>] {
+        &mut self.0
+    }
+
+    fn children(&self) -> &[Handle<Self, H>] {
+        &self.0
+    }
+}
+
+/// A store for byte streams, returning a single snapshot per blob
+///
+/// Since the store is content-addressed, identical parts of different blobs
+/// are only stored once.
+#[derive(Clone)]
+pub struct BlobStore<H: ByteHash>(Store<H>);
+
+impl<H: ByteHash> BlobStore<H> {
+    /// Creates a new BlobStore on top of `store`
+    pub fn new(store: Store<H>) -> Self {
+        BlobStore(store)
+    }
+
+    /// Chunks and stores the contents of `reader`
+    pub fn put<R: Read>(&self, reader: R) -> io::Result<Snapshot<Blob<H>, H>> {
+        let mut blob = Blob::from_reader(reader)?;
+        self.0.persist(&mut blob)
+    }
+
+    /// Fetches a previously stored blob, its chunks are loaded as they are
+    /// read
+    pub fn get(&self, snapshot: &Snapshot<Blob<H>, H>) -> io::Result<Blob<H>> {
+        self.0.restore(snapshot)
+    }
+
+    /// Returns a reader streaming the contents of a previously stored blob,
+    /// loading one chunk at a time
+    pub fn stream(
+        &self,
+        snapshot: &Snapshot<Blob<H>, H>,
+    ) -> io::Result<BlobStream<H>> {
+        Ok(BlobStream::new(self.get(snapshot)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use kelvin::tests::assert_root_hash;
+    use kelvin::Blake2b;
+
+    // deterministic pseudo-random test data
+    fn data(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    fn read_all(blob: &Blob<Blake2b>) -> Vec<u8> {
+        let mut out = vec![];
+        blob.reader().read_to_end(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn empty_blob() {
+        let blob = Blob::<Blake2b>::from_reader(&[][..]).unwrap();
+        assert!(blob.is_empty());
+        assert_eq!(read_all(&blob), vec![]);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let store = BlobStore::new(Store::<Blake2b>::volatile().unwrap());
+
+        for len in [1, 100, MIN_CHUNK, MAX_CHUNK + 1, 1024 * 1024].iter() {
+            let bytes = data(*len, *len as u64);
+            let snapshot = store.put(&bytes[..]).unwrap();
+            let blob = store.get(&snapshot).unwrap();
+
+            assert_eq!(blob.len(), *len as u64);
+            assert_eq!(read_all(&blob), bytes);
+            blob.validate().unwrap();
+        }
+    }
+
+    #[test]
+    fn validate() {
+        let bytes = data(3 * 1024 * 1024, 3);
+        let blob = Blob::<Blake2b>::from_reader(&bytes[..]).unwrap();
+        blob.validate().unwrap();
+
+        let mut short = Blob::<Blake2b>::new();
+        short.0.push(Handle::new_leaf(vec![0; 10]));
+        short.validate().unwrap();
+        short.0.push(Handle::new_leaf(vec![0; 10]));
+        let err = short.validate().unwrap_err();
+        assert_eq!(err.to_string(), "chunk of 10 at node []");
+
+        let mut unbalanced = Blob::<Blake2b>::new();
+        unbalanced.0.push(Handle::new_node(blob.clone()));
+        unbalanced.0.push(Handle::new_leaf(vec![0; 10]));
+        assert!(unbalanced.validate().is_err());
+    }
+
+    #[test]
+    fn stream_values() {
+        let store = BlobStore::new(Store::<Blake2b>::volatile().unwrap());
+
+        // blobs kept as values by their snapshots
+        let lens = [0, MAX_CHUNK * 3, 1024 * 1024];
+        let values: Vec<_> = lens
+            .iter()
+            .map(|len| store.put(&data(*len, 7)[..]).unwrap())
+            .collect();
+
+        for (len, snapshot) in lens.iter().zip(values.iter()) {
+            let mut stream = store.stream(snapshot).unwrap();
+            let mut out = vec![];
+            stream.read_to_end(&mut out).unwrap();
+            assert_eq!(out, data(*len, 7));
+        }
+    }
+
+    #[test]
+    fn chunk_sizes() {
+        let bytes = data(1024 * 1024, 0);
+        let chunks: Vec<_> =
+            Chunker::new(&bytes[..]).map(|c| c.unwrap()).collect();
+
+        let (last, rest) = chunks.split_last().unwrap();
+        assert!(last.len() <= MAX_CHUNK);
+        for chunk in rest {
+            assert!(chunk.len() >= MIN_CHUNK && chunk.len() <= MAX_CHUNK);
+        }
+        assert_eq!(chunks.concat(), bytes);
+    }
+
+    #[test]
+    fn edits_keep_most_chunks() {
+        let bytes = data(1024 * 1024, 1);
+        let mut edited = vec![42];
+        edited.extend_from_slice(&bytes);
+
+        let a: Vec<_> = Chunker::new(&bytes[..]).map(|c| c.unwrap()).collect();
+        let b: Vec<_> = Chunker::new(&edited[..]).map(|c| c.unwrap()).collect();
+
+        let shared = b.iter().filter(|chunk| a.contains(chunk)).count();
+        assert!(shared + 2 >= a.len());
+    }
+
+    #[test]
+    fn too_many_children() {
+        match kelvin::tests::restore_bytes::<Blob<Blake2b>>(&[17]) {
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            Ok(_) => panic!("restored a blob of 17 children"),
+        }
+    }
+
+    #[test]
+    fn golden_root_hash() {
+        let bytes: Vec<u8> = (0..100_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        let mut blob = Blob::<Blake2b>::from_reader(&bytes[..]).unwrap();
+        assert_root_hash(
+            &mut blob,
+            "0f0710a56885809156a9e9a63599403010433bc69db3612e0b4e39828e4736ed",
+        );
+    }
+}
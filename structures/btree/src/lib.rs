@@ -1,29 +1,55 @@
 use std::borrow::Borrow;
 use std::io;
-use std::iter::Iterator;
+use std::iter::{FromIterator, Iterator};
 use std::marker::PhantomData;
 use std::mem;
 
-use arrayvec::ArrayVec;
-
 use kelvin::{
     annotation,
-    annotations::{Cardinality, Counter, MaxKey, MaxKeyType},
-    ByteHash, Compound, Content, Handle, HandleMut, HandleType, Map, Method,
-    Sink, Source,
+    annotations::{Cardinality, Count, Counter, MaxKey, MaxKeyType, MinKey},
+    Branch, ByteHash, Compound, Content, Drain, First, Handle, HandleMut,
+    HandleRef, HandleType, LeafIter, LeafIterable, Map, MapMut, MapRemove,
+    MaxKeySearch, Method, MinKeySearch, Sink, Source, Tagged,
 };
 
-const N: usize = 2;
-const M: usize = 3;
+/// The size of the nodes of a BTree
+///
+/// Every node but the root holds between `MIN` and `2 * MIN - 1` children.
+/// Small nodes are cheap to copy on write in memory, while stores backed by
+/// disk or network are better served by fewer, bigger nodes.
+pub trait Fanout: 'static + Clone {
+    /// The minimum number of children of a node, at least 2 and at most 128
+    const MIN: usize;
+}
+
+/// Nodes of 2 to 3 children, suited for in-memory use
+#[derive(Clone)]
+pub struct Narrow;
+
+impl Fanout for Narrow {
+    const MIN: usize = 2;
+}
+
+/// Nodes of 64 to 127 children, suited for disk and network backends
+#[derive(Clone)]
+pub struct Wide;
+
+impl Fanout for Wide {
+    const MIN: usize = 64;
+}
 
 /// A hash array mapped trie
 #[derive(Clone)]
-pub struct BTree<K, V, H: ByteHash>(ArrayVec<[Handle<Self, H>; M]>)
+pub struct BTree<K, V, H: ByteHash, A: Fanout = Narrow>(Vec<Handle<Self, H>>)
 where
     Self: Compound<H>;
 
-impl<K: Content<H> + Ord, V: Content<H>, H: ByteHash> Default
-    for BTree<K, V, H>
+impl<K, V, H, A> Default for BTree<K, V, H, A>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+    A: Fanout,
 {
     fn default() -> Self {
         BTree(Default::default())
@@ -33,6 +59,7 @@ impl<K: Content<H> + Ord, V: Content<H>, H: ByteHash> Default
 annotation! {
     pub struct BTreeAnnotation<K, U> {
         key: MaxKey<K>,
+        min_key: MinKey<K>,
         count: Cardinality<U>,
     }
     where
@@ -42,7 +69,7 @@ annotation! {
 
 pub struct BTreeSearch<'a, K, O: ?Sized>(&'a O, PhantomData<K>);
 
-impl<'a, K, O> BTreeSearch<'a, K, O> {
+impl<'a, K, O: ?Sized> BTreeSearch<'a, K, O> {
     fn new(key: &'a O) -> Self {
         BTreeSearch(key, PhantomData)
     }
@@ -81,6 +108,94 @@ where
     }
 }
 
+/// Search method finding the first key greater than or equal to a byte
+/// prefix
+pub struct PrefixSearch<'a, K>(&'a [u8], PhantomData<K>);
+
+impl<'a, K, C, H> Method<C, H> for PrefixSearch<'a, K>
+where
+    C: Compound<H>,
+    C::Annotation: Borrow<MaxKey<K>>,
+    H: ByteHash,
+    K: AsRef<[u8]>,
+{
+    fn select(&mut self, handles: &[Handle<C, H>]) -> Option<usize> {
+        for (i, h) in handles.iter().enumerate() {
+            if let Some(ann) = h.annotation() {
+                let handle_key: &MaxKey<K> = (*ann).borrow();
+                if self.0 <= (**handle_key).as_ref() {
+                    return Some(i);
+                }
+            }
+        }
+        None
+    }
+}
+
+// Finds the smallest key greater than or equal to the given key, and every
+// key following it
+struct FromSearch<'a, K>(&'a K);
+
+impl<'a, K, C, H> Method<C, H> for FromSearch<'a, K>
+where
+    C: Compound<H>,
+    C::Annotation: Borrow<MaxKey<K>>,
+    H: ByteHash,
+    K: Ord,
+{
+    fn select(&mut self, handles: &[Handle<C, H>]) -> Option<usize> {
+        handles.iter().position(|h| match h.annotation() {
+            Some(ann) => {
+                let max: &MaxKey<K> = (*ann).borrow();
+                **max >= *self.0
+            }
+            None => false,
+        })
+    }
+}
+
+// Finds the smallest key strictly greater than the given key
+struct NextSearch<'a, K>(&'a K);
+
+impl<'a, K, C, H> Method<C, H> for NextSearch<'a, K>
+where
+    C: Compound<H>,
+    C::Annotation: Borrow<MaxKey<K>>,
+    H: ByteHash,
+    K: Ord,
+{
+    fn select(&mut self, handles: &[Handle<C, H>]) -> Option<usize> {
+        handles.iter().position(|h| match h.annotation() {
+            Some(ann) => {
+                let max: &MaxKey<K> = (*ann).borrow();
+                **max > *self.0
+            }
+            None => false,
+        })
+    }
+}
+
+// Finds the largest key strictly less than the given key
+struct PrevSearch<'a, K>(&'a K);
+
+impl<'a, K, C, H> Method<C, H> for PrevSearch<'a, K>
+where
+    C: Compound<H>,
+    C::Annotation: Borrow<MinKey<K>>,
+    H: ByteHash,
+    K: Ord,
+{
+    fn select(&mut self, handles: &[Handle<C, H>]) -> Option<usize> {
+        handles.iter().rposition(|h| match h.annotation() {
+            Some(ann) => {
+                let min: &MinKey<K> = (*ann).borrow();
+                **min < *self.0
+            }
+            None => false,
+        })
+    }
+}
+
 enum InsertResult<C, H>
 where
     C: Compound<H>,
@@ -107,11 +222,506 @@ where
     V: Content<H>,
     H: ByteHash,
 {
-    /// Creates a new BTree
+    /// Creates a new BTree with the default node size
+    ///
+    /// Trees of other node sizes are created with `BTree::default()`
     pub fn new() -> Self {
         BTree(Default::default())
     }
 
+    /// Returns a builder of a BTree, to pick its node size and initial
+    /// entries
+    pub fn builder() -> Builder<K, V, H> {
+        Builder {
+            entries: vec![],
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Builder of a BTree, returned by `BTree::builder`
+///
+/// The node size is part of the type of the tree, rather than stored in its
+/// nodes, so it is picked as a type as well:
+/// `BTree::builder().fanout::<Wide>().entries(pairs).build()`.
+pub struct Builder<K, V, H, A = Narrow> {
+    entries: Vec<(K, V)>,
+    _marker: PhantomData<(H, A)>,
+}
+
+impl<K, V, H, A> Builder<K, V, H, A>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+    A: Fanout,
+{
+    /// Sets the node size of the tree
+    pub fn fanout<B: Fanout>(self) -> Builder<K, V, H, B> {
+        Builder {
+            entries: self.entries,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Adds entries to the tree, for duplicate keys the last value is kept
+    pub fn entries<I>(mut self, iter: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        self.entries.extend(iter);
+        self
+    }
+
+    /// Builds the tree, bottom-up from its entries
+    pub fn build(self) -> io::Result<BTree<K, V, H, A>> {
+        BTree::from_unsorted_iter(self.entries)
+    }
+}
+
+impl<K, V, H, A> BTree<K, V, H, A>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+    A: Fanout,
+{
+    // the minimum and maximum number of children of a non-root node
+    const N: usize = A::MIN;
+    const M: usize = 2 * A::MIN - 1;
+
+    /// Returns the number of entries in the tree
+    pub fn len(&self) -> u64 {
+        self.count()
+    }
+
+    /// Returns true if the tree contains no entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Checks the invariants of the tree, returning an error describing the
+    /// first violation found
+    ///
+    /// On top of the checks of `kelvin::validate`, all leaves have to be at
+    /// the same depth and in strictly ascending key order, and every node
+    /// but the root has to hold between `MIN` and `2 * MIN - 1` children.
+    pub fn validate(&self) -> io::Result<()> {
+        let mut leaf_depth = None;
+        let mut last: Option<K> = None;
+        kelvin::validate(self, |node: &Self, path| {
+            let len = node.0.len();
+            if len > Self::M || (!path.is_empty() && len < Self::N) {
+                return Err(format!("{} children", len));
+            }
+            let mut leaves = 0;
+            for handle in &node.0 {
+                match handle.handle_type() {
+                    HandleType::Leaf => leaves += 1,
+                    HandleType::Node => continue,
+                    HandleType::None => return Err("empty handle".into()),
+                }
+                if let HandleRef::Leaf((k, _)) =
+                    handle.inner().map_err(|e| e.to_string())?
+                {
+                    if last.as_ref().is_some_and(|last| last >= k) {
+                        return Err("keys out of order".into());
+                    }
+                    last = Some(k.clone());
+                }
+            }
+            match (leaves, leaf_depth) {
+                (0, _) => Ok(()),
+                (n, _) if n < len => Err("leaves next to nodes".into()),
+                (_, None) => {
+                    leaf_depth = Some(path.len());
+                    Ok(())
+                }
+                (_, Some(depth)) if depth == path.len() => Ok(()),
+                (_, Some(depth)) => Err(format!(
+                    "leaves at depth {}, others at {}",
+                    path.len(),
+                    depth
+                )),
+            }
+        })
+    }
+
+    /// Builds a BTree from key-value pairs sorted by strictly ascending keys
+    ///
+    /// The tree is built bottom-up out of full nodes, instead of inserting
+    /// the pairs one by one. Returns an error if the keys are out of order.
+    pub fn from_sorted_iter<I>(iter: I) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let pairs: Vec<_> = iter.into_iter().collect();
+
+        if pairs.windows(2).any(|w| w[0].0 >= w[1].0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Keys not in strictly ascending order",
+            ));
+        }
+
+        let mut level: Vec<_> =
+            pairs.into_iter().map(Handle::new_leaf).collect();
+
+        while level.len() > Self::M {
+            level = Self::group(level);
+        }
+
+        let mut root = Self::default();
+        for handle in level {
+            root.0.push(handle);
+        }
+        Ok(root)
+    }
+
+    /// Builds a BTree from key-value pairs in any order
+    ///
+    /// The pairs are sorted before building the tree, for duplicate keys the
+    /// last value is kept.
+    pub fn from_unsorted_iter<I>(iter: I) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut pairs: Vec<(K, V)> = iter.into_iter().collect();
+        // stable sort, keeps later duplicates after earlier ones
+        pairs.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut deduped: Vec<(K, V)> = Vec::with_capacity(pairs.len());
+        for pair in pairs {
+            match deduped.last_mut() {
+                Some(last) if last.0 == pair.0 => *last = pair,
+                _ => deduped.push(pair),
+            }
+        }
+        Self::from_sorted_iter(deduped)
+    }
+
+    // Groups a level of handles into as few nodes as possible, distributing
+    // the handles evenly to keep every node at least `N` long.
+    fn group(handles: Vec<Handle<Self, H>>) -> Vec<Handle<Self, H>> {
+        let len = handles.len();
+        let n_nodes = len.div_ceil(Self::M);
+        let base = len / n_nodes;
+        let extra = len % n_nodes;
+
+        let mut handles = handles.into_iter();
+        let mut nodes = Vec::with_capacity(n_nodes);
+
+        for i in 0..n_nodes {
+            let size = if i < extra { base + 1 } else { base };
+            debug_assert!((Self::N..=Self::M).contains(&size));
+            let mut node = Self::default();
+            for handle in handles.by_ref().take(size) {
+                node.0.push(handle);
+            }
+            nodes.push(Handle::new_node(node));
+        }
+        nodes
+    }
+
+    /// Returns the entry with the smallest key, if any
+    pub fn first_key_value(&self) -> io::Result<Option<Branch<'_, Self, H>>> {
+        Branch::new(self, &mut MinKeySearch::default())
+    }
+
+    /// Returns the entry with the largest key, if any
+    pub fn last_key_value(&self) -> io::Result<Option<Branch<'_, Self, H>>> {
+        Branch::new(self, &mut MaxKeySearch::default())
+    }
+
+    /// Retains only the entries for which `f` returns true
+    ///
+    /// Subtrees without any removed entries are kept as-is, only the nodes
+    /// along the paths to removed entries are rebuilt.
+    pub fn retain<F>(&mut self, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let height = self.height()?;
+        let mut pieces = vec![];
+        mem::take(self).retain_pieces(height, &mut f, &mut pieces)?;
+        *self = Self::assemble(pieces, false)?;
+        Ok(())
+    }
+
+    // Consumes the tree, collecting the retained subtrees in order along with
+    // their heights. Returns true if any entry was removed.
+    fn retain_pieces<F>(
+        self,
+        height: usize,
+        f: &mut F,
+        pieces: &mut Vec<(usize, Handle<Self, H>)>,
+    ) -> io::Result<bool>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let mut removed = false;
+        for handle in self.0 {
+            if handle.handle_type() == HandleType::Leaf {
+                let keep = match handle.inner()? {
+                    HandleRef::Leaf((k, v)) => f(k, v),
+                    _ => unreachable!(),
+                };
+                if keep {
+                    pieces.push((height - 1, handle))
+                } else {
+                    removed = true
+                }
+                continue;
+            }
+
+            let persisted = handle.snapshot().map(|_| handle.clone());
+            let mark = pieces.len();
+
+            if Self::restore_node(handle)?.retain_pieces(
+                height - 1,
+                f,
+                pieces,
+            )? {
+                removed = true;
+            } else {
+                // untouched, put the subtree back together
+                let handle = match persisted {
+                    Some(handle) => {
+                        pieces.truncate(mark);
+                        handle
+                    }
+                    None => {
+                        let mut node = Self::default();
+                        for (_, child) in pieces.drain(mark..) {
+                            node.0.push(child);
+                        }
+                        Handle::new_node(node)
+                    }
+                };
+                pieces.push((height - 1, handle));
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Returns the entry with the smallest key greater than `k`, if any
+    pub fn get_next(&self, k: &K) -> io::Result<Option<Branch<'_, Self, H>>> {
+        Branch::new(self, &mut NextSearch(k))
+    }
+
+    /// Returns the entry with the largest key less than `k`, if any
+    pub fn get_prev(&self, k: &K) -> io::Result<Option<Branch<'_, Self, H>>> {
+        Branch::new(self, &mut PrevSearch(k))
+    }
+
+    /// Returns an iterator over the entries with keys greater than or equal
+    /// to `k`, in key order
+    ///
+    /// Seeks directly to the first such entry, skipping the subtrees before.
+    pub fn iter_from<'a>(
+        &'a self,
+        k: &'a K,
+    ) -> impl Iterator<Item = io::Result<&'a (K, V)>> {
+        self.iter_by(FromSearch(k))
+    }
+
+    /// Returns an iterator over the entries whose keys start with `prefix`,
+    /// in key order
+    ///
+    /// Only the subtrees that can contain such keys are visited.
+    pub fn iter_prefix<'a>(
+        &'a self,
+        prefix: &'a [u8],
+    ) -> impl Iterator<Item = io::Result<&'a (K, V)>>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.iter_by(PrefixSearch(prefix, PhantomData))
+            .take_while(move |res| match res {
+                Ok((k, _)) => k.as_ref().starts_with(prefix),
+                Err(_) => true,
+            })
+    }
+
+    /// Splits the tree in two at the given key, returning everything
+    /// greater than or equal to `k`
+    ///
+    /// Subtrees that lie entirely on one side of `k` are moved over as-is,
+    /// only the nodes along the path to `k` are rebuilt.
+    pub fn split_off(&mut self, k: &K) -> io::Result<Self> {
+        let height = self.height()?;
+        let mut left = vec![];
+        let mut right = vec![];
+
+        mem::take(self).split_pieces(k, height, &mut left, &mut right)?;
+
+        *self = Self::assemble(left, false)?;
+        Self::assemble(right.into_iter().rev(), true)
+    }
+
+    // Number of levels of nodes in the tree, all leaves are at the same depth
+    fn height(&self) -> io::Result<usize> {
+        match self.0.first() {
+            None => Ok(0),
+            Some(handle) => match handle.inner()? {
+                HandleRef::Node(n) => Ok(n.height()? + 1),
+                _ => Ok(1),
+            },
+        }
+    }
+
+    // Consumes the tree, collecting the subtrees to the left and right of
+    // `k` in order, along with their heights.
+    fn split_pieces(
+        self,
+        k: &K,
+        height: usize,
+        left: &mut Vec<(usize, Handle<Self, H>)>,
+        right: &mut Vec<(usize, Handle<Self, H>)>,
+    ) -> io::Result<()> {
+        let boundary = self.0.iter().position(|h| {
+            let ann = h.annotation().expect("invalid handle");
+            let max_key: &MaxKey<K> = (*ann).borrow();
+            **max_key >= *k
+        });
+
+        for (i, handle) in self.0.into_iter().enumerate() {
+            match boundary {
+                Some(b)
+                    if i == b && handle.handle_type() == HandleType::Node =>
+                {
+                    Self::restore_node(handle)?.split_pieces(
+                        k,
+                        height - 1,
+                        left,
+                        right,
+                    )?
+                }
+                Some(b) if i >= b => right.push((height - 1, handle)),
+                _ => left.push((height - 1, handle)),
+            }
+        }
+        Ok(())
+    }
+
+    // Builds a tree out of subtrees given in key order, pushed at the back,
+    // or at the front if `front` is set.
+    fn assemble<I>(pieces: I, front: bool) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = (usize, Handle<Self, H>)>,
+    {
+        let mut root = Self::default();
+        let mut height = 0;
+
+        for (piece_height, piece) in pieces {
+            if piece_height < height {
+                root.push_piece(&mut height, piece, piece_height, front)?;
+                continue;
+            }
+
+            // the piece is at least as tall as the tree, so the tree is
+            // moved into the piece instead
+            let old_root = mem::take(&mut root);
+            let old_height = height;
+            root.0.push(piece);
+            height = piece_height + 1;
+
+            let children: Vec<_> = old_root.0.into_iter().collect();
+            if front {
+                for child in children {
+                    root.push_piece(&mut height, child, old_height - 1, false)?
+                }
+            } else {
+                for child in children.into_iter().rev() {
+                    root.push_piece(&mut height, child, old_height - 1, true)?
+                }
+            }
+        }
+
+        // the root might be left with a single node
+        while root.0.len() == 1 && root.0[0].handle_type() == HandleType::Node {
+            let only = root.0.pop().expect("one child");
+            root = Self::restore_node(only)?;
+        }
+        Ok(root)
+    }
+
+    // Pushes a subtree at the back or front of the root, growing the tree if
+    // the root overflows
+    fn push_piece(
+        &mut self,
+        height: &mut usize,
+        piece: Handle<Self, H>,
+        piece_height: usize,
+        front: bool,
+    ) -> io::Result<()> {
+        if let Some(split) =
+            self.push_end(*height, piece, piece_height, front)?
+        {
+            let old_root = Handle::new_node(mem::take(self));
+            if front {
+                self.0.push(split);
+                self.0.push(old_root);
+            } else {
+                self.0.push(old_root);
+                self.0.push(split);
+            }
+            *height += 1;
+        }
+        Ok(())
+    }
+
+    // Pushes a subtree at the back or front of the tree, returning the node
+    // split off if this node overflowed.
+    fn push_end(
+        &mut self,
+        height: usize,
+        piece: Handle<Self, H>,
+        piece_height: usize,
+        front: bool,
+    ) -> io::Result<Option<Handle<Self, H>>> {
+        let piece = if piece_height + 1 == height {
+            piece
+        } else {
+            let i = if front { 0 } else { self.0.len() - 1 };
+            match &mut *self.0[i].inner_mut()? {
+                HandleMut::Node(n) => {
+                    match n.push_end(height - 1, piece, piece_height, front)? {
+                        Some(split) => split,
+                        None => return Ok(None),
+                    }
+                }
+                _ => unreachable!("leaves are all at the same depth"),
+            }
+        };
+
+        if self.0.len() < Self::M {
+            if front {
+                self.0.insert(0, piece);
+            } else {
+                self.0.push(piece);
+            }
+            return Ok(None);
+        }
+
+        // split into two nodes of length N
+        let new_node = if front {
+            self.0.insert(0, piece);
+            let rest = self.0.split_off(Self::N);
+            BTree(mem::replace(&mut self.0, rest))
+        } else {
+            self.0.push(piece);
+            BTree(self.0.split_off(Self::N))
+        };
+        Ok(Some(Handle::new_node(new_node)))
+    }
+
+    // Takes the node out of a handle, restoring it if persisted
+    fn restore_node(mut handle: Handle<Self, H>) -> io::Result<Self> {
+        handle.inner_mut()?;
+        Ok(handle.into_node())
+    }
+
     /// Insert key-value pair into the BTree, optionally returning expelled value
     pub fn insert(&mut self, k: K, v: V) -> io::Result<Option<V>> {
         match self._insert(Handle::new_leaf((k, v)), 0)? {
@@ -187,7 +797,7 @@ where
                     return Ok(InsertResult::Replaced(replaced));
                 }
                 Action::Insert(i) => {
-                    if !self.0.is_full() {
+                    if self.0.len() < Self::M {
                         self.0.insert(i, handle);
                         return Ok(InsertResult::Ok);
                     } else {
@@ -198,49 +808,27 @@ where
                     // initial state
                     // [ 1, 3, 5 ]
 
-                    // # SPLIT CASE A
-
-                    // [ 1, 3, 5 ]
-                    // [ 0 ] [ 3, 5 ]
-                    // [ 0, 1 ] [ 3, 5 ]
-
-                    // # SPLIT CASE B
+                    // insert the new handle, overflowing the node
+                    // [ 1, 3, 4, 5 ]
 
-                    // [ 1, 3, 5 ]
-                    // [ 1, 3 ] [ 4 ]
+                    // split into two nodes of length N
                     // [ 1, 3 ] [ 4, 5 ]
 
                     // The new (second) node is returned down the stack for merging
 
-                    debug_assert!(self.0.len() == M);
-
-                    let mut new_node = Self::new();
-                    let popped =
-                        self.0.pop().expect("attempt to split empty node");
-
-                    if i < N {
-                        // CASE A
-                        let second =
-                            self.0.pop().expect("attempt to split empty node");
-
-                        new_node.0.push(second);
-                        new_node.0.push(popped);
+                    debug_assert!(self.0.len() == Self::M);
 
-                        self.0.insert(i, handle);
-                    } else {
-                        // CASE B
-                        new_node.0.push(popped);
-                        new_node.0.insert(i - N, handle);
-                    }
+                    self.0.insert(i, handle);
+                    let new_node = BTree(self.0.split_off(Self::N));
 
-                    debug_assert!(self.0.len() == N);
-                    debug_assert!(new_node.0.len() == N);
+                    debug_assert!(self.0.len() == Self::N);
+                    debug_assert!(new_node.0.len() == Self::N);
 
                     let new_handle = Handle::new_node(new_node);
 
                     if depth == 0 {
                         // if we're on the top level, we create a new root.
-                        let old_root = mem::replace(self, Self::new());
+                        let old_root = mem::take(self);
                         self.0.push(Handle::new_node(old_root));
                         self.0.push(new_handle);
 
@@ -254,7 +842,13 @@ where
     }
 
     /// Remove element with given key, returning it.
-    pub fn remove(&mut self, k: &K) -> io::Result<Option<V>> {
+    ///
+    /// The key may be any borrowed form of the key type.
+    pub fn remove<O>(&mut self, k: &O) -> io::Result<Option<V>>
+    where
+        K: Borrow<O>,
+        O: Ord + ?Sized,
+    {
         match self._remove(k, 0)? {
             RemoveResult::Removed((_, v)) => Ok(Some(v)),
             RemoveResult::Noop => Ok(None),
@@ -262,11 +856,15 @@ where
         }
     }
 
-    fn _remove(
+    fn _remove<O>(
         &mut self,
-        k: &K,
+        k: &O,
         depth: usize,
-    ) -> io::Result<RemoveResult<Self, H>> {
+    ) -> io::Result<RemoveResult<Self, H>>
+    where
+        K: Borrow<O>,
+        O: Ord + ?Sized,
+    {
         enum Action<L> {
             Noop,
             Remove(usize),
@@ -275,12 +873,12 @@ where
         // The default action
         let mut action = Action::Noop;
 
-        match BTreeSearch::new(k).select(self.children()) {
+        match BTreeSearch::<K, O>::new(k).select(self.children()) {
             Some(i) => {
                 match &mut *self.0[i].inner_mut()? {
                     HandleMut::None => unreachable!(),
                     HandleMut::Leaf((key, _)) => {
-                        if key == k {
+                        if Borrow::<O>::borrow(&*key) == k {
                             action = Action::Remove(i);
                         }
                     }
@@ -290,8 +888,8 @@ where
                         let max_key: &MaxKey<K> = ann.borrow();
 
                         // Recurse
-                        if **max_key >= *k {
-                            match n._remove(&k, depth + 1)? {
+                        if (**max_key).borrow() >= k {
+                            match n._remove(k, depth + 1)? {
                                 RemoveResult::Noop => (),
                                 RemoveResult::Removed(leaf) => {
                                     return Ok(RemoveResult::Removed(leaf))
@@ -308,134 +906,180 @@ where
         }
 
         match action {
-            Action::Remove(i) => {
-                let removed = self.0.remove(i);
-                // are we under-filled at a depth of at least 1?
-                if self.0.len() < N && depth > 0 {
-                    Ok(RemoveResult::Merge(removed.into_leaf()))
-                } else {
-                    Ok(RemoveResult::Removed(removed.into_leaf()))
-                }
-            }
+            Action::Remove(i) => Ok(self.remove_leaf(i, depth)),
             Action::Noop => Ok(RemoveResult::Noop),
-            Action::Merge(i, leaf) => {
-                // Case A
-                // [0, 1] [2] ... -> [0, 1, 2] ...
-
-                // Case B
-                // [0, 1, 2] [3] ... -> [0, 1] [2, 3] ...
-
-                // Case C
-                // [0] [1, 2] ... -> [0, 1, 2] ...
-
-                // Case D
-                // [0] [1, 2, 3] ... -> [0, 1] [2, 3] ...
-
-                // in order to keep the borrow checker happy and do minimal
-                // lookups, we first replace the to-be-merged node with an empty one.
-
-                let mut to_merge =
-                    mem::replace(&mut self.0[i], Handle::default()).into_node();
-
-                // Is there a node before this one?
-                if i > 0 {
-                    // Case A/B
-                    match &mut *self.0[i - 1].inner_mut()? {
-                        HandleMut::Node(n) => {
-                            if n.0.len() == N {
-                                // Case A - move from to_merge into prev node
-                                let popped = to_merge
-                                    .0
-                                    .pop()
-                                    .expect("attempt to merge empty node");
-                                n.0.push(popped)
-                            } else {
-                                // Case B - pop from node and prepend to to_merge
-                                let popped =
-                                    n.0.pop().expect("len guaranteed > 0");
-                                to_merge.0.insert(0, popped);
-                            }
-                        }
-                        _ => unreachable!(),
-                    }
-                } else {
-                    // Case C/D
-                    match &mut *self.0[i + 1].inner_mut()? {
-                        HandleMut::Node(n) => {
-                            if n.0.len() == N {
-                                // Case C
-                                let popped = to_merge
-                                    .0
-                                    .pop()
-                                    .expect("attempt to merge empty node");
-                                // prepend into next node
-                                n.0.insert(0, popped)
-                            } else {
-                                // Case D
-                                let removed = n.0.remove(0);
-                                to_merge.0.push(removed);
-                            }
-                        }
-                        _ => unreachable!(),
-                    }
-                }
-
-                // did we empty the to_merge node?
-                if to_merge.0.len() > 0 {
-                    // swap back
-                    self.0[i] = Handle::new_node(to_merge);
-                    Ok(RemoveResult::Removed(leaf))
-                } else {
-                    // remove empty node
-                    self.0.remove(i);
-                    if self.0.len() < N {
-                        if depth > 0 {
-                            Ok(RemoveResult::Merge(leaf))
-                        } else {
-                            // replace root
-                            let singleton =
-                                mem::replace(&mut self.0[0], Handle::default());
-                            *self = singleton.into_node();
-                            Ok(RemoveResult::Removed(leaf))
-                        }
-                    } else {
-                        Ok(RemoveResult::Removed(leaf))
-                    }
-                }
-            }
+            Action::Merge(i, leaf) => self.merge_child(i, leaf, depth),
         }
     }
-}
 
-impl<K, V, H> Content<H> for BTree<K, V, H>
-where
-    K: Content<H> + Ord,
-    V: Content<H>,
-    H: ByteHash,
-{
-    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
-        (self.0.len() as u8).persist(sink)?;
-        for h in &mut self.0 {
-            h.persist(sink)?
-        }
-        Ok(())
+    /// Removes and returns the entry with the smallest key, if any
+    pub fn pop_min(&mut self) -> io::Result<Option<(K, V)>> {
+        self.pop(true)
     }
 
-    fn restore(source: &mut Source<H>) -> io::Result<Self> {
-        let mut b = BTree::default();
-        let len = u8::restore(source)?;
-        for _ in 0..len {
-            b.0.push(Handle::restore(source)?);
+    /// Removes and returns the entry with the largest key, if any
+    pub fn pop_max(&mut self) -> io::Result<Option<(K, V)>> {
+        self.pop(false)
+    }
+
+    fn pop(&mut self, first: bool) -> io::Result<Option<(K, V)>> {
+        match self._pop(first, 0)? {
+            RemoveResult::Removed(leaf) => Ok(Some(leaf)),
+            RemoveResult::Noop => Ok(None),
+            _ => unreachable!(),
         }
-        Ok(b)
     }
-}
 
-impl<K, V, H> Compound<H> for BTree<K, V, H>
-where
-    H: ByteHash,
-    K: Content<H> + Ord,
-    V: Content<H>,
+    // Removes the first or last leaf, descending the tree only once
+    fn _pop(
+        &mut self,
+        first: bool,
+        depth: usize,
+    ) -> io::Result<RemoveResult<Self, H>> {
+        if self.0.is_empty() {
+            return Ok(RemoveResult::Noop);
+        }
+        let i = if first { 0 } else { self.0.len() - 1 };
+
+        let merge = match &mut *self.0[i].inner_mut()? {
+            HandleMut::None => unreachable!(),
+            HandleMut::Leaf(_) => None,
+            HandleMut::Node(n) => match n._pop(first, depth + 1)? {
+                RemoveResult::Noop => unreachable!("empty node"),
+                removed @ RemoveResult::Removed(_) => return Ok(removed),
+                RemoveResult::Merge(leaf) => Some(leaf),
+            },
+        };
+
+        match merge {
+            None => Ok(self.remove_leaf(i, depth)),
+            Some(leaf) => self.merge_child(i, leaf, depth),
+        }
+    }
+
+    // Removes the leaf at `i`, signaling if the node is left under-filled
+    fn remove_leaf(&mut self, i: usize, depth: usize) -> RemoveResult<Self, H> {
+        let removed = self.0.remove(i);
+        // are we under-filled at a depth of at least 1?
+        if self.0.len() < Self::N && depth > 0 {
+            RemoveResult::Merge(removed.into_leaf())
+        } else {
+            RemoveResult::Removed(removed.into_leaf())
+        }
+    }
+
+    // Rebalances the under-filled child at `i` with one of its siblings
+    fn merge_child(
+        &mut self,
+        i: usize,
+        leaf: (K, V),
+        depth: usize,
+    ) -> io::Result<RemoveResult<Self, H>> {
+        // Case A
+        // [0, 1] [2] ... -> [0, 1, 2] ...
+
+        // Case B
+        // [0, 1, 2] [3] ... -> [0, 1] [2, 3] ...
+
+        // Case C
+        // [0] [1, 2] ... -> [0, 1, 2] ...
+
+        // Case D
+        // [0] [1, 2, 3] ... -> [0, 1] [2, 3] ...
+
+        // in order to keep the borrow checker happy and do minimal
+        // lookups, we first replace the to-be-merged node with an empty one.
+
+        let mut to_merge =
+            mem::replace(&mut self.0[i], Handle::default()).into_node();
+
+        // Is there a node before this one?
+        if i > 0 {
+            // Case A/B
+            match &mut *self.0[i - 1].inner_mut()? {
+                HandleMut::Node(n) => {
+                    if n.0.len() == Self::N {
+                        // Case A - move all of to_merge into prev node
+                        n.0.append(&mut to_merge.0)
+                    } else {
+                        // Case B - pop from node and prepend to to_merge
+                        let popped = n.0.pop().expect("len guaranteed > 0");
+                        to_merge.0.insert(0, popped);
+                    }
+                }
+                _ => unreachable!(),
+            }
+        } else {
+            // Case C/D
+            match &mut *self.0[i + 1].inner_mut()? {
+                HandleMut::Node(n) => {
+                    if n.0.len() == Self::N {
+                        // Case C - prepend all of to_merge into next node
+                        to_merge.0.append(&mut n.0);
+                        mem::swap(&mut n.0, &mut to_merge.0);
+                    } else {
+                        // Case D
+                        let removed = n.0.remove(0);
+                        to_merge.0.push(removed);
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        // did we empty the to_merge node?
+        if to_merge.0.len() > 0 {
+            // swap back
+            self.0[i] = Handle::new_node(to_merge);
+            Ok(RemoveResult::Removed(leaf))
+        } else {
+            // remove empty node
+            self.0.remove(i);
+            if self.0.len() < Self::N && depth > 0 {
+                Ok(RemoveResult::Merge(leaf))
+            } else if self.0.len() == 1 && depth == 0 {
+                // replace root
+                let singleton = mem::replace(&mut self.0[0], Handle::default());
+                *self = singleton.into_node();
+                Ok(RemoveResult::Removed(leaf))
+            } else {
+                Ok(RemoveResult::Removed(leaf))
+            }
+        }
+    }
+}
+
+impl<K, V, H, A> Content<H> for BTree<K, V, H, A>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+    A: Fanout,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        (self.0.len() as u8).persist(sink)?;
+        for h in &mut self.0 {
+            h.persist(sink)?
+        }
+        Ok(())
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let mut b = BTree::default();
+        let len = u8::restore(source)?;
+        for _ in 0..len {
+            b.0.push(Handle::restore(source)?);
+        }
+        Ok(b)
+    }
+}
+
+impl<K, V, H, A> Compound<H> for BTree<K, V, H, A>
+where
+    H: ByteHash,
+    K: Content<H> + Ord,
+    V: Content<H>,
+    A: Fanout,
 {
     type Leaf = (K, V);
     type Annotation = BTreeAnnotation<K, u64>;
@@ -449,22 +1093,159 @@ where
     }
 }
 
-impl<'a, O, K, V, H> Map<'a, O, K, V, H> for BTree<K, V, H>
+/// Consumes the structure, yielding its entries by value as it is torn down
+impl<K, V, H, A> IntoIterator for BTree<K, V, H, A>
+where
+    H: ByteHash,
+    K: Content<H> + Ord,
+    V: Content<H>,
+    A: Fanout,
+{
+    type Item = io::Result<(K, V)>;
+    type IntoIter = Drain<Self, H>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        self.drain()
+    }
+}
+
+/// Iterates over the entries by reference
+impl<'a, K, V, H, A> IntoIterator for &'a BTree<K, V, H, A>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+    A: Fanout,
+{
+    type Item = io::Result<&'a (K, V)>;
+    type IntoIter = LeafIter<'a, BTree<K, V, H, A>, First, H>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Builds a BTree with `insert_batch`, for duplicate keys the last value is
+/// kept
+impl<K, V, H, A> FromIterator<(K, V)> for BTree<K, V, H, A>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+    A: Fanout,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::default();
+        map.extend(iter);
+        map
+    }
+}
+
+/// Inserts the entries with `insert_batch`
+///
+/// Panics if a node of the BTree fails to restore from its store, use
+/// `insert_batch` directly to handle the error instead.
+impl<K, V, H, A> Extend<(K, V)> for BTree<K, V, H, A>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+    A: Fanout,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        MapMut::insert_batch(self, iter).expect("BTree node failed to restore");
+    }
+}
+
+impl<'a, O, K, V, H, A> Map<'a, O, K, V, H> for BTree<K, V, H, A>
 where
     K: Content<H> + Ord + Borrow<O>,
     V: Content<H>,
     H: ByteHash,
     O: Ord + ?Sized + 'a,
+    A: Fanout,
 {
     type KeySearch = BTreeSearch<'a, K, O>;
 }
 
+impl<K, V, H, A> MapMut<K, V, H> for BTree<K, V, H, A>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+    A: Fanout,
+{
+    fn insert(&mut self, k: K, v: V) -> io::Result<Option<V>> {
+        BTree::insert(self, k, v)
+    }
+
+    fn remove(&mut self, k: &K) -> io::Result<Option<V>> {
+        BTree::remove(self, k)
+    }
+
+    /// Sorts the batch first, so consecutive keys share most of their path
+    /// down the tree, which stays in memory between the inserts. Every pair
+    /// is still inserted from the root. An empty tree is built bottom-up
+    /// from the sorted batch instead, annotating each node once.
+    fn insert_batch<I>(&mut self, iter: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        if self.0.is_empty() {
+            *self = Self::from_unsorted_iter(iter)?;
+            return Ok(());
+        }
+        let mut pairs: Vec<_> = iter.into_iter().collect();
+        // stable sort, keeps later duplicates after earlier ones
+        pairs.sort_by(|a, b| a.0.cmp(&b.0));
+        for (k, v) in pairs {
+            BTree::insert(self, k, v)?;
+        }
+        Ok(())
+    }
+}
+
+impl<O, K, V, H, A> MapRemove<O, K, V, H> for BTree<K, V, H, A>
+where
+    K: Content<H> + Ord + Borrow<O>,
+    V: Content<H>,
+    H: ByteHash,
+    O: Ord + ?Sized,
+    A: Fanout,
+{
+    fn remove_borrowed(&mut self, k: &O) -> io::Result<Option<V>> {
+        BTree::remove(self, k)
+    }
+}
+
+// trees of another fanout share the tag, as they restore the same way
+impl<K, V, H, A> Tagged for BTree<K, V, H, A>
+where
+    K: Content<H> + Ord + Tagged,
+    V: Content<H> + Tagged,
+    H: ByteHash,
+    A: Fanout,
+{
+    const TAG: u64 = kelvin::tag("kelvin_btree::BTree", &[K::TAG, V::TAG]);
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
-    use kelvin::quickcheck_map;
+    use std::collections::HashMap;
+
+    use kelvin::annotations::{AggregateRange, FirstKey, LastKey};
+    use kelvin::tests::{
+        assert_equiv, assert_root_hash, check_corruption, Corruption,
+    };
     use kelvin::Blake2b;
+    use kelvin::{
+        quickcheck_annotation, quickcheck_ordered_map, quickcheck_proofs,
+    };
+    use kelvin::{Cursor, Decision, Proof, Select};
+
+    quickcheck_annotation!(BTreeAnnotation<u8, u64>, (u8, u8));
 
     #[test]
     fn trivial_map() {
@@ -506,9 +1287,11 @@ mod test {
             let i = bigger - i - 1;
             h.insert(i, i).unwrap();
         }
+        assert_eq!(h.len(), bigger);
         for i in 0..bigger {
             assert_eq!(h.remove(&i).unwrap().unwrap(), i);
         }
+        assert!(h.is_empty());
     }
 
     #[test]
@@ -524,11 +1307,1105 @@ mod test {
         }
     }
 
+    fn fanout<A: Fanout>() {
+        let n = 2048u32;
+        let mut h = BTree::<_, _, Blake2b, A>::default();
+        for i in 0..n {
+            h.insert(i, i).unwrap();
+        }
+        assert!(h.children().len() < 2 * A::MIN);
+        h.validate().unwrap();
+
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let snapshot = store.persist(&mut h).unwrap();
+        let mut restored = store.restore(&snapshot).unwrap();
+        for i in 0..n {
+            assert_eq!(*restored.get(&i).unwrap().unwrap(), i);
+        }
+        for i in 0..n {
+            let i = if i % 2 == 0 { i / 2 } else { n - 1 - i / 2 };
+            assert_eq!(restored.remove(&i).unwrap(), Some(i));
+            if i % 64 == 0 {
+                restored.validate().unwrap();
+            }
+        }
+        assert!(restored.is_empty());
+        restored.validate().unwrap();
+
+        let mut sorted =
+            BTree::<_, _, Blake2b, A>::from_sorted_iter((0..n).map(|i| (i, i)))
+                .unwrap();
+        assert_eq!(sorted.count(), n as u64);
+
+        sorted.retain(|k, _| k % 3 != 0).unwrap();
+        let right = sorted.split_off(&(n / 2)).unwrap();
+        sorted.validate().unwrap();
+        right.validate().unwrap();
+        for i in 0..n {
+            let expected = if i % 3 == 0 { None } else { Some(i) };
+            let tree = if i < n / 2 { &sorted } else { &right };
+            assert_eq!(tree.get(&i).unwrap().map(|v| *v), expected);
+        }
+    }
+
+    #[test]
+    fn validate() {
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..100 {
+            tree.insert(i, i).unwrap();
+        }
+        tree.validate().unwrap();
+
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let snapshot = store.persist(&mut tree).unwrap();
+        store.restore(&snapshot).unwrap().validate().unwrap();
+
+        let mut unordered = BTree::<u32, u32, Blake2b>::new();
+        unordered.0.push(Handle::new_leaf((2, 2)));
+        unordered.0.push(Handle::new_leaf((1, 1)));
+        let err = unordered.validate().unwrap_err();
+        assert_eq!(err.to_string(), "keys out of order at node []");
+
+        let mut unbalanced = BTree::<u32, u32, Blake2b>::new();
+        unbalanced.0.push(Handle::new_leaf((0, 0)));
+        unbalanced.0.push(Handle::new_node(tree.clone()));
+        assert!(unbalanced.validate().is_err());
+
+        let mut empty = BTree::<u32, u32, Blake2b>::new();
+        empty.0.push(Handle::new_empty());
+        assert!(empty.validate().is_err());
+    }
+
+    #[derive(Clone)]
+    struct Odd;
+
+    impl Fanout for Odd {
+        const MIN: usize = 3;
+    }
+
+    #[test]
+    fn configurable_fanout() {
+        fanout::<Narrow>();
+        fanout::<Odd>();
+        fanout::<Wide>();
+    }
+
+    #[test]
+    fn builder() {
+        let tree = BTree::<u32, u32, Blake2b>::builder()
+            .entries((0..1000).map(|i| (i, i)))
+            .fanout::<Wide>()
+            .entries(vec![(0, 1), (1000, 1000)])
+            .build()
+            .unwrap();
+        assert_eq!(tree.len(), 1001);
+        assert_eq!(*tree.get(&0).unwrap().unwrap(), 1);
+        assert!(tree.children().len() < 2 * Wide::MIN);
+        tree.validate().unwrap();
+
+        let empty = BTree::<u32, u32, Blake2b>::builder().build().unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn insert_batch() {
+        use std::collections::BTreeMap;
+
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        let mut reference = BTreeMap::new();
+
+        // into an empty tree, then into a populated one
+        for round in 0..2u32 {
+            let batch: Vec<_> =
+                (0..500u32).map(|i| (i * 7 % 300, i + round)).collect();
+            tree.insert_batch(batch.clone()).unwrap();
+            reference.extend(batch);
+
+            assert_eq!(tree.len(), reference.len() as u64);
+            for (k, v) in &reference {
+                assert_eq!(*tree.get(k).unwrap().unwrap(), *v);
+            }
+        }
+    }
+
+    #[test]
+    fn collect_extend() {
+        let mut tree: BTree<u32, u32, Blake2b> =
+            (0..500u32).map(|i| (i % 300, i)).collect();
+        assert_eq!(tree.len(), 300);
+        tree.validate().unwrap();
+
+        tree.extend((300..600u32).map(|i| (i, i)));
+        assert_eq!(tree.len(), 600);
+
+        let mut expected = 0;
+        for entry in &tree {
+            let (k, v) = entry.unwrap();
+            assert_eq!(*k, expected);
+            // later duplicates win
+            assert_eq!(*v, if *k < 200 { k + 300 } else { *k });
+            expected += 1;
+        }
+        assert_eq!(expected, 600);
+    }
+
+    #[test]
+    fn from_sorted_iter() {
+        let n = 1000u32;
+        let mut h =
+            BTree::<_, _, Blake2b>::from_sorted_iter((0..n).map(|i| (i, i)))
+                .unwrap();
+
+        assert_eq!(h.count(), n as u64);
+        let keys: Vec<_> = h.iter().map(|res| res.unwrap().0).collect();
+        assert_eq!(keys, (0..n).collect::<Vec<_>>());
+
+        // the tree stays valid for further modification
+        for i in 0..n {
+            h.insert(i + n, i).unwrap();
+        }
+        for i in 0..2 * n {
+            assert_eq!(h.remove(&i).unwrap(), Some(i % n));
+        }
+    }
+
+    #[test]
+    fn from_sorted_iter_small() {
+        for n in 0..10u32 {
+            let h = BTree::<_, _, Blake2b>::from_sorted_iter(
+                (0..n).map(|i| (i, i)),
+            )
+            .unwrap();
+            for i in 0..n {
+                assert_eq!(*h.get(&i).unwrap().unwrap(), i);
+            }
+        }
+    }
+
+    #[test]
+    fn from_sorted_iter_unsorted() {
+        let res =
+            BTree::<_, _, Blake2b>::from_sorted_iter(vec![(1, 1), (0, 0)]);
+        assert!(res.is_err());
+        let res =
+            BTree::<_, _, Blake2b>::from_sorted_iter(vec![(1, 1), (1, 1)]);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn from_unsorted_iter() {
+        let pairs = vec![(3, 0), (1, 1), (2, 2), (3, 3), (0, 4)];
+        let h = BTree::<_, _, Blake2b>::from_unsorted_iter(pairs).unwrap();
+        assert_eq!(h.count(), 4);
+        // last value wins
+        assert_eq!(*h.get(&3).unwrap().unwrap(), 3);
+        assert_eq!(*h.get(&0).unwrap().unwrap(), 4);
+    }
+
+    #[test]
+    fn first_last() {
+        let mut tree = BTree::<_, _, Blake2b>::new();
+        assert!(tree.first_key_value().unwrap().is_none());
+        assert!(tree.last_key_value().unwrap().is_none());
+
+        for i in 0..1000u32 {
+            let k = (i * 7919) % 1000;
+            tree.insert(k, i).unwrap();
+        }
+        assert_eq!(tree.first_key_value().unwrap().unwrap().0, 0);
+        assert_eq!(tree.last_key_value().unwrap().unwrap().0, 999);
+
+        tree.remove(&0).unwrap();
+        tree.remove(&999).unwrap();
+        assert_eq!(tree.first_key_value().unwrap().unwrap().0, 1);
+        assert_eq!(tree.last_key_value().unwrap().unwrap().0, 998);
+    }
+
+    #[test]
+    fn retain() {
+        for n in [0u32, 1, 2, 3, 10, 100, 257].iter().cloned() {
+            for modulo in 1..5 {
+                let mut tree = BTree::<_, _, Blake2b>::new();
+                for i in 0..n {
+                    tree.insert(i, i).unwrap();
+                }
+
+                tree.retain(|k, _| k % modulo == 0).unwrap();
+
+                let expected: Vec<_> =
+                    (0..n).filter(|i| i % modulo == 0).collect();
+                assert_eq!(tree.count(), expected.len() as u64);
+                let keys: Vec<_> = tree.iter().map(|l| l.unwrap().0).collect();
+                assert_eq!(keys, expected);
+
+                // still a valid tree
+                for i in 0..n {
+                    tree.insert(i, i).unwrap();
+                }
+                for i in 0..n {
+                    assert_eq!(tree.remove(&i).unwrap(), Some(i));
+                }
+                assert_eq!(tree.count(), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn retain_shares_subtrees() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut tree = BTree::<_, _, Blake2b>::new();
+        for i in 0..1000u32 {
+            tree.insert(i, i).unwrap();
+        }
+        let snapshot = store.persist(&mut tree).unwrap();
+        let mut tree: BTree<u32, u32, Blake2b> =
+            store.restore(&snapshot).unwrap();
+
+        tree.retain(|k, _| *k != 999).unwrap();
+
+        assert!(tree.0[0].snapshot().is_some());
+        assert_eq!(tree.count(), 999);
+    }
+
+    #[test]
+    fn next_prev() {
+        let mut tree = BTree::<_, _, Blake2b>::new();
+        assert!(tree.get_next(&0).unwrap().is_none());
+        assert!(tree.get_prev(&0).unwrap().is_none());
+
+        // only even keys
+        for i in 0..500u32 {
+            tree.insert(i * 2, i).unwrap();
+        }
+
+        for k in 0..1000u32 {
+            let next = tree.get_next(&k).unwrap().map(|b| b.0);
+            let expected = if k >= 998 { None } else { Some(k / 2 * 2 + 2) };
+            assert_eq!(next, expected);
+
+            let prev = tree.get_prev(&k).unwrap().map(|b| b.0);
+            let expected = if k == 0 { None } else { Some((k - 1) / 2 * 2) };
+            assert_eq!(prev, expected);
+        }
+    }
+
+    #[test]
+    fn double_ended() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..1000 {
+            tree.insert(i, i).unwrap();
+        }
+        // leave some gaps
+        for i in (0..1000).step_by(7) {
+            tree.remove(&i).unwrap();
+        }
+        let expected: Vec<u32> = (0..1000).filter(|i| i % 7 != 0).collect();
+
+        let snapshot = store.persist(&mut tree).unwrap();
+        let restored = store.restore(&snapshot).unwrap();
+
+        for tree in [&tree, &restored].iter() {
+            let reversed: Vec<u32> =
+                tree.iter().rev().map(|leaf| leaf.unwrap().0).collect();
+            assert_eq!(
+                reversed,
+                expected.iter().rev().cloned().collect::<Vec<_>>()
+            );
+
+            assert_eq!(*tree.values().last().unwrap().unwrap(), 999);
+
+            // alternating between the ends visits every leaf exactly once
+            let mut iter = tree.iter();
+            let mut front = vec![];
+            let mut back = vec![];
+            while let Some(leaf) = iter.next() {
+                front.push(leaf.unwrap().0);
+                match iter.next_back() {
+                    Some(leaf) => back.push(leaf.unwrap().0),
+                    None => break,
+                }
+            }
+            assert!(iter.next().is_none());
+            assert!(iter.next_back().is_none());
+            back.reverse();
+            front.extend(back);
+            assert_eq!(front, expected);
+        }
+
+        assert_eq!(*tree.keys().next_back().unwrap().unwrap(), 999);
+
+        let empty = BTree::<u32, u32, Blake2b>::new();
+        assert!(empty.iter().next_back().is_none());
+    }
+
+    #[test]
+    fn pages() {
+        use kelvin::annotations::Paginate;
+
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..1000 {
+            tree.insert(i * 2, i).unwrap();
+        }
+        let snapshot = store.persist(&mut tree).unwrap();
+        let restored = store.restore(&snapshot).unwrap();
+
+        for tree in [&tree, &restored].iter() {
+            for &(offset, limit) in
+                [(0, 10), (10, 10), (995, 10), (1000, 10), (250, 0)].iter()
+            {
+                let page: Vec<u32> = tree
+                    .iter_page(offset as u64, limit)
+                    .map(|leaf| leaf.unwrap().0)
+                    .collect();
+                let expected: Vec<u32> =
+                    (offset..1000).take(limit).map(|i| i * 2).collect();
+                assert_eq!(page, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn resumable_cursor() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..1000 {
+            tree.insert(i, i).unwrap();
+        }
+        for i in (0..1000).step_by(3) {
+            tree.remove(&i).unwrap();
+        }
+        let snapshot = store.persist(&mut tree).unwrap();
+
+        // export in batches, persisting the cursor in between
+        let mut cursor = store.persist(&mut Cursor::new(&snapshot)).unwrap();
+        let mut exported = vec![];
+        loop {
+            let mut resumed = store.restore(&cursor).unwrap();
+            let batch = resumed.read(64).unwrap();
+            if batch.is_empty() {
+                assert!(resumed.is_exhausted());
+                break;
+            }
+            exported.extend(batch.into_iter().map(|(k, _)| k));
+            cursor = store.persist(&mut resumed).unwrap();
+        }
+
+        let expected: Vec<u32> = (0..1000).filter(|i| i % 3 != 0).collect();
+        assert_eq!(exported, expected);
+
+        // exact batches
+        let mut cursor = Cursor::new(&snapshot);
+        assert_eq!(cursor.read(expected.len()).unwrap().len(), expected.len());
+        assert!(!cursor.is_exhausted());
+        assert!(cursor.read(10).unwrap().is_empty());
+        assert!(cursor.is_exhausted());
+    }
+
+    #[test]
+    fn iter_snapshot() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..1000 {
+            tree.insert(i, i).unwrap();
+        }
+
+        let reader = tree.iter_snapshot(&store).unwrap();
+        let reader = std::thread::spawn(move || {
+            reader.map(|leaf| leaf.unwrap()).collect::<Vec<_>>()
+        });
+
+        // the writer keeps mutating its copy meanwhile
+        for i in 0..1000 {
+            tree.remove(&i).unwrap();
+            tree.insert(i + 1000, i).unwrap();
+        }
+
+        let read = reader.join().unwrap();
+        assert_eq!(read, (0..1000).map(|i| (i, i)).collect::<Vec<_>>());
+
+        let keys: Vec<u32> = tree
+            .iter_snapshot(&store)
+            .unwrap()
+            .map(|leaf| leaf.unwrap().0)
+            .collect();
+        assert_eq!(keys, (1000..2000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn select_by_annotation() {
+        let mut tree = BTree::<_, _, Blake2b>::new();
+        for i in 0..1000u32 {
+            tree.insert(i, i * 2).unwrap();
+        }
+
+        // leftmost entry with a key of at least `bound`
+        let at_least = |bound: u32| {
+            move |ann: &BTreeAnnotation<u32, u64>| {
+                let max: &MaxKey<u32> = ann.borrow();
+                if **max >= bound {
+                    Decision::Descend
+                } else {
+                    Decision::Skip
+                }
+            }
+        };
+
+        let found = tree.select(at_least(617)).unwrap().unwrap();
+        assert_eq!(*found, (617, 1234));
+        assert!(tree.select(at_least(1000)).unwrap().is_none());
+
+        let keys: Vec<_> = tree
+            .iter_by(Select::new(at_least(990)))
+            .map(|leaf| leaf.unwrap().0)
+            .collect();
+        assert_eq!(keys, (990..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn iter_where() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..1000 {
+            tree.insert(i, i).unwrap();
+        }
+        let snapshot = store.persist(&mut tree).unwrap();
+        let restored = store.restore(&snapshot).unwrap();
+
+        for tree in [&tree, &restored].iter() {
+            let mut visited = 0;
+            let keys: Vec<u32> = tree
+                .iter_where(|ann: &BTreeAnnotation<u32, u64>| {
+                    visited += 1;
+                    let max: &MaxKey<u32> = ann.borrow();
+                    let min: &MinKey<u32> = ann.borrow();
+                    **max >= 300 && **min < 310
+                })
+                .map(|leaf| leaf.unwrap().0)
+                .collect();
+            assert_eq!(keys, (300..310).collect::<Vec<_>>());
+            // only the subtrees around the range were looked into
+            assert!(visited < 100);
+        }
+    }
+
+    #[test]
+    fn aggregate_range() {
+        let mut tree = BTree::<_, _, Blake2b>::new();
+        for i in 0..500u32 {
+            tree.insert(i * 2, i).unwrap();
+        }
+
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+        let snapshot = store.persist(&mut tree).unwrap();
+        let tree = store.restore(&snapshot).unwrap();
+
+        let count = |range: &dyn Fn(u32) -> bool| {
+            (0..500u32).filter(|i| range(i * 2)).count() as u64
+        };
+
+        for &(lo, hi) in
+            &[(0, 1000), (1, 2), (17, 923), (400, 401), (998, 5000)]
+        {
+            let aggregate: Option<Cardinality<u64>> =
+                tree.aggregate_range(&(lo..hi)).unwrap();
+            let expected = count(&|k| k >= lo && k < hi);
+            assert_eq!(aggregate.map(|c| *c).unwrap_or(0), expected);
+
+            let aggregate: Option<Cardinality<u64>> =
+                tree.aggregate_range(&(lo..=hi)).unwrap();
+            let expected = count(&|k| k >= lo && k <= hi);
+            assert_eq!(aggregate.map(|c| *c).unwrap_or(0), expected);
+        }
+
+        let all: Option<Cardinality<u64>> =
+            tree.aggregate_range(&(..)).unwrap();
+        assert_eq!(*all.unwrap(), 500);
+    }
+
+    #[test]
+    fn proofs() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..1000 {
+            tree.insert(i, i * 3).unwrap();
+        }
+        let snapshot = store.persist(&mut tree).unwrap();
+
+        let proof = Proof::new(&snapshot, &mut BTreeSearch::new(&617))
+            .unwrap()
+            .unwrap();
+        let proven = proof.verify(snapshot.hash()).unwrap().unwrap();
+        assert_eq!(proven.leaf, (617, 1851));
+
+        // the proof also proves the size of the whole tree
+        let count: &Cardinality<u64> = proven.annotations[0].borrow();
+        assert_eq!(**count, 1000);
+
+        tree.insert(1000, 0).unwrap();
+        let other = store.persist(&mut tree).unwrap();
+        assert!(proof.verify(other.hash()).unwrap().is_none());
+    }
+
+    #[test]
+    fn kv_store() {
+        use kelvin::KvStore;
+
+        type Bytes = BTree<Vec<u8>, Vec<u8>, Blake2b>;
+
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+        let mut kv = KvStore::<Bytes, _>::new(store);
+        for i in 0..100u32 {
+            kv.set(&i.to_be_bytes(), format!("{}", i).as_bytes())
+                .unwrap();
+        }
+        assert!(kv.delete(&7u32.to_be_bytes()).unwrap());
+        assert!(!kv.delete(&7u32.to_be_bytes()).unwrap());
+        assert!(kv.prove(&8u32.to_be_bytes()).unwrap().is_none());
+
+        let snapshot = kv.commit().unwrap();
+        let kv = KvStore::open(&snapshot).unwrap();
+        assert_eq!(kv.get(&42u32.to_be_bytes()).unwrap(), Some(b"42".to_vec()));
+        assert!(!kv.contains(&7u32.to_be_bytes()).unwrap());
+
+        let proof = kv.prove(&8u32.to_be_bytes()).unwrap().unwrap();
+        let proven = proof.verify(snapshot.hash()).unwrap().unwrap();
+        assert_eq!(proven.leaf, (8u32.to_be_bytes().to_vec(), b"8".to_vec()));
+        assert!(kv.prove(&7u32.to_be_bytes()).unwrap().is_none());
+    }
+
+    #[test]
+    fn any_map() {
+        use kelvin::{AnyMap, Erased};
+
+        type Any = BTree<String, Erased<Blake2b>, Blake2b>;
+
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+        let mut map = AnyMap::<Any, _>::new(store);
+        map.insert("answer", 42u64).unwrap();
+        map.insert("name", String::from("kelvin")).unwrap();
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        tree.insert(1, 2).unwrap();
+        map.insert("tree", tree).unwrap();
+
+        let snapshot = map.commit().unwrap();
+        let mut map = AnyMap::open(&snapshot).unwrap();
+        assert_eq!(map.get::<u64>("answer").unwrap(), Some(42));
+        assert_eq!(map.get::<String>("name").unwrap().unwrap(), "kelvin");
+        let tree: BTree<u32, u32, Blake2b> = map.get("tree").unwrap().unwrap();
+        assert_eq!(*tree.get(&1).unwrap().unwrap(), 2);
+        assert!(map.get::<u64>("missing").unwrap().is_none());
+
+        let err = map.get::<u32>("answer").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(map.holds::<u64>("answer").unwrap());
+        assert!(!map.holds::<String>("answer").unwrap());
+
+        // replaced by a value of another type
+        map.insert("answer", String::from("42")).unwrap();
+        assert_eq!(map.get::<String>("answer").unwrap().unwrap(), "42");
+        assert!(map.remove("answer").unwrap());
+        assert!(!map.contains("answer").unwrap());
+    }
+
+    #[test]
+    fn par_iter() {
+        use kelvin::ParLeafIterable;
+        use rayon::iter::ParallelIterator;
+
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut tree = BTree::<u32, u64, Blake2b>::new();
+        for i in 0..1000 {
+            tree.insert(i, i as u64).unwrap();
+        }
+        let snapshot = store.persist(&mut tree).unwrap();
+        let restored = store.restore(&snapshot).unwrap();
+
+        // in memory as well as restored from the backend
+        for tree in [&tree, &restored].iter() {
+            let mut keys: Vec<u32> =
+                tree.par_iter().map(|leaf| leaf.unwrap().0).collect();
+            keys.sort();
+            assert_eq!(keys, (0..1000).collect::<Vec<_>>());
+
+            let sum: u64 = tree.par_values().map(|v| v.unwrap()).sum();
+            assert_eq!(sum, 999 * 1000 / 2);
+        }
+    }
+
+    #[test]
+    fn persist_parallel() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..1000 {
+            tree.insert(i, i).unwrap();
+        }
+        let mut copy = tree.clone();
+
+        let snapshot = store.persist(&mut tree).unwrap();
+        let parallel = store.persist_parallel(&mut copy).unwrap();
+        assert_eq!(snapshot.hash(), parallel.hash());
+
+        let restored = store.restore(&parallel).unwrap();
+        for i in 0..1000 {
+            assert_eq!(*restored.get(&i).unwrap().unwrap(), i);
+        }
+    }
+
+    #[test]
+    fn persist_pipelined() {
+        let dir = kelvin::tests::tempfile::tempdir().unwrap();
+        let store = kelvin::Store::<Blake2b>::new(dir.path()).unwrap();
+
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..1000 {
+            tree.insert(i, i).unwrap();
+        }
+        let mut copy = tree.clone();
+
+        let snapshot = store.persist(&mut tree).unwrap();
+        let pipelined = store.persist_pipelined(&mut copy).unwrap();
+        assert_eq!(snapshot.hash(), pipelined.hash());
+
+        let restored = store.restore(&pipelined).unwrap();
+        for i in 0..1000 {
+            assert_eq!(*restored.get(&i).unwrap().unwrap(), i);
+        }
+    }
+
+    #[test]
+    fn write_amplification() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..1000 {
+            tree.insert(i, i).unwrap();
+        }
+        let before = store.stats();
+        store.persist(&mut tree).unwrap();
+        let initial = store.stats() - before;
+        // every leaf is new, and written once
+        assert_eq!(initial.leaves, 1000 * 8);
+        assert_eq!(initial.encoded, initial.written);
+
+        let before = store.stats();
+        tree.insert(500, 0).unwrap();
+        store.persist(&mut tree).unwrap();
+        let update = store.stats() - before;
+        assert!(update.leaves < 8 * 8);
+        assert!(update.written < initial.written / 10);
+        assert!(update.write_amplification().unwrap() > 1.0);
+    }
+
+    #[test]
+    fn crash_consistency() {
+        let dir = kelvin::tests::tempfile::tempdir().unwrap();
+
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..100 {
+            tree.insert(i, i).unwrap();
+        }
+        kelvin::tests::check_crash_consistency(&tree, dir.path()).unwrap();
+    }
+
+    #[test]
+    fn concurrent_store() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        // trees differing in a single entry, sharing most of their nodes
+        kelvin::tests::stress_store(&store, 8, 100, |seed| {
+            let mut tree = BTree::<u32, u32, Blake2b>::new();
+            for i in 0..200 {
+                tree.insert(i, i).unwrap();
+            }
+            tree.insert(seed as u32 * 20, 0).unwrap();
+            tree
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn warm() {
+        let dir = kelvin::tests::tempfile::tempdir().unwrap();
+        let store = kelvin::Store::<Blake2b>::new(dir.path()).unwrap();
+
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..1000 {
+            tree.insert(i, i).unwrap();
+        }
+        let snapshot = store.persist(&mut tree).unwrap();
+
+        store.warm(&snapshot, 3).unwrap();
+        let restored = store.restore(&snapshot).unwrap();
+        for i in 0..1000 {
+            assert_eq!(*restored.get(&i).unwrap().unwrap(), i);
+        }
+    }
+
+    #[test]
+    fn sync_to() {
+        let source = kelvin::Store::<Blake2b>::volatile().unwrap();
+        let target = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..1000 {
+            tree.insert(i, i).unwrap();
+        }
+        let snapshot = source.persist(&mut tree).unwrap();
+        let full = source.sync_to(&snapshot, &target).unwrap();
+        assert!(target.wants(&[*snapshot.hash()]).is_empty());
+
+        let restored = target.restore(&snapshot).unwrap();
+        for i in 0..1000 {
+            assert_eq!(*restored.get(&i).unwrap().unwrap(), i);
+        }
+
+        // only the path to the changed leaf is transferred
+        tree.insert(500, 0).unwrap();
+        let snapshot = source.persist(&mut tree).unwrap();
+        let partial = source.sync_to(&snapshot, &target).unwrap();
+        assert!(partial < full / 10);
+        assert_eq!(
+            *target
+                .restore(&snapshot)
+                .unwrap()
+                .get(&500)
+                .unwrap()
+                .unwrap(),
+            0
+        );
+        assert_eq!(source.sync_to(&snapshot, &target).unwrap(), 0);
+    }
+
+    #[test]
+    fn root_annotation() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        let snapshot = store.persist(&mut tree).unwrap();
+        assert!(snapshot
+            .root_annotation::<Cardinality<u64>>()
+            .unwrap()
+            .is_none());
+
+        for i in 0..1000 {
+            tree.insert(i, i).unwrap();
+        }
+        let snapshot = store.persist(&mut tree).unwrap();
+
+        let count: Cardinality<u64> =
+            snapshot.root_annotation().unwrap().unwrap();
+        assert_eq!(*count, 1000);
+        let max: MaxKey<u32> = snapshot.root_annotation().unwrap().unwrap();
+        assert_eq!(*max, 999);
+    }
+
+    #[test]
+    fn pop_min_max() {
+        let mut tree = BTree::<_, _, Blake2b>::new();
+        assert_eq!(tree.pop_min().unwrap(), None);
+        assert_eq!(tree.pop_max().unwrap(), None);
+
+        let n = 1000u32;
+        for i in 0..n {
+            // scramble insertion order
+            let k = (i * 7919) % n;
+            tree.insert(k, k + 1).unwrap();
+        }
+
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+        let snapshot = store.persist(&mut tree).unwrap();
+        let mut tree = store.restore(&snapshot).unwrap();
+
+        let (mut lo, mut hi) = (0, n - 1);
+        while lo <= hi {
+            assert_eq!(tree.first_key(), Some(lo));
+            assert_eq!(tree.last_key(), Some(hi));
+            assert_eq!(tree.pop_min().unwrap(), Some((lo, lo + 1)));
+            lo += 1;
+            if lo > hi {
+                break;
+            }
+            assert_eq!(tree.pop_max().unwrap(), Some((hi, hi + 1)));
+            hi -= 1;
+            assert_eq!(tree.count(), (hi + 1 - lo) as u64);
+        }
+
+        assert_eq!(tree.count(), 0);
+        assert_eq!(tree.first_key(), None::<u32>);
+        assert_eq!(tree.pop_min().unwrap(), None);
+        assert_eq!(tree.pop_max().unwrap(), None);
+    }
+
+    #[test]
+    fn iter_from() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..1000 {
+            tree.insert(i * 2, i).unwrap();
+        }
+        let snapshot = store.persist(&mut tree).unwrap();
+        let restored = store.restore(&snapshot).unwrap();
+
+        for tree in [&tree, &restored].iter() {
+            let keys = |k| {
+                tree.iter_from(k)
+                    .map(|leaf| leaf.unwrap().0)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(keys(&0), (0..1000).map(|i| i * 2).collect::<Vec<_>>());
+            // present and absent keys
+            assert_eq!(keys(&1990), vec![1990, 1992, 1994, 1996, 1998]);
+            assert_eq!(keys(&1991), vec![1992, 1994, 1996, 1998]);
+            assert!(keys(&1999).is_empty());
+        }
+
+        // a root holding only leaves
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        tree.insert(5, 0).unwrap();
+        assert!(tree.iter_from(&15).next().is_none());
+    }
+
+    #[test]
+    fn into_iter() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut tree = BTree::<u32, String, Blake2b>::new();
+        for i in 0..1000 {
+            tree.insert(i, format!("{}", i)).unwrap();
+        }
+        let snapshot = store.persist(&mut tree).unwrap();
+        let restored = store.restore(&snapshot).unwrap();
+
+        let expected: Vec<_> =
+            (0..1000).map(|i| (i, format!("{}", i))).collect();
+        for tree in [tree, restored] {
+            let entries: Vec<_> =
+                tree.into_iter().map(|entry| entry.unwrap()).collect();
+            assert_eq!(entries, expected);
+        }
+    }
+
+    #[test]
+    fn joined() {
+        use kelvin::{iter_joined, Joined};
+
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut a = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..1000 {
+            a.insert(i, i).unwrap();
+        }
+        let mut b = a.clone();
+        b.remove(&10).unwrap();
+        b.insert(500, 0).unwrap();
+        b.insert(2000, 2000).unwrap();
+
+        let snap_a = store.persist(&mut a).unwrap();
+        let snap_b = store.persist(&mut b).unwrap();
+
+        let joined: Vec<_> = iter_joined(&snap_a, &snap_b)
+            .map(|entry| entry.unwrap())
+            .collect();
+
+        let changes: Vec<_> = joined
+            .iter()
+            .filter(|entry| match entry {
+                Joined::Both(_, a, b) => a != b,
+                _ => true,
+            })
+            .cloned()
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                Joined::Left(10, 10),
+                Joined::Both(500, 500, 0),
+                Joined::Right(2000, 2000)
+            ]
+        );
+
+        // the shared subtrees were skipped
+        assert!(joined.len() < 100);
+        assert_eq!(iter_joined(&snap_a, &snap_a).count(), 0);
+    }
+
+    #[test]
+    fn merge3() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut base = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..1000 {
+            base.insert(i, i).unwrap();
+        }
+        let mut ours = base.clone();
+        ours.insert(10, 0).unwrap();
+        ours.remove(&20).unwrap();
+        ours.insert(30, 1).unwrap();
+        ours.insert(40, 1).unwrap();
+        let mut theirs = base.clone();
+        theirs.insert(30, 1).unwrap();
+        theirs.insert(40, 2).unwrap();
+        theirs.remove(&50).unwrap();
+        theirs.insert(2000, 2000).unwrap();
+
+        let base = store.persist(&mut base).unwrap();
+        let ours = store.persist(&mut ours).unwrap();
+        let theirs = store.persist(&mut theirs).unwrap();
+
+        let mut conflicts = vec![];
+        let merged =
+            kelvin::merge3(&base, &ours, &theirs, |k, base, ours, theirs| {
+                conflicts.push((
+                    *k,
+                    base.cloned(),
+                    ours.cloned(),
+                    theirs.cloned(),
+                ));
+                Some(ours.unwrap() + theirs.unwrap())
+            })
+            .unwrap();
+
+        // only 40 was changed differently on both sides
+        assert_eq!(conflicts, vec![(40, Some(40), Some(1), Some(2))]);
+        assert_eq!(*merged.get(&10).unwrap().unwrap(), 0);
+        assert!(merged.get(&20).unwrap().is_none());
+        assert_eq!(*merged.get(&30).unwrap().unwrap(), 1);
+        assert_eq!(*merged.get(&40).unwrap().unwrap(), 3);
+        assert!(merged.get(&50).unwrap().is_none());
+        assert_eq!(*merged.get(&2000).unwrap().unwrap(), 2000);
+        assert_eq!(merged.count(), 999);
+        merged.validate().unwrap();
+    }
+
+    #[test]
+    fn persist_writes_dirty_paths() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..20_000 {
+            tree.insert(i, i).unwrap();
+        }
+        store.persist(&mut tree).unwrap();
+        let full = store.size();
+
+        // only the root is written again
+        store.persist(&mut tree).unwrap();
+        let root = store.size() - full;
+        assert!(root * 100 < full);
+
+        // only the paths down to the touched keys are written
+        for i in 0..10 {
+            tree.insert(i * 2_000, 0).unwrap();
+        }
+        let before = store.size();
+        store.persist(&mut tree).unwrap();
+        let written = store.size() - before;
+        assert!(written * 50 < full);
+    }
+
+    #[test]
+    fn iter_prefix() {
+        let mut tree = BTree::<String, u32, Blake2b>::new();
+        for user in 0..50u32 {
+            for item in 0..10u32 {
+                let key = format!("user/{}/{}", user, item);
+                tree.insert(key, user * 10 + item).unwrap();
+            }
+        }
+
+        let found: Vec<_> = tree
+            .iter_prefix(b"user/42/")
+            .map(|res| res.unwrap().1)
+            .collect();
+        assert_eq!(found, (420..430).collect::<Vec<_>>());
+
+        // "user/4" also covers user/40 - user/49
+        assert_eq!(tree.iter_prefix(b"user/4").count(), 110);
+        assert_eq!(tree.iter_prefix(b"").count(), 500);
+        assert_eq!(tree.iter_prefix(b"user/50").count(), 0);
+        assert_eq!(tree.iter_prefix(b"zzz").count(), 0);
+    }
+
+    #[test]
+    fn split_off() {
+        for n in [0u32, 1, 2, 3, 10, 100, 257].iter().cloned() {
+            for at in 0..=n {
+                let mut left = BTree::<_, _, Blake2b>::new();
+                for i in 0..n {
+                    left.insert(i, i).unwrap();
+                }
+
+                let mut right = left.split_off(&at).unwrap();
+
+                assert_eq!(left.count(), at as u64);
+                assert_eq!(right.count(), (n - at) as u64);
+
+                for i in 0..n {
+                    let (inside, outside) = if i < at {
+                        (&mut left, &mut right)
+                    } else {
+                        (&mut right, &mut left)
+                    };
+                    assert_eq!(*inside.get(&i).unwrap().unwrap(), i);
+                    assert!(outside.get(&i).unwrap().is_none());
+                }
+
+                // both halves are still valid trees
+                for i in 0..n {
+                    let half = if i < at { &mut left } else { &mut right };
+                    half.insert(i + n, i).unwrap();
+                    assert_eq!(half.remove(&i).unwrap(), Some(i));
+                }
+                for i in 0..n {
+                    let half = if i < at { &mut left } else { &mut right };
+                    assert_eq!(half.remove(&(i + n)).unwrap(), Some(i));
+                }
+                assert_eq!(left.count(), 0);
+                assert_eq!(right.count(), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn split_off_shares_subtrees() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut tree = BTree::<_, _, Blake2b>::new();
+        for i in 0..1000u32 {
+            tree.insert(i, i).unwrap();
+        }
+        let snapshot = store.persist(&mut tree).unwrap();
+        let mut left: BTree<u32, u32, Blake2b> =
+            store.restore(&snapshot).unwrap();
+
+        let right = left.split_off(&500).unwrap();
+
+        assert!(left.0[0].snapshot().is_some());
+        assert!(right.0[right.0.len() - 1].snapshot().is_some());
+        assert_eq!(left.count(), 500);
+        assert_eq!(right.count(), 500);
+    }
+
     #[test]
     fn borrowed_keys() {
         let mut map = BTree::<String, u8, Blake2b>::new();
         map.insert("hello".into(), 8).unwrap();
         assert_eq!(*map.get("hello").unwrap().unwrap(), 8);
+        assert!(map.contains_key("hello").unwrap());
+        assert_eq!(map.remove("hello").unwrap(), Some(8));
+        assert_eq!(map.remove("hello").unwrap(), None);
     }
 
     #[test]
@@ -553,5 +2430,222 @@ mod test {
         }
     }
 
-    quickcheck_map!(|| BTree::new());
+    #[test]
+    fn entry_counting() {
+        let mut map = BTree::<_, _, Blake2b>::new();
+        for i in 0..100u32 {
+            *map.entry(i % 10).unwrap().or_insert(0u32).unwrap() += 1;
+        }
+        for i in 0..10 {
+            assert_eq!(*map.get(&i).unwrap().unwrap(), 10);
+        }
+    }
+
+    #[test]
+    fn entry_persisted() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut map = BTree::<_, _, Blake2b>::new();
+        for i in 0..100u32 {
+            map.insert(i, i).unwrap();
+        }
+        let snapshot = store.persist(&mut map).unwrap();
+        let mut map = store.restore(&snapshot).unwrap();
+
+        for i in 0..200u32 {
+            map.entry(i)
+                .unwrap()
+                .and_modify(|v| *v += 1)
+                .unwrap()
+                .or_insert_with(|| 0)
+                .unwrap();
+        }
+        for i in 0..200u32 {
+            let expected = if i < 100 { i + 1 } else { 0 };
+            assert_eq!(*map.get(&i).unwrap().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn entry_lookup_untouched() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut map = BTree::<_, _, Blake2b>::new();
+        for i in 0..1000u32 {
+            map.insert(i, i).unwrap();
+        }
+        let snapshot = store.persist(&mut map).unwrap();
+        let mut map = store.restore(&snapshot).unwrap();
+
+        match map.entry(500).unwrap() {
+            kelvin::Entry::Occupied(entry) => {
+                assert_eq!(*entry.get().unwrap(), 500)
+            }
+            kelvin::Entry::Vacant(_) => panic!("500 is present"),
+        }
+
+        // only the root, already in memory, is encoded again
+        let before = store.stats();
+        let again = store.persist(&mut map).unwrap();
+        assert_eq!(again.hash(), snapshot.hash());
+        assert_eq!((store.stats() - before).values, 1);
+    }
+
+    #[test]
+    fn get_or_insert_with() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut map = BTree::<_, _, Blake2b>::new();
+        for i in 0..100u32 {
+            map.insert(i, i).unwrap();
+        }
+        let snapshot = store.persist(&mut map).unwrap();
+        let mut map = store.restore(&snapshot).unwrap();
+
+        for i in 0..200u32 {
+            let mut calls = 0;
+            let inserted = || {
+                calls += 1;
+                0
+            };
+            *map.get_or_insert_with(i, inserted).unwrap() += 1;
+            assert_eq!(calls, if i < 100 { 0 } else { 1 });
+        }
+        for i in 0..200u32 {
+            let expected = if i < 100 { i + 1 } else { 1 };
+            assert_eq!(*map.get(&i).unwrap().unwrap(), expected);
+        }
+        map.validate().unwrap();
+    }
+
+    #[test]
+    fn in_memory() {
+        let mut map: kelvin::InMemory<BTree<u32, u32, Blake2b>> =
+            (0..100).map(|i| (i, i)).collect();
+        assert_eq!(map.insert(100, 100), None);
+        assert_eq!(map.insert(0, 1), Some(0));
+        assert_eq!(*map.get(&0).unwrap(), 1);
+        *map.get_mut(&1).unwrap() += 1;
+        assert_eq!(map.remove(&1), Some(2));
+        assert!(!map.contains_key(&1));
+        assert_eq!(map.count(), 100);
+
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+        let mut tree = map.into_inner();
+        let snapshot = store.persist(&mut tree).unwrap();
+        let restored = store.restore(&snapshot).unwrap();
+        assert_eq!(*restored.get(&100).unwrap().unwrap(), 100);
+    }
+
+    #[test]
+    fn try_insert() {
+        let mut map = BTree::<_, _, Blake2b>::new();
+        for i in 0..100u32 {
+            assert!(map.try_insert(i, i).unwrap());
+        }
+        for i in 0..200u32 {
+            assert_eq!(map.try_insert(i, 0).unwrap(), i >= 100);
+        }
+        for i in 0..200u32 {
+            let expected = if i < 100 { i } else { 0 };
+            assert_eq!(*map.get(&i).unwrap().unwrap(), expected);
+        }
+        map.validate().unwrap();
+
+        // refusing to insert leaves a restored map untouched
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+        let snapshot = store.persist(&mut map).unwrap();
+        let mut map = store.restore(&snapshot).unwrap();
+        assert!(!map.try_insert(150, 1).unwrap());
+        let before = store.stats();
+        let again = store.persist(&mut map).unwrap();
+        assert_eq!(again.hash(), snapshot.hash());
+        assert_eq!((store.stats() - before).values, 1);
+    }
+
+    quickcheck_ordered_map!(|| BTree::new());
+
+    // the same operations against a store in memory
+    mod volatile {
+        use super::BTree;
+
+        use kelvin::quickcheck_ordered_map;
+        use kelvin::Blake2b;
+
+        quickcheck_ordered_map!(BTree::new, volatile);
+    }
+
+    quickcheck_proofs!(BTree::new);
+
+    fn restored_with_model() -> (BTree<u32, u32, Blake2b>, HashMap<u32, u32>) {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let mut tree = BTree::new();
+        let mut model = HashMap::new();
+        for i in 0..100 {
+            tree.insert(i * 7 % 100, i).unwrap();
+            model.insert(i * 7 % 100, i);
+        }
+        let snapshot = store.persist(&mut tree).unwrap();
+        (store.restore(&snapshot).unwrap(), model)
+    }
+
+    #[test]
+    fn equiv_after_restore() {
+        let (restored, model) = restored_with_model();
+        assert_equiv(&restored, &model);
+    }
+
+    #[test]
+    #[should_panic(expected = "structure differs from the model")]
+    fn equiv_missing_entry() {
+        let (restored, mut model) = restored_with_model();
+        model.insert(100, 0);
+        assert_equiv(&restored, &model);
+    }
+
+    #[test]
+    fn dot_graph() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..100 {
+            tree.insert(i, i).unwrap();
+        }
+        let snapshot = store.persist(&mut tree).unwrap();
+
+        let mut dot = vec![];
+        store.to_dot(&snapshot, &mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph kelvin {"));
+        assert!(dot.contains("count: Cardinality(100)"));
+        assert_eq!(dot.matches("shape=ellipse").count(), 100);
+    }
+
+    #[test]
+    fn corrupted_nodes() {
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..100 {
+            tree.insert(i * 7 % 100, i).unwrap();
+        }
+        let flips = (0..64).map(|i| Corruption::FlipBit(i * 37));
+        let cuts = (0..16).map(|i| Corruption::Truncate(i * 13));
+        check_corruption(&tree, flips.chain(cuts)).unwrap();
+    }
+
+    #[test]
+    fn golden_root_hash() {
+        let mut tree = BTree::<u32, u32, Blake2b>::new();
+        for i in 0..100 {
+            tree.insert(i * 7 % 100, i).unwrap();
+        }
+        assert_root_hash(
+            &mut tree,
+            "b785d6ddde536f904ddc6438aaa7968dd95220a92da735af95061fe2dc39cbf7",
+        );
+
+        tree.remove(&42).unwrap();
+        assert_root_hash(
+            &mut tree,
+            "0d0b4e2d30429f075229f8510fea15118cbd61e43347bd64b1ffbd80c456c1dd",
+        );
+    }
 }
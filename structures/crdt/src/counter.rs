@@ -0,0 +1,177 @@
+use std::io;
+
+use kelvin::{ByteHash, Content, LeafIterable, Map, Sink, Source};
+use kelvin_btree::BTree;
+
+use crate::Merge;
+
+/// A grow-only counter, keeping one count per replica
+#[derive(Clone)]
+pub struct GCounter<R, H>(BTree<R, u64, H>)
+where
+    R: Content<H> + Ord,
+    H: ByteHash;
+
+impl<R, H> Default for GCounter<R, H>
+where
+    R: Content<H> + Ord,
+    H: ByteHash,
+{
+    fn default() -> Self {
+        GCounter(BTree::new())
+    }
+}
+
+impl<R, H> GCounter<R, H>
+where
+    R: Content<H> + Ord,
+    H: ByteHash,
+{
+    /// Creates a new, zeroed GCounter
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Increment the count of `replica` by `by`
+    pub fn increment(&mut self, replica: R, by: u64) -> io::Result<()> {
+        if let Some(mut count) = self.0.get_mut(&replica)? {
+            *count += by;
+            return Ok(());
+        }
+        self.0.insert(replica, by)?;
+        Ok(())
+    }
+
+    /// Checks the invariants of the underlying tree, returning an error
+    /// describing the first violation found
+    pub fn validate(&self) -> io::Result<()> {
+        self.0.validate()
+    }
+
+    /// Returns the count contributed by `replica`
+    pub fn get(&self, replica: &R) -> io::Result<u64> {
+        Ok(self.0.get(replica)?.map(|count| *count).unwrap_or(0))
+    }
+
+    /// Returns the total value of the counter
+    pub fn value(&self) -> io::Result<u64> {
+        let mut sum = 0;
+        for res in self.0.iter() {
+            sum += res?.1;
+        }
+        Ok(sum)
+    }
+}
+
+impl<R, H> Merge for GCounter<R, H>
+where
+    R: Content<H> + Ord,
+    H: ByteHash,
+{
+    fn merge(&mut self, other: &Self) -> io::Result<()> {
+        for res in other.0.iter() {
+            let (replica, count) = res?;
+            if let Some(mut own) = self.0.get_mut(replica)? {
+                if *own < *count {
+                    *own = *count;
+                }
+                continue;
+            }
+            self.0.insert(replica.clone(), *count)?;
+        }
+        Ok(())
+    }
+}
+
+impl<R, H> Content<H> for GCounter<R, H>
+where
+    R: Content<H> + Ord,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.0.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(GCounter(BTree::restore(source)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use kelvin::tests::assert_root_hash;
+    use kelvin::tests::quickcheck::quickcheck;
+    use kelvin::Blake2b;
+
+    fn counter(increments: &[(u8, u8)]) -> GCounter<u8, Blake2b> {
+        let mut c = GCounter::new();
+        for (replica, by) in increments {
+            c.increment(*replica, *by as u64).unwrap();
+        }
+        c
+    }
+
+    fn state(c: &GCounter<u8, Blake2b>) -> Vec<(u8, u64)> {
+        c.0.iter().map(|res| *res.unwrap()).collect()
+    }
+
+    #[test]
+    fn increments() {
+        let c = counter(&[(0, 1), (1, 2), (0, 3)]);
+        assert_eq!(c.get(&0).unwrap(), 4);
+        assert_eq!(c.get(&1).unwrap(), 2);
+        assert_eq!(c.get(&2).unwrap(), 0);
+        assert_eq!(c.value().unwrap(), 6);
+    }
+
+    #[test]
+    fn merge_takes_maximum() {
+        let mut a = counter(&[(0, 5), (1, 1)]);
+        let b = counter(&[(0, 2), (1, 3), (2, 1)]);
+        a.merge(&b).unwrap();
+        assert_eq!(state(&a), vec![(0, 5), (1, 3), (2, 1)]);
+        a.validate().unwrap();
+    }
+
+    quickcheck! {
+        fn merge_laws(
+            a: Vec<(u8, u8)>,
+            b: Vec<(u8, u8)>,
+            c: Vec<(u8, u8)>
+        ) -> bool {
+            let (a, b, c) = (counter(&a), counter(&b), counter(&c));
+
+            // commutative
+            let mut ab = a.clone();
+            ab.merge(&b).unwrap();
+            let mut ba = b.clone();
+            ba.merge(&a).unwrap();
+
+            // associative
+            let mut ab_c = ab.clone();
+            ab_c.merge(&c).unwrap();
+            let mut bc = b.clone();
+            bc.merge(&c).unwrap();
+            let mut a_bc = a.clone();
+            a_bc.merge(&bc).unwrap();
+
+            // idempotent
+            let mut aa = a.clone();
+            aa.merge(&a).unwrap();
+
+            state(&ab) == state(&ba)
+                && state(&ab_c) == state(&a_bc)
+                && state(&aa) == state(&a)
+        }
+    }
+
+    #[test]
+    fn golden_root_hash() {
+        assert_root_hash(
+            &mut counter(&[(0, 1), (1, 2), (0, 3)]),
+            "2cdaf7903de8575af35d784033407ce99eb7e0538802241b91a3ee30066b8239",
+        );
+    }
+}
@@ -0,0 +1,20 @@
+//! Conflict-free replicated data types built on kelvin structures
+use std::io;
+
+mod counter;
+mod register;
+mod set;
+
+pub use counter::GCounter;
+pub use register::LWWRegister;
+pub use set::GSet;
+
+/// Types that can converge with other replicas of themselves.
+///
+/// Implementations must make `merge` commutative, associative and
+/// idempotent, so that replicas receiving the same updates in any order
+/// end up in the same state.
+pub trait Merge {
+    /// Merge the state of `other` into `self`
+    fn merge(&mut self, other: &Self) -> io::Result<()>;
+}
@@ -0,0 +1,149 @@
+use std::io;
+
+use kelvin::{ByteHash, Content, Sink, Source};
+
+use crate::Merge;
+
+/// A last-writer-wins register.
+///
+/// Writes are ordered by their timestamp, with the replica id breaking ties
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LWWRegister<R, T> {
+    timestamp: u64,
+    replica: R,
+    value: T,
+}
+
+impl<R, T> LWWRegister<R, T>
+where
+    R: Ord + Clone,
+    T: Clone,
+{
+    /// Creates a new register with an initial write
+    pub fn new(timestamp: u64, replica: R, value: T) -> Self {
+        LWWRegister {
+            timestamp,
+            replica,
+            value,
+        }
+    }
+
+    /// Write a value to the register, ignored if older than the current one
+    pub fn set(&mut self, timestamp: u64, replica: R, value: T) {
+        if (timestamp, &replica) > (self.timestamp, &self.replica) {
+            self.timestamp = timestamp;
+            self.replica = replica;
+            self.value = value;
+        }
+    }
+
+    /// Returns the current value of the register
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    /// Returns the timestamp of the current value
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+impl<R, T> Merge for LWWRegister<R, T>
+where
+    R: Ord + Clone,
+    T: Clone,
+{
+    fn merge(&mut self, other: &Self) -> io::Result<()> {
+        self.set(other.timestamp, other.replica.clone(), other.value.clone());
+        Ok(())
+    }
+}
+
+impl<R, T, H> Content<H> for LWWRegister<R, T>
+where
+    R: Content<H>,
+    T: Content<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.timestamp.persist(sink)?;
+        self.replica.persist(sink)?;
+        self.value.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(LWWRegister {
+            timestamp: u64::restore(source)?,
+            replica: R::restore(source)?,
+            value: T::restore(source)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use kelvin::tests::assert_root_hash;
+    use kelvin::tests::quickcheck::quickcheck;
+
+    #[test]
+    fn last_writer_wins() {
+        let mut a = LWWRegister::new(1, 0u8, 10u8);
+        let b = LWWRegister::new(2, 1u8, 20u8);
+        a.merge(&b).unwrap();
+        assert_eq!(*a.get(), 20);
+
+        // older writes are ignored
+        a.set(1, 2, 30);
+        assert_eq!(*a.get(), 20);
+    }
+
+    quickcheck! {
+        fn merge_laws(
+            a: (u64, u8, u8),
+            b: (u64, u8, u8),
+            c: (u64, u8, u8)
+        ) -> bool {
+            let new = |(t, r, v): (u64, u8, u8)| LWWRegister::new(t, r, v);
+            let (a, b, c) = (new(a), new(b), new(c));
+
+            // Registers written by the same replica at the same time
+            // must hold the same value
+            let same_write = |x: &LWWRegister<u8, u8>, y: &LWWRegister<u8, u8>| {
+                x.timestamp == y.timestamp && x.replica == y.replica
+                    && x.value != y.value
+            };
+            if same_write(&a, &b) || same_write(&b, &c) || same_write(&a, &c) {
+                return true;
+            }
+
+            let mut ab = a.clone();
+            ab.merge(&b).unwrap();
+            let mut ba = b.clone();
+            ba.merge(&a).unwrap();
+
+            let mut ab_c = ab.clone();
+            ab_c.merge(&c).unwrap();
+            let mut bc = b.clone();
+            bc.merge(&c).unwrap();
+            let mut a_bc = a.clone();
+            a_bc.merge(&bc).unwrap();
+
+            let mut aa = a.clone();
+            aa.merge(&a).unwrap();
+
+            ab == ba && ab_c == a_bc && aa == a
+        }
+    }
+
+    #[test]
+    fn golden_root_hash() {
+        let mut register = LWWRegister::new(1, 0u8, 10u8);
+        register.set(2, 1, 20);
+        assert_root_hash(
+            &mut register,
+            "f72f56f312eab5b991da0e3977dfe9844183ee3a567cddb155e813307aa19651",
+        );
+    }
+}
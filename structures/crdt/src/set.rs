@@ -0,0 +1,182 @@
+use std::io;
+
+use kelvin::{
+    annotations::Count, ByteHash, Content, LeafIterable, Map, Sink, Source,
+};
+use kelvin_btree::BTree;
+
+use crate::Merge;
+
+/// A grow-only set, merged by union
+#[derive(Clone)]
+pub struct GSet<T, H>(BTree<T, (), H>)
+where
+    T: Content<H> + Ord,
+    H: ByteHash;
+
+impl<T, H> Default for GSet<T, H>
+where
+    T: Content<H> + Ord,
+    H: ByteHash,
+{
+    fn default() -> Self {
+        GSet(BTree::new())
+    }
+}
+
+impl<T, H> GSet<T, H>
+where
+    T: Content<H> + Ord,
+    H: ByteHash,
+{
+    /// Creates a new empty GSet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of elements in the set
+    pub fn len(&self) -> u64 {
+        self.0.count()
+    }
+
+    /// Returns true if the set contains no elements
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add an element to the set, returns true if it was not present
+    pub fn insert(&mut self, t: T) -> io::Result<bool> {
+        Ok(self.0.insert(t, ())?.is_none())
+    }
+
+    /// Checks the invariants of the underlying tree, returning an error
+    /// describing the first violation found
+    pub fn validate(&self) -> io::Result<()> {
+        self.0.validate()
+    }
+
+    /// Returns true if the set contains `t`
+    pub fn contains(&self, t: &T) -> io::Result<bool> {
+        Ok(self.0.get(t)?.is_some())
+    }
+
+    /// Iterator over the elements of the set, in order
+    pub fn iter(&self) -> impl Iterator<Item = io::Result<&T>> {
+        self.0.iter().map(|res| res.map(|(t, _)| t))
+    }
+}
+
+impl<T, H> Merge for GSet<T, H>
+where
+    T: Content<H> + Ord,
+    H: ByteHash,
+{
+    fn merge(&mut self, other: &Self) -> io::Result<()> {
+        for res in other.0.iter() {
+            let (t, _) = res?;
+            if self.0.get(t)?.is_none() {
+                self.0.insert(t.clone(), ())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T, H> Content<H> for GSet<T, H>
+where
+    T: Content<H> + Ord,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.0.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(GSet(BTree::restore(source)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use kelvin::tests::assert_root_hash;
+    use kelvin::tests::quickcheck::quickcheck;
+    use kelvin::tests::ModelSet;
+    use kelvin::{quickcheck_set, Blake2b};
+
+    fn set(elements: &[u8]) -> GSet<u8, Blake2b> {
+        let mut s = GSet::new();
+        for e in elements {
+            s.insert(*e).unwrap();
+        }
+        s
+    }
+
+    fn state(s: &GSet<u8, Blake2b>) -> Vec<u8> {
+        s.iter().map(|res| *res.unwrap()).collect()
+    }
+
+    #[test]
+    fn union() {
+        let mut a = set(&[1, 3, 5]);
+        a.merge(&set(&[2, 3, 4])).unwrap();
+        a.validate().unwrap();
+        assert_eq!(state(&a), vec![1, 2, 3, 4, 5]);
+        assert!(a.contains(&4).unwrap());
+        assert!(!a.contains(&6).unwrap());
+    }
+
+    quickcheck! {
+        fn merge_laws(a: Vec<u8>, b: Vec<u8>, c: Vec<u8>) -> bool {
+            let (a, b, c) = (set(&a), set(&b), set(&c));
+
+            let mut ab = a.clone();
+            ab.merge(&b).unwrap();
+            let mut ba = b.clone();
+            ba.merge(&a).unwrap();
+
+            let mut ab_c = ab.clone();
+            ab_c.merge(&c).unwrap();
+            let mut bc = b.clone();
+            bc.merge(&c).unwrap();
+            let mut a_bc = a.clone();
+            a_bc.merge(&bc).unwrap();
+
+            let mut aa = a.clone();
+            aa.merge(&a).unwrap();
+
+            state(&ab) == state(&ba)
+                && state(&ab_c) == state(&a_bc)
+                && state(&aa) == state(&a)
+        }
+    }
+
+    impl ModelSet for GSet<u8, Blake2b> {
+        fn insert(&mut self, e: u8) -> io::Result<bool> {
+            GSet::insert(self, e)
+        }
+
+        fn contains(&self, e: u8) -> io::Result<bool> {
+            GSet::contains(self, &e)
+        }
+
+        fn union(&mut self, other: &Self) -> io::Result<()> {
+            self.merge(other)
+        }
+
+        fn elements(&self) -> io::Result<Vec<u8>> {
+            self.iter().map(|res| res.copied()).collect()
+        }
+    }
+
+    quickcheck_set!(GSet::<u8, Blake2b>::new, grow_only);
+
+    #[test]
+    fn golden_root_hash() {
+        assert_root_hash(
+            &mut set(&[3, 1, 4, 1, 5, 9, 2, 6]),
+            "3ad58f6623c76030ea493075bd03b5490f1198930d20f9024a8d26e667ca415b",
+        );
+    }
+}
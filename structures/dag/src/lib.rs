@@ -0,0 +1,237 @@
+use std::collections::HashSet;
+use std::io;
+
+use kelvin::{ByteHash, Content, Sink, Snapshot, Source, Store};
+
+/// An edge in the DAG, the snapshot of the node it points at
+pub type Link<T, H> = Snapshot<DagNode<T, H>, H>;
+
+/// A node in a hash-linked DAG, holding a value and edges to other nodes
+///
+/// Since the edges are part of the content of a node, and thereby of its
+/// hash, a node can only link to nodes that existed before it. This makes
+/// cycles impossible to construct.
+#[derive(Clone)]
+pub struct DagNode<T, H: ByteHash> {
+    value: T,
+    edges: Vec<Link<T, H>>,
+}
+
+impl<T, H> DagNode<T, H>
+where
+    T: Content<H>,
+    H: ByteHash,
+{
+    /// Returns a reference to the value of the node
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    /// Returns the snapshots of the nodes this node links to
+    pub fn edges(&self) -> &[Link<T, H>] {
+        &self.edges
+    }
+}
+
+impl<T, H> Content<H> for DagNode<T, H>
+where
+    T: Content<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.value.persist(sink)?;
+        self.edges.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(DagNode {
+            value: T::restore(source)?,
+            edges: Vec::restore(source)?,
+        })
+    }
+}
+
+/// A directed acyclic graph of nodes linked by their hashes
+#[derive(Clone)]
+pub struct Dag<T, H: ByteHash> {
+    store: Store<H>,
+    _marker: std::marker::PhantomData<T>,
+}
+
+impl<T, H> Dag<T, H>
+where
+    T: Content<H>,
+    H: ByteHash,
+{
+    /// Creates a new Dag, keeping its nodes in `store`
+    pub fn new(store: Store<H>) -> Self {
+        Dag {
+            store,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Adds a node with edges to existing nodes, returning its snapshot
+    ///
+    /// Returns an error if any of the edges points at a node missing from
+    /// the store.
+    pub fn insert(
+        &self,
+        value: T,
+        edges: Vec<Link<T, H>>,
+    ) -> io::Result<Link<T, H>> {
+        for edge in &edges {
+            self.store.restore(edge)?;
+        }
+        self.store.persist(&mut DagNode { value, edges })
+    }
+
+    /// Fetches a node from the store
+    pub fn get(&self, node: &Link<T, H>) -> io::Result<DagNode<T, H>> {
+        self.store.restore(node)
+    }
+
+    /// Returns all nodes reachable from `from`, excluding `from` itself, in
+    /// breadth-first order
+    pub fn descendants(
+        &self,
+        from: &Link<T, H>,
+    ) -> io::Result<Vec<Link<T, H>>> {
+        let mut seen = HashSet::new();
+        let mut found = vec![];
+        let mut next = 0;
+
+        seen.insert(*from.hash());
+        let mut node = self.get(from)?;
+        loop {
+            for edge in node.edges {
+                if seen.insert(*edge.hash()) {
+                    found.push(edge);
+                }
+            }
+            match found.get(next) {
+                Some(snapshot) => node = self.get(snapshot)?,
+                None => return Ok(found),
+            }
+            next += 1;
+        }
+    }
+
+    /// Returns true if `to` can be reached from `from` by following edges
+    pub fn is_reachable(
+        &self,
+        from: &Link<T, H>,
+        to: &Link<T, H>,
+    ) -> io::Result<bool> {
+        let mut seen = HashSet::new();
+        let mut stack = vec![from.clone()];
+
+        while let Some(snapshot) = stack.pop() {
+            if snapshot.hash() == to.hash() {
+                return Ok(true);
+            }
+            if seen.insert(*snapshot.hash()) {
+                stack.extend(self.get(&snapshot)?.edges);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns `from` and all nodes reachable from it, ordered so that every
+    /// node comes before the nodes it links to
+    pub fn topological(
+        &self,
+        from: &Link<T, H>,
+    ) -> io::Result<Vec<Link<T, H>>> {
+        let mut seen = HashSet::new();
+        let mut order = vec![];
+        self.post_order(from, &mut seen, &mut order)?;
+        order.reverse();
+        Ok(order)
+    }
+
+    fn post_order(
+        &self,
+        snapshot: &Link<T, H>,
+        seen: &mut HashSet<H::Digest>,
+        order: &mut Vec<Link<T, H>>,
+    ) -> io::Result<()> {
+        if !seen.insert(*snapshot.hash()) {
+            return Ok(());
+        }
+        for edge in self.get(snapshot)?.edges() {
+            self.post_order(edge, seen, order)?;
+        }
+        order.push(snapshot.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use kelvin::tests::assert_root_hash;
+    use kelvin::Blake2b;
+
+    // a -> b -> d
+    // a -> c -> d
+    fn diamond() -> (Dag<u32, Blake2b>, Vec<Link<u32, Blake2b>>) {
+        let dag = Dag::<u32, Blake2b>::new(Store::volatile().unwrap());
+        let d = dag.insert(3, vec![]).unwrap();
+        let c = dag.insert(2, vec![d.clone()]).unwrap();
+        let b = dag.insert(1, vec![d.clone()]).unwrap();
+        let a = dag.insert(0, vec![b.clone(), c.clone()]).unwrap();
+        (dag, vec![a, b, c, d])
+    }
+
+    fn values(
+        dag: &Dag<u32, Blake2b>,
+        nodes: &[Link<u32, Blake2b>],
+    ) -> Vec<u32> {
+        nodes.iter().map(|n| *dag.get(n).unwrap().value()).collect()
+    }
+
+    #[test]
+    fn descendants() {
+        let (dag, nodes) = diamond();
+        let mut found = values(&dag, &dag.descendants(&nodes[0]).unwrap());
+        found.sort();
+        assert_eq!(found, vec![1, 2, 3]);
+        assert!(dag.descendants(&nodes[3]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reachability() {
+        let (dag, nodes) = diamond();
+        assert!(dag.is_reachable(&nodes[0], &nodes[3]).unwrap());
+        assert!(dag.is_reachable(&nodes[1], &nodes[3]).unwrap());
+        assert!(!dag.is_reachable(&nodes[1], &nodes[2]).unwrap());
+        assert!(!dag.is_reachable(&nodes[3], &nodes[0]).unwrap());
+    }
+
+    #[test]
+    fn topological() {
+        let (dag, nodes) = diamond();
+        let order = values(&dag, &dag.topological(&nodes[0]).unwrap());
+        assert_eq!(order.len(), 4);
+        assert_eq!(order[0], 0);
+        assert_eq!(order[3], 3);
+    }
+
+    #[test]
+    fn rejects_unknown_edges() {
+        let (_, nodes) = diamond();
+        let other = Dag::<u32, Blake2b>::new(Store::volatile().unwrap());
+        assert!(other.insert(4, vec![nodes[0].clone()]).is_err());
+    }
+
+    #[test]
+    fn golden_root_hash() {
+        let (dag, nodes) = diamond();
+        assert_root_hash(
+            &mut dag.get(&nodes[0]).unwrap(),
+            "c1a60385fa4982e50c14a57d220ab1883293485d09ffd655d9d622fe9fe97ba0",
+        );
+    }
+}
@@ -0,0 +1,233 @@
+use std::io;
+use std::ops::Deref;
+
+use kelvin::{
+    annotations::Count, ByteHash, Content, LeafIterable, Map, Sink, Source,
+    ValRef,
+};
+use kelvin_btree::BTree;
+
+/// A map of entries expiring at a deadline
+///
+/// Deadlines are plain `u64`s in any unit of time the user picks, an entry
+/// is expired once `now` has reached its deadline. Next to the entries, the
+/// map keeps them ordered by deadline, so `purge_expired` only visits the
+/// entries it drops.
+#[derive(Clone)]
+pub struct ExpiringMap<K, V, H>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+{
+    // key -> (deadline, value)
+    entries: BTree<K, (u64, V), H>,
+    // (deadline, key), soonest first
+    deadlines: BTree<(u64, K), (), H>,
+}
+
+impl<K, V, H> ExpiringMap<K, V, H>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+{
+    /// Creates a new, empty ExpiringMap
+    pub fn new() -> Self {
+        ExpiringMap {
+            entries: BTree::new(),
+            deadlines: BTree::new(),
+        }
+    }
+
+    /// Returns the number of entries in the map, expired or not
+    pub fn len(&self) -> u64 {
+        self.entries.count()
+    }
+
+    /// Returns true if the map contains no entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Insert a key-value pair expiring at `deadline`, returning the
+    /// replaced value, if any
+    pub fn insert(
+        &mut self,
+        k: K,
+        v: V,
+        deadline: u64,
+    ) -> io::Result<Option<V>> {
+        let replaced = self.entries.insert(k.clone(), (deadline, v))?;
+        if let Some((old_deadline, _)) = replaced {
+            self.deadlines.remove(&(old_deadline, k.clone()))?;
+        }
+        self.deadlines.insert((deadline, k), ())?;
+        Ok(replaced.map(|(_, v)| v))
+    }
+
+    /// Returns a reference to the value of `k`, unless absent or expired at
+    /// `now`
+    pub fn get(
+        &self,
+        k: &K,
+        now: u64,
+    ) -> io::Result<Option<impl Deref<Target = V> + '_>> {
+        Ok(match self.entries.get(k)? {
+            Some(entry) if entry.0 > now => Some(entry.wrap(|(_, v)| v)),
+            _ => None,
+        })
+    }
+
+    /// Returns the deadline of `k`, if present
+    pub fn deadline(&self, k: &K) -> io::Result<Option<u64>> {
+        Ok(self.entries.get(k)?.map(|entry| entry.0))
+    }
+
+    /// Remove the entry for `k`, returning its value
+    pub fn remove(&mut self, k: &K) -> io::Result<Option<V>> {
+        match self.entries.remove(k)? {
+            Some((deadline, v)) => {
+                self.deadlines.remove(&(deadline, k.clone()))?;
+                Ok(Some(v))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Removes all entries expired at `now`, returning how many
+    ///
+    /// Entries are removed soonest deadline first, stopping at the first
+    /// one still alive, so the sweep costs the number of entries dropped
+    /// rather than the size of the map.
+    pub fn purge_expired(&mut self, now: u64) -> io::Result<u64> {
+        let mut purged = 0;
+        loop {
+            let soonest = match self.deadlines.iter().next() {
+                Some(res) => res?.0.clone(),
+                None => break,
+            };
+            if soonest.0 > now {
+                break;
+            }
+            self.deadlines.remove(&soonest)?;
+            self.entries.remove(&soonest.1)?;
+            purged += 1;
+        }
+        Ok(purged)
+    }
+
+    /// Checks the invariants of the map, returning an error describing the
+    /// first violation found
+    ///
+    /// On top of validating both trees, the deadline tree has to list every
+    /// entry exactly once, under its deadline.
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid =
+            |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        self.entries.validate()?;
+        self.deadlines.validate()?;
+        if self.deadlines.count() != self.len() {
+            return Err(invalid(format!(
+                "{} deadlines for {} entries",
+                self.deadlines.count(),
+                self.len()
+            )));
+        }
+        for res in self.deadlines.iter() {
+            let ((deadline, k), _) = res?;
+            match self.entries.get(k)? {
+                Some(entry) if entry.0 == *deadline => (),
+                _ => {
+                    return Err(invalid(format!("stale deadline {}", deadline)))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K, V, H> Default for ExpiringMap<K, V, H>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, H> Content<H> for ExpiringMap<K, V, H>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.entries.persist(sink)?;
+        self.deadlines.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(ExpiringMap {
+            entries: BTree::restore(source)?,
+            deadlines: BTree::restore(source)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use kelvin::{Blake2b, Store};
+
+    #[test]
+    fn expires() {
+        let mut map = ExpiringMap::<_, _, Blake2b>::new();
+        for i in 0..10u32 {
+            map.insert(i, i, u64::from(i) * 10).unwrap();
+        }
+        assert_eq!(*map.get(&5, 49).unwrap().unwrap(), 5);
+        assert!(map.get(&5, 50).unwrap().is_none());
+        assert_eq!(map.deadline(&5).unwrap(), Some(50));
+
+        // extending the deadline of 2
+        assert_eq!(map.insert(2, 20, 100).unwrap(), Some(2));
+        assert_eq!(map.purge_expired(45).unwrap(), 4);
+        assert_eq!(map.len(), 6);
+        assert_eq!(*map.get(&2, 45).unwrap().unwrap(), 20);
+        assert!(map.deadline(&4).unwrap().is_none());
+        map.validate().unwrap();
+
+        assert_eq!(map.purge_expired(45).unwrap(), 0);
+        assert_eq!(map.remove(&9).unwrap(), Some(9));
+        assert_eq!(map.purge_expired(100).unwrap(), 5);
+        assert!(map.is_empty());
+        map.validate().unwrap();
+
+        map.insert(0, 0, 10).unwrap();
+        map.deadlines.insert((20, 0), ()).unwrap();
+        map.deadlines.remove(&(10, 0)).unwrap();
+        let err = map.validate().unwrap_err();
+        assert_eq!(err.to_string(), "stale deadline 20");
+    }
+
+    #[test]
+    fn persist_restore() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+
+        let mut map = ExpiringMap::<_, _, Blake2b>::new();
+        for i in 0..32u32 {
+            map.insert(i, i, u64::from(i)).unwrap();
+        }
+
+        let snapshot = store.persist(&mut map).unwrap();
+        let mut restored = store.restore(&snapshot).unwrap();
+
+        assert_eq!(restored.purge_expired(15).unwrap(), 16);
+        assert_eq!(*restored.get(&16, 15).unwrap().unwrap(), 16);
+        restored.validate().unwrap();
+    }
+}
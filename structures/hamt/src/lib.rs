@@ -1,26 +1,37 @@
 use std::borrow::Borrow;
 use std::io;
-use std::iter::Iterator;
+use std::iter::{FromIterator, Iterator};
 use std::mem;
 
 use kelvin::{
     annotation,
-    annotations::{Cardinality, MaxKey, MaxKeyType},
-    ByteHash, Compound, Content, Handle, HandleMut, HandleOwned, HandleRef,
-    HandleType, Map, Method, Sink, Source,
+    annotations::{Bloom, Cardinality, Count},
+    ByteHash, Compound, Content, Drain, First, Handle, HandleMut, HandleOwned,
+    HandleRef, HandleType, LeafIter, LeafIterable, Map, MapMut, MapRemove,
+    Method, Sink, Source, Store,
 };
 use seahash::SeaHasher;
 use std::hash::{Hash, Hasher};
 
+mod sorted;
+
+pub use sorted::SortedIter;
+
 const N_BUCKETS: usize = 16;
 
 /// A hash array mapped trie
+///
+/// The annotations of the nodes hold a bloom filter of their keys, which
+/// searches check before fetching a subtree. Since version 0.3, snapshots
+/// written by earlier versions, without the filters, can not be restored.
 #[derive(Clone)]
 pub struct HAMT<K, V, H: ByteHash>([Handle<Self, H>; N_BUCKETS])
 where
     Self: Compound<H>;
 
-impl<K: Content<H>, V: Content<H>, H: ByteHash> Default for HAMT<K, V, H> {
+impl<K: Content<H> + Hash, V: Content<H>, H: ByteHash> Default
+    for HAMT<K, V, H>
+{
     fn default() -> Self {
         HAMT(Default::default())
     }
@@ -46,6 +57,8 @@ fn calculate_slot(mut h: u64, mut depth: usize) -> usize {
 pub struct HAMTSearch {
     hash: u64,
     depth: usize,
+    bloom: Bloom,
+    done: bool,
 }
 
 impl<T> From<&T> for HAMTSearch
@@ -56,6 +69,8 @@ where
         HAMTSearch {
             hash: hash(t),
             depth: 0,
+            bloom: Bloom::from_key(t),
+            done: false,
         }
     }
 }
@@ -63,12 +78,39 @@ where
 impl<C, H> Method<C, H> for HAMTSearch
 where
     C: Compound<H>,
+    C::Annotation: Borrow<Bloom>,
     H: ByteHash,
 {
-    fn select(&mut self, _: &[Handle<C, H>]) -> Option<usize> {
+    fn select(&mut self, handles: &[Handle<C, H>]) -> Option<usize> {
+        // A key can only be in one place, once we have decided where,
+        // there is nothing more to search for.
+        if self.done {
+            return None;
+        }
         let slot = calculate_slot(self.hash, self.depth);
         self.depth += 1;
-        Some(slot)
+        let handle = handles.get(slot)?;
+        match handle.handle_type() {
+            HandleType::None => {
+                self.done = true;
+                None
+            }
+            HandleType::Leaf => {
+                self.done = true;
+                Some(slot)
+            }
+            HandleType::Node => {
+                let ann = handle.annotation().expect("node has annotation");
+                let bloom: &Bloom = (*ann).borrow();
+                // skip fetching subtrees that definitely lack the key
+                if bloom.may_contain_all(&self.bloom) {
+                    Some(slot)
+                } else {
+                    self.done = true;
+                    None
+                }
+            }
+        }
     }
 }
 
@@ -89,6 +131,55 @@ where
         HAMT(Default::default())
     }
 
+    /// Returns the number of entries in the HAMT
+    pub fn len(&self) -> u64 {
+        self.count()
+    }
+
+    /// Returns true if the HAMT contains no entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Checks the invariants of the HAMT, returning an error describing the
+    /// first violation found
+    ///
+    /// On top of the checks of `kelvin::validate`, every entry has to be in
+    /// the slots its key hashes to on the path from the root, and no node
+    /// below the root may hold a single leaf and nothing else, since removals
+    /// collapse such nodes into their parent.
+    pub fn validate(&self) -> io::Result<()> {
+        kelvin::validate(self, |node: &Self, path| {
+            let (mut leaves, mut nodes) = (0, 0);
+            for (s, handle) in node.0.iter().enumerate() {
+                match handle.handle_type() {
+                    HandleType::Leaf => leaves += 1,
+                    HandleType::Node => {
+                        nodes += 1;
+                        continue;
+                    }
+                    HandleType::None => continue,
+                }
+                if let HandleRef::Leaf((k, _)) =
+                    handle.inner().map_err(|e| e.to_string())?
+                {
+                    let h = hash(k);
+                    let misplaced =
+                        path.iter().chain(Some(&s)).enumerate().any(
+                            |(depth, &slot)| calculate_slot(h, depth) != slot,
+                        );
+                    if misplaced {
+                        return Err(format!("key misplaced in slot {}", s));
+                    }
+                }
+            }
+            if !path.is_empty() && leaves == 1 && nodes == 0 {
+                return Err("single leaf not collapsed".into());
+            }
+            Ok(())
+        })
+    }
+
     /// Insert key-value pair into the HAMT, optionally returning expelled value
     pub fn insert(&mut self, k: K, v: V) -> io::Result<Option<V>> {
         self.sub_insert(0, hash(&k), k, v)
@@ -151,20 +242,30 @@ where
     }
 
     /// Remove element with given key, returning it.
-    pub fn remove(&mut self, k: &K) -> io::Result<Option<V>> {
-        match self.sub_remove(0, hash(&k), k)? {
+    ///
+    /// The key may be any borrowed form of the key type.
+    pub fn remove<O>(&mut self, k: &O) -> io::Result<Option<V>>
+    where
+        K: Borrow<O>,
+        O: Hash + Eq + ?Sized,
+    {
+        match self.sub_remove(0, hash(k), k)? {
             Removed::None => Ok(None),
             Removed::Leaf((_, v)) => Ok(Some(v)),
             _ => unreachable!(),
         }
     }
 
-    fn sub_remove(
+    fn sub_remove<O>(
         &mut self,
         depth: usize,
         h: u64,
-        k: &K,
-    ) -> io::Result<Removed<(K, V)>> {
+        k: &O,
+    ) -> io::Result<Removed<(K, V)>>
+    where
+        K: Borrow<O>,
+        O: Eq + ?Sized,
+    {
         let removed_leaf;
         {
             let s = calculate_slot(h, depth);
@@ -175,7 +276,7 @@ where
             match &mut *slot.inner_mut()? {
                 HandleMut::None => return Ok(Removed::None),
                 HandleMut::Leaf((place_k, _)) => {
-                    if place_k != k {
+                    if Borrow::<O>::borrow(&*place_k) != k {
                         return Ok(Removed::None);
                     }
                 }
@@ -213,6 +314,215 @@ where
         }
     }
 
+    /// Merges the entries of `other` into `self`
+    ///
+    /// For keys present in both maps, `resolve` is called with the key, the
+    /// value in `self` and the value in `other`, and the result is kept.
+    /// Subtrees that are persisted with the same digest in both maps are
+    /// identical, and are kept as-is without being restored.
+    pub fn merge<F>(&mut self, other: &Self, mut resolve: F) -> io::Result<()>
+    where
+        F: FnMut(&K, &V, &V) -> V,
+    {
+        self.sub_merge(0, other, &mut resolve)
+    }
+
+    fn sub_merge<F>(
+        &mut self,
+        depth: usize,
+        other: &Self,
+        resolve: &mut F,
+    ) -> io::Result<()>
+    where
+        F: FnMut(&K, &V, &V) -> V,
+    {
+        for (s, theirs) in other.0.iter().enumerate() {
+            if let (Some(a), Some(b)) =
+                (self.0[s].snapshot(), theirs.snapshot())
+            {
+                if a.hash() == b.hash() {
+                    continue;
+                }
+            }
+
+            match theirs.inner()? {
+                HandleRef::None => (),
+                HandleRef::Leaf((k, v)) => {
+                    self.merge_leaf(depth, k.clone(), v.clone(), resolve)?
+                }
+                HandleRef::Node(their_node) => {
+                    enum Action {
+                        Share,
+                        Adopt,
+                        Done,
+                    }
+
+                    let action = match &mut *self.0[s].inner_mut()? {
+                        HandleMut::None => Action::Share,
+                        HandleMut::Leaf(_) => Action::Adopt,
+                        HandleMut::Node(node) => {
+                            node.sub_merge(depth + 1, &their_node, resolve)?;
+                            Action::Done
+                        }
+                    };
+
+                    match action {
+                        Action::Share => self.0[s] = theirs.clone(),
+                        Action::Adopt => {
+                            // Take over their subtree, and merge our leaf
+                            // into it, keeping the argument order of
+                            // `resolve` intact.
+                            let (k, v) = mem::replace(
+                                &mut self.0[s],
+                                Handle::new_empty(),
+                            )
+                            .into_leaf();
+                            let mut node = (*their_node).clone();
+                            let h = hash(&k);
+                            if let Some(their_v) = node.sub_insert(
+                                depth + 1,
+                                h,
+                                k.clone(),
+                                v.clone(),
+                            )? {
+                                let merged = resolve(&k, &v, &their_v);
+                                node.sub_insert(depth + 1, h, k, merged)?;
+                            }
+                            self.0[s] = Handle::new_node(node);
+                        }
+                        Action::Done => (),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn merge_leaf<F>(
+        &mut self,
+        depth: usize,
+        k: K,
+        v: V,
+        resolve: &mut F,
+    ) -> io::Result<()>
+    where
+        F: FnMut(&K, &V, &V) -> V,
+    {
+        let h = hash(&k);
+        if let Some(ours) = self.sub_insert(depth, h, k.clone(), v.clone())? {
+            let merged = resolve(&k, &ours, &v);
+            self.sub_insert(depth, h, k, merged)?;
+        }
+        Ok(())
+    }
+
+    /// Returns an iterator over the entries of the HAMT in key order
+    ///
+    /// The entries are sorted in runs of up to `run_len`, persisted to
+    /// `store` and merged lazily, so memory use is bounded by the run length
+    /// and a page per run, rather than by the size of the HAMT.
+    ///
+    /// The pages of the runs stay in `store` for good, as stores do not
+    /// collect garbage. `store` should be a throwaway store, such as one
+    /// created on a temporary directory and dropped with it after the
+    /// iteration, not the store the HAMT is persisted to. A volatile store
+    /// works too, but holds all the runs in memory.
+    pub fn iter_sorted(
+        &self,
+        store: &Store<H>,
+        run_len: usize,
+    ) -> io::Result<SortedIter<K, V, H>>
+    where
+        K: Ord,
+    {
+        let entries = self.iter().map(|leaf| leaf.cloned());
+        SortedIter::new(entries, store, run_len)
+    }
+
+    /// Retains only the entries for which `f` returns true
+    ///
+    /// Persisted subtrees without any removed entries are left untouched.
+    pub fn retain<F>(&mut self, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.sub_retain(&mut f).map(|_| ())
+    }
+
+    // Returns true if any entry was removed
+    fn sub_retain<F>(&mut self, f: &mut F) -> io::Result<bool>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let mut removed = false;
+
+        for slot in self.0.iter_mut() {
+            if slot.snapshot().is_some() {
+                // work on a restored copy, keeping the persisted handle if
+                // nothing was removed
+                let mut node = match slot.inner()? {
+                    HandleRef::Node(node) => (*node).clone(),
+                    _ => unreachable!(),
+                };
+                if node.sub_retain(f)? {
+                    removed = true;
+                    *slot = node.into_handle()?;
+                }
+                continue;
+            }
+
+            enum Action {
+                Keep,
+                Remove,
+                Collapse,
+            }
+
+            let action = match &mut *slot.inner_mut()? {
+                HandleMut::None => Action::Keep,
+                HandleMut::Leaf((k, v)) => {
+                    if f(k, v) {
+                        Action::Keep
+                    } else {
+                        Action::Remove
+                    }
+                }
+                HandleMut::Node(node) => {
+                    if node.sub_retain(f)? {
+                        Action::Collapse
+                    } else {
+                        Action::Keep
+                    }
+                }
+            };
+
+            match action {
+                Action::Keep => (),
+                Action::Remove => {
+                    removed = true;
+                    *slot = Handle::new_empty();
+                }
+                Action::Collapse => {
+                    removed = true;
+                    let node = mem::replace(slot, Handle::new_empty());
+                    *slot = node.into_node().into_handle()?;
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    // Turns a sub-node into a handle, collapsing it if it holds less than
+    // two leaves
+    fn into_handle(mut self) -> io::Result<Handle<Self, H>> {
+        if self.0.iter().all(Handle::is_none) {
+            return Ok(Handle::new_empty());
+        }
+        Ok(match self.remove_singleton()? {
+            Some(leaf) => Handle::new_leaf(leaf),
+            None => Handle::new_node(self),
+        })
+    }
+
     fn remove_singleton(&mut self) -> io::Result<Option<(K, V)>> {
         let mut singleton = None;
 
@@ -239,7 +549,7 @@ where
 
 impl<K, V, H> Content<H> for HAMT<K, V, H>
 where
-    K: Content<H>,
+    K: Content<H> + Hash,
     V: Content<H>,
     H: ByteHash,
 {
@@ -286,20 +596,20 @@ where
 }
 
 annotation! {
-    struct HAMTAnnotation<K> {
+    pub struct HAMTAnnotation {
         cardinality: Cardinality<u64>,
-        key: MaxKey<K>,
-    } where K: MaxKeyType
+        bloom: Bloom,
+    }
 }
 
 impl<K, V, H> Compound<H> for HAMT<K, V, H>
 where
     H: ByteHash,
-    K: Content<H>,
+    K: Content<H> + Hash,
     V: Content<H>,
 {
     type Leaf = (K, V);
-    type Annotation = Cardinality<u64>;
+    type Annotation = HAMTAnnotation;
 
     fn children_mut(&mut self) -> &mut [Handle<Self, H>] {
         &mut self.0
@@ -310,12 +620,100 @@ where
     }
 }
 
+/// Consumes the structure, yielding its entries by value as it is torn down
+impl<K, V, H> IntoIterator for HAMT<K, V, H>
+where
+    H: ByteHash,
+    K: Content<H> + Hash,
+    V: Content<H>,
+{
+    type Item = io::Result<(K, V)>;
+    type IntoIter = Drain<Self, H>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        self.drain()
+    }
+}
+
+/// Iterates over the entries by reference
+impl<'a, K, V, H> IntoIterator for &'a HAMT<K, V, H>
+where
+    K: Content<H> + Hash + Eq,
+    V: Content<H>,
+    H: ByteHash,
+{
+    type Item = io::Result<&'a (K, V)>;
+    type IntoIter = LeafIter<'a, HAMT<K, V, H>, First, H>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Builds a HAMT with `insert_batch`, for duplicate keys the last value is
+/// kept
+impl<K, V, H> FromIterator<(K, V)> for HAMT<K, V, H>
+where
+    K: Content<H> + Hash + Eq,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        map.extend(iter);
+        map
+    }
+}
+
+/// Inserts the entries with `insert_batch`
+///
+/// Panics if a node of the HAMT fails to restore from its store, use
+/// `insert_batch` directly to handle the error instead.
+impl<K, V, H> Extend<(K, V)> for HAMT<K, V, H>
+where
+    K: Content<H> + Hash + Eq,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        MapMut::insert_batch(self, iter).expect("HAMT node failed to restore");
+    }
+}
+
+impl<K, V, H> MapMut<K, V, H> for HAMT<K, V, H>
+where
+    K: Content<H> + Hash + Eq,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn insert(&mut self, k: K, v: V) -> io::Result<Option<V>> {
+        HAMT::insert(self, k, v)
+    }
+
+    fn remove(&mut self, k: &K) -> io::Result<Option<V>> {
+        HAMT::remove(self, k)
+    }
+}
+
+impl<O, K, V, H> MapRemove<O, K, V, H> for HAMT<K, V, H>
+where
+    K: Content<H> + Hash + Eq + Borrow<O>,
+    V: Content<H>,
+    H: ByteHash,
+    O: Hash + Eq + ?Sized,
+{
+    fn remove_borrowed(&mut self, k: &O) -> io::Result<Option<V>> {
+        HAMT::remove(self, k)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
-    use kelvin::quickcheck_map;
+    use kelvin::tests::{assert_root_hash, check_corruption, Corruption};
     use kelvin::Blake2b;
+    use kelvin::{quickcheck_map, quickcheck_proofs};
 
     #[test]
     fn trivial_map() {
@@ -324,6 +722,19 @@ mod test {
         assert_eq!(*h.get(&28).unwrap().unwrap(), 28);
     }
 
+    #[test]
+    fn borrowed_keys() {
+        let mut map = HAMT::<String, u8, Blake2b>::new();
+        for i in 0..100u8 {
+            map.insert(format!("key{}", i), i).unwrap();
+        }
+        assert_eq!(*map.get("key42").unwrap().unwrap(), 42);
+        assert!(map.contains_key("key7").unwrap());
+        assert_eq!(map.remove("key7").unwrap(), Some(7));
+        assert_eq!(map.remove("key7").unwrap(), None);
+        assert!(!map.contains_key("key7").unwrap());
+    }
+
     #[test]
     fn bigger_map() {
         let mut h = HAMT::<_, _, Blake2b>::new();
@@ -333,6 +744,129 @@ mod test {
         }
     }
 
+    #[test]
+    fn validate() {
+        let mut h = HAMT::<_, _, Blake2b>::new();
+        for i in 0..1000u32 {
+            h.insert(i, i).unwrap();
+        }
+        h.validate().unwrap();
+        for i in 0..1000u32 {
+            h.remove(&i).unwrap();
+            if i % 100 == 0 {
+                h.validate().unwrap();
+            }
+        }
+        h.validate().unwrap();
+
+        let mut misplaced = HAMT::<u32, u32, Blake2b>::new();
+        let slot = (calculate_slot(hash(1u32), 0) + 1) % N_BUCKETS;
+        misplaced.0[slot] = Handle::new_leaf((1, 1));
+        let err = misplaced.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("key misplaced in slot {} at node []", slot)
+        );
+
+        let mut singleton = HAMT::<u32, u32, Blake2b>::new();
+        singleton.sub_insert(1, hash(1u32), 1, 1).unwrap();
+        let mut uncollapsed = HAMT::<u32, u32, Blake2b>::new();
+        let slot = calculate_slot(hash(1u32), 0);
+        uncollapsed.0[slot] = Handle::new_node(singleton);
+        let err = uncollapsed.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("single leaf not collapsed at node [{}]", slot)
+        );
+    }
+
+    #[test]
+    fn len_persisted() {
+        let mut h = HAMT::<_, _, Blake2b>::new();
+        assert!(h.is_empty());
+        for i in 0..1000u32 {
+            h.insert(i, i).unwrap();
+        }
+        assert_eq!(h.len(), 1000);
+
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+        let snapshot = store.persist(&mut h).unwrap();
+        let restored: HAMT<_, _, _> = store.restore(&snapshot).unwrap();
+        restored.validate().unwrap();
+        // read from the annotations of the persisted children
+        assert_eq!(restored.len(), 1000);
+        assert!(!restored.is_empty());
+    }
+
+    #[test]
+    fn iter_sorted() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut h = HAMT::<_, _, Blake2b>::new();
+        let sorted = |h: &HAMT<u32, u32, Blake2b>, run_len| {
+            h.iter_sorted(&store, run_len)
+                .unwrap()
+                .map(|entry| entry.unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert!(sorted(&h, 10).is_empty());
+
+        for i in (0..1000u32).rev() {
+            h.insert(i, i * 2).unwrap();
+        }
+        let expected: Vec<_> = (0..1000).map(|i| (i, i * 2)).collect();
+
+        for run_len in [1, 7, 100, 1000, 5000].iter() {
+            assert_eq!(sorted(&h, *run_len), expected);
+        }
+    }
+
+    #[test]
+    fn into_iter() {
+        let mut h = HAMT::<_, _, Blake2b>::new();
+        for i in 0..1000u32 {
+            h.insert(i, i).unwrap();
+        }
+
+        let mut entries: Vec<_> =
+            h.into_iter().map(|entry| entry.unwrap()).collect();
+        entries.sort();
+        assert_eq!(entries, (0..1000).map(|i| (i, i)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn collect_extend() {
+        let mut h: HAMT<u32, u32, Blake2b> =
+            (0..500u32).map(|i| (i, i)).collect();
+        h.extend((250..750u32).map(|i| (i, i + 1)));
+        assert_eq!(h.len(), 750);
+
+        let mut entries: Vec<_> =
+            (&h).into_iter().map(|entry| *entry.unwrap()).collect();
+        entries.sort();
+        let expected: Vec<_> = (0..750u32)
+            .map(|i| (i, if i < 250 { i } else { i + 1 }))
+            .collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn bloom_prunes_misses() {
+        let mut h = HAMT::<_, _, Blake2b>::new();
+        for i in 0..64u32 {
+            h.insert(i, i).unwrap();
+        }
+
+        let mut pruned = 0;
+        for i in 64..1064u32 {
+            assert!(h.get(&i).unwrap().is_none());
+            if HAMTSearch::from(&i).select(h.children()).is_none() {
+                pruned += 1;
+            }
+        }
+        assert!(pruned > 0);
+    }
+
     #[test]
     fn nested_maps() {
         let mut map_a = HAMT::<_, _, Blake2b>::new();
@@ -355,5 +889,195 @@ mod test {
         }
     }
 
+    #[test]
+    fn entry_counting() {
+        let mut map = HAMT::<_, _, Blake2b>::new();
+        for i in 0..100u32 {
+            *map.entry(i % 10).unwrap().or_insert(0u32).unwrap() += 1;
+        }
+        for i in 0..10 {
+            assert_eq!(*map.get(&i).unwrap().unwrap(), 10);
+        }
+    }
+
+    #[test]
+    fn entry_persisted() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut map = HAMT::<_, _, Blake2b>::new();
+        for i in 0..100u32 {
+            map.insert(i, i).unwrap();
+        }
+        let snapshot = store.persist(&mut map).unwrap();
+        let mut map = store.restore(&snapshot).unwrap();
+
+        for i in 0..200u32 {
+            map.entry(i)
+                .unwrap()
+                .and_modify(|v| *v += 1)
+                .unwrap()
+                .or_insert_with(|| 0)
+                .unwrap();
+        }
+        for i in 0..200u32 {
+            let expected = if i < 100 { i + 1 } else { 0 };
+            assert_eq!(*map.get(&i).unwrap().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn retain() {
+        let mut h = HAMT::<_, _, Blake2b>::new();
+        for i in 0..1000u32 {
+            h.insert(i, i).unwrap();
+        }
+
+        h.retain(|k, _| k % 3 == 0).unwrap();
+
+        assert_eq!(h.count(), 334);
+        for i in 0..1000u32 {
+            assert_eq!(h.get(&i).unwrap().is_some(), i % 3 == 0);
+        }
+
+        h.retain(|_, _| false).unwrap();
+        h.assert_correct_empty_state();
+    }
+
+    #[test]
+    fn retain_shares_subtrees() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut h = HAMT::<_, _, Blake2b>::new();
+        for i in 0..1000u32 {
+            h.insert(i, i).unwrap();
+        }
+        let snapshot = store.persist(&mut h).unwrap();
+        let mut h: HAMT<u32, u32, Blake2b> = store.restore(&snapshot).unwrap();
+
+        h.retain(|k, _| *k != 500).unwrap();
+
+        let changed = calculate_slot(hash(500u32), 0);
+        for (i, handle) in h.0.iter().enumerate() {
+            if i != changed {
+                assert!(handle.snapshot().is_some(), "bucket {} restored", i);
+            }
+        }
+        assert_eq!(h.count(), 999);
+        assert!(h.get(&500).unwrap().is_none());
+    }
+
+    #[test]
+    fn merge_maps() {
+        let mut a = HAMT::<_, _, Blake2b>::new();
+        let mut b = HAMT::<_, _, Blake2b>::new();
+        for i in 0..300u32 {
+            a.insert(i, i).unwrap();
+        }
+        for i in 200..500u32 {
+            b.insert(i, i).unwrap();
+        }
+
+        a.merge(&b, |_, x, y| x + y).unwrap();
+        a.validate().unwrap();
+
+        assert_eq!(a.count(), 500);
+        for i in 0..500u32 {
+            let expected = if (200..300).contains(&i) { i * 2 } else { i };
+            assert_eq!(*a.get(&i).unwrap().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn merge_argument_order() {
+        let mut a = HAMT::<_, _, Blake2b>::new();
+        let mut b = HAMT::<_, _, Blake2b>::new();
+        for i in 0..100u32 {
+            a.insert(i, 2u32).unwrap();
+            b.insert(i, 1u32).unwrap();
+        }
+        // make the shapes differ, so that leaves meet nodes
+        for i in 100..400u32 {
+            b.insert(i, 1).unwrap();
+        }
+
+        a.merge(&b, |_, ours, theirs| ours * 10 + theirs).unwrap();
+
+        for i in 0..400u32 {
+            let expected = if i < 100 { 21 } else { 1 };
+            assert_eq!(*a.get(&i).unwrap().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn merge_shares_identical_subtrees() {
+        let store = kelvin::Store::<Blake2b>::volatile().unwrap();
+
+        let mut base = HAMT::<_, _, Blake2b>::new();
+        for i in 0..1000u32 {
+            base.insert(i, i).unwrap();
+        }
+        let snapshot = store.persist(&mut base).unwrap();
+
+        let mut a: HAMT<u32, u32, Blake2b> = store.restore(&snapshot).unwrap();
+        let mut b: HAMT<u32, u32, Blake2b> = store.restore(&snapshot).unwrap();
+
+        // touches exactly one top-level bucket of b
+        b.insert(1000, 1000).unwrap();
+        let changed = calculate_slot(hash(1000u32), 0);
+        let b_snapshot = store.persist(&mut b).unwrap();
+        let b = store.restore(&b_snapshot).unwrap();
+
+        a.merge(&b, |_, ours, _| *ours).unwrap();
+
+        for (i, handle) in a.0.iter().enumerate() {
+            if i != changed {
+                assert!(handle.snapshot().is_some(), "bucket {} restored", i);
+            }
+        }
+        assert_eq!(a.count(), 1001);
+        assert_eq!(*a.get(&1000).unwrap().unwrap(), 1000);
+    }
+
     quickcheck_map!(|| HAMT::new());
+
+    // the same operations against a store in memory
+    mod volatile {
+        use super::HAMT;
+
+        use kelvin::quickcheck_map;
+        use kelvin::Blake2b;
+
+        quickcheck_map!(HAMT::new, volatile);
+    }
+
+    quickcheck_proofs!(HAMT::new);
+
+    #[test]
+    fn corrupted_nodes() {
+        let mut hamt = HAMT::<u32, u32, Blake2b>::new();
+        for i in 0..100 {
+            hamt.insert(i, i * 2).unwrap();
+        }
+        let flips = (0..64).map(|i| Corruption::FlipBit(i * 37));
+        let cuts = (0..16).map(|i| Corruption::Truncate(i * 13));
+        check_corruption(&hamt, flips.chain(cuts)).unwrap();
+    }
+
+    #[test]
+    fn golden_root_hash() {
+        let mut hamt = HAMT::<u32, u32, Blake2b>::new();
+        for i in 0..100 {
+            hamt.insert(i, i * 2).unwrap();
+        }
+        assert_root_hash(
+            &mut hamt,
+            "0aa1aaf808399dbcba48e041c19b4f4f4e8b90ad526d4b35cc7733e234f19068",
+        );
+
+        hamt.remove(&42).unwrap();
+        assert_root_hash(
+            &mut hamt,
+            "73e5aa7727f96d78589a335660ef9f83f6a84caefa9a2408fdfb12cfea3291ca",
+        );
+    }
 }
@@ -0,0 +1,182 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::io;
+use std::vec;
+
+use kelvin::{ByteHash, Content, Sink, Snapshot, Source, Store};
+
+// number of entries per persisted page of a sorted run
+const PAGE_LEN: usize = 64;
+
+// A page of a sorted run, linking to the rest of the run
+#[derive(Clone)]
+struct Page<K, V, H: ByteHash> {
+    entries: Vec<(K, V)>,
+    next: Option<Snapshot<Page<K, V, H>, H>>,
+}
+
+impl<K, V, H> Content<H> for Page<K, V, H>
+where
+    K: Content<H>,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.entries.persist(sink)?;
+        self.next.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(Page {
+            entries: Vec::restore(source)?,
+            next: Option::restore(source)?,
+        })
+    }
+}
+
+// A sorted run being merged, with only its current page in memory
+struct Run<K, V, H: ByteHash> {
+    page: vec::IntoIter<(K, V)>,
+    next: Option<Snapshot<Page<K, V, H>, H>>,
+}
+
+impl<K, V, H> Run<K, V, H>
+where
+    K: Content<H>,
+    V: Content<H>,
+    H: ByteHash,
+{
+    // persists a sorted run back to front, so each page can link the next
+    fn persist(mut entries: Vec<(K, V)>, store: &Store<H>) -> io::Result<Self> {
+        let mut next = None;
+        while entries.len() > PAGE_LEN {
+            let split = (entries.len() - 1) / PAGE_LEN * PAGE_LEN;
+            let entries = entries.split_off(split);
+            next = Some(store.persist(&mut Page { entries, next })?);
+        }
+        Ok(Run {
+            page: entries.into_iter(),
+            next,
+        })
+    }
+
+    fn pop(&mut self, store: &Store<H>) -> io::Result<Option<(K, V)>> {
+        if let Some(entry) = self.page.next() {
+            return Ok(Some(entry));
+        }
+        match self.next {
+            Some(ref snapshot) => {
+                let page = store.restore(snapshot)?;
+                self.page = page.entries.into_iter();
+                self.next = page.next;
+                Ok(self.page.next())
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+// The smallest unyielded entry of a run, ordered for a min-heap on keys
+struct Head<K, V> {
+    entry: (K, V),
+    run: usize,
+}
+
+impl<K: Ord, V> PartialEq for Head<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.entry.0 == other.entry.0
+    }
+}
+
+impl<K: Ord, V> Eq for Head<K, V> {}
+
+impl<K: Ord, V> PartialOrd for Head<K, V> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord, V> Ord for Head<K, V> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.entry.0.cmp(&self.entry.0)
+    }
+}
+
+/// An iterator over the entries of a HAMT in key order
+///
+/// The entries are sorted in runs that are persisted to a store and merged,
+/// so only a page of entries per run is held in memory. The runs are left
+/// in the store afterwards, see `HAMT::iter_sorted`.
+pub struct SortedIter<K, V, H: ByteHash> {
+    store: Store<H>,
+    runs: Vec<Run<K, V, H>>,
+    heads: BinaryHeap<Head<K, V>>,
+}
+
+impl<K, V, H> SortedIter<K, V, H>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+{
+    pub(crate) fn new<I>(
+        entries: I,
+        store: &Store<H>,
+        run_len: usize,
+    ) -> io::Result<Self>
+    where
+        I: Iterator<Item = io::Result<(K, V)>>,
+    {
+        let run_len = run_len.max(1);
+        let mut runs = vec![];
+        let mut run = Vec::with_capacity(run_len);
+        for entry in entries {
+            run.push(entry?);
+            if run.len() == run_len {
+                run.sort_by(|a, b| a.0.cmp(&b.0));
+                runs.push(Run::persist(run, store)?);
+                run = Vec::with_capacity(run_len);
+            }
+        }
+        if !run.is_empty() {
+            run.sort_by(|a, b| a.0.cmp(&b.0));
+            runs.push(Run::persist(run, store)?);
+        }
+
+        let mut heads = BinaryHeap::with_capacity(runs.len());
+        for (i, run) in runs.iter_mut().enumerate() {
+            if let Some(entry) = run.pop(store)? {
+                heads.push(Head { entry, run: i });
+            }
+        }
+
+        Ok(SortedIter {
+            store: store.clone(),
+            runs,
+            heads,
+        })
+    }
+}
+
+impl<K, V, H> Iterator for SortedIter<K, V, H>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+{
+    type Item = io::Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Head { entry, run } = self.heads.pop()?;
+        match self.runs[run].pop(&self.store) {
+            Ok(Some(next)) => self.heads.push(Head { entry: next, run }),
+            Ok(None) => (),
+            Err(e) => {
+                // keep the entry around for a retry
+                self.heads.push(Head { entry, run });
+                return Some(Err(e));
+            }
+        }
+        Some(Ok(entry))
+    }
+}
@@ -0,0 +1,292 @@
+use std::borrow::Borrow;
+use std::io;
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+use kelvin::{
+    annotations::{Count, MaxKey},
+    Branch, ByteHash, Compound, Content, Handle, LeafIterable, Map, Method,
+    Sink, Source,
+};
+use kelvin_btree::BTree;
+
+/// Extracts the index value from a map value
+pub trait Extractor<V, I> {
+    /// Returns the value to index `v` by
+    fn extract(v: &V) -> I;
+}
+
+/// A map from `K` to `V`, which also maintains an index from the values
+/// extracted by `E` to the keys of the map.
+///
+/// Both the map and the index are persisted, and kept consistent across
+/// inserts and removals. Values can only be changed through `insert`, since
+/// changing them in place would make the index stale.
+#[derive(Clone)]
+pub struct IndexedMap<K, V, I, E, H>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    I: Content<H> + Ord,
+    H: ByteHash,
+{
+    entries: BTree<K, V, H>,
+    // (index value, key) pairs
+    index: BTree<(I, K), (), H>,
+    _marker: PhantomData<E>,
+}
+
+// Finds the first entry in the index with the given index value
+struct IndexSearch<'a, I, K>(&'a I, PhantomData<K>);
+
+impl<'a, I, K, C, H> Method<C, H> for IndexSearch<'a, I, K>
+where
+    C: Compound<H>,
+    C::Annotation: Borrow<MaxKey<(I, K)>>,
+    I: Ord,
+    H: ByteHash,
+{
+    fn select(&mut self, handles: &[Handle<C, H>]) -> Option<usize> {
+        for (i, h) in handles.iter().enumerate() {
+            if let Some(ann) = h.annotation() {
+                let max: &MaxKey<(I, K)> = (*ann).borrow();
+                if (**max).0 >= *self.0 {
+                    return Some(i);
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<K, V, I, E, H> IndexedMap<K, V, I, E, H>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    I: Content<H> + Ord,
+    E: Extractor<V, I> + 'static,
+    H: ByteHash,
+{
+    /// Creates a new, empty IndexedMap
+    pub fn new() -> Self {
+        IndexedMap {
+            entries: BTree::new(),
+            index: BTree::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the number of entries in the map
+    pub fn len(&self) -> u64 {
+        self.entries.count()
+    }
+
+    /// Returns true if the map contains no entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Insert key-value pair into the map, optionally returning expelled
+    /// value
+    pub fn insert(&mut self, k: K, v: V) -> io::Result<Option<V>> {
+        let i = E::extract(&v);
+        let replaced = self.entries.insert(k.clone(), v)?;
+        if let Some(ref old) = replaced {
+            self.index.remove(&(E::extract(old), k.clone()))?;
+        }
+        self.index.insert((i, k), ())?;
+        Ok(replaced)
+    }
+
+    /// Returns a reference to the value of `k`, if any
+    pub fn get(
+        &self,
+        k: &K,
+    ) -> io::Result<Option<impl Deref<Target = V> + '_>> {
+        self.entries.get(k)
+    }
+
+    /// Returns true if the map contains a value for `k`
+    pub fn contains_key(&self, k: &K) -> io::Result<bool> {
+        self.entries.contains_key(k)
+    }
+
+    /// Remove element with given key, returning it.
+    pub fn remove(&mut self, k: &K) -> io::Result<Option<V>> {
+        let removed = self.entries.remove(k)?;
+        if let Some(ref v) = removed {
+            self.index.remove(&(E::extract(v), k.clone()))?;
+        }
+        Ok(removed)
+    }
+
+    /// Returns the keys of all entries whose values are indexed by `i`, in
+    /// ascending order
+    pub fn keys_by(&self, i: &I) -> io::Result<Vec<K>> {
+        let mut keys = vec![];
+        let mut search = IndexSearch(i, PhantomData);
+        let mut branch = Branch::new(&self.index, &mut search)?;
+
+        while let Some(b) = branch {
+            let ((leaf_i, k), _) = &*b;
+            if leaf_i != i {
+                break;
+            }
+            keys.push(k.clone());
+            branch = b.search(&mut search)?;
+        }
+        Ok(keys)
+    }
+
+    /// Checks the invariants of the map, returning an error describing the
+    /// first violation found
+    ///
+    /// On top of validating both trees, the index has to hold exactly one
+    /// pair for every entry, made of the extracted value and the key.
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid =
+            |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        self.entries.validate()?;
+        self.index.validate()?;
+        if self.index.count() != self.len() {
+            return Err(invalid(format!(
+                "{} index pairs for {} entries",
+                self.index.count(),
+                self.len()
+            )));
+        }
+        for (n, res) in self.entries.iter().enumerate() {
+            let (k, v) = res?;
+            if self.index.get(&(E::extract(v), k.clone()))?.is_none() {
+                return Err(invalid(format!("entry {} not indexed", n)));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K, V, I, E, H> Default for IndexedMap<K, V, I, E, H>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    I: Content<H> + Ord,
+    E: Extractor<V, I> + 'static,
+    H: ByteHash,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, I, E, H> Content<H> for IndexedMap<K, V, I, E, H>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    I: Content<H> + Ord,
+    E: Extractor<V, I> + Clone + 'static,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.entries.persist(sink)?;
+        self.index.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(IndexedMap {
+            entries: BTree::restore(source)?,
+            index: BTree::restore(source)?,
+            _marker: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use kelvin::tests::assert_root_hash;
+    use kelvin::{Blake2b, Store};
+
+    #[derive(Clone)]
+    struct Parity;
+
+    impl Extractor<u32, u32> for Parity {
+        fn extract(v: &u32) -> u32 {
+            v % 2
+        }
+    }
+
+    type Map = IndexedMap<u32, u32, u32, Parity, Blake2b>;
+
+    #[test]
+    fn index_follows_inserts() {
+        let mut map = Map::new();
+        for i in 0..100u32 {
+            map.insert(i, i).unwrap();
+        }
+        assert_eq!(map.keys_by(&0).unwrap().len(), 50);
+
+        // key 1 now has an even value
+        assert_eq!(map.insert(1, 2).unwrap(), Some(1));
+        let even = map.keys_by(&0).unwrap();
+        assert_eq!(even.len(), 51);
+        assert_eq!(even[..3], [0, 1, 2]);
+        assert_eq!(map.keys_by(&1).unwrap().len(), 49);
+        map.validate().unwrap();
+
+        map.index.remove(&(0, 2)).unwrap();
+        map.index.insert((1, 2), ()).unwrap();
+        let err = map.validate().unwrap_err();
+        assert_eq!(err.to_string(), "entry 2 not indexed");
+    }
+
+    #[test]
+    fn index_follows_removals() {
+        let mut map = Map::new();
+        for i in 0..100u32 {
+            map.insert(i, i).unwrap();
+        }
+        for i in 0..50u32 {
+            assert_eq!(map.remove(&(i * 2)).unwrap(), Some(i * 2));
+        }
+        assert_eq!(map.remove(&0).unwrap(), None);
+        assert!(!map.contains_key(&0).unwrap());
+        assert!(map.contains_key(&1).unwrap());
+        map.validate().unwrap();
+        assert!(map.keys_by(&0).unwrap().is_empty());
+        assert_eq!(
+            map.keys_by(&1).unwrap(),
+            (0..50).map(|i| i * 2 + 1).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn persist_restore() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+
+        let mut map = Map::new();
+        for i in 0..100u32 {
+            map.insert(i, i).unwrap();
+        }
+        let snapshot = store.persist(&mut map).unwrap();
+        let mut restored = store.restore(&snapshot).unwrap();
+
+        assert_eq!(restored.len(), 100);
+        restored.validate().unwrap();
+        restored.insert(0, 1).unwrap();
+        assert_eq!(restored.keys_by(&0).unwrap().len(), 49);
+        assert_eq!(*restored.get(&0).unwrap().unwrap(), 1);
+    }
+
+    #[test]
+    fn golden_root_hash() {
+        let mut map = Map::new();
+        for i in 0..100 {
+            map.insert(i, i * 7 % 100).unwrap();
+        }
+        assert_root_hash(
+            &mut map,
+            "fd169ceb48673a719b306c5747dd7133d1f45673d850b821423b3166223cdfb9",
+        );
+    }
+}
@@ -0,0 +1,544 @@
+use std::borrow::Borrow;
+use std::io;
+use std::mem;
+use std::ops::Deref;
+
+use arrayvec::ArrayVec;
+
+use kelvin::{
+    annotation,
+    annotations::{Associative, Cardinality, Count, Counter},
+    ByteHash, Compound, Content, Drain, Handle, HandleMut, HandleRef,
+    HandleType, LeafIterable, Method, Sink, Source,
+};
+
+const N: usize = 4;
+
+/// A map from half-open intervals `[start, end)` to values
+///
+/// Every subtree is annotated with the smallest start and the largest end
+/// of the intervals it contains, so that queries only visit the subtrees
+/// that can hold a matching interval.
+#[derive(Clone)]
+pub struct IntervalMap<K, V, H: ByteHash>(ArrayVec<[Handle<Self, H>; N]>)
+where
+    Self: Compound<H>;
+
+impl<K, V, H> Default for IntervalMap<K, V, H>
+where
+    K: Content<H> + Ord + Clone,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn default() -> Self {
+        IntervalMap(Default::default())
+    }
+}
+
+/// Annotation used to keep track of the smallest interval start in subtrees
+#[derive(Clone, Debug, PartialEq)]
+pub struct MinStart<K>(K);
+
+impl<K> Deref for MinStart<K> {
+    type Target = K;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<K> Associative for MinStart<K>
+where
+    K: Ord + Clone,
+{
+    fn op(&mut self, b: &Self) {
+        if b.0 < self.0 {
+            self.0 = b.0.clone()
+        }
+    }
+}
+
+impl<K, V> From<&((K, K), V)> for MinStart<K>
+where
+    K: Clone,
+{
+    fn from(((start, _), _): &((K, K), V)) -> Self {
+        MinStart(start.clone())
+    }
+}
+
+impl<H: ByteHash, K: Content<H>> Content<H> for MinStart<K> {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.0.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(MinStart(K::restore(source)?))
+    }
+}
+
+/// Annotation used to keep track of the largest interval end in subtrees
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaxEnd<K>(K);
+
+impl<K> Deref for MaxEnd<K> {
+    type Target = K;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<K> Associative for MaxEnd<K>
+where
+    K: Ord + Clone,
+{
+    fn op(&mut self, b: &Self) {
+        if b.0 > self.0 {
+            self.0 = b.0.clone()
+        }
+    }
+}
+
+impl<K, V> From<&((K, K), V)> for MaxEnd<K>
+where
+    K: Clone,
+{
+    fn from(((_, end), _): &((K, K), V)) -> Self {
+        MaxEnd(end.clone())
+    }
+}
+
+impl<H: ByteHash, K: Content<H>> Content<H> for MaxEnd<K> {
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.0.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(MaxEnd(K::restore(source)?))
+    }
+}
+
+annotation! {
+    pub struct IntervalAnnotation<K, U> {
+        start: MinStart<K>,
+        end: MaxEnd<K>,
+        count: Cardinality<U>,
+    }
+    where
+        K: Ord + Clone,
+        U: Counter
+}
+
+/// Search method finding the intervals overlapping `[start, end)`
+///
+/// A point query is the special case of `start == end`, matching the
+/// intervals that contain the point.
+pub struct OverlapSearch<'a, K> {
+    start: &'a K,
+    end: &'a K,
+}
+
+impl<'a, K, C, H> Method<C, H> for OverlapSearch<'a, K>
+where
+    C: Compound<H>,
+    C::Annotation: Borrow<MinStart<K>> + Borrow<MaxEnd<K>>,
+    H: ByteHash,
+    K: Ord,
+{
+    fn select(&mut self, handles: &[Handle<C, H>]) -> Option<usize> {
+        handles.iter().position(|h| match h.annotation() {
+            Some(ann) => {
+                let min_start: &MinStart<K> = (*ann).borrow();
+                let max_end: &MaxEnd<K> = (*ann).borrow();
+                // a point query includes intervals starting at the point
+                (**min_start < *self.end
+                    || (self.start == self.end && **min_start == *self.end))
+                    && **max_end > *self.start
+            }
+            None => false,
+        })
+    }
+}
+
+impl<K, V, H> IntervalMap<K, V, H>
+where
+    K: Content<H> + Ord + Clone,
+    V: Content<H>,
+    H: ByteHash,
+{
+    /// Creates a new IntervalMap
+    pub fn new() -> Self {
+        IntervalMap(Default::default())
+    }
+
+    /// Returns the number of intervals in the map
+    pub fn len(&self) -> u64 {
+        self.count()
+    }
+
+    /// Returns true if the map contains no intervals
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Checks the invariants of the map, returning an error describing the
+    /// first violation found
+    ///
+    /// On top of the checks of `kelvin::validate`, every node but the root
+    /// has to hold at least two children, since removals collapse nodes of a
+    /// single child into their parent, and no interval may be empty.
+    pub fn validate(&self) -> io::Result<()> {
+        kelvin::validate(self, |node: &Self, path| {
+            // empty nodes are reported by `kelvin::validate`
+            if !path.is_empty() && node.0.len() == 1 {
+                return Err("single child".into());
+            }
+            for handle in node.0.iter() {
+                match handle.handle_type() {
+                    HandleType::Leaf => (),
+                    HandleType::Node => continue,
+                    HandleType::None => return Err("empty handle".into()),
+                }
+                if let HandleRef::Leaf(((start, end), _)) =
+                    handle.inner().map_err(|e| e.to_string())?
+                {
+                    if start >= end {
+                        return Err("empty interval".into());
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Insert a value for the interval `[start, end)`
+    ///
+    /// Intervals may overlap, and the same interval may be inserted more
+    /// than once. Returns an error if the interval is empty.
+    pub fn insert(&mut self, start: K, end: K, v: V) -> io::Result<()> {
+        if start >= end {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Empty interval",
+            ));
+        }
+        self._insert(((start, end), v))
+    }
+
+    fn _insert(&mut self, leaf: ((K, K), V)) -> io::Result<()> {
+        if !self.0.is_full() {
+            self.0.push(Handle::new_leaf(leaf));
+            return Ok(());
+        }
+
+        // Insert into the least populated subtree, to keep the tree balanced
+        let mut least: Option<(usize, u64)> = None;
+        for (i, h) in self.0.iter().enumerate() {
+            let ann = h.annotation().expect("full node with empty handle");
+            let count: &Cardinality<u64> = (*ann).borrow();
+            match least {
+                Some((_, c)) if c <= **count => (),
+                _ => least = Some((i, **count)),
+            }
+        }
+        let i = least.expect("full node").0;
+
+        enum Action {
+            Split,
+            Done,
+        }
+
+        let mut leaf = Some(leaf);
+
+        let action = match &mut *self.0[i].inner_mut()? {
+            HandleMut::None => unreachable!(),
+            HandleMut::Leaf(_) => Action::Split,
+            HandleMut::Node(n) => {
+                n._insert(leaf.take().expect("leaf taken once"))?;
+                Action::Done
+            }
+        };
+
+        if let Action::Split = action {
+            let old =
+                mem::replace(&mut self.0[i], Handle::new_empty()).into_leaf();
+            let mut node = Self::new();
+            node.0.push(Handle::new_leaf(old));
+            node.0
+                .push(Handle::new_leaf(leaf.take().expect("leaf present")));
+            self.0[i] = Handle::new_node(node);
+        }
+        Ok(())
+    }
+
+    /// Removes the interval `[start, end)`, returning its value
+    ///
+    /// If the interval was inserted more than once, only one of the values
+    /// is removed.
+    pub fn remove(&mut self, start: &K, end: &K) -> io::Result<Option<V>> {
+        enum Action {
+            Remove,
+            Collapse,
+            Keep,
+            Skip,
+        }
+
+        for i in 0..self.0.len() {
+            // skip subtrees that cannot contain the interval
+            let candidate = match self.0[i].annotation() {
+                Some(ann) => {
+                    let min_start: &MinStart<K> = (*ann).borrow();
+                    let max_end: &MaxEnd<K> = (*ann).borrow();
+                    **min_start <= *start && **max_end >= *end
+                }
+                None => false,
+            };
+            if !candidate {
+                continue;
+            }
+
+            let mut removed = None;
+
+            let action = match &mut *self.0[i].inner_mut()? {
+                HandleMut::None => unreachable!(),
+                HandleMut::Leaf(((s, e), _)) => {
+                    if s == start && e == end {
+                        Action::Remove
+                    } else {
+                        Action::Skip
+                    }
+                }
+                HandleMut::Node(n) => match n.remove(start, end)? {
+                    Some(v) => {
+                        removed = Some(v);
+                        match n.0.len() {
+                            0 => Action::Remove,
+                            1 => Action::Collapse,
+                            _ => Action::Keep,
+                        }
+                    }
+                    None => Action::Skip,
+                },
+            };
+
+            match action {
+                Action::Skip => continue,
+                Action::Keep => (),
+                Action::Remove => {
+                    let handle = self.0.remove(i);
+                    if removed.is_none() {
+                        removed = Some(handle.into_leaf().1);
+                    }
+                }
+                Action::Collapse => {
+                    // replace the node with its only child
+                    let mut node =
+                        mem::replace(&mut self.0[i], Handle::new_empty())
+                            .into_node();
+                    self.0[i] = node.0.pop().expect("one child");
+                }
+            }
+            return Ok(removed);
+        }
+        Ok(None)
+    }
+
+    /// Returns an iterator over the intervals containing `point`
+    pub fn containing<'a>(
+        &'a self,
+        point: &'a K,
+    ) -> impl Iterator<Item = io::Result<&'a ((K, K), V)>> {
+        self.iter_by(OverlapSearch {
+            start: point,
+            end: point,
+        })
+    }
+
+    /// Returns an iterator over the intervals overlapping `[start, end)`
+    pub fn overlapping<'a>(
+        &'a self,
+        start: &'a K,
+        end: &'a K,
+    ) -> impl Iterator<Item = io::Result<&'a ((K, K), V)>> {
+        self.iter_by(OverlapSearch { start, end })
+    }
+}
+
+impl<K, V, H> Content<H> for IntervalMap<K, V, H>
+where
+    K: Content<H> + Ord + Clone,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        (self.0.len() as u8).persist(sink)?;
+        for h in &mut self.0 {
+            h.persist(sink)?
+        }
+        Ok(())
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let mut map = IntervalMap::default();
+        let len = u8::restore(source)? as usize;
+        if len > N {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Too many IntervalMap children",
+            ));
+        }
+        for _ in 0..len {
+            map.0.push(Handle::restore(source)?);
+        }
+        Ok(map)
+    }
+}
+
+impl<K, V, H> Compound<H> for IntervalMap<K, V, H>
+where
+    K: Content<H> + Ord + Clone,
+    V: Content<H>,
+    H: ByteHash,
+{
+    type Leaf = ((K, K), V);
+    type Annotation = IntervalAnnotation<K, u64>;
+
+    fn children_mut(&mut self) -> &mut [Handle<Self, H>] {
+        &mut self.0
+    }
+
+    fn children(&self) -> &[Handle<Self, H>] {
+        &self.0
+    }
+}
+
+/// Consumes the structure, yielding its entries by value as it is torn down
+impl<K, V, H> IntoIterator for IntervalMap<K, V, H>
+where
+    K: Content<H> + Ord + Clone,
+    V: Content<H>,
+    H: ByteHash,
+{
+    type Item = io::Result<((K, K), V)>;
+    type IntoIter = Drain<Self, H>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        self.drain()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use kelvin::tests::assert_root_hash;
+    use kelvin::{Blake2b, Store};
+
+    // intervals [i, i + 10) for i in 0..100 stepping by 5
+    fn sample() -> IntervalMap<u32, u32, Blake2b> {
+        let mut map = IntervalMap::new();
+        for i in 0..20u32 {
+            map.insert(i * 5, i * 5 + 10, i).unwrap();
+        }
+        map
+    }
+
+    fn values<'a, I>(iter: I) -> Vec<u32>
+    where
+        I: Iterator<Item = io::Result<&'a ((u32, u32), u32)>>,
+    {
+        let mut values: Vec<_> = iter.map(|res| res.unwrap().1).collect();
+        values.sort();
+        values
+    }
+
+    #[test]
+    fn containing() {
+        let map = sample();
+        assert_eq!(map.len(), 20);
+
+        assert_eq!(values(map.containing(&0)), vec![0]);
+        assert_eq!(values(map.containing(&7)), vec![0, 1]);
+        // starts are inclusive, ends exclusive
+        assert_eq!(values(map.containing(&10)), vec![1, 2]);
+        assert_eq!(values(map.containing(&104)), vec![19]);
+        assert_eq!(values(map.containing(&105)), vec![]);
+    }
+
+    #[test]
+    fn overlapping() {
+        let map = sample();
+
+        assert_eq!(values(map.overlapping(&12, &18)), vec![1, 2, 3]);
+        assert_eq!(values(map.overlapping(&10, &11)), vec![1, 2]);
+        assert_eq!(values(map.overlapping(&200, &300)), vec![]);
+        assert_eq!(map.overlapping(&0, &200).count(), 20);
+    }
+
+    #[test]
+    fn insert_empty_interval() {
+        let mut map = IntervalMap::<u32, u32, Blake2b>::new();
+        assert!(map.insert(3, 3, 0).is_err());
+        assert!(map.insert(4, 3, 0).is_err());
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn remove_persisted() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+
+        let mut map = sample();
+        let snapshot = store.persist(&mut map).unwrap();
+        let mut map = store.restore(&snapshot).unwrap();
+        map.validate().unwrap();
+
+        assert_eq!(map.remove(&5, &15).unwrap(), Some(1));
+        assert_eq!(map.remove(&5, &15).unwrap(), None);
+        assert_eq!(map.remove(&5, &16).unwrap(), None);
+        assert_eq!(map.len(), 19);
+        assert_eq!(values(map.containing(&7)), vec![0]);
+
+        for i in 0..20u32 {
+            if i != 1 {
+                assert_eq!(
+                    map.remove(&(i * 5), &(i * 5 + 10)).unwrap(),
+                    Some(i)
+                );
+                map.validate().unwrap();
+            }
+        }
+        assert!(map.is_empty());
+        assert_eq!(map.containing(&7).count(), 0);
+    }
+
+    #[test]
+    fn validate() {
+        let map = sample();
+        map.validate().unwrap();
+
+        let mut single = IntervalMap::<u32, u32, Blake2b>::new();
+        let mut child = IntervalMap::new();
+        child.insert(0, 1, 0).unwrap();
+        single.0.push(Handle::new_node(child));
+        let err = single.validate().unwrap_err();
+        assert_eq!(err.to_string(), "single child at node [0]");
+
+        let mut empty = IntervalMap::<u32, u32, Blake2b>::new();
+        empty.0.push(Handle::new_leaf(((3, 3), 0)));
+        let err = empty.validate().unwrap_err();
+        assert_eq!(err.to_string(), "empty interval at node []");
+    }
+
+    #[test]
+    fn golden_root_hash() {
+        let mut map = IntervalMap::<u32, u32, Blake2b>::new();
+        for i in 0..100 {
+            map.insert(i * 3, i * 3 + i % 7 + 1, i).unwrap();
+        }
+        assert_root_hash(
+            &mut map,
+            "180a91be49751a7ef4da6459fe2fdc3cb1d95913dd2a60b1a173b87cb9476fa3",
+        );
+    }
+}
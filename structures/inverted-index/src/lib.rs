@@ -0,0 +1,287 @@
+use std::hash::Hash;
+use std::io;
+
+use kelvin::{ByteHash, Content, LeafIterable, Map, MapMut, Sink, Source};
+use kelvin_btree::BTree;
+use kelvin_hamt::HAMT;
+
+/// The documents containing a token, kept as a persisted ordered set
+pub type Postings<D, H> = BTree<D, (), H>;
+
+/// An index from tokens to the documents containing them
+///
+/// Every token maps to its posting list, the ordered set of ids of the
+/// documents containing it. Posting lists are persisted individually, so
+/// queries only restore the lists of the tokens asked for.
+#[derive(Clone)]
+pub struct InvertedIndex<T, D, H>
+where
+    T: Content<H> + Hash + Eq,
+    D: Content<H> + Ord + Clone,
+    H: ByteHash,
+{
+    postings: HAMT<T, Postings<D, H>, H>,
+}
+
+impl<T, D, H> InvertedIndex<T, D, H>
+where
+    T: Content<H> + Hash + Eq,
+    D: Content<H> + Ord + Clone,
+    H: ByteHash,
+{
+    /// Creates a new, empty InvertedIndex
+    pub fn new() -> Self {
+        InvertedIndex {
+            postings: HAMT::new(),
+        }
+    }
+
+    /// Returns the number of distinct tokens in the index
+    pub fn len(&self) -> u64 {
+        self.postings.len()
+    }
+
+    /// Returns true if the index contains no tokens
+    pub fn is_empty(&self) -> bool {
+        self.postings.is_empty()
+    }
+
+    /// Adds document `id` to the posting lists of all of `tokens`
+    pub fn insert_document<I>(&mut self, id: D, tokens: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        for token in tokens {
+            self.postings
+                .entry(token)?
+                .or_insert_with(BTree::new)?
+                .insert(id.clone(), ())?;
+        }
+        Ok(())
+    }
+
+    /// Removes document `id` from the posting lists of all of `tokens`
+    ///
+    /// Tokens left without documents are removed from the index.
+    pub fn remove_document<'t, I>(
+        &mut self,
+        id: &D,
+        tokens: I,
+    ) -> io::Result<()>
+    where
+        I: IntoIterator<Item = &'t T>,
+        T: 't,
+    {
+        for token in tokens {
+            let empty = match self.postings.get_mut(token)? {
+                Some(mut postings) => {
+                    postings.remove(id)?;
+                    postings.is_empty()
+                }
+                None => false,
+            };
+            if empty {
+                self.postings.remove(token)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of documents containing `token`
+    pub fn frequency(&self, token: &T) -> io::Result<u64> {
+        Ok(match self.postings.get(token)? {
+            Some(postings) => postings.len(),
+            None => 0,
+        })
+    }
+
+    /// Returns the ids of the documents containing all of `tokens`, in
+    /// ascending order
+    ///
+    /// Only the shortest posting list is iterated, the others are probed
+    /// for its ids.
+    pub fn query_and(&self, tokens: &[T]) -> io::Result<Vec<D>> {
+        let mut lists = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            match self.postings.get(token)? {
+                Some(postings) => lists.push(postings),
+                None => return Ok(vec![]),
+            }
+        }
+        lists.sort_by_key(|postings| postings.len());
+
+        let (shortest, rest) = match lists.split_first() {
+            Some(split) => split,
+            None => return Ok(vec![]),
+        };
+
+        let mut ids = vec![];
+        for leaf in shortest.iter() {
+            let (id, _) = leaf?;
+            let mut in_all = true;
+            for postings in rest {
+                if !postings.contains_key(id)? {
+                    in_all = false;
+                    break;
+                }
+            }
+            if in_all {
+                ids.push(id.clone());
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Returns the ids of the documents containing any of `tokens`, in
+    /// ascending order
+    pub fn query_or(&self, tokens: &[T]) -> io::Result<Vec<D>> {
+        let mut ids = vec![];
+        for token in tokens {
+            if let Some(postings) = self.postings.get(token)? {
+                for leaf in postings.iter() {
+                    ids.push(leaf?.0.clone());
+                }
+            }
+        }
+        ids.sort();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    /// Checks the invariants of the index, returning an error describing the
+    /// first violation found
+    ///
+    /// On top of validating the map of tokens and every posting list, no
+    /// posting list may be empty, since tokens left without documents are
+    /// removed.
+    pub fn validate(&self) -> io::Result<()> {
+        self.postings.validate()?;
+        for (n, res) in self.postings.iter().enumerate() {
+            let (_, postings) = res?;
+            if postings.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("empty posting list for token {}", n),
+                ));
+            }
+            postings.validate()?;
+        }
+        Ok(())
+    }
+}
+
+impl<T, D, H> Default for InvertedIndex<T, D, H>
+where
+    T: Content<H> + Hash + Eq,
+    D: Content<H> + Ord + Clone,
+    H: ByteHash,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, D, H> Content<H> for InvertedIndex<T, D, H>
+where
+    T: Content<H> + Hash + Eq,
+    D: Content<H> + Ord + Clone,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.postings.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(InvertedIndex {
+            postings: HAMT::restore(source)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use kelvin::tests::assert_root_hash;
+    use kelvin::{Blake2b, Store};
+
+    type Index = InvertedIndex<String, u32, Blake2b>;
+
+    fn tokens(text: &str) -> Vec<String> {
+        text.split_whitespace().map(String::from).collect()
+    }
+
+    fn sample() -> Index {
+        let mut index = Index::new();
+        let docs = [
+            "the quick brown fox",
+            "the lazy dog",
+            "a quick brown dog",
+            "the fox and the dog",
+        ];
+        for (id, text) in docs.iter().enumerate() {
+            index.insert_document(id as u32, tokens(text)).unwrap();
+        }
+        index
+    }
+
+    #[test]
+    fn query() {
+        let index = sample();
+
+        assert_eq!(index.frequency(&"the".into()).unwrap(), 3);
+        assert_eq!(index.query_and(&tokens("quick brown")).unwrap(), [0, 2]);
+        assert_eq!(index.query_and(&tokens("the dog")).unwrap(), [1, 3]);
+        assert_eq!(index.query_and(&tokens("fox cat")).unwrap(), []);
+        assert_eq!(index.query_and(&[]).unwrap(), []);
+
+        assert_eq!(index.query_or(&tokens("lazy fox")).unwrap(), [0, 1, 3]);
+        assert_eq!(index.query_or(&tokens("cat")).unwrap(), []);
+    }
+
+    #[test]
+    fn remove_document() {
+        let mut index = sample();
+        let len = index.len();
+
+        index.remove_document(&1, &tokens("the lazy dog")).unwrap();
+        assert_eq!(index.query_or(&tokens("the dog")).unwrap(), [0, 2, 3]);
+        // "lazy" only appeared in the removed document
+        assert_eq!(index.len(), len - 1);
+        assert_eq!(index.frequency(&"lazy".into()).unwrap(), 0);
+        index.validate().unwrap();
+
+        index.postings.insert("cat".into(), BTree::new()).unwrap();
+        assert!(index.validate().is_err());
+    }
+
+    #[test]
+    fn persist_restore() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+
+        let mut index = Index::new();
+        for id in 0..200u32 {
+            let mut doc = vec![format!("mod{}", id % 7)];
+            if id % 2 == 0 {
+                doc.push("even".into());
+            }
+            index.insert_document(id, doc).unwrap();
+        }
+
+        let snapshot = store.persist(&mut index).unwrap();
+        let restored: Index = store.restore(&snapshot).unwrap();
+        restored.validate().unwrap();
+
+        let expected: Vec<_> = (0..200).filter(|id| id % 14 == 0).collect();
+        assert_eq!(restored.query_and(&tokens("even mod0")).unwrap(), expected);
+        assert_eq!(restored.frequency(&"even".into()).unwrap(), 100);
+    }
+
+    #[test]
+    fn golden_root_hash() {
+        assert_root_hash(
+            &mut sample(),
+            "2facc81a64e68ce23b314f38f302a58c5bf84b2eb81cb259711afb3508b4951c",
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use std::io;
+
+use kelvin::{
+    annotations::{Cardinality, Count},
+    ByteHash, Compound, Content, Drain, Handle, HandleOwned, HandleRef,
+    HandleType, LeafIterable, Sink, Source,
+};
+
+const HEAD: usize = 0;
+const TAIL: usize = 1;
+
+/// A persistent singly-linked list
+///
+/// Each node holds the head element and a handle to the rest of the list.
+/// Once persisted, the tail is referenced by its snapshot, so cloning a list
+/// to keep an old version around is cheap, and all versions share their
+/// common tails.
+#[derive(Clone)]
+pub struct List<T, H: ByteHash>([Handle<Self, H>; 2])
+where
+    Self: Compound<H>;
+
+impl<T: Content<H>, H: ByteHash> Default for List<T, H> {
+    fn default() -> Self {
+        List(Default::default())
+    }
+}
+
+impl<T, H> List<T, H>
+where
+    T: Content<H>,
+    H: ByteHash,
+{
+    /// Creates a new, empty List
+    pub fn new() -> Self {
+        List(Default::default())
+    }
+
+    /// Returns the number of elements in the list
+    pub fn len(&self) -> u64 {
+        self.count()
+    }
+
+    /// Returns true if the list contains no elements
+    pub fn is_empty(&self) -> bool {
+        self.0[HEAD].is_none()
+    }
+
+    /// Prepends an element to the list
+    pub fn push(&mut self, t: T) {
+        let tail = std::mem::take(self);
+        self.0[HEAD] = Handle::new_leaf(t);
+        if !tail.is_empty() {
+            self.0[TAIL] = Handle::new_node(tail);
+        }
+    }
+
+    /// Returns a reference to the first element of the list, if any
+    pub fn head(&self) -> Option<&T> {
+        match self.0[HEAD].inner() {
+            Ok(HandleRef::Leaf(t)) => Some(t),
+            _ => None,
+        }
+    }
+
+    /// Removes the first element of the list and returns it, the rest of
+    /// the list is restored if persisted
+    pub fn pop(&mut self) -> io::Result<Option<T>> {
+        // make sure the tail is loaded
+        self.0[TAIL].inner_mut()?;
+
+        let head = match self.0[HEAD].replace(HandleOwned::None) {
+            HandleOwned::Leaf(t) => t,
+            _ => return Ok(None),
+        };
+        *self = match self.0[TAIL].replace(HandleOwned::None) {
+            HandleOwned::Node(tail) => tail,
+            _ => Self::new(),
+        };
+        Ok(Some(head))
+    }
+
+    /// Checks the invariants of the list, returning an error describing the
+    /// first violation found
+    ///
+    /// On top of the checks of `kelvin::validate`, the head of every node
+    /// has to be an element and its tail another node, and only the empty
+    /// list may lack a head. Nodes are checked recursively, so very long
+    /// lists can exhaust the stack.
+    pub fn validate(&self) -> io::Result<()> {
+        kelvin::validate(self, |node: &Self, _| {
+            match (node.0[HEAD].handle_type(), node.0[TAIL].handle_type()) {
+                (HandleType::Leaf, HandleType::Node)
+                | (HandleType::Leaf, HandleType::None)
+                | (HandleType::None, HandleType::None) => Ok(()),
+                (head, tail) => {
+                    Err(format!("head of {:?}, tail of {:?}", head, tail))
+                }
+            }
+        })
+    }
+}
+
+impl<T, H> Content<H> for List<T, H>
+where
+    T: Content<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        for h in self.0.iter_mut() {
+            h.persist(sink)?
+        }
+        Ok(())
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(List([Handle::restore(source)?, Handle::restore(source)?]))
+    }
+}
+
+impl<T, H> Compound<H> for List<T, H>
+where
+    T: Content<H>,
+    H: ByteHash,
+{
+    type Leaf = T;
+    type Annotation = Cardinality<u64>;
+
+    fn children_mut(&mut self) -> &mut [Handle<Self, H>] {
+        &mut self.0
+    }
+
+    fn children(&self) -> &[Handle<Self, H>] {
+        &self.0
+    }
+}
+
+/// Consumes the structure, yielding its entries by value as it is torn down
+impl<T, H> IntoIterator for List<T, H>
+where
+    T: Content<H>,
+    H: ByteHash,
+{
+    type Item = io::Result<T>;
+    type IntoIter = Drain<Self, H>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        self.drain()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use kelvin::tests::assert_root_hash;
+    use kelvin::{Blake2b, LeafIterable, Store};
+
+    #[test]
+    fn push_pop() {
+        let mut list = List::<_, Blake2b>::new();
+        assert!(list.is_empty());
+        assert_eq!(list.pop().unwrap(), None);
+
+        for i in 0..100u32 {
+            list.push(i);
+            assert_eq!(list.head(), Some(&i));
+        }
+        assert_eq!(list.count(), 100);
+
+        let iterated: Vec<_> = list.iter().map(|t| *t.unwrap()).collect();
+        assert_eq!(iterated, (0..100).rev().collect::<Vec<_>>());
+
+        for i in (0..100u32).rev() {
+            assert_eq!(list.pop().unwrap(), Some(i));
+        }
+        assert!(list.is_empty());
+        assert_eq!(list.count(), 0);
+    }
+
+    #[test]
+    fn versions_share_tails() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+
+        let mut list = List::<_, Blake2b>::new();
+        for i in 0..100u32 {
+            list.push(i);
+        }
+        store.persist(&mut list).unwrap();
+
+        // the persisted tail is shared between the two versions
+        let old = list.clone();
+        assert!(old.0[TAIL].snapshot().is_some());
+
+        list.pop().unwrap();
+        list.push(1000);
+        let size = store.size();
+        store.persist(&mut list).unwrap();
+        // only the new head node is written
+        assert!(store.size() - size < 100);
+
+        assert_eq!(old.head(), Some(&99));
+        assert_eq!(list.head(), Some(&1000));
+        assert_eq!(old.count(), list.count());
+    }
+
+    #[test]
+    fn persist_restore() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+
+        let mut list = List::<_, Blake2b>::new();
+        for i in 0..100u32 {
+            list.push(i);
+        }
+        let snapshot = store.persist(&mut list).unwrap();
+        let mut restored = store.restore(&snapshot).unwrap();
+        restored.validate().unwrap();
+
+        for i in (0..100u32).rev() {
+            assert_eq!(restored.pop().unwrap(), Some(i));
+        }
+        restored.validate().unwrap();
+        assert_eq!(restored.pop().unwrap(), None);
+    }
+
+    #[test]
+    fn validate() {
+        let mut list = List::<_, Blake2b>::new();
+        for i in 0..100u32 {
+            list.push(i);
+        }
+        list.validate().unwrap();
+
+        let mut headless = List::<u32, Blake2b>::new();
+        headless.0[TAIL] = Handle::new_node(list);
+        let err = headless.validate().unwrap_err();
+        assert_eq!(err.to_string(), "head of None, tail of Node at node []");
+    }
+
+    #[test]
+    fn golden_root_hash() {
+        let mut list = List::<u32, Blake2b>::new();
+        for i in 0..100 {
+            list.push(i);
+        }
+        assert_root_hash(
+            &mut list,
+            "9dd2c9b296842f02779316e94c6aefa0f4a7711b375ef01937deec5a4d8828ba",
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use std::hash::Hash;
+use std::io;
+use std::ops::Deref;
+
+use kelvin::{
+    annotations::Count, ByteHash, Content, LeafIterable, Map, Sink, Source,
+    ValRef,
+};
+use kelvin_btree::BTree;
+use kelvin_hamt::HAMT;
+
+/// A least-recently-used map with a fixed capacity.
+///
+/// Both the entries and the recency list are kelvin structures, so the
+/// whole cache state can be persisted and restored.
+#[derive(Clone)]
+pub struct LRU<K, V, H>
+where
+    K: Content<H> + Hash + Eq,
+    V: Content<H>,
+    H: ByteHash,
+{
+    capacity: u64,
+    tick: u64,
+    // key -> (last access tick, value)
+    entries: HAMT<K, (u64, V), H>,
+    // last access tick -> key
+    recency: BTree<u64, K, H>,
+}
+
+impl<K, V, H> LRU<K, V, H>
+where
+    K: Content<H> + Hash + Eq,
+    V: Content<H>,
+    H: ByteHash,
+{
+    /// Creates a new LRU map, holding at most `capacity` entries
+    pub fn new(capacity: u64) -> Self {
+        assert!(capacity > 0, "LRU capacity must be at least 1");
+        LRU {
+            capacity,
+            tick: 0,
+            entries: HAMT::new(),
+            recency: BTree::new(),
+        }
+    }
+
+    /// Returns the maximum number of entries
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// Returns the number of entries in the map
+    pub fn len(&self) -> u64 {
+        self.entries.count()
+    }
+
+    /// Returns true if the map contains no entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// Insert a key-value pair, marking it as the most recently used.
+    ///
+    /// Returns the replaced value, if any. If the capacity is exceeded, the
+    /// least recently used entry is evicted.
+    pub fn insert(&mut self, k: K, v: V) -> io::Result<Option<V>> {
+        let tick = self.next_tick();
+        let replaced = self.entries.insert(k.clone(), (tick, v))?;
+
+        if let Some((old_tick, _)) = replaced {
+            self.recency.remove(&old_tick)?;
+        }
+        self.recency.insert(tick, k)?;
+
+        if self.len() > self.capacity {
+            self.evict()?;
+        }
+
+        Ok(replaced.map(|(_, v)| v))
+    }
+
+    /// Returns a reference to the value of `k`, marking it as the most
+    /// recently used.
+    pub fn get(
+        &mut self,
+        k: &K,
+    ) -> io::Result<Option<impl Deref<Target = V> + '_>> {
+        let tick = self.next_tick();
+
+        let old_tick = match self.entries.get_mut(k)? {
+            Some(mut entry) => std::mem::replace(&mut entry.0, tick),
+            None => return Ok(None),
+        };
+
+        self.recency.remove(&old_tick)?;
+        self.recency.insert(tick, k.clone())?;
+
+        Ok(self.entries.get(k)?.map(|entry| entry.wrap(|(_, v)| v)))
+    }
+
+    /// Returns a reference to the value of `k` without affecting recency
+    pub fn peek(
+        &self,
+        k: &K,
+    ) -> io::Result<Option<impl Deref<Target = V> + '_>> {
+        Ok(self.entries.get(k)?.map(|entry| entry.wrap(|(_, v)| v)))
+    }
+
+    /// Returns true if the map contains a value for `k`, without affecting
+    /// recency
+    pub fn contains_key(&self, k: &K) -> io::Result<bool> {
+        self.entries.contains_key(k)
+    }
+
+    /// Remove the entry for `k`, returning its value
+    pub fn remove(&mut self, k: &K) -> io::Result<Option<V>> {
+        match self.entries.remove(k)? {
+            Some((tick, v)) => {
+                self.recency.remove(&tick)?;
+                Ok(Some(v))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Evicts the least recently used entry
+    fn evict(&mut self) -> io::Result<()> {
+        let oldest = match self.recency.iter().next() {
+            Some(res) => res?.0,
+            None => return Ok(()),
+        };
+        if let Some(k) = self.recency.remove(&oldest)? {
+            self.entries.remove(&k)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the invariants of the map, returning an error describing the
+    /// first violation found
+    ///
+    /// On top of validating both trees, the map has to be within capacity,
+    /// and the recency tree has to list every entry exactly once, under the
+    /// tick of its last access.
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid =
+            |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        self.entries.validate()?;
+        self.recency.validate()?;
+        if self.len() > self.capacity {
+            return Err(invalid(format!(
+                "{} entries over capacity",
+                self.len()
+            )));
+        }
+        if self.recency.count() != self.len() {
+            return Err(invalid(format!(
+                "{} recency ticks for {} entries",
+                self.recency.count(),
+                self.len()
+            )));
+        }
+        for res in self.recency.iter() {
+            let (tick, k) = res?;
+            match self.entries.get(k)? {
+                Some(entry) if entry.0 == *tick && *tick <= self.tick => (),
+                _ => return Err(invalid(format!("stale tick {}", tick))),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K, V, H> Content<H> for LRU<K, V, H>
+where
+    K: Content<H> + Hash + Eq,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.capacity.persist(sink)?;
+        self.tick.persist(sink)?;
+        self.entries.persist(sink)?;
+        self.recency.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(LRU {
+            capacity: u64::restore(source)?,
+            tick: u64::restore(source)?,
+            entries: HAMT::restore(source)?,
+            recency: BTree::restore(source)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use kelvin::tests::assert_root_hash;
+    use kelvin::{Blake2b, Store};
+
+    #[test]
+    fn evicts_least_recently_inserted() {
+        let mut lru = LRU::<_, _, Blake2b>::new(3);
+        for i in 0..4u32 {
+            lru.insert(i, i).unwrap();
+        }
+        assert_eq!(lru.len(), 3);
+        assert!(lru.peek(&0).unwrap().is_none());
+        lru.validate().unwrap();
+        for i in 1..4u32 {
+            assert_eq!(*lru.peek(&i).unwrap().unwrap(), i);
+        }
+    }
+
+    #[test]
+    fn get_promotes() {
+        let mut lru = LRU::<_, _, Blake2b>::new(3);
+        for i in 0..3u32 {
+            lru.insert(i, i).unwrap();
+        }
+        // 0 is now the most recently used, checking 1 does not promote it
+        assert_eq!(*lru.get(&0).unwrap().unwrap(), 0);
+        assert!(lru.contains_key(&1).unwrap());
+        lru.insert(3, 3).unwrap();
+
+        assert_eq!(*lru.peek(&0).unwrap().unwrap(), 0);
+        assert!(lru.peek(&1).unwrap().is_none());
+        lru.validate().unwrap();
+
+        lru.recency.insert(0, 3).unwrap();
+        lru.recency.remove(&lru.tick).unwrap();
+        let err = lru.validate().unwrap_err();
+        assert_eq!(err.to_string(), "stale tick 0");
+    }
+
+    #[test]
+    fn replace_does_not_evict() {
+        let mut lru = LRU::<_, _, Blake2b>::new(2);
+        lru.insert(0u32, 0u32).unwrap();
+        lru.insert(1, 1).unwrap();
+        assert_eq!(lru.insert(0, 10).unwrap(), Some(0));
+        assert_eq!(lru.len(), 2);
+        lru.insert(2, 2).unwrap();
+
+        // 1 was the least recently used
+        assert!(lru.peek(&1).unwrap().is_none());
+        assert_eq!(*lru.peek(&0).unwrap().unwrap(), 10);
+    }
+
+    #[test]
+    fn persist_restore() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+
+        let mut lru = LRU::<_, _, Blake2b>::new(16);
+        for i in 0..32u32 {
+            lru.insert(i, i).unwrap();
+        }
+
+        let snapshot = store.persist(&mut lru).unwrap();
+        let mut restored = store.restore(&snapshot).unwrap();
+
+        assert_eq!(restored.len(), 16);
+        assert_eq!(restored.capacity(), 16);
+        restored.get(&16).unwrap();
+        restored.insert(32, 32).unwrap();
+
+        assert_eq!(*restored.peek(&16).unwrap().unwrap(), 16);
+        assert!(restored.peek(&17).unwrap().is_none());
+        restored.validate().unwrap();
+    }
+
+    #[test]
+    fn golden_root_hash() {
+        let mut lru = LRU::<u32, u32, Blake2b>::new(16);
+        for i in 0..32 {
+            lru.insert(i, i).unwrap();
+        }
+        lru.get(&20).unwrap();
+        assert_root_hash(
+            &mut lru,
+            "e1b4c3084e3e92730f0df8a074eba5c032b3dbde75b6ede926d742d25ac6842f",
+        );
+    }
+}
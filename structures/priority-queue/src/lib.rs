@@ -0,0 +1,428 @@
+use std::borrow::{Borrow, Cow};
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::Deref;
+
+use arrayvec::ArrayVec;
+
+use kelvin::{
+    annotation,
+    annotations::{Associative, Cardinality, Count, Counter},
+    Branch, ByteHash, Compound, Content, Handle, HandleMut, Method, Sink,
+    Source,
+};
+
+const N: usize = 4;
+
+/// A priority queue, where the element with the highest priority can be
+/// found by following the annotations of the tree
+#[derive(Clone)]
+pub struct PriorityQueue<K, P, H: ByteHash>(ArrayVec<[Handle<Self, H>; N]>)
+where
+    Self: Compound<H>;
+
+impl<K, P, H> Default for PriorityQueue<K, P, H>
+where
+    K: Content<H>,
+    P: Content<H> + Ord,
+    H: ByteHash,
+{
+    fn default() -> Self {
+        PriorityQueue(Default::default())
+    }
+}
+
+/// Annotation used to keep track of the maximum priority in subtrees
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaxPriority<P>(P);
+
+impl<P> Deref for MaxPriority<P> {
+    type Target = P;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<P> Associative for MaxPriority<P>
+where
+    P: Ord + Clone,
+{
+    // Take the maximum priority
+    fn op(&mut self, b: &Self) {
+        if b.0 > self.0 {
+            self.0 = b.0.clone()
+        }
+    }
+}
+
+impl<K, P> From<&(K, P)> for MaxPriority<P>
+where
+    P: Ord + Clone,
+{
+    fn from((_, p): &(K, P)) -> Self {
+        MaxPriority(p.clone())
+    }
+}
+
+impl<H: ByteHash, P: Content<H>> Content<H> for MaxPriority<P>
+where
+    P: Ord,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.0.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(MaxPriority(P::restore(source)?))
+    }
+}
+
+annotation! {
+    pub struct PriorityAnnotation<P, U> {
+        priority: MaxPriority<P>,
+        count: Cardinality<U>,
+    }
+    where
+        P: Ord + Clone,
+        U: Counter
+}
+
+/// Search method that always descends into the subtree with the highest
+/// priority
+pub struct MaxPrioritySearch<P>(PhantomData<P>);
+
+impl<P> Default for MaxPrioritySearch<P> {
+    fn default() -> Self {
+        MaxPrioritySearch(PhantomData)
+    }
+}
+
+impl<C, H, P> Method<C, H> for MaxPrioritySearch<P>
+where
+    C: Compound<H>,
+    C::Annotation: Borrow<MaxPriority<P>>,
+    P: Ord,
+    H: ByteHash,
+{
+    fn select(&mut self, handles: &[Handle<C, H>]) -> Option<usize> {
+        let mut best: Option<(usize, Cow<C::Annotation>)> = None;
+        for (i, h) in handles.iter().enumerate() {
+            if let Some(ann) = h.annotation() {
+                let priority: &MaxPriority<P> = (*ann).borrow();
+                let better = match best {
+                    None => true,
+                    Some((_, ref best_ann)) => {
+                        let best_priority: &MaxPriority<P> =
+                            (**best_ann).borrow();
+                        priority.0 > best_priority.0
+                    }
+                };
+                if better {
+                    best = Some((i, ann))
+                }
+            }
+        }
+        best.map(|(i, _)| i)
+    }
+}
+
+impl<K, P, H> PriorityQueue<K, P, H>
+where
+    K: Content<H>,
+    P: Content<H> + Ord,
+    H: ByteHash,
+{
+    /// Creates a new PriorityQueue
+    pub fn new() -> Self {
+        PriorityQueue(Default::default())
+    }
+
+    /// Returns the number of keys in the queue
+    pub fn len(&self) -> u64 {
+        self.count()
+    }
+
+    /// Returns true if the queue contains no keys
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Checks the invariants of the queue, returning an error describing
+    /// the first violation found
+    ///
+    /// On top of the checks of `kelvin::validate`, every node but the root
+    /// has to hold at least two children, since popping collapses nodes of a
+    /// single child into their parent.
+    pub fn validate(&self) -> io::Result<()> {
+        kelvin::validate(self, |node: &Self, path| {
+            // empty nodes are reported by `kelvin::validate`
+            if !path.is_empty() && node.0.len() == 1 {
+                return Err("single child".into());
+            }
+            if node.0.iter().any(Handle::is_none) {
+                return Err("empty handle".into());
+            }
+            Ok(())
+        })
+    }
+
+    /// Insert a key into the queue with the given priority
+    pub fn insert(&mut self, k: K, p: P) -> io::Result<()> {
+        self._insert((k, p))
+    }
+
+    fn _insert(&mut self, leaf: (K, P)) -> io::Result<()> {
+        if !self.0.is_full() {
+            self.0.push(Handle::new_leaf(leaf));
+            return Ok(());
+        }
+
+        // Insert into the least populated subtree, to keep the tree balanced
+        let mut least: Option<(usize, u64)> = None;
+        for (i, h) in self.0.iter().enumerate() {
+            let ann = h.annotation().expect("full node with empty handle");
+            let count: &Cardinality<u64> = (*ann).borrow();
+            match least {
+                Some((_, c)) if c <= **count => (),
+                _ => least = Some((i, **count)),
+            }
+        }
+        let i = least.expect("full node").0;
+
+        /// Use an enum to get around borrow issues
+        enum Action {
+            Split,
+            Done,
+        }
+
+        let mut leaf = Some(leaf);
+
+        let action = match &mut *self.0[i].inner_mut()? {
+            HandleMut::None => unreachable!(),
+            HandleMut::Leaf(_) => Action::Split,
+            HandleMut::Node(n) => {
+                n._insert(leaf.take().expect("leaf taken once"))?;
+                Action::Done
+            }
+        };
+
+        if let Action::Split = action {
+            let old =
+                mem::replace(&mut self.0[i], Handle::new_empty()).into_leaf();
+            let mut node = Self::new();
+            node.0.push(Handle::new_leaf(old));
+            node.0
+                .push(Handle::new_leaf(leaf.take().expect("leaf present")));
+            self.0[i] = Handle::new_node(node);
+        }
+        Ok(())
+    }
+
+    /// Returns a reference to the element with the highest priority, if any
+    pub fn peek_max(&self) -> io::Result<Option<Branch<'_, Self, H>>> {
+        Branch::new(self, &mut MaxPrioritySearch::default())
+    }
+
+    /// Removes and returns the element with the highest priority, if any
+    pub fn pop_max(&mut self) -> io::Result<Option<(K, P)>> {
+        let i = match MaxPrioritySearch::default().select(self.children()) {
+            Some(i) => i,
+            None => return Ok(None),
+        };
+
+        enum Action {
+            Remove,
+            Collapse,
+            Keep,
+        }
+
+        let mut popped = None;
+
+        let action = match &mut *self.0[i].inner_mut()? {
+            HandleMut::None => unreachable!(),
+            HandleMut::Leaf(_) => Action::Remove,
+            HandleMut::Node(n) => {
+                popped = n.pop_max()?;
+                match n.0.len() {
+                    0 => Action::Remove,
+                    1 => Action::Collapse,
+                    _ => Action::Keep,
+                }
+            }
+        };
+
+        match action {
+            Action::Remove => {
+                let removed = self.0.remove(i);
+                if popped.is_none() {
+                    popped = Some(removed.into_leaf());
+                }
+            }
+            Action::Collapse => {
+                // replace the node with its only child
+                let mut node =
+                    mem::replace(&mut self.0[i], Handle::new_empty())
+                        .into_node();
+                self.0[i] = node.0.pop().expect("one child");
+            }
+            Action::Keep => (),
+        }
+        Ok(popped)
+    }
+}
+
+impl<K, P, H> Content<H> for PriorityQueue<K, P, H>
+where
+    K: Content<H>,
+    P: Content<H> + Ord,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        (self.0.len() as u8).persist(sink)?;
+        for h in &mut self.0 {
+            h.persist(sink)?
+        }
+        Ok(())
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        let mut q = PriorityQueue::default();
+        let len = u8::restore(source)? as usize;
+        if len > N {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Too many PriorityQueue children",
+            ));
+        }
+        for _ in 0..len {
+            q.0.push(Handle::restore(source)?);
+        }
+        Ok(q)
+    }
+}
+
+impl<K, P, H> Compound<H> for PriorityQueue<K, P, H>
+where
+    H: ByteHash,
+    K: Content<H>,
+    P: Content<H> + Ord,
+{
+    type Leaf = (K, P);
+    type Annotation = PriorityAnnotation<P, u64>;
+
+    fn children_mut(&mut self) -> &mut [Handle<Self, H>] {
+        &mut self.0
+    }
+
+    fn children(&self) -> &[Handle<Self, H>] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use kelvin::tests::assert_root_hash;
+    use kelvin::{Blake2b, Store};
+
+    #[test]
+    fn trivial_queue() {
+        let mut q = PriorityQueue::<_, _, Blake2b>::new();
+        q.insert(28u32, 3u32).unwrap();
+        assert_eq!(*q.peek_max().unwrap().unwrap(), (28, 3));
+        assert_eq!(q.pop_max().unwrap(), Some((28, 3)));
+        assert_eq!(q.pop_max().unwrap(), None);
+    }
+
+    #[test]
+    fn pops_in_priority_order() {
+        let mut q = PriorityQueue::<_, _, Blake2b>::new();
+        let n = 1024u32;
+        for i in 0..n {
+            // scramble the insertion order
+            let p = (i * 7919) % n;
+            q.insert(i, p).unwrap();
+        }
+        assert_eq!(q.len(), n as u64);
+
+        q.validate().unwrap();
+
+        for expected in (0..n).rev() {
+            let (_, p) = q.pop_max().unwrap().unwrap();
+            assert_eq!(p, expected);
+            if expected % 64 == 0 {
+                q.validate().unwrap();
+            }
+        }
+        assert_eq!(q.pop_max().unwrap(), None);
+        assert_eq!(q.len(), 0);
+    }
+
+    #[test]
+    fn duplicate_priorities() {
+        let mut q = PriorityQueue::<_, _, Blake2b>::new();
+        for i in 0..100u32 {
+            q.insert(i, i % 3).unwrap();
+        }
+        let mut last = u32::MAX;
+        while let Some((_, p)) = q.pop_max().unwrap() {
+            assert!(p <= last);
+            last = p;
+        }
+    }
+
+    #[test]
+    fn persist_restore() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+
+        let mut q = PriorityQueue::<_, _, Blake2b>::new();
+        for i in 0..256u32 {
+            q.insert(i, i).unwrap();
+        }
+
+        let snapshot = store.persist(&mut q).unwrap();
+        let mut restored = store.restore(&snapshot).unwrap();
+        restored.validate().unwrap();
+
+        for i in (0..256u32).rev() {
+            assert_eq!(restored.pop_max().unwrap(), Some((i, i)));
+        }
+    }
+
+    #[test]
+    fn validate() {
+        let mut single = PriorityQueue::<u32, u32, Blake2b>::new();
+        let mut child = PriorityQueue::new();
+        child.insert(0, 0).unwrap();
+        single.0.push(Handle::new_leaf((1, 1)));
+        single.0.push(Handle::new_node(child));
+        let err = single.validate().unwrap_err();
+        assert_eq!(err.to_string(), "single child at node [1]");
+
+        let mut empty = PriorityQueue::<u32, u32, Blake2b>::new();
+        empty.0.push(Handle::new_empty());
+        let err = empty.validate().unwrap_err();
+        assert_eq!(err.to_string(), "empty handle at node []");
+    }
+
+    #[test]
+    fn golden_root_hash() {
+        let mut queue = PriorityQueue::<u32, u32, Blake2b>::new();
+        for i in 0..100 {
+            queue.insert(i, i * 7 % 100).unwrap();
+        }
+        assert_root_hash(
+            &mut queue,
+            "696966476af6607f04048a1e44483d3dc7b171dc9efd01eeaf2f14fb9d5b6bb5",
+        );
+
+        queue.pop_max().unwrap();
+        assert_root_hash(
+            &mut queue,
+            "1941bf9c1a975d2a5cc5915938562ad08624fba11e2ef070d1708610577926c3",
+        );
+    }
+}
@@ -0,0 +1,422 @@
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::io;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+
+use arrayvec::ArrayVec;
+
+use kelvin::{
+    annotations::Count, ByteHash, Content, LeafIterable, Map, Sink, Source,
+};
+use kelvin_hamt::HAMT;
+
+/// Maximum number of entries stored inline
+pub const INLINE: usize = 8;
+
+/// A map that keeps up to `INLINE` entries in a single flat node, and only
+/// turns into a HAMT when growing beyond that.
+///
+/// When removals bring a HAMT down to half of `INLINE`, the entries are
+/// moved back inline.
+#[derive(Clone)]
+pub enum SmallMap<K, V, H>
+where
+    K: Content<H> + Hash + Eq,
+    V: Content<H>,
+    H: ByteHash,
+{
+    /// Entries stored inline
+    Inline(ArrayVec<[(K, V); INLINE]>),
+    /// Entries stored in a tree
+    Tree(HAMT<K, V, H>),
+}
+
+/// Reference to a value in a SmallMap
+pub enum ValueRef<'a, V, T> {
+    /// Value stored inline
+    Inline(&'a V),
+    /// Value stored in a tree
+    Tree(T),
+}
+
+impl<'a, V, T: Deref<Target = V>> Deref for ValueRef<'a, V, T> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        match self {
+            ValueRef::Inline(v) => v,
+            ValueRef::Tree(t) => t,
+        }
+    }
+}
+
+/// Mutable reference to a value in a SmallMap
+pub enum ValueRefMut<'a, V, T> {
+    /// Value stored inline
+    Inline(&'a mut V),
+    /// Value stored in a tree
+    Tree(T),
+}
+
+impl<'a, V, T: Deref<Target = V>> Deref for ValueRefMut<'a, V, T> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        match self {
+            ValueRefMut::Inline(v) => v,
+            ValueRefMut::Tree(t) => t,
+        }
+    }
+}
+
+impl<'a, V, T: DerefMut<Target = V>> DerefMut for ValueRefMut<'a, V, T> {
+    fn deref_mut(&mut self) -> &mut V {
+        match self {
+            ValueRefMut::Inline(v) => v,
+            ValueRefMut::Tree(t) => t,
+        }
+    }
+}
+
+impl<K, V, H> Default for SmallMap<K, V, H>
+where
+    K: Content<H> + Hash + Eq,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn default() -> Self {
+        SmallMap::Inline(ArrayVec::new())
+    }
+}
+
+impl<K, V, H> SmallMap<K, V, H>
+where
+    K: Content<H> + Hash + Eq,
+    V: Content<H>,
+    H: ByteHash,
+{
+    /// Creates a new, empty SmallMap
+    pub fn new() -> Self {
+        SmallMap::Inline(ArrayVec::new())
+    }
+
+    /// Returns true if the entries are stored inline
+    pub fn is_inline(&self) -> bool {
+        match self {
+            SmallMap::Inline(_) => true,
+            SmallMap::Tree(_) => false,
+        }
+    }
+
+    /// Returns the number of entries in the map
+    pub fn len(&self) -> u64 {
+        match self {
+            SmallMap::Inline(entries) => entries.len() as u64,
+            SmallMap::Tree(tree) => tree.count(),
+        }
+    }
+
+    /// Returns true if the map contains no entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Insert key-value pair into the map, optionally returning expelled
+    /// value
+    pub fn insert(&mut self, k: K, v: V) -> io::Result<Option<V>> {
+        let entries = match self {
+            SmallMap::Tree(tree) => return tree.insert(k, v),
+            SmallMap::Inline(entries) => entries,
+        };
+
+        if let Some(entry) = entries.iter_mut().find(|(ek, _)| *ek == k) {
+            return Ok(Some(mem::replace(&mut entry.1, v)));
+        }
+
+        if let Err(err) = entries.try_push((k, v)) {
+            // out of inline space, move everything into a tree
+            let mut tree = HAMT::new();
+            for (k, v) in entries.drain(..) {
+                tree.insert(k, v)?;
+            }
+            let (k, v) = err.element();
+            tree.insert(k, v)?;
+            *self = SmallMap::Tree(tree);
+        }
+        Ok(None)
+    }
+
+    /// Returns a reference to the value of `k`, if any
+    pub fn get<O>(
+        &self,
+        k: &O,
+    ) -> io::Result<Option<impl Deref<Target = V> + '_>>
+    where
+        K: Borrow<O>,
+        O: Hash + Eq + ?Sized,
+    {
+        Ok(match self {
+            SmallMap::Inline(entries) => entries
+                .iter()
+                .find(|(ek, _)| ek.borrow() == k)
+                .map(|(_, v)| ValueRef::Inline(v)),
+            SmallMap::Tree(tree) => tree.get(k)?.map(ValueRef::Tree),
+        })
+    }
+
+    /// Returns true if the map contains a value for `k`
+    pub fn contains_key<O>(&self, k: &O) -> io::Result<bool>
+    where
+        K: Borrow<O>,
+        O: Hash + Eq + ?Sized,
+    {
+        match self {
+            SmallMap::Inline(entries) => {
+                Ok(entries.iter().any(|(ek, _)| ek.borrow() == k))
+            }
+            SmallMap::Tree(tree) => tree.contains_key(k),
+        }
+    }
+
+    /// Returns a mutable reference to the value of `k`, if any
+    pub fn get_mut<O>(
+        &mut self,
+        k: &O,
+    ) -> io::Result<Option<impl DerefMut<Target = V> + '_>>
+    where
+        K: Borrow<O>,
+        O: Hash + Eq + ?Sized,
+    {
+        Ok(match self {
+            SmallMap::Inline(entries) => entries
+                .iter_mut()
+                .find(|(ek, _)| ek.borrow() == k)
+                .map(|(_, v)| ValueRefMut::Inline(v)),
+            SmallMap::Tree(tree) => tree.get_mut(k)?.map(ValueRefMut::Tree),
+        })
+    }
+
+    /// Remove element with given key, returning it.
+    pub fn remove<O>(&mut self, k: &O) -> io::Result<Option<V>>
+    where
+        K: Borrow<O>,
+        O: Hash + Eq + ?Sized,
+    {
+        let tree = match self {
+            SmallMap::Inline(entries) => {
+                return Ok(entries
+                    .iter()
+                    .position(|(ek, _)| ek.borrow() == k)
+                    .map(|i| entries.remove(i).1))
+            }
+            SmallMap::Tree(tree) => tree,
+        };
+
+        let removed = tree.remove(k)?;
+
+        // shrunk enough to move back inline
+        if tree.count() as usize <= INLINE / 2 {
+            let mut entries = ArrayVec::new();
+            for leaf in tree.drain() {
+                entries.push(leaf?);
+            }
+            *self = SmallMap::Inline(entries);
+        }
+        Ok(removed)
+    }
+
+    /// Checks the invariants of the map, returning an error describing the
+    /// first violation found
+    ///
+    /// Inline entries have to have distinct keys, and a HAMT has to hold
+    /// more than half of `INLINE` entries, or it would have been moved back
+    /// inline. The HAMT itself is checked with `HAMT::validate`.
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid =
+            |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        match self {
+            SmallMap::Inline(entries) => {
+                for (i, (k, _)) in entries.iter().enumerate() {
+                    if entries[..i].iter().any(|(other, _)| other == k) {
+                        return Err(invalid(format!("duplicate key at {}", i)));
+                    }
+                }
+                Ok(())
+            }
+            SmallMap::Tree(tree) if tree.count() as usize <= INLINE / 2 => {
+                Err(invalid(format!("{} entries in tree", tree.count())))
+            }
+            SmallMap::Tree(tree) => tree.validate(),
+        }
+    }
+}
+
+impl<K, V, H> Content<H> for SmallMap<K, V, H>
+where
+    K: Content<H> + Hash + Eq,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        match self {
+            SmallMap::Inline(entries) => {
+                0u8.persist(sink)?;
+                (entries.len() as u8).persist(sink)?;
+                for entry in entries.iter_mut() {
+                    entry.persist(sink)?;
+                }
+                Ok(())
+            }
+            SmallMap::Tree(tree) => {
+                1u8.persist(sink)?;
+                tree.persist(sink)
+            }
+        }
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        match u8::restore(source)? {
+            0 => {
+                let len = u8::restore(source)? as usize;
+                if len > INLINE {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Too many inline entries",
+                    ));
+                }
+                let mut entries = ArrayVec::new();
+                for _ in 0..len {
+                    entries.push(<(K, V)>::restore(source)?);
+                }
+                Ok(SmallMap::Inline(entries))
+            }
+            1 => Ok(SmallMap::Tree(HAMT::restore(source)?)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid SmallMap encoding",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use kelvin::tests::assert_root_hash;
+    use kelvin::{Blake2b, Store};
+
+    #[test]
+    fn stays_inline() {
+        let mut map = SmallMap::<_, _, Blake2b>::new();
+        for i in 0..INLINE as u32 {
+            assert_eq!(map.insert(i, i).unwrap(), None);
+        }
+        assert!(map.is_inline());
+        assert_eq!(map.insert(0, 10).unwrap(), Some(0));
+        assert!(map.is_inline());
+        assert_eq!(map.len(), INLINE as u64);
+        assert_eq!(*map.get(&0).unwrap().unwrap(), 10);
+    }
+
+    #[test]
+    fn grows_and_shrinks() {
+        let mut map = SmallMap::<_, _, Blake2b>::new();
+        for i in 0..100u32 {
+            map.insert(i, i).unwrap();
+        }
+        assert!(!map.is_inline());
+        map.validate().unwrap();
+        assert!(map.contains_key(&42).unwrap());
+        assert!(!map.contains_key(&100).unwrap());
+        for i in 0..100u32 {
+            *map.get_mut(&i).unwrap().unwrap() += 1;
+        }
+        for i in 0..100u32 {
+            assert_eq!(*map.get(&i).unwrap().unwrap(), i + 1);
+        }
+
+        for i in 0..96u32 {
+            assert_eq!(map.remove(&i).unwrap(), Some(i + 1));
+        }
+        assert!(map.is_inline());
+        assert_eq!(map.len(), 4);
+        map.validate().unwrap();
+        assert!(!map.contains_key(&0).unwrap());
+        assert!(map.contains_key(&99).unwrap());
+        for i in 96..100u32 {
+            assert_eq!(*map.get(&i).unwrap().unwrap(), i + 1);
+        }
+    }
+
+    #[test]
+    fn validate() {
+        let mut duplicate = SmallMap::<u32, u32, Blake2b>::new();
+        if let SmallMap::Inline(entries) = &mut duplicate {
+            entries.push((1, 1));
+            entries.push((1, 2));
+        }
+        let err = duplicate.validate().unwrap_err();
+        assert_eq!(err.to_string(), "duplicate key at 1");
+
+        let mut tree = HAMT::new();
+        tree.insert(1u32, 1u32).unwrap();
+        let small = SmallMap::<_, _, Blake2b>::Tree(tree);
+        let err = small.validate().unwrap_err();
+        assert_eq!(err.to_string(), "1 entries in tree");
+    }
+
+    #[test]
+    fn borrowed_keys() {
+        for n in [3u32, 100].iter() {
+            let mut map = SmallMap::<String, u32, Blake2b>::new();
+            for i in 0..*n {
+                map.insert(format!("key{}", i), i).unwrap();
+            }
+            assert_eq!(*map.get("key2").unwrap().unwrap(), 2);
+            *map.get_mut("key2").unwrap().unwrap() += 1;
+            assert!(map.contains_key("key2").unwrap());
+            assert_eq!(map.remove("key2").unwrap(), Some(3));
+            assert!(map.get("key2").unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn persist_restore() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+
+        for n in [3u32, 100].iter() {
+            let mut map = SmallMap::<_, _, Blake2b>::new();
+            for i in 0..*n {
+                map.insert(i, i).unwrap();
+            }
+            let snapshot = store.persist(&mut map).unwrap();
+            let restored = store.restore(&snapshot).unwrap();
+
+            assert_eq!(restored.is_inline(), map.is_inline());
+            restored.validate().unwrap();
+            for i in 0..*n {
+                assert_eq!(*restored.get(&i).unwrap().unwrap(), i);
+            }
+        }
+    }
+
+    #[test]
+    fn golden_root_hash() {
+        let mut map = SmallMap::<u32, u32, Blake2b>::new();
+        for i in 0..INLINE as u32 {
+            map.insert(i, i).unwrap();
+        }
+        assert_root_hash(
+            &mut map,
+            "29d9e789c318f3bbae7577e76e9f9932f09bc07af071f2441490d8098f5d63e2",
+        );
+
+        map.insert(INLINE as u32, 0).unwrap();
+        assert_root_hash(
+            &mut map,
+            "17e2262625355d7397b0924898460ce01664339dfe6babe5a55d88be1af73997",
+        );
+    }
+}
@@ -0,0 +1,728 @@
+use std::borrow::Borrow;
+use std::io;
+use std::iter::FromIterator;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+
+use kelvin::{
+    annotation,
+    annotations::{Cardinality, Count, Counter, MaxKey, MaxKeyType},
+    ByteHash, Compound, Content, Drain, First, Handle, HandleMut, HandleRef,
+    HandleType, LeafIter, LeafIterable, Map, MapMut, MapRemove, Method, Sink,
+    Source,
+};
+use kelvin_btree::BTree;
+
+/// Maximum number of entries in a chunk
+pub const CHUNK: usize = 256;
+
+/// Number of entries above which a SortedVecMap is promoted to a BTree
+pub const MAX_ENTRIES: u64 = 4096;
+
+/// A sorted sequence of entries, split into chunks of up to `CHUNK` entries
+///
+/// The root holds the chunks, and the chunks hold the entries, both sorted
+/// by key, so lookups are two binary searches. Each chunk is persisted as
+/// a single node.
+#[derive(Clone)]
+pub struct SortedVec<K, V, H: ByteHash>(Vec<Handle<Self, H>>)
+where
+    Self: Compound<H>;
+
+impl<K, V, H> Default for SortedVec<K, V, H>
+where
+    K: Content<H> + MaxKeyType,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn default() -> Self {
+        SortedVec(vec![])
+    }
+}
+
+annotation! {
+    pub struct SortedVecAnnotation<K, U> {
+        key: MaxKey<K>,
+        count: Cardinality<U>,
+    }
+    where
+        K: MaxKeyType,
+        U: Counter
+}
+
+/// Search method binary searching for the first handle with a maximum key
+/// not less than the given key
+pub struct SortedVecSearch<'a, K, O: ?Sized>(&'a O, PhantomData<K>);
+
+impl<'a, K, O: ?Sized> From<&'a O> for SortedVecSearch<'a, K, O> {
+    fn from(k: &'a O) -> Self {
+        SortedVecSearch(k, PhantomData)
+    }
+}
+
+impl<'a, K, O, C, H> Method<C, H> for SortedVecSearch<'a, K, O>
+where
+    C: Compound<H>,
+    C::Annotation: Borrow<MaxKey<K>>,
+    H: ByteHash,
+    K: Ord + Borrow<O>,
+    O: Ord + ?Sized,
+{
+    fn select(&mut self, handles: &[Handle<C, H>]) -> Option<usize> {
+        let i = position::<K, O, C, H>(handles, self.0);
+        if i < handles.len() {
+            Some(i)
+        } else {
+            None
+        }
+    }
+}
+
+// Index of the first handle with a maximum key not less than `k`
+fn position<K, O, C, H>(handles: &[Handle<C, H>], k: &O) -> usize
+where
+    C: Compound<H>,
+    C::Annotation: Borrow<MaxKey<K>>,
+    H: ByteHash,
+    K: Ord + Borrow<O>,
+    O: Ord + ?Sized,
+{
+    handles.partition_point(|h| match h.annotation() {
+        Some(ann) => {
+            let max_key: &MaxKey<K> = (*ann).borrow();
+            (**max_key).borrow() < k
+        }
+        None => false,
+    })
+}
+
+// Returns true if `handle` is a leaf with key `k`
+fn has_key<K, O, C, H>(handle: &Handle<C, H>, k: &O) -> bool
+where
+    C: Compound<H>,
+    C::Annotation: Borrow<MaxKey<K>>,
+    H: ByteHash,
+    K: Ord + Borrow<O>,
+    O: Ord + ?Sized,
+{
+    match handle.annotation() {
+        Some(ann) => {
+            let max_key: &MaxKey<K> = (*ann).borrow();
+            (**max_key).borrow() == k
+        }
+        None => false,
+    }
+}
+
+impl<K, V, H> SortedVec<K, V, H>
+where
+    K: Content<H> + MaxKeyType,
+    V: Content<H>,
+    H: ByteHash,
+{
+    /// Creates a new, empty SortedVec
+    pub fn new() -> Self {
+        SortedVec(vec![])
+    }
+
+    /// Returns the number of entries
+    pub fn len(&self) -> u64 {
+        self.count()
+    }
+
+    /// Returns true if there are no entries
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Insert key-value pair, optionally returning expelled value
+    pub fn insert(&mut self, k: K, v: V) -> io::Result<Option<V>> {
+        if self.0.is_empty() {
+            let mut chunk = Self::new();
+            chunk.0.push(Handle::new_leaf((k, v)));
+            self.0.push(Handle::new_node(chunk));
+            return Ok(None);
+        }
+
+        // keys past the last chunk go into the last chunk
+        let i = position::<K, K, _, _>(&self.0, &k).min(self.0.len() - 1);
+
+        let split = match &mut *self.0[i].inner_mut()? {
+            HandleMut::Node(chunk) => {
+                let j = position::<K, K, _, _>(&chunk.0, &k);
+                if j < chunk.0.len() && has_key(&chunk.0[j], &k) {
+                    if let HandleMut::Leaf((_, val)) =
+                        &mut *chunk.0[j].inner_mut()?
+                    {
+                        return Ok(Some(mem::replace(val, v)));
+                    }
+                    unreachable!("chunks only contain leaves")
+                }
+                chunk.0.insert(j, Handle::new_leaf((k, v)));
+
+                if chunk.0.len() > CHUNK {
+                    let half = chunk.0.len() / 2;
+                    Some(SortedVec(chunk.0.split_off(half)))
+                } else {
+                    None
+                }
+            }
+            _ => unreachable!("the root only contains chunks"),
+        };
+
+        if let Some(tail) = split {
+            self.0.insert(i + 1, Handle::new_node(tail));
+        }
+        Ok(None)
+    }
+
+    /// Remove element with given key, returning it.
+    pub fn remove<O>(&mut self, k: &O) -> io::Result<Option<V>>
+    where
+        K: Borrow<O>,
+        O: Ord + ?Sized,
+    {
+        let i = position(&self.0, k);
+        if i == self.0.len() {
+            return Ok(None);
+        }
+
+        let (removed, empty) = match &mut *self.0[i].inner_mut()? {
+            HandleMut::Node(chunk) => {
+                let j = position(&chunk.0, k);
+                if j < chunk.0.len() && has_key(&chunk.0[j], k) {
+                    let (_, v) = chunk.0.remove(j).into_leaf();
+                    (Some(v), chunk.0.is_empty())
+                } else {
+                    (None, false)
+                }
+            }
+            _ => unreachable!("the root only contains chunks"),
+        };
+
+        if empty {
+            self.0.remove(i);
+        }
+        Ok(removed)
+    }
+
+    /// Checks the invariants of the sorted vector, returning an error
+    /// describing the first violation found
+    ///
+    /// On top of the checks of `kelvin::validate`, the root has to hold only
+    /// chunks, each of up to `CHUNK` entries, with all keys in strictly
+    /// ascending order.
+    pub fn validate(&self) -> io::Result<()> {
+        let mut last: Option<K> = None;
+        kelvin::validate(self, |node: &Self, path| {
+            let expected = match path.len() {
+                0 => HandleType::Node,
+                _ if node.0.len() > CHUNK => {
+                    return Err(format!("chunk of {}", node.0.len()))
+                }
+                _ => HandleType::Leaf,
+            };
+            for handle in &node.0 {
+                let found = handle.handle_type();
+                if found != expected {
+                    return Err(format!(
+                        "{:?} instead of {:?}",
+                        found, expected
+                    ));
+                }
+                if let HandleType::Node = found {
+                    continue;
+                }
+                if let HandleRef::Leaf((k, _)) =
+                    handle.inner().map_err(|e| e.to_string())?
+                {
+                    if last.as_ref().is_some_and(|last| last >= k) {
+                        return Err("keys out of order".into());
+                    }
+                    last = Some(k.clone());
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+impl<K, V, H> Content<H> for SortedVec<K, V, H>
+where
+    K: Content<H> + MaxKeyType,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.0.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(SortedVec(Vec::restore(source)?))
+    }
+}
+
+impl<K, V, H> Compound<H> for SortedVec<K, V, H>
+where
+    K: Content<H> + MaxKeyType,
+    V: Content<H>,
+    H: ByteHash,
+{
+    type Leaf = (K, V);
+    type Annotation = SortedVecAnnotation<K, u64>;
+
+    fn children_mut(&mut self) -> &mut [Handle<Self, H>] {
+        &mut self.0
+    }
+
+    fn children(&self) -> &[Handle<Self, H>] {
+        &self.0
+    }
+}
+
+/// Consumes the structure, yielding its entries by value as it is torn down
+impl<K, V, H> IntoIterator for SortedVec<K, V, H>
+where
+    K: Content<H> + MaxKeyType,
+    V: Content<H>,
+    H: ByteHash,
+{
+    type Item = io::Result<(K, V)>;
+    type IntoIter = Drain<Self, H>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        self.drain()
+    }
+}
+
+/// Iterates over the entries by reference
+impl<'a, K, V, H> IntoIterator for &'a SortedVec<K, V, H>
+where
+    K: Content<H> + MaxKeyType,
+    V: Content<H>,
+    H: ByteHash,
+{
+    type Item = io::Result<&'a (K, V)>;
+    type IntoIter = LeafIter<'a, SortedVec<K, V, H>, First, H>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Builds a SortedVec with `insert_batch`, for duplicate keys the last value is
+/// kept
+impl<K, V, H> FromIterator<(K, V)> for SortedVec<K, V, H>
+where
+    K: Content<H> + MaxKeyType,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::default();
+        map.extend(iter);
+        map
+    }
+}
+
+/// Inserts the entries with `insert_batch`
+///
+/// Panics if a node of the SortedVec fails to restore from its store, use
+/// `insert_batch` directly to handle the error instead.
+impl<K, V, H> Extend<(K, V)> for SortedVec<K, V, H>
+where
+    K: Content<H> + MaxKeyType,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        MapMut::insert_batch(self, iter)
+            .expect("SortedVec node failed to restore");
+    }
+}
+
+impl<'a, O, K, V, H> Map<'a, O, K, V, H> for SortedVec<K, V, H>
+where
+    K: Content<H> + MaxKeyType + Borrow<O>,
+    V: Content<H>,
+    H: ByteHash,
+    O: Ord + ?Sized + 'a,
+{
+    type KeySearch = SortedVecSearch<'a, K, O>;
+}
+
+impl<K, V, H> MapMut<K, V, H> for SortedVec<K, V, H>
+where
+    K: Content<H> + MaxKeyType,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn insert(&mut self, k: K, v: V) -> io::Result<Option<V>> {
+        SortedVec::insert(self, k, v)
+    }
+
+    fn remove(&mut self, k: &K) -> io::Result<Option<V>> {
+        SortedVec::remove(self, k)
+    }
+}
+
+impl<O, K, V, H> MapRemove<O, K, V, H> for SortedVec<K, V, H>
+where
+    K: Content<H> + MaxKeyType + Borrow<O>,
+    V: Content<H>,
+    H: ByteHash,
+    O: Ord + ?Sized,
+{
+    fn remove_borrowed(&mut self, k: &O) -> io::Result<Option<V>> {
+        SortedVec::remove(self, k)
+    }
+}
+
+/// A map for small, dense key spaces, kept as a sorted vector of chunks
+///
+/// When growing beyond `MAX_ENTRIES`, the entries are moved into a BTree.
+#[derive(Clone)]
+pub enum SortedVecMap<K, V, H>
+where
+    K: Content<H> + MaxKeyType,
+    V: Content<H>,
+    H: ByteHash,
+{
+    /// Entries stored in sorted chunks
+    Vec(SortedVec<K, V, H>),
+    /// Entries stored in a tree
+    Tree(BTree<K, V, H>),
+}
+
+/// Reference to a value in a SortedVecMap
+pub enum ValueRef<A, B> {
+    /// Value stored in sorted chunks
+    Vec(A),
+    /// Value stored in a tree
+    Tree(B),
+}
+
+impl<T, A, B> Deref for ValueRef<A, B>
+where
+    A: Deref<Target = T>,
+    B: Deref<Target = T>,
+{
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self {
+            ValueRef::Vec(a) => a,
+            ValueRef::Tree(b) => b,
+        }
+    }
+}
+
+impl<T, A, B> DerefMut for ValueRef<A, B>
+where
+    A: DerefMut<Target = T>,
+    B: DerefMut<Target = T>,
+{
+    fn deref_mut(&mut self) -> &mut T {
+        match self {
+            ValueRef::Vec(a) => a,
+            ValueRef::Tree(b) => b,
+        }
+    }
+}
+
+impl<K, V, H> Default for SortedVecMap<K, V, H>
+where
+    K: Content<H> + MaxKeyType,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn default() -> Self {
+        SortedVecMap::Vec(SortedVec::new())
+    }
+}
+
+impl<K, V, H> SortedVecMap<K, V, H>
+where
+    K: Content<H> + MaxKeyType,
+    V: Content<H>,
+    H: ByteHash,
+{
+    /// Creates a new, empty SortedVecMap
+    pub fn new() -> Self {
+        SortedVecMap::Vec(SortedVec::new())
+    }
+
+    /// Returns true if the entries have been moved into a tree
+    pub fn is_tree(&self) -> bool {
+        match self {
+            SortedVecMap::Vec(_) => false,
+            SortedVecMap::Tree(_) => true,
+        }
+    }
+
+    /// Returns the number of entries in the map
+    pub fn len(&self) -> u64 {
+        match self {
+            SortedVecMap::Vec(vec) => vec.len(),
+            SortedVecMap::Tree(tree) => tree.len(),
+        }
+    }
+
+    /// Returns true if the map contains no entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Insert key-value pair into the map, optionally returning expelled
+    /// value
+    pub fn insert(&mut self, k: K, v: V) -> io::Result<Option<V>> {
+        let vec = match self {
+            SortedVecMap::Tree(tree) => return tree.insert(k, v),
+            SortedVecMap::Vec(vec) => vec,
+        };
+
+        let replaced = vec.insert(k, v)?;
+
+        if vec.len() > MAX_ENTRIES {
+            // entries come out in order, build the tree bottom-up
+            let entries: Vec<_> = vec.drain().collect::<io::Result<_>>()?;
+            *self = SortedVecMap::Tree(BTree::from_sorted_iter(entries)?);
+        }
+        Ok(replaced)
+    }
+
+    /// Returns a reference to the value of `k`, if any
+    pub fn get<O>(
+        &self,
+        k: &O,
+    ) -> io::Result<Option<impl Deref<Target = V> + '_>>
+    where
+        K: Borrow<O>,
+        O: Ord + ?Sized,
+    {
+        Ok(match self {
+            SortedVecMap::Vec(vec) => vec.get(k)?.map(ValueRef::Vec),
+            SortedVecMap::Tree(tree) => tree.get(k)?.map(ValueRef::Tree),
+        })
+    }
+
+    /// Returns true if the map contains a value for `k`
+    pub fn contains_key<O>(&self, k: &O) -> io::Result<bool>
+    where
+        K: Borrow<O>,
+        O: Ord + ?Sized,
+    {
+        match self {
+            SortedVecMap::Vec(vec) => vec.contains_key(k),
+            SortedVecMap::Tree(tree) => tree.contains_key(k),
+        }
+    }
+
+    /// Returns a mutable reference to the value of `k`, if any
+    pub fn get_mut<O>(
+        &mut self,
+        k: &O,
+    ) -> io::Result<Option<impl DerefMut<Target = V> + '_>>
+    where
+        K: Borrow<O>,
+        O: Ord + ?Sized,
+    {
+        Ok(match self {
+            SortedVecMap::Vec(vec) => vec.get_mut(k)?.map(ValueRef::Vec),
+            SortedVecMap::Tree(tree) => tree.get_mut(k)?.map(ValueRef::Tree),
+        })
+    }
+
+    /// Remove element with given key, returning it.
+    pub fn remove<O>(&mut self, k: &O) -> io::Result<Option<V>>
+    where
+        K: Borrow<O>,
+        O: Ord + ?Sized,
+    {
+        match self {
+            SortedVecMap::Vec(vec) => vec.remove(k),
+            SortedVecMap::Tree(tree) => tree.remove(k),
+        }
+    }
+
+    /// Checks the invariants of the map, returning an error describing the
+    /// first violation found
+    ///
+    /// Checks the sorted vector or tree holding the entries, and that a
+    /// sorted vector holds no more than `MAX_ENTRIES` of them.
+    pub fn validate(&self) -> io::Result<()> {
+        match self {
+            SortedVecMap::Vec(vec) if vec.len() > MAX_ENTRIES => {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} entries in sorted vector", vec.len()),
+                ))
+            }
+            SortedVecMap::Vec(vec) => vec.validate(),
+            SortedVecMap::Tree(tree) => tree.validate(),
+        }
+    }
+}
+
+impl<K, V, H> Content<H> for SortedVecMap<K, V, H>
+where
+    K: Content<H> + MaxKeyType,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        match self {
+            SortedVecMap::Vec(vec) => {
+                0u8.persist(sink)?;
+                vec.persist(sink)
+            }
+            SortedVecMap::Tree(tree) => {
+                1u8.persist(sink)?;
+                tree.persist(sink)
+            }
+        }
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        match u8::restore(source)? {
+            0 => Ok(SortedVecMap::Vec(SortedVec::restore(source)?)),
+            1 => Ok(SortedVecMap::Tree(BTree::restore(source)?)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid SortedVecMap encoding",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use kelvin::tests::assert_root_hash;
+    use kelvin::{quickcheck_map, quickcheck_proofs, Blake2b};
+
+    #[test]
+    fn chunks() {
+        let mut vec = SortedVec::<_, _, Blake2b>::new();
+        let n = 2000u32;
+        for i in 0..n {
+            // scramble insertion order
+            let k = (i * 7919) % n;
+            assert_eq!(vec.insert(k, k).unwrap(), None);
+        }
+        assert_eq!(vec.len(), n as u64);
+        assert!(vec.0.len() > 1);
+        vec.validate().unwrap();
+
+        let keys: Vec<_> = vec.iter().map(|res| res.unwrap().0).collect();
+        assert_eq!(keys, (0..n).collect::<Vec<_>>());
+
+        for i in 0..n {
+            assert_eq!(*vec.get(&i).unwrap().unwrap(), i);
+        }
+        assert!(vec.get(&n).unwrap().is_none());
+
+        for i in 0..n {
+            assert_eq!(vec.remove(&i).unwrap(), Some(i));
+        }
+        assert!(vec.is_empty());
+        vec.validate().unwrap();
+    }
+
+    #[test]
+    fn collect_extend() {
+        let mut vec: SortedVec<u32, u32, Blake2b> =
+            (0..300u32).rev().map(|i| (i, i)).collect();
+        vec.extend((300..600u32).map(|i| (i, i)));
+        vec.validate().unwrap();
+
+        let keys: Vec<_> =
+            (&vec).into_iter().map(|entry| entry.unwrap().0).collect();
+        assert_eq!(keys, (0..600).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn promotes_to_tree() {
+        let mut map = SortedVecMap::<_, _, Blake2b>::new();
+        for i in 0..MAX_ENTRIES as u32 {
+            map.insert(i, i).unwrap();
+        }
+        assert!(!map.is_tree());
+        map.insert(MAX_ENTRIES as u32, 0).unwrap();
+        assert!(map.is_tree());
+        assert_eq!(map.len(), MAX_ENTRIES + 1);
+        map.validate().unwrap();
+
+        for i in 0..MAX_ENTRIES as u32 {
+            *map.get_mut(&i).unwrap().unwrap() += 1;
+        }
+        for i in 0..MAX_ENTRIES as u32 {
+            assert_eq!(*map.get(&i).unwrap().unwrap(), i + 1);
+        }
+    }
+
+    #[test]
+    fn persist_restore() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+
+        let mut map = SortedVecMap::<String, u32, Blake2b>::new();
+        for i in 0..1000u32 {
+            map.insert(format!("key{}", i), i).unwrap();
+        }
+
+        let snapshot = store.persist(&mut map).unwrap();
+        let mut restored = store.restore(&snapshot).unwrap();
+        restored.validate().unwrap();
+
+        for i in 0..1000u32 {
+            let key = format!("key{}", i);
+            assert_eq!(*restored.get(key.as_str()).unwrap().unwrap(), i);
+        }
+        assert_eq!(restored.remove("key500").unwrap(), Some(500));
+        assert!(!restored.contains_key("key500").unwrap());
+        assert_eq!(restored.len(), 999);
+    }
+
+    #[test]
+    fn validate() {
+        let mut chunk = SortedVec::<u32, u32, Blake2b>::new();
+        chunk.0.push(Handle::new_leaf((2, 2)));
+        chunk.0.push(Handle::new_leaf((1, 1)));
+
+        let mut unordered = SortedVec::<u32, u32, Blake2b>::new();
+        unordered.0.push(Handle::new_node(chunk.clone()));
+        let err = unordered.validate().unwrap_err();
+        assert_eq!(err.to_string(), "keys out of order at node [0]");
+
+        let mut unchunked = SortedVec::<u32, u32, Blake2b>::new();
+        unchunked.0.push(Handle::new_leaf((0, 0)));
+        let err = unchunked.validate().unwrap_err();
+        assert_eq!(err.to_string(), "Leaf instead of Node at node []");
+    }
+
+    quickcheck_map!(|| SortedVec::new());
+    quickcheck_proofs!(SortedVec::new);
+
+    #[test]
+    fn golden_root_hash() {
+        let mut map = SortedVecMap::<u32, u32, Blake2b>::new();
+        for i in 0..1000 {
+            map.insert(i * 7 % 1000, i).unwrap();
+        }
+        assert_root_hash(
+            &mut map,
+            "699dd517265ca10609cdbbd52365f0e588897c22eece42f5d2e87f9fbfd634cd",
+        );
+
+        for i in 1000..MAX_ENTRIES as u32 + 1 {
+            map.insert(i, i).unwrap();
+        }
+        assert!(map.is_tree());
+        assert_root_hash(
+            &mut map,
+            "7df59b96508fe1d7395bbeef0c1a2a70e3d06acf5be451ab661eab23eb071480",
+        );
+    }
+}
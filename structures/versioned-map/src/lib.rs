@@ -0,0 +1,252 @@
+use std::io;
+use std::ops::Deref;
+
+use kelvin::{
+    annotations::Count, ByteHash, Content, LeafIterable, Map, MapMut, Sink,
+    Source, ValRef,
+};
+use kelvin_btree::BTree;
+use kelvin_list::List;
+
+/// A map keeping the full history of values for every key
+///
+/// Every insert is tagged with a map-wide version number. The history of
+/// each key is a persistent list, so older versions share their storage
+/// with newer ones.
+#[derive(Clone)]
+pub struct VersionedMap<K, V, H>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+{
+    version: u64,
+    // key -> (version, value), newest first
+    entries: BTree<K, List<(u64, V), H>, H>,
+}
+
+impl<K, V, H> VersionedMap<K, V, H>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+{
+    /// Creates a new, empty VersionedMap
+    pub fn new() -> Self {
+        VersionedMap {
+            version: 0,
+            entries: BTree::new(),
+        }
+    }
+
+    /// Returns the current version of the map, the number of inserts made
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns the number of keys in the map
+    pub fn len(&self) -> u64 {
+        self.entries.count()
+    }
+
+    /// Returns true if the map contains no keys
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Inserts a new value for `k`, returning the version of the insert
+    pub fn insert(&mut self, k: K, v: V) -> io::Result<u64> {
+        self.version += 1;
+        let version = self.version;
+
+        self.entries
+            .entry(k)?
+            .or_insert_with(List::new)?
+            .push((version, v));
+        Ok(version)
+    }
+
+    /// Returns a reference to the latest value of `k`, if any
+    pub fn get(
+        &self,
+        k: &K,
+    ) -> io::Result<Option<impl Deref<Target = V> + '_>> {
+        Ok(self.entries.get(k)?.map(|history| {
+            history.wrap(|list| &list.head().expect("non-empty history").1)
+        }))
+    }
+
+    /// Returns true if the map holds a value for `k`
+    pub fn contains_key(&self, k: &K) -> io::Result<bool> {
+        self.entries.contains_key(k)
+    }
+
+    /// Returns the value of `k` as of `version`, if any
+    pub fn get_at(&self, k: &K, version: u64) -> io::Result<Option<V>> {
+        if let Some(history) = self.entries.get(k)? {
+            for entry in history.iter() {
+                let (v_version, v) = entry?;
+                if *v_version <= version {
+                    return Ok(Some(v.clone()));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns all values ever inserted for `k` along with their versions,
+    /// oldest first
+    pub fn history(&self, k: &K) -> io::Result<Vec<(u64, V)>> {
+        let mut history = vec![];
+        if let Some(list) = self.entries.get(k)? {
+            for entry in list.iter() {
+                history.push(entry?.clone());
+            }
+        }
+        history.reverse();
+        Ok(history)
+    }
+
+    /// Checks the invariants of the map, returning an error describing the
+    /// first violation found
+    ///
+    /// On top of validating the tree of keys and every history list, each
+    /// history has to be non-empty and hold strictly descending versions,
+    /// none of them newer than the version of the map.
+    pub fn validate(&self) -> io::Result<()> {
+        self.entries.validate()?;
+        for (i, entry) in self.entries.iter().enumerate() {
+            let (_, history) = entry?;
+            history.validate()?;
+            let mut newer = self.version + 1;
+            for value in history.iter() {
+                let (version, _) = value?;
+                if *version >= newer {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "version {} out of order in key {}",
+                            version, i
+                        ),
+                    ));
+                }
+                newer = *version;
+            }
+            if newer > self.version {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("empty history in key {}", i),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K, V, H> Default for VersionedMap<K, V, H>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, H> Content<H> for VersionedMap<K, V, H>
+where
+    K: Content<H> + Ord,
+    V: Content<H>,
+    H: ByteHash,
+{
+    fn persist(&mut self, sink: &mut Sink<H>) -> io::Result<()> {
+        self.version.persist(sink)?;
+        self.entries.persist(sink)
+    }
+
+    fn restore(source: &mut Source<H>) -> io::Result<Self> {
+        Ok(VersionedMap {
+            version: u64::restore(source)?,
+            entries: BTree::restore(source)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use kelvin::tests::assert_root_hash;
+    use kelvin::{Blake2b, Store};
+
+    #[test]
+    fn history() {
+        let mut map = VersionedMap::<_, _, Blake2b>::new();
+        assert_eq!(map.insert(0u32, 10u32).unwrap(), 1);
+        assert_eq!(map.insert(1, 100).unwrap(), 2);
+        assert_eq!(map.insert(0, 11).unwrap(), 3);
+        assert_eq!(map.insert(0, 12).unwrap(), 4);
+        assert_eq!(map.len(), 2);
+
+        assert_eq!(*map.get(&0).unwrap().unwrap(), 12);
+        assert_eq!(map.history(&0).unwrap(), vec![(1, 10), (3, 11), (4, 12)]);
+        assert_eq!(map.history(&1).unwrap(), vec![(2, 100)]);
+        assert!(map.history(&2).unwrap().is_empty());
+        assert!(map.contains_key(&1).unwrap());
+        assert!(!map.contains_key(&2).unwrap());
+        map.validate().unwrap();
+
+        map.version = 3;
+        let err = map.validate().unwrap_err();
+        assert_eq!(err.to_string(), "version 4 out of order in key 0");
+    }
+
+    #[test]
+    fn get_at() {
+        let mut map = VersionedMap::<_, _, Blake2b>::new();
+        map.insert(0u32, 10u32).unwrap();
+        map.insert(1, 100).unwrap();
+        map.insert(0, 11).unwrap();
+
+        assert_eq!(map.get_at(&0, 0).unwrap(), None);
+        assert_eq!(map.get_at(&0, 1).unwrap(), Some(10));
+        assert_eq!(map.get_at(&0, 2).unwrap(), Some(10));
+        assert_eq!(map.get_at(&0, 3).unwrap(), Some(11));
+        assert_eq!(map.get_at(&1, 1).unwrap(), None);
+        assert_eq!(map.get_at(&1, 3).unwrap(), Some(100));
+    }
+
+    #[test]
+    fn persist_restore() {
+        let store = Store::<Blake2b>::volatile().unwrap();
+
+        let mut map = VersionedMap::<_, _, Blake2b>::new();
+        for i in 0..100u32 {
+            map.insert(i % 10, i).unwrap();
+        }
+        let snapshot = store.persist(&mut map).unwrap();
+        let mut restored = store.restore(&snapshot).unwrap();
+        restored.validate().unwrap();
+
+        assert_eq!(restored.version(), 100);
+        assert_eq!(restored.insert(3, 1000).unwrap(), 101);
+        let history = restored.history(&3).unwrap();
+        assert_eq!(history.len(), 11);
+        assert_eq!(history[0], (4, 3));
+        assert_eq!(history[10], (101, 1000));
+        assert_eq!(restored.get_at(&3, 100).unwrap(), Some(93));
+    }
+
+    #[test]
+    fn golden_root_hash() {
+        let mut map = VersionedMap::<u32, u32, Blake2b>::new();
+        for i in 0..100 {
+            map.insert(i % 10, i).unwrap();
+        }
+        assert_root_hash(
+            &mut map,
+            "16063390073e9a928eb098a7bf580cdc03c0f1ff3ac3d17dd3ba76eb3469b261",
+        );
+    }
+}
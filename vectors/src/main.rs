@@ -0,0 +1,209 @@
+//! Writes conformance vectors of the kelvin encoding as JSON
+//!
+//! Usage: `kelvin-vectors [output file]`, writing to stdout unless a file is
+//! given.
+//!
+//! Every vector persists a fixed structure, built from the input it
+//! describes, to a store of blake2b digests of 32 bytes. The vector holds
+//! the digest of the root, and every value written to the store, in the
+//! order written: children before the nodes referring to them. Other
+//! implementations of the format can check their encodings and digests
+//! against these, value by value.
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use kelvin::{
+    Backend, Blake2b, ByteHash, Content, MemBackend, PutResult, Reader, Store,
+};
+use kelvin_btree::BTree;
+use kelvin_hamt::HAMT;
+
+type Digest = <Blake2b as ByteHash>::Digest;
+
+// A backend keeping the values new to it in the order they are put
+#[derive(Default)]
+struct Recorder {
+    inner: MemBackend<Blake2b>,
+    values: Mutex<Vec<(Digest, Vec<u8>)>>,
+}
+
+impl Backend<Blake2b> for Recorder {
+    fn get<'a>(&'a self, digest: &Digest) -> io::Result<Reader<'a>> {
+        self.inner.get(digest)
+    }
+
+    fn put(&self, digest: Digest, bytes: &[u8]) -> io::Result<PutResult> {
+        let result = self.inner.put(digest, bytes)?;
+        if let PutResult::Ok = result {
+            let mut values = self.values.lock().expect("poisoned recorder");
+            values.push((digest, bytes.to_vec()));
+        }
+        Ok(result)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct Vector {
+    name: &'static str,
+    input: &'static str,
+    root: Digest,
+    values: Vec<(Digest, Vec<u8>)>,
+}
+
+// Persists `content` to a fresh store, recording the values written
+fn vector<C: Content<Blake2b>>(
+    name: &'static str,
+    input: &'static str,
+    mut content: C,
+) -> io::Result<Vector> {
+    let recorder = Arc::new(Recorder::default());
+    let store = Store::with_backend(recorder.clone());
+    let root = *store.persist(&mut content)?.hash();
+    let values = recorder.values.lock().expect("poisoned recorder").clone();
+    Ok(Vector {
+        name,
+        input,
+        root,
+        values,
+    })
+}
+
+fn vectors() -> io::Result<Vec<Vector>> {
+    let mut btree = BTree::<u32, u32, Blake2b>::new();
+    for i in 0..100 {
+        btree.insert(i * 7 % 100, i)?;
+    }
+    let mut btree_removed = btree.clone();
+    btree_removed.remove(&42)?;
+
+    let mut hamt = HAMT::<u32, u32, Blake2b>::new();
+    for i in 0..100 {
+        hamt.insert(i, i * 2)?;
+    }
+    let mut hamt_removed = hamt.clone();
+    hamt_removed.remove(&42)?;
+
+    Ok(vec![
+        vector("u64", "0", 0u64)?,
+        vector("i128", "-1", -1i128)?,
+        vector("string", "\"kelvin\"", String::from("kelvin"))?,
+        vector("bytes", "[1, 2, 3] as a Vec<u8>", vec![1u8, 2, 3])?,
+        vector(
+            "options",
+            "(Some(7u32), None::<u32>)",
+            (Some(7u32), None::<u32>),
+        )?,
+        vector(
+            "btree",
+            "BTree<u32, u32>, inserting (i * 7 % 100, i) for i in 0..100",
+            btree,
+        )?,
+        vector(
+            "btree_removed",
+            "the btree vector, then removing the key 42",
+            btree_removed,
+        )?,
+        vector(
+            "hamt",
+            "HAMT<u32, u32>, inserting (i, i * 2) for i in 0..100",
+            hamt,
+        )?,
+        vector(
+            "hamt_removed",
+            "the hamt vector, then removing the key 42",
+            hamt_removed,
+        )?,
+    ])
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Writes the vectors as JSON. Names and hex strings need no escaping, and
+// the ASCII inputs are escaped by their `Debug` rendering.
+fn write_json<W: Write>(w: &mut W, vectors: &[Vector]) -> io::Result<()> {
+    writeln!(w, "{{")?;
+    writeln!(w, "  \"hash\": \"blake2b-256\",")?;
+    writeln!(w, "  \"vectors\": [")?;
+    for (i, vector) in vectors.iter().enumerate() {
+        writeln!(w, "    {{")?;
+        writeln!(w, "      \"name\": \"{}\",", vector.name)?;
+        writeln!(w, "      \"input\": {:?},", vector.input)?;
+        writeln!(w, "      \"root\": \"{}\",", hex(&vector.root))?;
+        writeln!(w, "      \"values\": [")?;
+        for (j, (digest, bytes)) in vector.values.iter().enumerate() {
+            let comma = if j + 1 < vector.values.len() { "," } else { "" };
+            writeln!(
+                w,
+                "        {{ \"digest\": \"{}\", \"bytes\": \"{}\" }}{}",
+                hex(digest),
+                hex(bytes),
+                comma
+            )?;
+        }
+        writeln!(w, "      ]")?;
+        let comma = if i + 1 < vectors.len() { "," } else { "" };
+        writeln!(w, "    }}{}", comma)?;
+    }
+    writeln!(w, "  ]")?;
+    writeln!(w, "}}")
+}
+
+fn run() -> io::Result<()> {
+    let vectors = vectors()?;
+    match env::args().nth(1) {
+        Some(path) => write_json(&mut File::create(path)?, &vectors),
+        None => write_json(&mut io::stdout().lock(), &vectors),
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        process::exit(1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytehash::State;
+
+    #[test]
+    fn roots_match_golden() {
+        let vectors = vectors().unwrap();
+        let find = |name| vectors.iter().find(|v| v.name == name).unwrap();
+
+        // as checked in by the golden tests of kelvin and the structures
+        assert_eq!(
+            hex(&find("u64").root),
+            "81e47a19e6b29b0a65b9591762ce5143ed30d0261e5d24a3201752506b20f15c"
+        );
+        assert_eq!(
+            hex(&find("btree").root),
+            "b785d6ddde536f904ddc6438aaa7968dd95220a92da735af95061fe2dc39cbf7"
+        );
+        assert_eq!(
+            hex(&find("hamt_removed").root),
+            "73e5aa7727f96d78589a335660ef9f83f6a84caefa9a2408fdfb12cfea3291ca"
+        );
+
+        for vector in &vectors {
+            // the root is written last, every value under its own digest
+            assert_eq!(vector.values.last().unwrap().0, vector.root);
+            for (digest, bytes) in &vector.values {
+                let mut state = Blake2b::state();
+                state.write_all(bytes).unwrap();
+                assert_eq!(state.fin(), *digest);
+            }
+        }
+    }
+}
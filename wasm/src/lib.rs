@@ -0,0 +1,185 @@
+//! wasm-bindgen bindings for kelvin stores, maps and proofs
+//!
+//! Maps are HAMTs from byte strings to byte strings, taking and returning
+//! `Uint8Array`s, persisted to stores with blake2b digests of 32 bytes.
+//! Failing calls throw the message of their error.
+//!
+//! Proofs are created from a persisted map with `Store.prove` and checked
+//! against the digest of the map with `verifyProof`, which needs no store,
+//! so a browser can verify values served to it without having the map.
+#![warn(missing_docs)]
+
+use std::io;
+
+use kelvin::tests::{persist_bytes, restore_bytes};
+use kelvin::{Blake2b, ByteHash, Map, Proof, Store};
+use kelvin_hamt::{HAMTSearch, HAMT};
+use wasm_bindgen::prelude::*;
+
+type Digest = <Blake2b as ByteHash>::Digest;
+type BytesMap = HAMT<Vec<u8>, Vec<u8>, Blake2b>;
+
+/// A store, in memory or in the local storage of the browser
+#[wasm_bindgen(js_name = Store)]
+pub struct KelvinStore(Store<Blake2b>);
+
+#[wasm_bindgen(js_class = Store)]
+impl KelvinStore {
+    /// Creates a store keeping its values in memory
+    pub fn volatile() -> Result<KelvinStore, JsValue> {
+        Ok(KelvinStore(Store::volatile().map_err(js_error)?))
+    }
+
+    /// Opens a store in local storage, under keys starting with `name`
+    #[wasm_bindgen(js_name = localStorage)]
+    pub fn local_storage(name: &str) -> Result<KelvinStore, JsValue> {
+        Ok(KelvinStore(Store::new(name).map_err(js_error)?))
+    }
+
+    /// Persists `map`, returning its digest
+    pub fn persist(&self, map: &mut KelvinMap) -> Result<Vec<u8>, JsValue> {
+        let snapshot = self.0.persist(&mut map.0).map_err(js_error)?;
+        Ok(snapshot.hash().to_vec())
+    }
+
+    /// Restores the map persisted with `digest`
+    pub fn restore(&self, digest: &[u8]) -> Result<KelvinMap, JsValue> {
+        let snapshot = self.0.snapshot(parse_digest(digest)?);
+        Ok(KelvinMap(self.0.restore(&snapshot).map_err(js_error)?))
+    }
+
+    /// Returns a proof of the value at `key` in the map persisted with
+    /// `digest`, or `undefined` if the key is missing
+    pub fn prove(
+        &self,
+        digest: &[u8],
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, JsValue> {
+        let digest = parse_digest(digest)?;
+        let snapshot = self.0.snapshot::<BytesMap>(digest);
+        let mut proof = match Proof::new(&snapshot, &mut HAMTSearch::from(key))
+        {
+            Ok(Some(proof)) => proof,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(js_error(e)),
+        };
+        // the search can end at the leaf of another key, on a false
+        // positive of the bloom filters along the path
+        match proof.verify(&digest).map_err(js_error)? {
+            Some(proven) if proven.leaf.0 == key => {
+                Ok(Some(persist_bytes(&mut proof).map_err(js_error)?))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// A map from byte strings to byte strings
+#[wasm_bindgen(js_name = Map)]
+#[derive(Default)]
+pub struct KelvinMap(BytesMap);
+
+#[wasm_bindgen(js_class = Map)]
+impl KelvinMap {
+    /// Creates an empty map
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> KelvinMap {
+        KelvinMap::default()
+    }
+
+    /// Returns the value at `key`, or `undefined`
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, JsValue> {
+        let found = self.0.get(key).map_err(js_error)?;
+        Ok(found.map(|val| val.to_vec()))
+    }
+
+    /// Inserts `val` at `key`, returning the value replaced, or `undefined`
+    pub fn insert(
+        &mut self,
+        key: &[u8],
+        val: &[u8],
+    ) -> Result<Option<Vec<u8>>, JsValue> {
+        self.0.insert(key.to_vec(), val.to_vec()).map_err(js_error)
+    }
+
+    /// Removes the value at `key`, returning it, or `undefined`
+    pub fn remove(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, JsValue> {
+        self.0.remove(key).map_err(js_error)
+    }
+}
+
+/// Verifies a proof created by `Store.prove` against the `digest` of a map,
+/// returning the proven value at `key`, or `undefined` if the proof is
+/// invalid
+#[wasm_bindgen(js_name = verifyProof)]
+pub fn verify_proof(
+    digest: &[u8],
+    proof: &[u8],
+    key: &[u8],
+) -> Option<Vec<u8>> {
+    if digest.len() != Digest::default().len() {
+        return None;
+    }
+    let mut root = Digest::default();
+    root.copy_from_slice(digest);
+    let proof: Proof<BytesMap, Blake2b> = restore_bytes(proof).ok()?;
+    match proof.verify(&root) {
+        Ok(Some(proven)) if proven.leaf.0 == key => Some(proven.leaf.1),
+        _ => None,
+    }
+}
+
+fn parse_digest(bytes: &[u8]) -> Result<Digest, JsValue> {
+    let mut digest = Digest::default();
+    if bytes.len() != digest.len() {
+        return Err(JsValue::from_str("digest is not 32 bytes"));
+    }
+    digest.copy_from_slice(bytes);
+    Ok(digest)
+}
+
+fn js_error(e: io::Error) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // errors are only thrown on wasm, so only successful calls are tested
+    #[test]
+    fn persist_prove_verify() {
+        let store = KelvinStore::volatile().unwrap();
+        let mut map = KelvinMap::new();
+        for i in 0..100u32 {
+            map.insert(&i.to_be_bytes(), &[i as u8; 2]).unwrap();
+        }
+        assert_eq!(
+            map.remove(&99u32.to_be_bytes()).unwrap(),
+            Some(vec![99; 2])
+        );
+        let digest = store.persist(&mut map).unwrap();
+
+        let restored = store.restore(&digest).unwrap();
+        assert_eq!(
+            restored.get(&7u32.to_be_bytes()).unwrap(),
+            Some(vec![7; 2])
+        );
+        assert_eq!(restored.get(&99u32.to_be_bytes()).unwrap(), None);
+
+        let key = 42u32.to_be_bytes();
+        let proof = store.prove(&digest, &key).unwrap().unwrap();
+        assert_eq!(verify_proof(&digest, &proof, &key), Some(vec![42; 2]));
+        for i in 99..1000u32 {
+            assert_eq!(store.prove(&digest, &i.to_be_bytes()).unwrap(), None);
+        }
+
+        // a proof of another key, against another root, or tampered with
+        assert_eq!(verify_proof(&digest, &proof, &[0, 0, 0, 41]), None);
+        assert_eq!(verify_proof(&[0; 32], &proof, &key), None);
+        assert_eq!(verify_proof(&digest[1..], &proof, &key), None);
+        let mut tampered = proof.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(verify_proof(&digest, &tampered, &key), None);
+    }
+}